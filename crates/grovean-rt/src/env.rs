@@ -0,0 +1,105 @@
+use core::ffi::{c_char, CStr};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The argument and environment vectors from the initial stack, null until `init`.
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(ptr::null_mut());
+static ENVP: AtomicPtr<*const c_char> = AtomicPtr::new(ptr::null_mut());
+
+/// Where the argument and environment vectors are on a System V initial stack: after the
+/// argument count, each a list of string pointers ending with a null one.
+fn vectors(stack: *const usize) -> (*const *const c_char, *const *const c_char) {
+    unsafe {
+        let count = *stack;
+        let argv = stack.add(1) as *const *const c_char;
+        (argv, argv.add(count + 1))
+    }
+}
+
+/// Remember the vectors on the initial stack at `stack`.
+///
+/// # Safety
+///
+/// `stack` must be the stack pointer the kernel started the program with.
+pub(crate) unsafe fn init(stack: *const usize) {
+    let (argv, envp) = vectors(stack);
+    ARGV.store(argv as *mut _, Ordering::Relaxed);
+    ENVP.store(envp as *mut _, Ordering::Relaxed);
+}
+
+/// The strings of a null-terminated vector. Strings that are not UTF-8 come out empty.
+#[derive(Debug, Clone)]
+pub struct Strings {
+    next: *const *const c_char,
+}
+
+impl Strings {
+    fn new(vector: *const *const c_char) -> Self {
+        Self { next: vector }
+    }
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        if self.next.is_null() {
+            return None;
+        }
+        let string = unsafe { *self.next };
+        if string.is_null() {
+            self.next = ptr::null();
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        Some(unsafe { CStr::from_ptr(string) }.to_str().unwrap_or(""))
+    }
+}
+
+/// The program's arguments, its name first.
+pub fn args() -> Strings {
+    Strings::new(ARGV.load(Ordering::Relaxed))
+}
+
+/// The program's environment, as `NAME=value` strings.
+pub fn vars() -> Strings {
+    Strings::new(ENVP.load(Ordering::Relaxed))
+}
+
+/// The value of environment variable `name`.
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find_map(|variable| {
+        variable
+            .split_once('=')
+            .filter(|&(key, _)| key == name)
+            .map(|(_, value)| value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ffi::c_char;
+    use core::ptr;
+    use kunit::kunit;
+
+    use super::{args, init, var, Strings};
+
+    #[kunit]
+    fn vectors_come_from_the_initial_stack() {
+        static STRINGS: [&[u8]; 3] = [b"cat\0", b"motd\0", b"HOME=/\0"];
+        let string = |index: usize| STRINGS[index].as_ptr() as usize;
+        static mut STACK: [usize; 6] = [0; 6];
+        let stack = &raw mut STACK;
+        unsafe {
+            *stack = [2, string(0), string(1), 0, string(2), 0];
+            init(stack as *const usize);
+        }
+
+        let mut args = args();
+        assert_eq!((args.next(), args.next()), (Some("cat"), Some("motd")));
+        assert_eq!((args.next(), args.next()), (None, None));
+        assert_eq!(var("HOME"), Some("/"));
+        assert_eq!(var("HOM"), None);
+        assert_eq!(Strings::new(ptr::null::<*const c_char>()).count(), 0);
+    }
+}
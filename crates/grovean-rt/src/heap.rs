@@ -0,0 +1,164 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use spin::Mutex;
+
+use crate::syscall::{self, PROT_READ, PROT_WRITE};
+
+const PAGE_SIZE: usize = 4096;
+/// Blocks come in power-of-two size classes from `MIN_BLOCK` to `MAX_BLOCK` bytes. Larger
+/// allocations get a mapping of their own.
+const MIN_BLOCK: usize = 16;
+const MAX_BLOCK: usize = 2048;
+const CLASSES: usize = (MAX_BLOCK / MIN_BLOCK).trailing_zeros() as usize + 1;
+/// How much memory is mapped at a time to carve small blocks from.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[cfg(not(test))]
+#[global_allocator]
+static HEAP: Heap = Heap::new();
+
+/// The program's heap: free lists of small blocks carved from chunks of memory mapped with
+/// `mmap`. There is no `munmap` yet, so large blocks are never given back once freed.
+pub struct Heap {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// The first free block of each size class, each holding the address of the next, or zero.
+    free: [usize; CLASSES],
+    /// The part of the last chunk no block has been carved from yet.
+    next: usize,
+    end: usize,
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                free: [0; CLASSES],
+                next: 0,
+                end: 0,
+            }),
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let address = match size_class(layout) {
+            Some(class) => self.inner.lock().allocate(class, map),
+            // Mappings are page aligned, and that is as far as alignment goes.
+            None if layout.align() <= PAGE_SIZE => map(layout.size()),
+            None => None,
+        };
+        address.map_or(ptr::null_mut(), |address| address as *mut u8)
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        if let Some(class) = size_class(layout) {
+            unsafe { self.inner.lock().free(class, block as usize) };
+        }
+    }
+}
+
+impl Inner {
+    /// Take a block of size class `class`, reusing a freed one if there is one and mapping a
+    /// new chunk with `map` when the last one is used up.
+    fn allocate(
+        &mut self,
+        class: usize,
+        map: impl FnOnce(usize) -> Option<usize>,
+    ) -> Option<usize> {
+        let head = self.free[class];
+        if head != 0 {
+            self.free[class] = unsafe { *(head as *const usize) };
+            return Some(head);
+        }
+        // Blocks are aligned to their size, which covers the alignment they were asked for.
+        let size = block_size(class);
+        let mut start = self.next.next_multiple_of(size);
+        if self.end - self.next < start - self.next + size {
+            let chunk = map(CHUNK_SIZE)?;
+            start = chunk;
+            self.end = chunk + CHUNK_SIZE;
+        }
+        self.next = start + size;
+        Some(start)
+    }
+
+    /// Put the block at `address` back on the free list of size class `class`.
+    ///
+    /// # Safety
+    ///
+    /// The block must have come from `allocate` with the same class and not be in use.
+    unsafe fn free(&mut self, class: usize, address: usize) {
+        unsafe { *(address as *mut usize) = self.free[class] };
+        self.free[class] = address;
+    }
+}
+
+/// The size class a block for `layout` comes from, or `None` if it needs its own mapping.
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_BLOCK);
+    (size <= MAX_BLOCK).then(|| (size.next_power_of_two() / MIN_BLOCK).trailing_zeros() as usize)
+}
+
+fn block_size(class: usize) -> usize {
+    MIN_BLOCK << class
+}
+
+/// Map `length` bytes of zeroed, writable memory.
+fn map(length: usize) -> Option<usize> {
+    syscall::mmap(length, PROT_READ | PROT_WRITE)
+        .ok()
+        .map(|address| address as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+    use kunit::kunit;
+
+    use super::{size_class, Inner, CHUNK_SIZE, CLASSES, MAX_BLOCK};
+
+    #[repr(align(4096))]
+    struct Chunk([u8; CHUNK_SIZE]);
+
+    static mut CHUNK: Chunk = Chunk([0; CHUNK_SIZE]);
+
+    #[kunit]
+    fn layouts_fall_into_power_of_two_classes() {
+        let class = |size, align| size_class(Layout::from_size_align(size, align).unwrap());
+        assert_eq!(class(1, 1), Some(0));
+        assert_eq!(class(17, 8), Some(1));
+        assert_eq!(class(8, 64), Some(2));
+        assert_eq!(class(MAX_BLOCK, 8), Some(CLASSES - 1));
+        assert_eq!(class(MAX_BLOCK + 1, 8), None);
+    }
+
+    #[kunit]
+    fn freed_blocks_are_reused_before_new_memory() {
+        let chunk = unsafe { (&raw mut CHUNK.0) as usize };
+        let mut inner = Inner {
+            free: [0; CLASSES],
+            next: 0,
+            end: 0,
+        };
+        let first = inner.allocate(0, |_| Some(chunk)).unwrap();
+        let second = inner.allocate(0, |_| None).unwrap();
+        assert_eq!((first, second), (chunk, chunk + 16));
+
+        // A larger class starts at its own alignment past what is carved already.
+        assert_eq!(inner.allocate(2, |_| None), Some(chunk + 64));
+
+        unsafe { inner.free(0, first) };
+        assert_eq!(inner.allocate(0, |_| None), Some(first));
+        assert_eq!(inner.allocate(0, |_| None), Some(chunk + 128));
+    }
+}
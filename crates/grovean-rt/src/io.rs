@@ -0,0 +1,70 @@
+use core::fmt;
+
+use crate::syscall::{self, Errno};
+
+/// The descriptors every process starts with, all on the console.
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// An open file, closed when dropped.
+#[derive(Debug)]
+pub struct File {
+    descriptor: usize,
+}
+
+impl File {
+    /// Open the file at `path` with `syscall::OPEN_*` flags.
+    pub fn open(path: &str, flags: usize) -> Result<Self, Errno> {
+        syscall::open(path, flags).map(|descriptor| Self { descriptor })
+    }
+
+    pub fn descriptor(&self) -> usize {
+        self.descriptor
+    }
+
+    /// Read up to `buffer.len()` bytes, returning how many; zero at the end of the file.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Errno> {
+        syscall::read(self.descriptor, buffer)
+    }
+
+    pub fn write_all(&self, data: &[u8]) -> Result<(), Errno> {
+        write_all(self.descriptor, data)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.descriptor);
+    }
+}
+
+/// Write all of `data` to `descriptor`, failing with `Io` if it stops taking bytes.
+pub fn write_all(descriptor: usize, mut data: &[u8]) -> Result<(), Errno> {
+    while !data.is_empty() {
+        match syscall::write(descriptor, data)? {
+            0 => return Err(Errno::Io),
+            written => data = &data[written..],
+        }
+    }
+    Ok(())
+}
+
+/// Formatted output to a descriptor the program does not own, like standard output.
+struct Writer(usize);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        write_all(self.0, string.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Writer(STDOUT), args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Writer(STDERR), args);
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![cfg_attr(test, no_main)]
+#![cfg_attr(test, feature(custom_test_frameworks))] // test setup: enable custom test frameworks
+#![cfg_attr(test, test_runner(kunit::runner))] // test setup: use the custom test runner only in test mode
+#![cfg_attr(test, reexport_test_harness_main = "test_main")] // test setup: rename the test harness entry point
+
+#[cfg(test)]
+kunit::klib!("grovean-rt");
+
+pub mod env;
+pub mod heap;
+pub mod io;
+#[cfg(not(test))]
+mod start;
+pub mod syscall;
+
+/// What a program's main function may return, turned into its exit code.
+pub trait Termination {
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl<E: core::fmt::Debug> Termination for Result<(), E> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(()) => 0,
+            Err(error) => {
+                eprintln!("error: {:?}", error);
+                1
+            }
+        }
+    }
+}
+
+/// Make `$main` the program's main function, called by `_start` once the runtime is set up.
+/// Its return value, anything implementing `Termination`, becomes the exit code.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[unsafe(no_mangle)]
+        fn __grovean_rt_main() -> i32 {
+            $crate::Termination::report($main())
+        }
+    };
+}
+
+/// Prints to standard output.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to standard output, followed by a newline.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Prints to standard error.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// Prints to standard error, followed by a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    eprintln!("{}", info);
+    syscall::exit(101)
+}
@@ -0,0 +1,43 @@
+use core::arch::naked_asm;
+
+use crate::{env, syscall};
+
+unsafe extern "Rust" {
+    /// The program's main function, defined by `entry!`.
+    safe fn __grovean_rt_main() -> i32;
+}
+
+/// Where the kernel starts the program, with the stack pointer at the argument count. Clear
+/// the frame pointer so backtraces end here and call `start` with the stack as it was.
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _start() -> ! {
+    naked_asm!(
+        "xor ebp, ebp",
+        "mov rdi, rsp",
+        "call {start}",
+        "ud2",
+        start = sym start,
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _start() -> ! {
+    naked_asm!(
+        "mov x29, xzr",
+        "mov x30, xzr",
+        "mov x0, sp",
+        "bl {start}",
+        "brk #0",
+        start = sym start,
+    )
+}
+
+/// Set up the runtime, run the program, and exit with what it returns.
+unsafe extern "C" fn start(stack: *const usize) -> ! {
+    unsafe { env::init(stack) };
+    syscall::exit(__grovean_rt_main())
+}
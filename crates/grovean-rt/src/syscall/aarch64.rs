@@ -0,0 +1,21 @@
+use core::arch::asm;
+
+/// Make system call `number` with `svc #0`. The kernel preserves every register but `x0`,
+/// which holds the result.
+pub unsafe fn syscall(number: usize, arguments: [usize; 6]) -> usize {
+    let result: usize;
+    unsafe {
+        asm!(
+            "svc #0",
+            inlateout("x0") arguments[0] => result,
+            in("x1") arguments[1],
+            in("x2") arguments[2],
+            in("x3") arguments[3],
+            in("x4") arguments[4],
+            in("x5") arguments[5],
+            in("x8") number,
+            options(nostack),
+        );
+    }
+    result
+}
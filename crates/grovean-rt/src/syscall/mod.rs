@@ -0,0 +1,171 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+// System call numbers, as the kernel's dispatch table has them.
+pub const READ: usize = 0;
+pub const WRITE: usize = 1;
+pub const OPEN: usize = 2;
+pub const CLOSE: usize = 3;
+pub const MMAP: usize = 4;
+pub const EXIT: usize = 5;
+pub const GETPID: usize = 6;
+
+/// Flags `open` takes.
+pub const OPEN_READ: usize = 1 << 0;
+pub const OPEN_WRITE: usize = 1 << 1;
+pub const OPEN_CREATE: usize = 1 << 2;
+pub const OPEN_EXCLUSIVE: usize = 1 << 3;
+pub const OPEN_TRUNCATE: usize = 1 << 4;
+pub const OPEN_APPEND: usize = 1 << 5;
+pub const OPEN_DIRECTORY: usize = 1 << 6;
+
+/// Protection `mmap` takes. Mapped memory is always readable.
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+/// Results from -4095 to -1 are negated error codes; anything else is a value.
+const MAX_ERROR: usize = 4095;
+
+/// Why a system call failed, with the codes the kernel returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    NoEntry,
+    NoSuchProcess,
+    Io,
+    BadDescriptor,
+    NoMemory,
+    BadAddress,
+    Busy,
+    Exists,
+    NotADirectory,
+    IsADirectory,
+    InvalidArgument,
+    NoSpace,
+    ReadOnly,
+    NameTooLong,
+    NoSystemCall,
+    NotEmpty,
+    TooManySymlinks,
+    NotSupported,
+    /// A code this runtime does not know.
+    Unknown(usize),
+}
+
+impl Errno {
+    fn from_code(code: usize) -> Self {
+        match code {
+            2 => Self::NoEntry,
+            3 => Self::NoSuchProcess,
+            5 => Self::Io,
+            9 => Self::BadDescriptor,
+            12 => Self::NoMemory,
+            14 => Self::BadAddress,
+            16 => Self::Busy,
+            17 => Self::Exists,
+            20 => Self::NotADirectory,
+            21 => Self::IsADirectory,
+            22 => Self::InvalidArgument,
+            28 => Self::NoSpace,
+            30 => Self::ReadOnly,
+            36 => Self::NameTooLong,
+            38 => Self::NoSystemCall,
+            39 => Self::NotEmpty,
+            40 => Self::TooManySymlinks,
+            95 => Self::NotSupported,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Make system call `number` with `arguments`.
+///
+/// # Safety
+///
+/// Whatever the arguments point to must be valid for the call: the kernel checks that user
+/// memory is mapped, not that writing it is sound.
+pub unsafe fn syscall(number: usize, arguments: [usize; 6]) -> Result<usize, Errno> {
+    decode(unsafe { arch::syscall(number, arguments) })
+}
+
+fn decode(result: usize) -> Result<usize, Errno> {
+    let code = result.wrapping_neg();
+    if (1..=MAX_ERROR).contains(&code) {
+        Err(Errno::from_code(code))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Read up to `buffer.len()` bytes from `descriptor`, returning how many were read; zero at
+/// the end of the file.
+pub fn read(descriptor: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let arguments = [
+        descriptor,
+        buffer.as_mut_ptr() as usize,
+        buffer.len(),
+        0,
+        0,
+        0,
+    ];
+    unsafe { syscall(READ, arguments) }
+}
+
+/// Write `buffer` to `descriptor`, returning how many bytes were taken.
+pub fn write(descriptor: usize, buffer: &[u8]) -> Result<usize, Errno> {
+    let arguments = [descriptor, buffer.as_ptr() as usize, buffer.len(), 0, 0, 0];
+    unsafe { syscall(WRITE, arguments) }
+}
+
+/// Open the file at `path` with `OPEN_*` flags, returning its descriptor.
+pub fn open(path: &str, flags: usize) -> Result<usize, Errno> {
+    let arguments = [path.as_ptr() as usize, path.len(), flags, 0, 0, 0];
+    unsafe { syscall(OPEN, arguments) }
+}
+
+pub fn close(descriptor: usize) -> Result<(), Errno> {
+    unsafe { syscall(CLOSE, [descriptor, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Map `length` bytes of zeroed memory with `PROT_*` protection, wherever the kernel chooses.
+pub fn mmap(length: usize, protection: usize) -> Result<*mut u8, Errno> {
+    unsafe { syscall(MMAP, [0, length, protection, 0, 0, 0]) }.map(|address| address as *mut u8)
+}
+
+/// End the process with `code`.
+pub fn exit(code: i32) -> ! {
+    let _ = unsafe { syscall(EXIT, [code as usize, 0, 0, 0, 0, 0]) };
+    // The kernel never returns from exit; panicking here would only call it again.
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall(GETPID, [0; 6]) }.unwrap_or(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{decode, Errno};
+
+    #[kunit]
+    fn negative_results_are_errors() {
+        assert_eq!(decode(5), Ok(5));
+        assert_eq!(decode(0), Ok(0));
+        assert_eq!(decode(-2isize as usize), Err(Errno::NoEntry));
+        assert_eq!(decode(-38isize as usize), Err(Errno::NoSystemCall));
+        assert_eq!(decode(-4095isize as usize), Err(Errno::Unknown(4095)));
+        // Addresses in the upper half are values, not errors.
+        assert_eq!(decode(-4096isize as usize), Ok(-4096isize as usize));
+    }
+}
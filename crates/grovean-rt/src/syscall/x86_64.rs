@@ -0,0 +1,23 @@
+use core::arch::asm;
+
+/// Make system call `number` with `syscall`. The kernel preserves every register but `rax`,
+/// which holds the result, and `rcx` and `r11`, which the instruction itself uses.
+pub unsafe fn syscall(number: usize, arguments: [usize; 6]) -> usize {
+    let result: usize;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number => result,
+            in("rdi") arguments[0],
+            in("rsi") arguments[1],
+            in("rdx") arguments[2],
+            in("r10") arguments[3],
+            in("r8") arguments[4],
+            in("r9") arguments[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
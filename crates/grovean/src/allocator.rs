@@ -1,4 +1,74 @@
+use core::alloc::{GlobalAlloc, Layout};
+use linked_list_allocator::LockedHeap;
+
+use crate::interrupts;
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::hhdm;
+
+/// Size of the kernel heap carved out of physical memory at boot.
+pub const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
 /// An extremely simple (bare minimum) heap allocator
 #[used]
 #[global_allocator]
-static ALLOCATOR: linked_list_allocator::LockedHeap = linked_list_allocator::LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
+
+/// The heap with interrupts disabled while its lock is held, so an interrupt handler that
+/// allocates cannot wait on the code it interrupted.
+struct KernelHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| unsafe { self.0.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| unsafe { self.0.dealloc(ptr, layout) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+/// Back the heap with physically contiguous frames reached through the direct map.
+pub fn init() {
+    let frames = HEAP_SIZE / FRAME_SIZE as usize;
+    let heap_phys =
+        frame::allocate_contiguous_frames(frames).expect("failed to allocate kernel heap frames");
+
+    unsafe {
+        ALLOCATOR
+            .0
+            .lock()
+            .init(hhdm::phys_to_virt(heap_phys) as usize, HEAP_SIZE);
+    }
+}
+
+/// Back the heap with a static buffer, since tests boot without a memory map.
+#[cfg(test)]
+pub fn init_for_tests() {
+    const TEST_HEAP_SIZE: usize = 4 * 1024 * 1024;
+    static mut TEST_HEAP: [u8; TEST_HEAP_SIZE] = [0; TEST_HEAP_SIZE];
+
+    unsafe {
+        ALLOCATOR
+            .0
+            .lock()
+            .init(core::ptr::addr_of_mut!(TEST_HEAP) as usize, TEST_HEAP_SIZE);
+    }
+}
+
+pub fn stats() -> HeapStats {
+    interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.0.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+        }
+    })
+}
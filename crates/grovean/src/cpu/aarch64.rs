@@ -0,0 +1,69 @@
+use core::arch::asm;
+#[cfg(not(test))]
+use core::arch::naked_asm;
+
+use super::PerCpu;
+
+/// MPIDR_EL1 affinity levels 3 to 0, leaving out the multithreading and uniprocessor flags.
+const AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// Exceptions are taken on the interrupted task's own stack and every CPU shares one vector
+/// table, so there is nothing per CPU to set up here yet.
+pub struct Tables;
+
+impl Tables {
+    pub fn new() -> Option<Self> {
+        Some(Self)
+    }
+
+    pub fn load(&'static self) {}
+
+    /// User mode enters the kernel on SP_EL1, which is left at the kernel stack's top when
+    /// returning to user mode, so there is nothing to record.
+    pub fn set_kernel_stack(&self, _top: usize) {}
+}
+
+/// Make `cpu` the calling CPU's block by storing its address in TPIDR_EL1.
+pub fn set_local_base(cpu: &'static PerCpu) {
+    unsafe {
+        asm!("msr tpidr_el1, {}", in(reg) cpu as *const PerCpu, options(nostack, preserves_flags));
+    }
+}
+
+/// The calling CPU's block, from TPIDR_EL1.
+pub fn local_base() -> *const PerCpu {
+    let base: *const PerCpu;
+    unsafe {
+        asm!("mrs {}, tpidr_el1", out(reg) base, options(nomem, nostack, preserves_flags));
+    }
+    base
+}
+
+/// The affinity of the calling CPU.
+pub fn hardware_id() -> u64 {
+    let mpidr: u64;
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack, preserves_flags));
+    }
+    mpidr & AFFINITY_MASK
+}
+
+#[cfg(not(test))]
+pub fn listed_id(cpu: &limine::mp::Cpu) -> u64 {
+    cpu.mpidr & AFFINITY_MASK
+}
+
+/// Where application processors start: allow FP and SIMD instructions before any compiled code
+/// can use them, then continue in `start` with the `Cpu` still in x0.
+#[cfg(not(test))]
+#[unsafe(naked)]
+pub unsafe extern "C" fn entry(cpu: &limine::mp::Cpu) -> ! {
+    naked_asm!(
+        "mrs x9, cpacr_el1",
+        "orr x9, x9, #(3 << 20)",
+        "msr cpacr_el1, x9",
+        "isb",
+        "b {start}",
+        start = sym super::start,
+    )
+}
@@ -0,0 +1,151 @@
+use core::sync::atomic::Ordering;
+
+use super::{current, current_id, PerCpu, MAX_CPUS};
+use crate::{interrupts, task};
+
+/// A value with one instance per CPU, usually declared with `cpu_local!`.
+///
+/// A CPU only reaches its own instance through `with`, and cannot be preempted meanwhile, so
+/// the instance needs no lock against other CPUs. An interrupt handler on the same CPU can
+/// still get in between, so data handlers also touch is used with interrupts disabled.
+pub struct CpuLocal<T> {
+    slots: [CacheAligned<T>; MAX_CPUS],
+}
+
+/// A value on cache lines of its own, so writes by one CPU do not evict the lines another CPU
+/// is using for its neighbouring instance.
+#[repr(align(64))]
+pub struct CacheAligned<T>(T);
+
+impl<T> CacheAligned<T> {
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+// Each slot is only used by its own CPU, except through `get`, which requires `T: Sync`.
+unsafe impl<T: Send> Sync for CpuLocal<T> {}
+
+impl<T> CpuLocal<T> {
+    pub const fn new(slots: [CacheAligned<T>; MAX_CPUS]) -> Self {
+        Self { slots }
+    }
+
+    /// Run `f` on the calling CPU's instance with preemption disabled.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let _guard = disable_preemption();
+        f(&self.slots[current_id()].0)
+    }
+
+    /// The instance of CPU `index`, for an owner that already has exclusive access.
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        &mut self.slots[index].0
+    }
+}
+
+impl<T: Sync> CpuLocal<T> {
+    /// The instance of CPU `index`, which other CPUs may be using at the same time.
+    pub fn get(&self, index: usize) -> &T {
+        &self.slots[index].0
+    }
+
+    /// The instances of every CPU the kernel runs on.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots[..super::count()].iter().map(|slot| &slot.0)
+    }
+}
+
+/// Declare statics with one instance per CPU, each starting out as the initializer, which must
+/// be a constant expression.
+#[macro_export]
+macro_rules! cpu_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::cpu::CpuLocal<$ty> =
+                $crate::cpu::CpuLocal::new(
+                    [const { $crate::cpu::CacheAligned::new($init) }; $crate::cpu::MAX_CPUS],
+                );
+        )+
+    };
+}
+
+/// Keeps the scheduler from switching the calling CPU to another task until dropped. Guards
+/// nest.
+pub struct PreemptGuard {
+    /// `None` before the per-CPU blocks exist, when nothing is scheduled anyway.
+    cpu: Option<&'static PerCpu>,
+}
+
+pub fn disable_preemption() -> PreemptGuard {
+    // Counted with interrupts off so the task cannot move between finding its CPU and
+    // counting on it.
+    let cpu = interrupts::without_interrupts(|| {
+        let cpu = current()?;
+        cpu.preempt_count.fetch_add(1, Ordering::Relaxed);
+        Some(cpu)
+    });
+    PreemptGuard { cpu }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        let Some(cpu) = self.cpu else {
+            return;
+        };
+        let previous = cpu.preempt_count.fetch_sub(1, Ordering::Relaxed);
+        // With interrupts enabled this is not a handler, which leaves the switch to `dispatch`.
+        if previous == 1 && cpu.need_resched.load(Ordering::Relaxed) && interrupts::are_enabled() {
+            task::yield_now();
+        }
+    }
+}
+
+/// Whether the scheduler may switch the calling CPU to another task.
+pub fn preemptible() -> bool {
+    current().is_none_or(|cpu| cpu.preempt_count.load(Ordering::Relaxed) == 0)
+}
+
+/// Have the calling CPU switch tasks as soon as it is preemptible again.
+pub fn request_reschedule() {
+    if let Some(cpu) = current() {
+        cpu.need_resched.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether a switch was requested on the calling CPU and has not happened yet.
+pub fn reschedule_requested() -> bool {
+    current().is_some_and(|cpu| cpu.need_resched.load(Ordering::Relaxed))
+}
+
+/// Forget a requested switch, because the calling CPU is switching now.
+pub fn clear_reschedule_request() {
+    if let Some(cpu) = current() {
+        cpu.need_resched.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use kunit::kunit;
+
+    crate::cpu_local! {
+        static COUNTER: Cell<u32> = Cell::new(0);
+    }
+
+    #[kunit]
+    fn each_cpu_sees_its_own_instance() {
+        COUNTER.with(|counter| counter.set(counter.get() + 2));
+        assert_eq!(COUNTER.with(Cell::get), 2);
+        assert_eq!(COUNTER.slots[1].0.get(), 0);
+    }
+
+    #[kunit]
+    fn instances_do_not_share_cache_lines() {
+        let first = &COUNTER.slots[0].0 as *const Cell<u32> as usize;
+        let second = &COUNTER.slots[1].0 as *const Cell<u32> as usize;
+        assert_eq!(first % 64, 0);
+        assert!(second - first >= 64);
+    }
+}
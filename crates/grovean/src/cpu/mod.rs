@@ -0,0 +1,233 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+mod local;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+#[cfg(not(test))]
+use core::time::Duration;
+#[cfg(not(test))]
+use limine::request::MpRequest;
+use spin::Once;
+
+#[cfg(target_arch = "x86_64")]
+pub use arch::{
+    DOUBLE_FAULT_STACK_INDEX, SYSCALL_STACK_OFFSET, USER_CODE_SELECTOR, USER_DATA_SELECTOR,
+    USER_STACK_OFFSET,
+};
+pub use local::{
+    clear_reschedule_request, disable_preemption, preemptible, request_reschedule,
+    reschedule_requested, CacheAligned, CpuLocal, PreemptGuard,
+};
+
+#[cfg(not(test))]
+#[used]
+#[unsafe(link_section = ".requests")]
+static MP_REQUEST: MpRequest = MpRequest::new();
+
+/// Most CPUs the kernel brings up; any beyond these stay parked in the bootloader.
+pub const MAX_CPUS: usize = 64;
+
+/// How long the bootstrap processor waits for the others to report in.
+#[cfg(not(test))]
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Every CPU the kernel brings up, the bootstrap processor first. Unset until `init`, when
+/// only the bootstrap processor runs.
+static CPUS: Once<Vec<PerCpu>> = Once::new();
+/// Set once the bootstrap processor points its CPU-local base register at its block. Each
+/// application processor does the same before anything else.
+static LOCAL_BASE_SET: AtomicBool = AtomicBool::new(false);
+
+/// What the kernel keeps for one CPU, set up by the bootstrap processor before the CPU starts.
+#[repr(C)]
+pub struct PerCpu {
+    /// Points back at the block itself, first so x86_64 can load it through the GS segment.
+    this: AtomicPtr<PerCpu>,
+    /// Index in `0..count()`.
+    pub index: usize,
+    /// The local APIC ID on x86_64, the MPIDR affinity fields on aarch64.
+    pub hardware_id: u64,
+    /// Set once the CPU has finished its own setup and joined the scheduler.
+    online: AtomicBool,
+    /// Nested `PreemptGuard`s alive on the CPU.
+    preempt_count: AtomicUsize,
+    /// A task switch was wanted while the CPU could not be preempted.
+    need_resched: AtomicBool,
+    /// Descriptor tables and exception stacks.
+    tables: arch::Tables,
+}
+
+impl PerCpu {
+    fn new(index: usize, hardware_id: u64) -> Self {
+        Self {
+            this: AtomicPtr::new(core::ptr::null_mut()),
+            index,
+            hardware_id,
+            online: AtomicBool::new(index == 0),
+            preempt_count: AtomicUsize::new(0),
+            need_resched: AtomicBool::new(false),
+            tables: arch::Tables::new().expect("failed to allocate per-CPU tables"),
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    /// Load this CPU's tables and make it the calling CPU's block for `current`.
+    fn install(&'static self) {
+        self.this
+            .store(self as *const Self as *mut Self, Ordering::Relaxed);
+        self.tables.load();
+        arch::set_local_base(self);
+        LOCAL_BASE_SET.store(true, Ordering::Release);
+    }
+}
+
+fn cpus() -> Option<&'static [PerCpu]> {
+    CPUS.r#try().map(Vec::as_slice)
+}
+
+/// Number of CPUs the kernel schedules work on, online or still starting.
+pub fn count() -> usize {
+    cpus().map_or(1, <[PerCpu]>::len)
+}
+
+/// Number of CPUs that finished starting.
+pub fn online() -> usize {
+    cpus().map_or(1, |cpus| cpus.iter().filter(|cpu| cpu.is_online()).count())
+}
+
+/// Enter the kernel on the stack ending at `top` when user mode on this CPU is interrupted or
+/// makes a system call.
+pub fn set_kernel_stack(top: usize) {
+    if let Some(cpu) = current() {
+        cpu.tables.set_kernel_stack(top);
+    }
+}
+
+/// Index of the CPU executing the caller, in `0..count()`.
+pub fn current_id() -> usize {
+    current().map_or(0, |cpu| cpu.index)
+}
+
+/// The block of the CPU executing the caller, found through its CPU-local base register, or
+/// `None` before `init`.
+pub fn current() -> Option<&'static PerCpu> {
+    if !LOCAL_BASE_SET.load(Ordering::Acquire) {
+        return None;
+    }
+    Some(unsafe { &*arch::local_base() })
+}
+
+#[cfg(not(test))]
+fn index_of(cpus: &[PerCpu], hardware_id: u64) -> Option<usize> {
+    cpus.iter().position(|cpu| cpu.hardware_id == hardware_id)
+}
+
+/// Hardware IDs in the order CPUs are numbered: the bootstrap processor first, then the rest as
+/// the firmware listed them, up to `MAX_CPUS`.
+fn boot_order(bootstrap: u64, listed: impl IntoIterator<Item = u64>) -> Vec<u64> {
+    let mut order = Vec::from([bootstrap]);
+    order.extend(listed.into_iter().filter(|&id| id != bootstrap));
+    order.truncate(MAX_CPUS);
+    order
+}
+
+/// Number every CPU the bootloader found, then load the bootstrap processor's own descriptor
+/// tables and point its CPU-local base register at its block. Runs before `interrupts::init`,
+/// whose handlers are tied to the code segment loaded here.
+pub fn init() {
+    let bootstrap = arch::hardware_id();
+    #[cfg(not(test))]
+    let listed: Vec<u64> = MP_REQUEST
+        .get_response()
+        .map(|response| {
+            response
+                .cpus()
+                .iter()
+                .map(|cpu| arch::listed_id(cpu))
+                .collect()
+        })
+        .unwrap_or_default();
+    #[cfg(test)]
+    let listed = Vec::new();
+
+    let cpus: &'static Vec<PerCpu> = CPUS.call_once(|| {
+        boot_order(bootstrap, listed)
+            .into_iter()
+            .enumerate()
+            .map(|(index, hardware_id)| PerCpu::new(index, hardware_id))
+            .collect()
+    });
+    cpus[0].install();
+}
+
+/// Send the application processors to `start` and wait for them to join the scheduler.
+/// Runs once the scheduler has an idle task for every CPU.
+pub fn start_application_processors() {
+    #[cfg(not(test))]
+    {
+        let Some(response) = MP_REQUEST.get_response() else {
+            crate::info_ln!("cpu: 1 of 1 CPUs online, no multiprocessor response");
+            return;
+        };
+        let cpus = cpus().expect("cpu::init ran");
+        for cpu in response.cpus() {
+            let Some(index) = index_of(cpus, arch::listed_id(cpu)) else {
+                continue;
+            };
+            if index != 0 {
+                cpu.extra.store(index as u64, Ordering::Relaxed);
+                cpu.goto_address.write(arch::entry);
+            }
+        }
+
+        let deadline = crate::time::uptime() + STARTUP_TIMEOUT;
+        while online() < count() && crate::time::uptime() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+    crate::info_ln!("cpu: {} of {} CPUs online", online(), count());
+}
+
+/// Where an application processor lands from `arch::entry`, still on the bootloader's stack
+/// and with interrupts disabled.
+#[cfg(not(test))]
+extern "C" fn start(cpu: &limine::mp::Cpu) -> ! {
+    let index = cpu.extra.load(Ordering::Relaxed) as usize;
+    let this = &cpus().expect("cpu::init ran")[index];
+    this.install();
+    crate::interrupts::init_secondary();
+    crate::syscall::init();
+    this.online.store(true, Ordering::Release);
+    crate::task::start_secondary()
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{boot_order, MAX_CPUS};
+
+    #[kunit]
+    fn bootstrap_processor_is_numbered_first() {
+        assert_eq!(boot_order(2, [0, 1, 2, 3]), [2, 0, 1, 3]);
+        assert_eq!(boot_order(5, []), [5]);
+    }
+
+    #[kunit]
+    fn processors_past_the_limit_stay_parked() {
+        let order = boot_order(0, 0..MAX_CPUS as u64 + 8);
+        assert_eq!(order.len(), MAX_CPUS);
+        assert_eq!(order[MAX_CPUS - 1], MAX_CPUS as u64 - 1);
+    }
+}
@@ -0,0 +1,134 @@
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::cell::UnsafeCell;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Once;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+use super::PerCpu;
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::hhdm;
+
+/// Interrupt stack table slot the double fault handler runs on, so a kernel stack overflow
+/// reports a fault instead of resetting the machine.
+pub const DOUBLE_FAULT_STACK_INDEX: u16 = 0;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 16 * 1024;
+
+/// User segment selectors with their privilege level, the same on every CPU. The user data
+/// segment comes right before the user code segment, the layout `sysret` expects.
+pub const USER_DATA_SELECTOR: u16 = 0x1b;
+pub const USER_CODE_SELECTOR: u16 = 0x23;
+
+/// Offsets from the GS base of what the system call entry code reads before it has a stack.
+pub const SYSCALL_STACK_OFFSET: usize = offset_of!(PerCpu, tables.syscall_stack);
+pub const USER_STACK_OFFSET: usize = offset_of!(PerCpu, tables.user_stack);
+
+struct Selectors {
+    code: SegmentSelector,
+    data: SegmentSelector,
+    tss: SegmentSelector,
+}
+
+/// One CPU's GDT and TSS. The GDT is built on first load, once the TSS has its final address.
+pub struct Tables {
+    /// Only written by its own CPU, to switch the kernel stack user mode enters the kernel on.
+    tss: UnsafeCell<TaskStateSegment>,
+    gdt: Once<(GlobalDescriptorTable, Selectors)>,
+    /// The kernel stack `syscall` switches to, the same one the TSS holds.
+    syscall_stack: AtomicUsize,
+    /// The user stack pointer, from `syscall` until it is saved on the kernel stack.
+    user_stack: AtomicUsize,
+}
+
+unsafe impl Sync for Tables {}
+
+impl Tables {
+    pub fn new() -> Option<Self> {
+        let frames = DOUBLE_FAULT_STACK_SIZE / FRAME_SIZE as usize;
+        let stack = hhdm::phys_to_virt(frame::allocate_contiguous_frames(frames)?);
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_STACK_INDEX as usize] =
+            VirtAddr::new(stack + DOUBLE_FAULT_STACK_SIZE as u64);
+        Some(Self {
+            tss: UnsafeCell::new(tss),
+            gdt: Once::new(),
+            syscall_stack: AtomicUsize::new(0),
+            user_stack: AtomicUsize::new(0),
+        })
+    }
+
+    /// Load the tables on the calling CPU, replacing the bootloader's GDT.
+    pub fn load(&'static self) {
+        let (gdt, selectors) = self.gdt.call_once(|| {
+            let mut gdt = GlobalDescriptorTable::new();
+            let code = gdt.add_entry(Descriptor::kernel_code_segment());
+            let data = gdt.add_entry(Descriptor::kernel_data_segment());
+            let user_data = gdt.add_entry(Descriptor::user_data_segment());
+            let user_code = gdt.add_entry(Descriptor::user_code_segment());
+            debug_assert_eq!(user_data.0, USER_DATA_SELECTOR);
+            debug_assert_eq!(user_code.0, USER_CODE_SELECTOR);
+            let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*self.tss.get() }));
+            (gdt, Selectors { code, data, tss })
+        });
+        gdt.load();
+        unsafe {
+            CS::set_reg(selectors.code);
+            SS::set_reg(selectors.data);
+            DS::set_reg(selectors.data);
+            ES::set_reg(selectors.data);
+            load_tss(selectors.tss);
+        }
+    }
+
+    /// Enter the kernel on the stack ending at `top` when an interrupt, exception or system
+    /// call arrives in user mode. Called only on the CPU the tables belong to.
+    pub fn set_kernel_stack(&self, top: usize) {
+        unsafe {
+            (*self.tss.get()).privilege_stack_table[0] = VirtAddr::new(top as u64);
+        }
+        self.syscall_stack.store(top, Ordering::Relaxed);
+    }
+}
+
+/// Make `cpu` the calling CPU's block by pointing the GS segment at it.
+///
+/// User mode has a GS base of its own, which it can change, so the two trade places through
+/// `swapgs` on every way into and out of user mode: the kernel's sits in `IA32_KERNEL_GS_BASE`
+/// while user code runs, and the user's, starting at zero, while the kernel does.
+pub fn set_local_base(cpu: &'static PerCpu) {
+    GsBase::write(VirtAddr::from_ptr(cpu));
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// The calling CPU's block, read from its first field through the GS segment.
+pub fn local_base() -> *const PerCpu {
+    let base: *const PerCpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) base, options(nostack, preserves_flags, readonly));
+    }
+    base
+}
+
+/// The initial local APIC ID of the calling CPU.
+pub fn hardware_id() -> u64 {
+    let leaf = __cpuid(1);
+    (leaf.ebx >> 24) as u64
+}
+
+#[cfg(not(test))]
+pub fn listed_id(cpu: &limine::mp::Cpu) -> u64 {
+    cpu.lapic_id as u64
+}
+
+/// Where application processors start.
+#[cfg(not(test))]
+pub unsafe extern "C" fn entry(cpu: &limine::mp::Cpu) -> ! {
+    super::start(cpu)
+}
@@ -0,0 +1,185 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// ATA commands (ACS-3).
+pub const ATA_READ_DMA_EXT: u8 = 0x25;
+pub const ATA_WRITE_DMA_EXT: u8 = 0x35;
+pub const ATA_FLUSH_CACHE_EXT: u8 = 0xea;
+pub const ATA_IDENTIFY_DEVICE: u8 = 0xec;
+
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+/// Set in the second byte when the FIS carries a new command rather than a control update.
+const FIS_COMMAND: u8 = 1 << 7;
+const DEVICE_LBA_MODE: u8 = 1 << 6;
+
+pub const H2D_FIS_SIZE: usize = 20;
+pub const COMMAND_HEADER_SIZE: usize = 32;
+pub const PRDT_OFFSET: usize = 0x80;
+pub const PRDT_ENTRY_SIZE: usize = 16;
+
+pub const IDENTIFY_SIZE: usize = 512;
+
+/// Build a host-to-device register FIS for a 48-bit LBA command.
+pub fn h2d_fis(command: u8, lba: u64, count: u16) -> [u8; H2D_FIS_SIZE] {
+    let lba = lba.to_le_bytes();
+    let count = count.to_le_bytes();
+    let mut fis = [0u8; H2D_FIS_SIZE];
+    fis[0] = FIS_TYPE_REG_H2D;
+    fis[1] = FIS_COMMAND;
+    fis[2] = command;
+    fis[4..7].copy_from_slice(&lba[..3]);
+    fis[7] = DEVICE_LBA_MODE;
+    fis[8..11].copy_from_slice(&lba[3..6]);
+    fis[12..14].copy_from_slice(&count);
+    fis
+}
+
+/// An entry of the port command list, pointing at a command table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandHeader {
+    pub write: bool,
+    pub prdt_length: u16,
+    pub table_addr: u64,
+}
+
+impl CommandHeader {
+    pub fn write_to(&self, entry: &mut [u8]) {
+        entry[..COMMAND_HEADER_SIZE].fill(0);
+        // The command FIS length is given in dwords.
+        let mut flags = (H2D_FIS_SIZE / 4) as u16;
+        if self.write {
+            flags |= 1 << 6;
+        }
+        entry[0..2].copy_from_slice(&flags.to_le_bytes());
+        entry[2..4].copy_from_slice(&self.prdt_length.to_le_bytes());
+        entry[8..16].copy_from_slice(&self.table_addr.to_le_bytes());
+    }
+}
+
+/// Describe `length` bytes at `phys` in the PRDT of `table`, one entry per `chunk` bytes.
+/// Returns the number of entries written.
+pub fn write_prdt(table: &mut [u8], phys: u64, length: usize, chunk: usize) -> u16 {
+    let mut entries = 0;
+    let mut offset = 0;
+    while offset < length {
+        let size = chunk.min(length - offset);
+        let entry = &mut table[PRDT_OFFSET + entries * PRDT_ENTRY_SIZE..][..PRDT_ENTRY_SIZE];
+        entry.fill(0);
+        entry[0..8].copy_from_slice(&(phys + offset as u64).to_le_bytes());
+        // The byte count is stored minus one.
+        entry[12..16].copy_from_slice(&(size as u32 - 1).to_le_bytes());
+        offset += size;
+        entries += 1;
+    }
+    entries as u16
+}
+
+/// The fields of the IDENTIFY DEVICE data the driver uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskInfo {
+    pub model: String,
+    pub serial: String,
+    pub sector_count: u64,
+    pub sector_size: usize,
+}
+
+impl DiskInfo {
+    pub fn parse(data: &[u8]) -> Self {
+        let word = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+        let words = |first: usize, count: usize| {
+            (first..first + count).fold(0u64, |value, index| {
+                value | (word(index) as u64) << ((index - first) * 16)
+            })
+        };
+
+        let sector_count = if word(83) & (1 << 10) != 0 {
+            words(100, 4)
+        } else {
+            words(60, 2)
+        };
+        // Word 106 is valid when bit 14 is set and bit 15 clear.
+        let sector_info = word(106);
+        let sector_size = if sector_info & 0xc000 == 0x4000 && sector_info & (1 << 12) != 0 {
+            words(117, 2) as usize * 2
+        } else {
+            512
+        };
+
+        Self {
+            model: ata_string(&data[54..94]),
+            serial: ata_string(&data[20..40]),
+            sector_count,
+            sector_size,
+        }
+    }
+}
+
+/// ATA strings store two characters per word with the first in the high byte.
+fn ata_string(bytes: &[u8]) -> String {
+    let swapped: Vec<u8> = bytes
+        .as_chunks::<2>()
+        .0
+        .iter()
+        .flat_map(|pair| [pair[1], pair[0]])
+        .collect();
+    String::from_utf8_lossy(&swapped).trim().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{
+        h2d_fis, write_prdt, CommandHeader, DiskInfo, ATA_READ_DMA_EXT, IDENTIFY_SIZE, PRDT_OFFSET,
+    };
+
+    #[kunit]
+    fn fis_splits_lba_across_both_register_banks() {
+        let fis = h2d_fis(ATA_READ_DMA_EXT, 0x0605_0403_0201, 16);
+
+        assert_eq!(fis[..4], [0x27, 0x80, ATA_READ_DMA_EXT, 0]);
+        assert_eq!(fis[4..8], [0x01, 0x02, 0x03, 0x40]);
+        assert_eq!(fis[8..11], [0x04, 0x05, 0x06]);
+        assert_eq!(fis[12..14], [16, 0]);
+    }
+
+    #[kunit]
+    fn header_encodes_fis_length_and_direction() {
+        let mut entry = [0xffu8; 32];
+        CommandHeader {
+            write: true,
+            prdt_length: 3,
+            table_addr: 0x1_2345_6780,
+        }
+        .write_to(&mut entry);
+
+        assert_eq!(entry[0..4], [0x45, 0x00, 3, 0]);
+        assert_eq!(entry[4..8], [0, 0, 0, 0]);
+        assert_eq!(entry[8..16], 0x1_2345_6780u64.to_le_bytes());
+    }
+
+    #[kunit]
+    fn prdt_splits_buffer_into_chunks() {
+        let mut table = [0u8; PRDT_OFFSET + 4 * 16];
+        let entries = write_prdt(&mut table, 0x10_0000, 0x2200, 0x1000);
+
+        assert_eq!(entries, 3);
+        let last = &table[PRDT_OFFSET + 32..PRDT_OFFSET + 48];
+        assert_eq!(last[0..8], 0x10_2000u64.to_le_bytes());
+        assert_eq!(last[12..16], 0x1ffu32.to_le_bytes());
+    }
+
+    #[kunit]
+    fn identify_prefers_lba48_capacity() {
+        let mut data = [0u8; IDENTIFY_SIZE];
+        data[54..58].copy_from_slice(b"EQUM");
+        data[60 * 2..60 * 2 + 4].copy_from_slice(&0x0fff_ffffu32.to_le_bytes());
+        data[83 * 2 + 1] = 1 << 2;
+        data[100 * 2..100 * 2 + 8].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+
+        let info = DiskInfo::parse(&data);
+        assert_eq!(info.model, "QEMU");
+        assert_eq!(info.sector_count, 0x1_0000_0000);
+        assert_eq!(info.sector_size, 512);
+    }
+}
@@ -0,0 +1,386 @@
+pub mod command;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+use self::command::{
+    h2d_fis, write_prdt, CommandHeader, DiskInfo, ATA_FLUSH_CACHE_EXT, ATA_IDENTIFY_DEVICE,
+    ATA_READ_DMA_EXT, ATA_WRITE_DMA_EXT, IDENTIFY_SIZE, PRDT_ENTRY_SIZE, PRDT_OFFSET,
+};
+use super::{check_request, BlockDevice, BlockError};
+use crate::dev::pci::{self, Bar, PciDevice};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::paging;
+
+/// PCI class code of a SATA controller in AHCI mode.
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const PROG_IF_AHCI: u8 = 0x01;
+
+/// The AHCI base address is always BAR5.
+const ABAR_INDEX: u8 = 5;
+
+/// Generic host control registers (AHCI 1.3.1, section 3.1).
+const HBA_CAP: usize = 0x00;
+const HBA_GHC: usize = 0x04;
+const HBA_PI: usize = 0x0c;
+
+const GHC_RESET: u32 = 1 << 0;
+const GHC_AHCI_ENABLE: u32 = 1 << 31;
+
+/// Port registers, relative to the start of each port's register block.
+const PORT_BASE: usize = 0x100;
+const PORT_STRIDE: usize = 0x80;
+const PORT_CLB: usize = 0x00;
+const PORT_FB: usize = 0x08;
+const PORT_IS: usize = 0x10;
+const PORT_CMD: usize = 0x18;
+const PORT_TFD: usize = 0x20;
+const PORT_SIG: usize = 0x24;
+const PORT_SSTS: usize = 0x28;
+const PORT_SERR: usize = 0x30;
+const PORT_CI: usize = 0x38;
+
+const CMD_START: u32 = 1 << 0;
+const CMD_FIS_RECEIVE: u32 = 1 << 4;
+const CMD_FIS_RUNNING: u32 = 1 << 14;
+const CMD_LIST_RUNNING: u32 = 1 << 15;
+
+const TFD_ERROR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BUSY: u32 = 1 << 7;
+
+const IS_TASK_FILE_ERROR: u32 = 1 << 30;
+
+const SSTS_DET_PRESENT: u32 = 3;
+const SSTS_IPM_ACTIVE: u32 = 1;
+
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+
+/// Layout of the per-port control page: command list, received FIS area and one command table.
+const COMMAND_LIST_OFFSET: usize = 0;
+const RECEIVED_FIS_OFFSET: usize = 0x400;
+const COMMAND_TABLE_OFFSET: usize = 0x800;
+const CONTROL_PAGE_SIZE: usize = 4096;
+
+/// Each port bounces data through this buffer, described one page per PRDT entry.
+const DATA_BUFFER_SIZE: usize = 64 * 1024;
+const PRDT_CHUNK_SIZE: usize = 4096;
+
+/// Bounds waits for the HBA and the drive.
+const SPIN_LIMIT: usize = 10_000_000;
+
+/// Disks are named `sda`, `sdb`, ... across every controller.
+static NEXT_DISK: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AhciError {
+    MissingBar,
+    MappingFailed,
+    OutOfMemory,
+    Timeout,
+    /// The drive reported an error; holds the task file status and error registers.
+    TaskFile(u32),
+}
+
+impl From<AhciError> for BlockError {
+    fn from(error: AhciError) -> Self {
+        match error {
+            AhciError::Timeout => BlockError::Timeout,
+            AhciError::OutOfMemory => BlockError::OutOfMemory,
+            _ => BlockError::DeviceError,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Registers {
+    base: u64,
+}
+
+impl Registers {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset as u64) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset as u64) as *mut u32).write_volatile(value) }
+    }
+
+    fn wait_until<F: Fn(u32) -> bool>(&self, offset: usize, done: F) -> Result<(), AhciError> {
+        for _ in 0..SPIN_LIMIT {
+            if done(self.read(offset)) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(AhciError::Timeout)
+    }
+}
+
+/// A SATA port with an attached drive, issuing one command at a time from slot 0.
+struct Port {
+    registers: Registers,
+    control: DmaBuffer,
+    data: DmaBuffer,
+}
+
+impl Port {
+    fn new(registers: Registers) -> Result<Self, AhciError> {
+        let control = DmaBuffer::new(CONTROL_PAGE_SIZE).map_err(|_| AhciError::OutOfMemory)?;
+        let data = DmaBuffer::new(DATA_BUFFER_SIZE).map_err(|_| AhciError::OutOfMemory)?;
+        let mut port = Self {
+            registers,
+            control,
+            data,
+        };
+
+        port.stop()?;
+        let base = port.control.phys_addr();
+        let write_u64 = |offset: usize, value: u64| {
+            registers.write(offset, value as u32);
+            registers.write(offset + 4, (value >> 32) as u32);
+        };
+        write_u64(PORT_CLB, base + COMMAND_LIST_OFFSET as u64);
+        write_u64(PORT_FB, base + RECEIVED_FIS_OFFSET as u64);
+        // Both registers are write-one-to-clear.
+        registers.write(PORT_SERR, u32::MAX);
+        registers.write(PORT_IS, u32::MAX);
+        port.start()?;
+
+        Ok(port)
+    }
+
+    fn stop(&mut self) -> Result<(), AhciError> {
+        let registers = self.registers;
+        registers.write(PORT_CMD, registers.read(PORT_CMD) & !CMD_START);
+        registers.wait_until(PORT_CMD, |cmd| cmd & CMD_LIST_RUNNING == 0)?;
+        registers.write(PORT_CMD, registers.read(PORT_CMD) & !CMD_FIS_RECEIVE);
+        registers.wait_until(PORT_CMD, |cmd| cmd & CMD_FIS_RUNNING == 0)
+    }
+
+    fn start(&mut self) -> Result<(), AhciError> {
+        let registers = self.registers;
+        registers.wait_until(PORT_CMD, |cmd| cmd & CMD_LIST_RUNNING == 0)?;
+        registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_FIS_RECEIVE);
+        registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_START);
+        Ok(())
+    }
+
+    /// Issue an ATA command moving `length` bytes through the data buffer.
+    fn execute(
+        &mut self,
+        command: u8,
+        lba: u64,
+        count: u16,
+        length: usize,
+        write: bool,
+    ) -> Result<(), AhciError> {
+        let table_addr = self.control.phys_addr() + COMMAND_TABLE_OFFSET as u64;
+        let data_addr = self.data.phys_addr();
+
+        let control = self.control.as_mut_slice();
+        let table = &mut control[COMMAND_TABLE_OFFSET..];
+        table[..PRDT_OFFSET].fill(0);
+        let fis = h2d_fis(command, lba, count);
+        table[..fis.len()].copy_from_slice(&fis);
+        let prdt_length = write_prdt(table, data_addr, length, PRDT_CHUNK_SIZE);
+
+        CommandHeader {
+            write,
+            prdt_length,
+            table_addr,
+        }
+        .write_to(&mut control[COMMAND_LIST_OFFSET..]);
+
+        let registers = self.registers;
+        registers.wait_until(PORT_TFD, |tfd| tfd & (TFD_BUSY | TFD_DRQ) == 0)?;
+        registers.write(PORT_IS, u32::MAX);
+        registers.write(PORT_CI, 1);
+
+        for _ in 0..SPIN_LIMIT {
+            if registers.read(PORT_IS) & IS_TASK_FILE_ERROR != 0 {
+                return Err(AhciError::TaskFile(registers.read(PORT_TFD)));
+            }
+            if registers.read(PORT_CI) & 1 == 0 {
+                let tfd = registers.read(PORT_TFD);
+                return match tfd & TFD_ERROR {
+                    0 => Ok(()),
+                    _ => Err(AhciError::TaskFile(tfd)),
+                };
+            }
+            core::hint::spin_loop();
+        }
+        Err(AhciError::Timeout)
+    }
+
+    fn identify(&mut self) -> Result<DiskInfo, AhciError> {
+        self.execute(ATA_IDENTIFY_DEVICE, 0, 0, IDENTIFY_SIZE, false)?;
+        Ok(DiskInfo::parse(&self.data.as_slice()[..IDENTIFY_SIZE]))
+    }
+}
+
+/// A SATA disk behind an AHCI port.
+pub struct AhciDisk {
+    name: String,
+    port: Mutex<Port>,
+    info: DiskInfo,
+}
+
+impl AhciDisk {
+    pub fn info(&self) -> &DiskInfo {
+        &self.info
+    }
+
+    fn max_sectors(&self) -> usize {
+        // PRDT entries must fit in the command table page.
+        let table_entries =
+            (CONTROL_PAGE_SIZE - COMMAND_TABLE_OFFSET - PRDT_OFFSET) / PRDT_ENTRY_SIZE;
+        let bytes = DATA_BUFFER_SIZE.min(table_entries * PRDT_CHUNK_SIZE);
+        bytes / self.info.sector_size
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.info.sector_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.info.sector_count
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        let mut port = self.port.lock();
+
+        let max_sectors = self.max_sectors();
+        for (index, chunk) in buffer
+            .chunks_mut(max_sectors * self.info.sector_size)
+            .enumerate()
+        {
+            let start = lba + (index * max_sectors) as u64;
+            let count = (chunk.len() / self.info.sector_size) as u16;
+            port.execute(ATA_READ_DMA_EXT, start, count, chunk.len(), false)?;
+            chunk.copy_from_slice(&port.data.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        let mut port = self.port.lock();
+
+        let max_sectors = self.max_sectors();
+        for (index, chunk) in buffer
+            .chunks(max_sectors * self.info.sector_size)
+            .enumerate()
+        {
+            let start = lba + (index * max_sectors) as u64;
+            let count = (chunk.len() / self.info.sector_size) as u16;
+            port.data.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            port.execute(ATA_WRITE_DMA_EXT, start, count, chunk.len(), true)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.port
+            .lock()
+            .execute(ATA_FLUSH_CACHE_EXT, 0, 0, 0, false)?;
+        Ok(())
+    }
+}
+
+/// Enable AHCI mode on the HBA behind `device` and bring up every port with a SATA disk.
+fn probe(device: &PciDevice) -> Result<Vec<AhciDisk>, AhciError> {
+    let Some(Bar::Memory { address, size, .. }) = device.bar(ABAR_INDEX) else {
+        return Err(AhciError::MissingBar);
+    };
+    let hba = Registers {
+        base: paging::map_mmio(address, size).map_err(|_| AhciError::MappingFailed)?,
+    };
+    device.enable_bus_master();
+    device.disable_legacy_interrupts();
+
+    hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_AHCI_ENABLE);
+    hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_RESET);
+    hba.wait_until(HBA_GHC, |ghc| ghc & GHC_RESET == 0)?;
+    hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_AHCI_ENABLE);
+
+    let port_count = (hba.read(HBA_CAP) & 0x1f) as usize + 1;
+    let implemented = hba.read(HBA_PI);
+
+    let mut disks = Vec::new();
+    for index in (0..port_count).filter(|index| implemented & (1 << index) != 0) {
+        let registers = Registers {
+            base: hba.base + (PORT_BASE + index * PORT_STRIDE) as u64,
+        };
+        let status = registers.read(PORT_SSTS);
+        if status & 0xf != SSTS_DET_PRESENT || (status >> 8) & 0xf != SSTS_IPM_ACTIVE {
+            continue;
+        }
+        // ATAPI drives and port multipliers are left alone.
+        if registers.read(PORT_SIG) != SIGNATURE_ATA {
+            continue;
+        }
+
+        let setup = Port::new(registers).and_then(|mut port| Ok((port.identify()?, port)));
+        let (info, port) = match setup {
+            Ok(parts) => parts,
+            Err(error) => {
+                crate::warn_ln!("ahci: port {} setup failed: {:?}", index, error);
+                continue;
+            }
+        };
+        let letter = (b'a' + NEXT_DISK.fetch_add(1, Ordering::Relaxed) as u8) as char;
+        disks.push(AhciDisk {
+            name: format!("sd{letter}"),
+            port: Mutex::new(port),
+            info,
+        });
+    }
+    Ok(disks)
+}
+
+/// Bring up every AHCI controller and register its disks.
+pub fn init() {
+    let devices = pci::find(|device| {
+        device.class == CLASS_MASS_STORAGE
+            && device.subclass == SUBCLASS_SATA
+            && device.prog_if == PROG_IF_AHCI
+    });
+
+    for device in devices {
+        match probe(&device) {
+            Ok(disks) => {
+                for disk in disks {
+                    crate::info_ln!(
+                        "{}: {} serial={} {} sectors of {} bytes",
+                        disk.name(),
+                        disk.info().model,
+                        disk.info().serial,
+                        disk.block_count(),
+                        disk.block_size()
+                    );
+                    super::register(Arc::new(disk));
+                }
+            }
+            Err(error) => {
+                crate::warn_ln!(
+                    "ahci: {} initialization failed: {:?}",
+                    device.address,
+                    error
+                );
+            }
+        }
+    }
+}
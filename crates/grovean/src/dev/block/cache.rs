@@ -0,0 +1,375 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError};
+
+struct CachedBlock {
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub write_backs: u64,
+    pub cached_blocks: usize,
+    pub dirty_blocks: usize,
+}
+
+struct CacheState {
+    blocks: BTreeMap<u64, CachedBlock>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// A write-back cache of whole blocks in front of another device.
+///
+/// Writes stay in memory until `sync` or `flush`, or until the least recently used block is
+/// evicted to make room.
+pub struct BlockCache {
+    name: String,
+    device: Arc<dyn BlockDevice>,
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl BlockCache {
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            name: format!("{}-cache", device.name()),
+            device,
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState {
+                blocks: BTreeMap::new(),
+                clock: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock();
+        CacheStats {
+            cached_blocks: state.blocks.len(),
+            dirty_blocks: state.blocks.values().filter(|block| block.dirty).count(),
+            ..state.stats
+        }
+    }
+
+    /// Write every dirty block back to the device, coalescing adjacent blocks.
+    pub fn sync(&self) -> Result<(), BlockError> {
+        let mut state = self.state.lock();
+        let block_size = self.device.block_size();
+
+        let dirty: Vec<u64> = state
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.dirty)
+            .map(|(&lba, _)| lba)
+            .collect();
+
+        let mut index = 0;
+        while index < dirty.len() {
+            let start = dirty[index];
+            let mut run = 1;
+            while index + run < dirty.len() && dirty[index + run] == start + run as u64 {
+                run += 1;
+            }
+
+            let mut buffer = Vec::with_capacity(run * block_size);
+            for lba in start..start + run as u64 {
+                buffer.extend_from_slice(&state.blocks[&lba].data);
+            }
+            self.device.write_blocks(start, &buffer)?;
+
+            for lba in start..start + run as u64 {
+                if let Some(block) = state.blocks.get_mut(&lba) {
+                    block.dirty = false;
+                }
+            }
+            state.stats.write_backs += run as u64;
+            index += run;
+        }
+        Ok(())
+    }
+
+    /// Read `buffer.len()` bytes starting at byte `offset`, which need not be block aligned.
+    pub fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let block_size = self.device.block_size() as u64;
+        let mut block = vec![0u8; block_size as usize];
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let lba = position / block_size;
+            let start = (position % block_size) as usize;
+            let chunk = (block_size as usize - start).min(buffer.len() - done);
+            self.read_blocks(lba, &mut block)?;
+            buffer[done..done + chunk].copy_from_slice(&block[start..start + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Write `buffer` at byte `offset`, merging partially covered blocks with their contents.
+    pub fn write_bytes(&self, offset: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        let block_size = self.device.block_size() as u64;
+        let mut block = vec![0u8; block_size as usize];
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let lba = position / block_size;
+            let start = (position % block_size) as usize;
+            let chunk = (block_size as usize - start).min(buffer.len() - done);
+            if chunk == block.len() {
+                self.write_blocks(lba, &buffer[done..done + chunk])?;
+            } else {
+                self.read_blocks(lba, &mut block)?;
+                block[start..start + chunk].copy_from_slice(&buffer[done..done + chunk]);
+                self.write_blocks(lba, &block)?;
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Drop every clean block, for example after the device changed underneath the cache.
+    pub fn invalidate_clean(&self) {
+        self.state.lock().blocks.retain(|_, block| block.dirty);
+    }
+
+    fn insert(
+        &self,
+        state: &mut CacheState,
+        lba: u64,
+        block: CachedBlock,
+    ) -> Result<(), BlockError> {
+        while state.blocks.len() >= self.capacity && !state.blocks.contains_key(&lba) {
+            self.evict(state)?;
+        }
+        state.blocks.insert(lba, block);
+        Ok(())
+    }
+
+    fn evict(&self, state: &mut CacheState) -> Result<(), BlockError> {
+        let Some(victim) = state
+            .blocks
+            .iter()
+            .min_by_key(|(_, block)| block.last_used)
+            .map(|(&lba, _)| lba)
+        else {
+            return Ok(());
+        };
+
+        if state.blocks[&victim].dirty {
+            self.device
+                .write_blocks(victim, &state.blocks[&victim].data)?;
+            state.stats.write_backs += 1;
+        }
+        state.blocks.remove(&victim);
+        Ok(())
+    }
+
+    /// Read the missing blocks in `start..end` from the device in as few requests as possible.
+    fn fill(&self, state: &mut CacheState, start: u64, end: u64) -> Result<(), BlockError> {
+        let block_size = self.device.block_size();
+        let mut lba = start;
+        while lba < end {
+            if state.blocks.contains_key(&lba) {
+                lba += 1;
+                continue;
+            }
+
+            let run_end = (lba..end)
+                .find(|candidate| state.blocks.contains_key(candidate))
+                .unwrap_or(end);
+            let mut buffer = vec![0u8; (run_end - lba) as usize * block_size];
+            self.device.read_blocks(lba, &mut buffer)?;
+            state.stats.misses += run_end - lba;
+
+            for (offset, data) in buffer.chunks(block_size).enumerate() {
+                state.clock += 1;
+                let block = CachedBlock {
+                    data: data.into(),
+                    dirty: false,
+                    last_used: state.clock,
+                };
+                self.insert(state, lba + offset as u64, block)?;
+            }
+            lba = run_end;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let blocks = check_request(self, lba, buffer.len())?;
+        let block_size = self.device.block_size();
+        let mut state = self.state.lock();
+
+        // Requests larger than the cache go straight to the device, apart from dirty blocks.
+        if blocks as usize > self.capacity {
+            drop(state);
+            self.sync()?;
+            return self.device.read_blocks(lba, buffer);
+        }
+
+        // Mark the cached part of the request as recent so filling the rest cannot evict it.
+        state.clock += 1;
+        let clock = state.clock;
+        for (_, block) in state.blocks.range_mut(lba..lba + blocks) {
+            block.last_used = clock;
+        }
+        let misses = state.stats.misses;
+        self.fill(&mut state, lba, lba + blocks)?;
+        state.stats.hits += blocks - (state.stats.misses - misses);
+
+        for (offset, chunk) in buffer.chunks_mut(block_size).enumerate() {
+            state.clock += 1;
+            let clock = state.clock;
+            let block = state
+                .blocks
+                .get_mut(&(lba + offset as u64))
+                .ok_or(BlockError::DeviceError)?;
+            block.last_used = clock;
+            chunk.copy_from_slice(&block.data);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        if self.device.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, lba, buffer.len())?;
+        let block_size = self.device.block_size();
+        let mut state = self.state.lock();
+
+        for (offset, chunk) in buffer.chunks(block_size).enumerate() {
+            state.clock += 1;
+            let block = CachedBlock {
+                data: chunk.into(),
+                dirty: true,
+                last_used: state.clock,
+            };
+            self.insert(&mut state, lba + offset as u64, block)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.sync()?;
+        self.device.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use kunit::kunit;
+
+    use super::BlockCache;
+    use crate::dev::block::ramdisk::RamDisk;
+    use crate::dev::block::BlockDevice;
+
+    fn cache(capacity: usize) -> (Arc<RamDisk>, BlockCache) {
+        let disk = Arc::new(RamDisk::new("ram0", 512, 16));
+        disk.with_contents(|image| {
+            for (index, block) in image.chunks_mut(512).enumerate() {
+                block.fill(index as u8);
+            }
+        });
+        let cache = BlockCache::new(disk.clone(), capacity);
+        (disk, cache)
+    }
+
+    #[kunit]
+    fn repeated_reads_hit_the_cache() {
+        let (_, cache) = cache(8);
+        let mut buffer = [0u8; 1024];
+        cache.read_blocks(2, &mut buffer).expect("first read");
+        cache.read_blocks(2, &mut buffer).expect("second read");
+
+        assert_eq!(buffer[0], 2);
+        assert_eq!(buffer[512], 3);
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[kunit]
+    fn writes_reach_the_disk_only_on_sync() {
+        let (disk, cache) = cache(8);
+        cache.write_blocks(4, &[0xaa; 1024]).expect("write");
+
+        let mut buffer = [0u8; 512];
+        cache.read_blocks(5, &mut buffer).expect("read back");
+        assert_eq!(buffer[0], 0xaa);
+        disk.with_contents(|image| assert_eq!(image[4 * 512], 4));
+        assert_eq!(cache.stats().dirty_blocks, 2);
+
+        cache.sync().expect("sync");
+        disk.with_contents(|image| assert_eq!(image[5 * 512], 0xaa));
+        assert_eq!(cache.stats().dirty_blocks, 0);
+        assert_eq!(cache.stats().write_backs, 2);
+    }
+
+    #[kunit]
+    fn evicting_dirty_blocks_writes_them_back() {
+        let (disk, cache) = cache(2);
+        cache.write_blocks(0, &[0xbb; 512]).expect("write");
+        cache.read_blocks(1, &mut [0; 512]).expect("read");
+        cache.read_blocks(2, &mut [0; 512]).expect("read");
+
+        disk.with_contents(|image| assert_eq!(image[0], 0xbb));
+        assert_eq!(cache.stats().cached_blocks, 2);
+    }
+
+    #[kunit]
+    fn byte_access_spans_block_boundaries() {
+        let (_, cache) = cache(8);
+        cache.write_bytes(510, &[0xdd; 4]).expect("write");
+
+        let mut buffer = [0u8; 6];
+        cache.read_bytes(509, &mut buffer).expect("read");
+        assert_eq!(buffer, [0, 0xdd, 0xdd, 0xdd, 0xdd, 1]);
+    }
+
+    #[kunit]
+    fn large_reads_bypass_but_see_dirty_data() {
+        let (_, cache) = cache(2);
+        cache.write_blocks(3, &[0xcc; 512]).expect("write");
+
+        let mut buffer = [0u8; 4 * 512];
+        cache.read_blocks(2, &mut buffer).expect("large read");
+        assert_eq!(buffer[512], 0xcc);
+        assert_eq!(buffer[2 * 512], 4);
+    }
+}
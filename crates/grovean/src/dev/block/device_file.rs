@@ -0,0 +1,167 @@
+use alloc::sync::Arc;
+use alloc::vec;
+
+use super::BlockDevice;
+use crate::fs::devfs::Device;
+use crate::fs::vfs::{FileType, FsError};
+
+/// `ioctl` requests understood by block device files.
+pub const IOCTL_GET_SIZE: u32 = 0x1200;
+pub const IOCTL_GET_BLOCK_SIZE: u32 = 0x1201;
+
+/// Byte-addressed access to a block device, as exposed under `/dev`.
+///
+/// Transfers go straight to the device, bypassing the block caches of mounted filesystems.
+/// Partial blocks at either end of a request are read, patched and written back.
+pub struct BlockFile {
+    device: Arc<dyn BlockDevice>,
+}
+
+impl BlockFile {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self { device }
+    }
+
+    fn size_bytes(&self) -> u64 {
+        self.device.block_count() * self.device.block_size() as u64
+    }
+
+    /// Clamp a transfer of `length` bytes at `offset` to the end of the device.
+    fn clamp(&self, offset: u64, length: usize) -> usize {
+        (self.size_bytes().saturating_sub(offset) as usize).min(length)
+    }
+}
+
+impl Device for BlockFile {
+    fn file_type(&self) -> FileType {
+        FileType::BlockDevice
+    }
+
+    fn mode(&self) -> u32 {
+        if self.device.is_read_only() {
+            0o440
+        } else {
+            0o660
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.size_bytes()
+    }
+
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let count = self.clamp(offset, buffer.len());
+        let block_size = self.device.block_size();
+        let mut scratch = vec![0u8; block_size];
+
+        let mut done = 0;
+        while done < count {
+            let position = offset + done as u64;
+            let block = position / block_size as u64;
+            let within = (position % block_size as u64) as usize;
+            let remaining = count - done;
+
+            if within == 0 && remaining >= block_size {
+                let length = remaining - remaining % block_size;
+                self.device
+                    .read_blocks(block, &mut buffer[done..done + length])?;
+                done += length;
+            } else {
+                let length = (block_size - within).min(remaining);
+                self.device.read_blocks(block, &mut scratch)?;
+                buffer[done..done + length].copy_from_slice(&scratch[within..within + length]);
+                done += length;
+            }
+        }
+        Ok(count)
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        let count = self.clamp(offset, buffer.len());
+        if count == 0 && !buffer.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+        let block_size = self.device.block_size();
+        let mut scratch = vec![0u8; block_size];
+
+        let mut done = 0;
+        while done < count {
+            let position = offset + done as u64;
+            let block = position / block_size as u64;
+            let within = (position % block_size as u64) as usize;
+            let remaining = count - done;
+
+            if within == 0 && remaining >= block_size {
+                let length = remaining - remaining % block_size;
+                self.device
+                    .write_blocks(block, &buffer[done..done + length])?;
+                done += length;
+            } else {
+                let length = (block_size - within).min(remaining);
+                self.device.read_blocks(block, &mut scratch)?;
+                scratch[within..within + length].copy_from_slice(&buffer[done..done + length]);
+                self.device.write_blocks(block, &scratch)?;
+                done += length;
+            }
+        }
+        Ok(count)
+    }
+
+    fn ioctl(&self, request: u32, _argument: usize) -> Result<usize, FsError> {
+        match request {
+            IOCTL_GET_SIZE => Ok(self.size_bytes() as usize),
+            IOCTL_GET_BLOCK_SIZE => Ok(self.device.block_size()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(self.device.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::{BlockFile, IOCTL_GET_SIZE};
+    use crate::dev::block::ramdisk::RamDisk;
+    use crate::fs::devfs::Device;
+    use crate::fs::vfs::FsError;
+
+    fn disk() -> (Arc<RamDisk>, BlockFile) {
+        let disk = Arc::new(RamDisk::new("ram0", 512, 4));
+        (disk.clone(), BlockFile::new(disk))
+    }
+
+    #[kunit]
+    fn unaligned_writes_patch_partial_blocks() {
+        let (disk, file) = disk();
+
+        assert_eq!(file.write(500, &[0xaa; 600]), Ok(600));
+
+        disk.with_contents(|bytes| {
+            assert!(bytes[..500].iter().all(|&byte| byte == 0));
+            assert!(bytes[500..1100].iter().all(|&byte| byte == 0xaa));
+            assert!(bytes[1100..].iter().all(|&byte| byte == 0));
+        });
+        let mut read = vec![0u8; 20];
+        assert_eq!(file.read(490, &mut read), Ok(20));
+        assert_eq!(&read[..10], &[0; 10]);
+        assert_eq!(&read[10..], &[0xaa; 10]);
+    }
+
+    #[kunit]
+    fn transfers_stop_at_the_end_of_the_device() {
+        let (_, file) = disk();
+        let mut read = vec![0u8; 1024];
+
+        assert_eq!(file.read(1536, &mut read), Ok(512));
+        assert_eq!(file.read(2048, &mut read), Ok(0));
+        assert_eq!(file.write(2000, &[1; 100]), Ok(48));
+        assert_eq!(file.write(2048, &[1]), Err(FsError::NoSpace));
+        assert_eq!(file.ioctl(IOCTL_GET_SIZE, 0), Ok(2048));
+    }
+}
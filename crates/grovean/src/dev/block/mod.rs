@@ -0,0 +1,195 @@
+pub mod ahci;
+pub mod cache;
+pub mod device_file;
+pub mod nvme;
+pub mod partition;
+pub mod ramdisk;
+pub mod virtio_blk;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+static BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches past the last block of the device.
+    OutOfRange,
+    /// The buffer length is not a whole number of blocks.
+    UnalignedBuffer,
+    ReadOnly,
+    Timeout,
+    OutOfMemory,
+    DeviceError,
+}
+
+/// A device addressed in fixed-size logical blocks.
+///
+/// Methods take `&self` so drivers can serve several CPUs at once, each on its own queue.
+pub trait BlockDevice: Send + Sync {
+    /// Name of the device, such as `nvme0n1`.
+    fn name(&self) -> &str;
+
+    /// Size of one logical block in bytes.
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    /// Read `buffer.len() / block_size()` blocks starting at `lba`.
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `buffer.len() / block_size()` blocks starting at `lba`.
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError>;
+
+    /// Make all completed writes durable.
+    fn flush(&self) -> Result<(), BlockError>;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Check that a transfer of `length` bytes at `lba` fits the device, returning its block count.
+pub fn check_request(device: &dyn BlockDevice, lba: u64, length: usize) -> Result<u64, BlockError> {
+    let block_size = device.block_size();
+    if !length.is_multiple_of(block_size) {
+        return Err(BlockError::UnalignedBuffer);
+    }
+
+    let blocks = (length / block_size) as u64;
+    match lba.checked_add(blocks) {
+        Some(end) if end <= device.block_count() => Ok(blocks),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Probe for block devices and register them.
+pub fn init() {
+    nvme::init();
+    ahci::init();
+    virtio_blk::init();
+
+    let disks: Vec<_> = BLOCK_DEVICES.lock().clone();
+    for disk in disks {
+        scan_partitions(disk);
+    }
+}
+
+/// Register every partition found on `disk` as a block device of its own.
+pub fn scan_partitions(disk: Arc<dyn BlockDevice>) {
+    let entries = match partition::scan(disk.as_ref()) {
+        Ok(entries) => entries,
+        Err(partition::PartitionError::NoPartitionTable) => return,
+        Err(error) => {
+            crate::warn_ln!("{}: partition table unreadable: {:?}", disk.name(), error);
+            return;
+        }
+    };
+
+    for entry in entries {
+        match partition::Partition::new(disk.clone(), &entry) {
+            Ok(partition) => {
+                crate::info_ln!(
+                    "{}: start={} blocks={}",
+                    partition.name(),
+                    entry.start_lba,
+                    entry.block_count
+                );
+                register(Arc::new(partition));
+            }
+            Err(_) => {
+                crate::warn_ln!(
+                    "{}: partition {} exceeds the disk",
+                    disk.name(),
+                    entry.number
+                );
+            }
+        }
+    }
+}
+
+/// Register a block device, returning its index. It also appears as `/dev/<name>`.
+pub fn register(device: Arc<dyn BlockDevice>) -> usize {
+    let file = Arc::new(device_file::BlockFile::new(device.clone()));
+    if let Err(error) = crate::fs::devfs::register(device.name(), file) {
+        crate::warn_ln!("{}: no device file: {:?}", device.name(), error);
+    }
+
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.push(device);
+    devices.len() - 1
+}
+
+pub fn device_count() -> usize {
+    BLOCK_DEVICES.lock().len()
+}
+
+pub fn device(index: usize) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().get(index).cloned()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{check_request, BlockDevice, BlockError};
+
+    struct NullDevice;
+
+    impl BlockDevice for NullDevice {
+        fn name(&self) -> &str {
+            "null0"
+        }
+
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            8
+        }
+
+        fn read_blocks(&self, _: u64, _: &mut [u8]) -> Result<(), BlockError> {
+            Ok(())
+        }
+
+        fn write_blocks(&self, _: u64, _: &[u8]) -> Result<(), BlockError> {
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<(), BlockError> {
+            Ok(())
+        }
+    }
+
+    #[kunit]
+    fn requests_must_be_whole_blocks() {
+        assert_eq!(check_request(&NullDevice, 0, 1024), Ok(2));
+        assert_eq!(
+            check_request(&NullDevice, 0, 100),
+            Err(BlockError::UnalignedBuffer)
+        );
+    }
+
+    #[kunit]
+    fn requests_must_stay_on_the_device() {
+        assert_eq!(check_request(&NullDevice, 6, 1024), Ok(2));
+        assert_eq!(
+            check_request(&NullDevice, 7, 1024),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            check_request(&NullDevice, u64::MAX, 512),
+            Err(BlockError::OutOfRange)
+        );
+    }
+}
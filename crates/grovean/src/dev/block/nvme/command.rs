@@ -0,0 +1,244 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const SUBMISSION_SIZE: usize = 64;
+pub const COMPLETION_SIZE: usize = 16;
+
+/// Admin command opcodes (NVMe base specification 2.0, figure 28).
+pub const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+pub const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+pub const ADMIN_IDENTIFY: u8 = 0x06;
+pub const ADMIN_SET_FEATURES: u8 = 0x09;
+
+/// NVM command set opcodes.
+pub const IO_FLUSH: u8 = 0x00;
+pub const IO_WRITE: u8 = 0x01;
+pub const IO_READ: u8 = 0x02;
+
+/// Identify CNS values.
+pub const IDENTIFY_NAMESPACE: u32 = 0x00;
+pub const IDENTIFY_CONTROLLER: u32 = 0x01;
+pub const IDENTIFY_ACTIVE_NAMESPACES: u32 = 0x02;
+
+pub const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+
+pub const IDENTIFY_SIZE: usize = 4096;
+
+/// A submission queue entry using PRPs for its data pointer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Command {
+    pub opcode: u8,
+    pub command_id: u16,
+    pub namespace_id: u32,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+}
+
+impl Command {
+    pub fn new(opcode: u8) -> Self {
+        Self {
+            opcode,
+            ..Self::default()
+        }
+    }
+
+    pub fn identify(cns: u32, namespace_id: u32) -> Self {
+        Self {
+            namespace_id,
+            cdw10: cns,
+            ..Self::new(ADMIN_IDENTIFY)
+        }
+    }
+
+    /// Read or write `blocks` logical blocks starting at `lba`.
+    pub fn transfer(opcode: u8, namespace_id: u32, lba: u64, blocks: u16) -> Self {
+        Self {
+            namespace_id,
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            cdw12: (blocks - 1) as u32,
+            ..Self::new(opcode)
+        }
+    }
+
+    pub fn write_to(&self, entry: &mut [u8]) {
+        entry.fill(0);
+        entry[0] = self.opcode;
+        entry[2..4].copy_from_slice(&self.command_id.to_le_bytes());
+        entry[4..8].copy_from_slice(&self.namespace_id.to_le_bytes());
+        entry[24..32].copy_from_slice(&self.prp1.to_le_bytes());
+        entry[32..40].copy_from_slice(&self.prp2.to_le_bytes());
+        entry[40..44].copy_from_slice(&self.cdw10.to_le_bytes());
+        entry[44..48].copy_from_slice(&self.cdw11.to_le_bytes());
+        entry[48..52].copy_from_slice(&self.cdw12.to_le_bytes());
+    }
+}
+
+/// A completion queue entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub result: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub command_id: u16,
+    pub phase: bool,
+    /// Status code type and status code; zero means success.
+    pub status: u16,
+}
+
+impl Completion {
+    pub fn from_bytes(entry: &[u8; COMPLETION_SIZE]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        let status = u16_at(14);
+        Self {
+            result: u32_at(entry, 0),
+            sq_head: u16_at(8),
+            sq_id: u16_at(10),
+            command_id: u16_at(12),
+            phase: status & 1 != 0,
+            // Drop the more and do-not-retry bits, keeping the status code type and code.
+            status: (status >> 1) & 0x7ff,
+        }
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(u32_at(bytes, offset)) | u64::from(u32_at(bytes, offset + 4)) << 32
+}
+
+fn ascii_field(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim().into()
+}
+
+/// The fields of the Identify Controller data structure the driver uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControllerInfo {
+    pub serial: String,
+    pub model: String,
+    /// Maximum transfer size as a power of two multiple of the minimum page size; zero means
+    /// no limit.
+    pub max_transfer_shift: u8,
+    pub namespace_count: u32,
+}
+
+impl ControllerInfo {
+    pub fn parse(data: &[u8]) -> Self {
+        Self {
+            serial: ascii_field(&data[4..24]),
+            model: ascii_field(&data[24..64]),
+            max_transfer_shift: data[77],
+            namespace_count: u32_at(data, 516),
+        }
+    }
+}
+
+/// The fields of the Identify Namespace data structure the driver uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamespaceInfo {
+    pub block_count: u64,
+    pub block_size: usize,
+}
+
+impl NamespaceInfo {
+    pub fn parse(data: &[u8]) -> Self {
+        let format = (data[26] & 0x0f) as usize;
+        let lba_format = u32_at(data, 128 + format * 4);
+        Self {
+            block_count: u64_at(data, 0),
+            block_size: 1 << ((lba_format >> 16) & 0xff),
+        }
+    }
+}
+
+/// Parse an active namespace list, which ends at the first zero identifier.
+pub fn parse_namespace_list(data: &[u8]) -> Vec<u32> {
+    data.as_chunks::<4>()
+        .0
+        .iter()
+        .map(|id| u32::from_le_bytes(*id))
+        .take_while(|&id| id != 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::{
+        parse_namespace_list, Command, Completion, ControllerInfo, NamespaceInfo, IDENTIFY_SIZE,
+        IO_READ, SUBMISSION_SIZE,
+    };
+
+    #[kunit]
+    fn transfer_commands_encode_lba_and_zero_based_count() {
+        let mut command = Command::transfer(IO_READ, 1, 0x1_0000_0002, 8);
+        command.command_id = 0x1234;
+        command.prp1 = 0x8000;
+        let mut entry = [0xffu8; SUBMISSION_SIZE];
+        command.write_to(&mut entry);
+
+        assert_eq!(entry[0], IO_READ);
+        assert_eq!(entry[2..4], [0x34, 0x12]);
+        assert_eq!(entry[4..8], [1, 0, 0, 0]);
+        assert_eq!(entry[24..32], 0x8000u64.to_le_bytes());
+        assert_eq!(entry[40..44], [2, 0, 0, 0]);
+        assert_eq!(entry[44..48], [1, 0, 0, 0]);
+        assert_eq!(entry[48..52], [7, 0, 0, 0]);
+        assert_eq!(entry[60..], [0, 0, 0, 0]);
+    }
+
+    #[kunit]
+    fn completion_status_drops_phase_bit() {
+        let mut entry = [0u8; 16];
+        entry[12] = 5;
+        entry[14..16].copy_from_slice(&((0x0281u16 << 1) | 1).to_le_bytes());
+        let completion = Completion::from_bytes(&entry);
+
+        assert_eq!(completion.command_id, 5);
+        assert!(completion.phase);
+        assert_eq!(completion.status, 0x0281);
+    }
+
+    #[kunit]
+    fn identify_structures_are_parsed() {
+        let mut controller = vec![b' '; IDENTIFY_SIZE];
+        controller[4..10].copy_from_slice(b"deadbe");
+        controller[24..28].copy_from_slice(b"QEMU");
+        controller[77] = 5;
+        controller[516..520].copy_from_slice(&2u32.to_le_bytes());
+        let info = ControllerInfo::parse(&controller);
+        assert_eq!(info.serial, "deadbe");
+        assert_eq!(info.model, "QEMU");
+        assert_eq!(info.max_transfer_shift, 5);
+        assert_eq!(info.namespace_count, 2);
+
+        let mut namespace = vec![0u8; IDENTIFY_SIZE];
+        namespace[0..8].copy_from_slice(&0x2_0000u64.to_le_bytes());
+        namespace[26] = 1;
+        namespace[132..136].copy_from_slice(&(12u32 << 16).to_le_bytes());
+        let info = NamespaceInfo::parse(&namespace);
+        assert_eq!(info.block_count, 0x2_0000);
+        assert_eq!(info.block_size, 4096);
+    }
+
+    #[kunit]
+    fn namespace_list_stops_at_zero() {
+        let mut list = vec![0u8; 64];
+        list[0] = 1;
+        list[4] = 3;
+        assert_eq!(parse_namespace_list(&list), vec![1, 3]);
+    }
+}
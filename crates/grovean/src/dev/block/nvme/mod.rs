@@ -0,0 +1,395 @@
+pub mod command;
+pub mod queue;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use self::command::{
+    Command, Completion, ControllerInfo, NamespaceInfo, ADMIN_CREATE_IO_CQ, ADMIN_CREATE_IO_SQ,
+    ADMIN_SET_FEATURES, FEATURE_NUMBER_OF_QUEUES, IDENTIFY_ACTIVE_NAMESPACES, IDENTIFY_CONTROLLER,
+    IDENTIFY_NAMESPACE, IDENTIFY_SIZE, IO_FLUSH, IO_READ, IO_WRITE,
+};
+use self::queue::QueuePair;
+use super::{check_request, BlockDevice, BlockError};
+use crate::dev::pci::{self, Bar, PciDevice};
+use crate::memory::paging;
+
+/// PCI class code of an NVM Express controller.
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const SUBCLASS_NVM: u8 = 0x08;
+const PROG_IF_NVME: u8 = 0x02;
+
+/// Controller registers (NVMe base specification 2.0, section 3.1.4).
+const REG_CAP: usize = 0x00;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1c;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+const DOORBELL_BASE: usize = 0x1000;
+
+const CC_ENABLE: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion entries, as powers of two.
+const CC_IOSQES: u32 = 6 << 16;
+const CC_IOCQES: u32 = 4 << 20;
+
+const CSTS_READY: u32 = 1 << 0;
+const CSTS_FATAL: u32 = 1 << 1;
+
+const CAP_CSS_NVM: u64 = 1 << 37;
+
+const ADMIN_QUEUE_SIZE: u16 = 32;
+const IO_QUEUE_SIZE: u16 = 64;
+
+/// Size of each I/O queue's bounce buffer. Its PRP list fits in a single page.
+const IO_BUFFER_SIZE: usize = 128 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+/// Polling iterations allowed per 500 ms unit of the controller's CAP.TO ready timeout.
+const READY_SPINS_PER_UNIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeError {
+    MissingBar,
+    MappingFailed,
+    /// The controller lacks the NVM command set or 4 KiB pages.
+    Unsupported,
+    ControllerFatal,
+    Timeout,
+    OutOfMemory,
+    UnexpectedCompletion,
+    CommandFailed(u16),
+}
+
+impl From<NvmeError> for BlockError {
+    fn from(error: NvmeError) -> Self {
+        match error {
+            NvmeError::Timeout => BlockError::Timeout,
+            NvmeError::OutOfMemory => BlockError::OutOfMemory,
+            _ => BlockError::DeviceError,
+        }
+    }
+}
+
+struct Registers {
+    base: u64,
+    doorbell_stride: usize,
+}
+
+impl Registers {
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset as u64) as *const u32).read_volatile() }
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset as u64) as *mut u32).write_volatile(value) }
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        u64::from(self.read_u32(offset)) | u64::from(self.read_u32(offset + 4)) << 32
+    }
+
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.write_u32(offset, value as u32);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+    }
+
+    /// Submission tail and completion head doorbells of queue `id`.
+    fn doorbells(&self, id: u16) -> (*mut u32, *mut u32) {
+        let doorbell = |index: usize| {
+            (self.base + (DOORBELL_BASE + index * self.doorbell_stride) as u64) as *mut u32
+        };
+        (doorbell(2 * id as usize), doorbell(2 * id as usize + 1))
+    }
+
+    fn wait_ready(&self, ready: bool, spin_limit: usize) -> Result<(), NvmeError> {
+        for _ in 0..spin_limit {
+            let status = self.read_u32(REG_CSTS);
+            if status & CSTS_FATAL != 0 {
+                return Err(NvmeError::ControllerFatal);
+            }
+            if (status & CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(NvmeError::Timeout)
+    }
+}
+
+/// An NVMe controller with one admin queue pair and one I/O queue pair per CPU.
+pub struct Controller {
+    name: String,
+    registers: Registers,
+    admin: Mutex<QueuePair>,
+    io: Vec<Mutex<QueuePair>>,
+    info: ControllerInfo,
+    /// Largest transfer a single I/O command may carry, in bytes.
+    max_transfer: usize,
+}
+
+impl Controller {
+    /// Reset and enable the controller behind `device`, then create its I/O queues.
+    pub fn new(device: &PciDevice, name: String) -> Result<Self, NvmeError> {
+        let Some(Bar::Memory { address, size, .. }) = device.bar(0) else {
+            return Err(NvmeError::MissingBar);
+        };
+        let base = paging::map_mmio(address, size).map_err(|_| NvmeError::MappingFailed)?;
+        device.enable_bus_master();
+        device.disable_legacy_interrupts();
+
+        let mut registers = Registers {
+            base,
+            doorbell_stride: 4,
+        };
+        let capabilities = registers.read_u64(REG_CAP);
+        let max_entries = (capabilities & 0xffff) as u16 + 1;
+        let spin_limit = ((capabilities >> 24) & 0xff).max(1) as usize * READY_SPINS_PER_UNIT;
+        registers.doorbell_stride = 4 << ((capabilities >> 32) & 0xf);
+        if capabilities & CAP_CSS_NVM == 0 || (capabilities >> 48) & 0xf != 0 {
+            return Err(NvmeError::Unsupported);
+        }
+
+        registers.write_u32(REG_CC, registers.read_u32(REG_CC) & !CC_ENABLE);
+        registers.wait_ready(false, spin_limit)?;
+
+        let admin_size = ADMIN_QUEUE_SIZE.min(max_entries);
+        let (sq_doorbell, cq_doorbell) = registers.doorbells(0);
+        let admin = QueuePair::new(0, admin_size, IDENTIFY_SIZE, sq_doorbell, cq_doorbell)?;
+        let queue_size = u32::from(admin_size - 1);
+        registers.write_u32(REG_AQA, queue_size << 16 | queue_size);
+        registers.write_u64(REG_ASQ, admin.submission_addr());
+        registers.write_u64(REG_ACQ, admin.completion_addr());
+        registers.write_u32(REG_CC, CC_ENABLE | CC_IOSQES | CC_IOCQES);
+        registers.wait_ready(true, spin_limit)?;
+
+        let mut controller = Self {
+            name,
+            registers,
+            admin: Mutex::new(admin),
+            io: Vec::new(),
+            info: ControllerInfo::parse(&[0; IDENTIFY_SIZE]),
+            max_transfer: IO_BUFFER_SIZE,
+        };
+
+        controller.info = ControllerInfo::parse(&controller.identify(IDENTIFY_CONTROLLER, 0)?);
+        if controller.info.max_transfer_shift != 0 {
+            let limit = PAGE_SIZE << controller.info.max_transfer_shift;
+            controller.max_transfer = controller.max_transfer.min(limit);
+        }
+        controller.create_io_queues(IO_QUEUE_SIZE.min(max_entries))?;
+
+        Ok(controller)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn info(&self) -> &ControllerInfo {
+        &self.info
+    }
+
+    pub fn io_queue_count(&self) -> usize {
+        self.io.len()
+    }
+
+    /// Discover the active namespaces, each of which becomes a block device.
+    pub fn namespaces(self: &Arc<Self>) -> Result<Vec<Namespace>, NvmeError> {
+        let ids = match self.identify(IDENTIFY_ACTIVE_NAMESPACES, 0) {
+            Ok(list) => command::parse_namespace_list(&list),
+            // Controllers before NVMe 1.1 only report a namespace count.
+            Err(NvmeError::CommandFailed(_)) => (1..=self.info.namespace_count).collect(),
+            Err(error) => return Err(error),
+        };
+
+        let mut namespaces = Vec::new();
+        for id in ids {
+            let info = NamespaceInfo::parse(&self.identify(IDENTIFY_NAMESPACE, id)?);
+            if info.block_count == 0 {
+                continue;
+            }
+            namespaces.push(Namespace {
+                controller: self.clone(),
+                id,
+                name: format!("{}n{}", self.name, id),
+                info,
+            });
+        }
+        Ok(namespaces)
+    }
+
+    fn identify(&self, cns: u32, namespace_id: u32) -> Result<Vec<u8>, NvmeError> {
+        let mut admin = self.admin.lock();
+        let mut command = Command::identify(cns, namespace_id);
+        admin.attach_data(&mut command, IDENTIFY_SIZE);
+        admin.execute(command)?;
+        Ok(admin.data()[..IDENTIFY_SIZE].to_vec())
+    }
+
+    fn admin_command(&self, command: Command) -> Result<Completion, NvmeError> {
+        self.admin.lock().execute(command)
+    }
+
+    fn create_io_queues(&mut self, size: u16) -> Result<(), NvmeError> {
+        let requested = crate::cpu::count() as u32;
+        let completion = self.admin_command(Command {
+            cdw10: FEATURE_NUMBER_OF_QUEUES,
+            cdw11: (requested - 1) << 16 | (requested - 1),
+            ..Command::new(ADMIN_SET_FEATURES)
+        })?;
+        // Both counts in the result are zero-based.
+        let granted = (completion.result & 0xffff).min(completion.result >> 16) + 1;
+
+        for id in 1..=requested.min(granted) as u16 {
+            let (sq_doorbell, cq_doorbell) = self.registers.doorbells(id);
+            let queue = QueuePair::new(id, size, IO_BUFFER_SIZE, sq_doorbell, cq_doorbell)?;
+            let queue_size = u32::from(size - 1) << 16 | u32::from(id);
+
+            // Physically contiguous queues without interrupts; completions are polled.
+            self.admin_command(Command {
+                prp1: queue.completion_addr(),
+                cdw10: queue_size,
+                cdw11: 1,
+                ..Command::new(ADMIN_CREATE_IO_CQ)
+            })?;
+            self.admin_command(Command {
+                prp1: queue.submission_addr(),
+                cdw10: queue_size,
+                cdw11: u32::from(id) << 16 | 1,
+                ..Command::new(ADMIN_CREATE_IO_SQ)
+            })?;
+            self.io.push(Mutex::new(queue));
+        }
+        Ok(())
+    }
+
+    /// The I/O queue pair owned by the current CPU.
+    fn io_queue(&self) -> &Mutex<QueuePair> {
+        &self.io[crate::cpu::current_id() % self.io.len()]
+    }
+}
+
+/// An NVMe namespace exposed as a block device.
+pub struct Namespace {
+    controller: Arc<Controller>,
+    id: u32,
+    name: String,
+    info: NamespaceInfo,
+}
+
+impl Namespace {
+    /// Most blocks a single read or write command may carry.
+    fn max_blocks(&self) -> usize {
+        (self.controller.max_transfer / self.info.block_size).min(u16::MAX as usize + 1)
+    }
+}
+
+impl BlockDevice for Namespace {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.info.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.info.block_count
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        let mut queue = self.controller.io_queue().lock();
+
+        let chunk_size = self.max_blocks() * self.info.block_size;
+        for (index, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let start = lba + (index * self.max_blocks()) as u64;
+            let blocks = (chunk.len() / self.info.block_size) as u16;
+            let mut command = Command::transfer(IO_READ, self.id, start, blocks);
+            queue.attach_data(&mut command, chunk.len());
+            queue.execute(command)?;
+            chunk.copy_from_slice(&queue.data()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        let mut queue = self.controller.io_queue().lock();
+
+        let chunk_size = self.max_blocks() * self.info.block_size;
+        for (index, chunk) in buffer.chunks(chunk_size).enumerate() {
+            let start = lba + (index * self.max_blocks()) as u64;
+            let blocks = (chunk.len() / self.info.block_size) as u16;
+            queue.data_mut()[..chunk.len()].copy_from_slice(chunk);
+            let mut command = Command::transfer(IO_WRITE, self.id, start, blocks);
+            queue.attach_data(&mut command, chunk.len());
+            queue.execute(command)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let command = Command {
+            namespace_id: self.id,
+            ..Command::new(IO_FLUSH)
+        };
+        self.controller.io_queue().lock().execute(command)?;
+        Ok(())
+    }
+}
+
+/// Bring up every NVMe controller and register its namespaces.
+pub fn init() {
+    let devices = pci::find(|device| {
+        device.class == CLASS_MASS_STORAGE
+            && device.subclass == SUBCLASS_NVM
+            && device.prog_if == PROG_IF_NVME
+    });
+
+    for (index, device) in devices.iter().enumerate() {
+        let controller = match Controller::new(device, format!("nvme{index}")) {
+            Ok(controller) => Arc::new(controller),
+            Err(error) => {
+                crate::warn_ln!(
+                    "nvme: {} initialization failed: {:?}",
+                    device.address,
+                    error
+                );
+                continue;
+            }
+        };
+        crate::info_ln!(
+            "{}: {} serial={} io_queues={}",
+            controller.name(),
+            controller.info().model,
+            controller.info().serial,
+            controller.io_queue_count()
+        );
+
+        match controller.namespaces() {
+            Ok(namespaces) => {
+                for namespace in namespaces {
+                    crate::info_ln!(
+                        "{}: {} blocks of {} bytes",
+                        namespace.name(),
+                        namespace.block_count(),
+                        namespace.block_size()
+                    );
+                    super::register(Arc::new(namespace));
+                }
+            }
+            Err(error) => {
+                crate::warn_ln!(
+                    "{}: namespace discovery failed: {:?}",
+                    controller.name(),
+                    error
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use super::command::{Command, Completion, COMPLETION_SIZE, SUBMISSION_SIZE};
+use super::NvmeError;
+use crate::memory::dma::DmaBuffer;
+use crate::memory::frame::FRAME_SIZE;
+
+/// Bounds the wait for a single command to complete.
+const COMPLETION_SPIN_LIMIT: usize = 10_000_000;
+
+/// A submission queue and its dedicated completion queue, driven one command at a time.
+pub struct QueuePair {
+    id: u16,
+    size: u16,
+    submissions: DmaBuffer,
+    completions: DmaBuffer,
+    sq_tail: u16,
+    cq_head: u16,
+    /// Phase tag the controller writes into new completions; flips on every wrap.
+    phase: bool,
+    next_command_id: u16,
+    sq_doorbell: *mut u32,
+    cq_doorbell: *mut u32,
+    /// Bounce buffer for data transfers, described to the controller through `prp_list`.
+    data: DmaBuffer,
+    prp_list: DmaBuffer,
+}
+
+// The doorbell pointers target controller registers, which any CPU may write.
+unsafe impl Send for QueuePair {}
+
+impl QueuePair {
+    pub fn new(
+        id: u16,
+        size: u16,
+        data_size: usize,
+        sq_doorbell: *mut u32,
+        cq_doorbell: *mut u32,
+    ) -> Result<Self, NvmeError> {
+        let allocate = |len| DmaBuffer::new(len).map_err(|_| NvmeError::OutOfMemory);
+        Ok(Self {
+            id,
+            size,
+            submissions: allocate(size as usize * SUBMISSION_SIZE)?,
+            completions: allocate(size as usize * COMPLETION_SIZE)?,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            next_command_id: 0,
+            sq_doorbell,
+            cq_doorbell,
+            data: allocate(data_size)?,
+            prp_list: allocate(FRAME_SIZE as usize)?,
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn submission_addr(&self) -> u64 {
+        self.submissions.phys_addr()
+    }
+
+    pub fn completion_addr(&self) -> u64 {
+        self.completions.phys_addr()
+    }
+
+    /// Capacity of the bounce buffer in bytes.
+    pub fn data_capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut_slice()
+    }
+
+    /// Point the command's PRP entries at the first `length` bytes of the bounce buffer.
+    ///
+    /// PRP1 covers the first page, PRP2 either the second page or a list of the remaining ones.
+    pub fn attach_data(&mut self, command: &mut Command, length: usize) {
+        let page = FRAME_SIZE as usize;
+        let base = self.data.phys_addr();
+        let pages = length.div_ceil(page).max(1);
+
+        command.prp1 = base;
+        command.prp2 = match pages {
+            1 => 0,
+            2 => base + FRAME_SIZE,
+            _ => {
+                let list = self.prp_list.as_mut_slice();
+                for index in 1..pages {
+                    let entry = base + index as u64 * FRAME_SIZE;
+                    list[(index - 1) * 8..index * 8].copy_from_slice(&entry.to_le_bytes());
+                }
+                self.prp_list.phys_addr()
+            }
+        };
+    }
+
+    /// Queue `command`, ring the doorbell and spin until its completion arrives.
+    pub fn execute(&mut self, mut command: Command) -> Result<Completion, NvmeError> {
+        command.command_id = self.next_command_id;
+        self.next_command_id = self.next_command_id.wrapping_add(1);
+
+        let offset = self.sq_tail as usize * SUBMISSION_SIZE;
+        command.write_to(&mut self.submissions.as_mut_slice()[offset..offset + SUBMISSION_SIZE]);
+        self.sq_tail = (self.sq_tail + 1) % self.size;
+        unsafe { self.sq_doorbell.write_volatile(self.sq_tail as u32) };
+
+        let mut spins = 0;
+        let completion = loop {
+            if let Some(completion) = self.pop_completion() {
+                break completion;
+            }
+            spins += 1;
+            if spins >= COMPLETION_SPIN_LIMIT {
+                return Err(NvmeError::Timeout);
+            }
+            core::hint::spin_loop();
+        };
+        unsafe { self.cq_doorbell.write_volatile(self.cq_head as u32) };
+
+        if completion.command_id != command.command_id {
+            return Err(NvmeError::UnexpectedCompletion);
+        }
+        match completion.status {
+            0 => Ok(completion),
+            status => Err(NvmeError::CommandFailed(status)),
+        }
+    }
+
+    fn pop_completion(&mut self) -> Option<Completion> {
+        let offset = self.cq_head as usize * COMPLETION_SIZE;
+        let entry = unsafe {
+            core::ptr::read_volatile(
+                self.completions.as_ptr().add(offset) as *const [u8; COMPLETION_SIZE]
+            )
+        };
+        let completion = Completion::from_bytes(&entry);
+        if completion.phase != self.phase {
+            return None;
+        }
+
+        self.cq_head += 1;
+        if self.cq_head == self.size {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+        Some(completion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use kunit::kunit;
+
+    use super::QueuePair;
+    use crate::dev::block::nvme::command::{Command, COMPLETION_SIZE};
+    use crate::dev::block::nvme::NvmeError;
+    use crate::memory::frame::FRAME_SIZE;
+
+    fn queue(size: u16, data_size: usize) -> (QueuePair, &'static mut [u32; 2]) {
+        let doorbells = Box::leak(Box::new([0u32; 2]));
+        let base = doorbells.as_mut_ptr();
+        let queue = QueuePair::new(1, size, data_size, base, unsafe { base.add(1) })
+            .expect("queue memory should be available");
+        (queue, doorbells)
+    }
+
+    /// Post a completion the way the controller would.
+    fn complete(queue: &mut QueuePair, slot: usize, command_id: u16, phase: bool, status: u16) {
+        let entry = &mut queue.completions.as_mut_slice()
+            [slot * COMPLETION_SIZE..(slot + 1) * COMPLETION_SIZE];
+        entry[12..14].copy_from_slice(&command_id.to_le_bytes());
+        let status_field = (status << 1) | phase as u16;
+        entry[14..16].copy_from_slice(&status_field.to_le_bytes());
+    }
+
+    #[kunit]
+    fn execute_consumes_matching_completion() {
+        let (mut queue, doorbells) = queue(4, 4096);
+        complete(&mut queue, 0, 0, true, 0);
+
+        let completion = queue
+            .execute(Command::new(0x06))
+            .expect("command should complete");
+
+        assert_eq!(completion.command_id, 0);
+        assert_eq!(queue.submissions.as_slice()[0], 0x06);
+        assert_eq!(doorbells[0], 1);
+        assert_eq!(doorbells[1], 1);
+    }
+
+    #[kunit]
+    fn phase_flips_when_the_completion_queue_wraps() {
+        let (mut queue, _) = queue(2, 4096);
+        complete(&mut queue, 0, 0, true, 0);
+        complete(&mut queue, 1, 1, true, 0);
+        queue.execute(Command::new(0)).expect("first command");
+        queue.execute(Command::new(0)).expect("second command");
+
+        // The old entry in slot 0 still carries the previous phase and must be ignored.
+        assert!(queue.pop_completion().is_none());
+        complete(&mut queue, 0, 2, false, 0);
+        assert_eq!(queue.execute(Command::new(0)).map(|c| c.command_id), Ok(2));
+        assert_eq!(queue.sq_tail, 1);
+    }
+
+    #[kunit]
+    fn error_status_is_reported() {
+        let (mut queue, _) = queue(4, 4096);
+        complete(&mut queue, 0, 0, true, 0x0002);
+
+        assert_eq!(
+            queue.execute(Command::new(0x02)).map(|_| ()),
+            Err(NvmeError::CommandFailed(0x0002))
+        );
+    }
+
+    #[kunit]
+    fn prp_entries_describe_the_bounce_buffer() {
+        let (mut queue, _) = queue(2, 4 * FRAME_SIZE as usize);
+        let base = queue.data.phys_addr();
+
+        let mut single = Command::new(0x02);
+        queue.attach_data(&mut single, 512);
+        assert_eq!((single.prp1, single.prp2), (base, 0));
+
+        let mut double = Command::new(0x02);
+        queue.attach_data(&mut double, 2 * FRAME_SIZE as usize);
+        assert_eq!(double.prp2, base + FRAME_SIZE);
+
+        let mut listed = Command::new(0x02);
+        queue.attach_data(&mut listed, 4 * FRAME_SIZE as usize);
+        assert_eq!(listed.prp2, queue.prp_list.phys_addr());
+        let list = queue.prp_list.as_slice();
+        assert_eq!(list[..8], (base + FRAME_SIZE).to_le_bytes());
+        assert_eq!(list[16..24], (base + 3 * FRAME_SIZE).to_le_bytes());
+    }
+}
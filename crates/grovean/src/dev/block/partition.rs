@@ -0,0 +1,462 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{check_request, BlockDevice, BlockError};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_SIGNATURE_OFFSET: usize = 510;
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_PROTECTIVE: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// Guards against corrupt headers asking for an absurd number of entries.
+const GPT_MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Io(BlockError),
+    /// Neither a valid MBR nor a GPT was found.
+    NoPartitionTable,
+    /// The disk has a protective MBR but both GPT headers failed validation.
+    InvalidGpt,
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(error: BlockError) -> Self {
+        PartitionError::Io(error)
+    }
+}
+
+/// A GUID as stored on disk, with its first three fields little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    Mbr {
+        system_id: u8,
+    },
+    Gpt {
+        type_guid: Guid,
+        unique_guid: Guid,
+        name: String,
+    },
+}
+
+/// A partition table entry, in blocks of the underlying device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// One-based position in the table.
+    pub number: usize,
+    pub start_lba: u64,
+    pub block_count: u64,
+    pub kind: PartitionKind,
+}
+
+/// Compute the IEEE 802.3 CRC-32 used by GPT.
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut value = index as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                value = if value & 1 != 0 {
+                    (value >> 1) ^ 0xedb8_8320
+                } else {
+                    value >> 1
+                };
+                bit += 1;
+            }
+            table[index] = value;
+            index += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_block(device: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>, BlockError> {
+    let mut block = vec![0u8; device.block_size()];
+    device.read_blocks(lba, &mut block)?;
+    Ok(block)
+}
+
+/// Read the partition table of `device`, preferring GPT when the MBR is protective.
+pub fn scan(device: &dyn BlockDevice) -> Result<Vec<PartitionEntry>, PartitionError> {
+    let mbr = read_block(device, 0)?;
+    if mbr[MBR_SIGNATURE_OFFSET..MBR_SIGNATURE_OFFSET + 2] != MBR_SIGNATURE {
+        return Err(PartitionError::NoPartitionTable);
+    }
+
+    let entries = parse_mbr(&mbr);
+    let protective = entries.iter().any(|entry| {
+        entry.kind
+            == PartitionKind::Mbr {
+                system_id: MBR_TYPE_PROTECTIVE,
+            }
+    });
+    if protective {
+        return scan_gpt(device);
+    }
+    Ok(entries)
+}
+
+/// Parse the four primary entries of an MBR. Extended partitions are listed but not followed.
+fn parse_mbr(mbr: &[u8]) -> Vec<PartitionEntry> {
+    (0..4)
+        .filter_map(|index| {
+            let entry = &mbr[MBR_TABLE_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            let system_id = entry[4];
+            let start_lba = u32_at(entry, 8) as u64;
+            let block_count = u32_at(entry, 12) as u64;
+            (system_id != 0 && block_count != 0).then_some(PartitionEntry {
+                number: index + 1,
+                start_lba,
+                block_count,
+                kind: PartitionKind::Mbr { system_id },
+            })
+        })
+        .collect()
+}
+
+fn scan_gpt(device: &dyn BlockDevice) -> Result<Vec<PartitionEntry>, PartitionError> {
+    let last_lba = device.block_count() - 1;
+    // Fall back to the backup header at the end of the disk when the primary is damaged.
+    for header_lba in [1, last_lba] {
+        let header = read_block(device, header_lba)?;
+        let Some(layout) = parse_gpt_header(&header, header_lba) else {
+            continue;
+        };
+
+        let table_bytes = layout.entry_count * layout.entry_size;
+        let table_blocks = table_bytes.div_ceil(device.block_size());
+        let mut table = vec![0u8; table_blocks * device.block_size()];
+        device.read_blocks(layout.entries_lba, &mut table)?;
+        if crc32(&table[..table_bytes]) != layout.entries_crc {
+            continue;
+        }
+
+        return Ok(parse_gpt_entries(&table[..table_bytes], layout.entry_size));
+    }
+    Err(PartitionError::InvalidGpt)
+}
+
+struct GptLayout {
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+/// Validate a GPT header read from `lba`, returning where its entry array lives.
+fn parse_gpt_header(block: &[u8], lba: u64) -> Option<GptLayout> {
+    if &block[..8] != GPT_SIGNATURE {
+        return None;
+    }
+    let header_size = u32_at(block, 12) as usize;
+    if !(GPT_MIN_HEADER_SIZE..=block.len()).contains(&header_size) {
+        return None;
+    }
+
+    let mut header = block[..header_size].to_vec();
+    header[16..20].fill(0);
+    if crc32(&header) != u32_at(block, 16) || u64_at(block, 24) != lba {
+        return None;
+    }
+
+    let entry_count = u32_at(block, 80) as usize;
+    let entry_size = u32_at(block, 84) as usize;
+    if entry_count > GPT_MAX_ENTRIES
+        || entry_size < GPT_MIN_ENTRY_SIZE
+        || !entry_size.is_multiple_of(8)
+    {
+        return None;
+    }
+
+    Some(GptLayout {
+        entries_lba: u64_at(block, 72),
+        entry_count,
+        entry_size,
+        entries_crc: u32_at(block, 88),
+    })
+}
+
+fn parse_gpt_entries(table: &[u8], entry_size: usize) -> Vec<PartitionEntry> {
+    table
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let type_guid = Guid(entry[0..16].try_into().unwrap());
+            let first_lba = u64_at(entry, 32);
+            let last_lba = u64_at(entry, 40);
+            if type_guid.is_zero() || last_lba < first_lba {
+                return None;
+            }
+
+            let name: Vec<u16> = entry[56..128]
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .map(|unit| u16::from_le_bytes(*unit))
+                .take_while(|&unit| unit != 0)
+                .collect();
+
+            Some(PartitionEntry {
+                number: index + 1,
+                start_lba: first_lba,
+                block_count: last_lba - first_lba + 1,
+                kind: PartitionKind::Gpt {
+                    type_guid,
+                    unique_guid: Guid(entry[16..32].try_into().unwrap()),
+                    name: String::from_utf16_lossy(&name),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Name a partition the way Linux does: `sda1`, but `nvme0n1p1` when the disk ends in a digit.
+pub fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{disk}p{number}")
+    } else {
+        format!("{disk}{number}")
+    }
+}
+
+/// A window onto a range of blocks of another device.
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    start_lba: u64,
+    block_count: u64,
+}
+
+impl Partition {
+    pub fn new(disk: Arc<dyn BlockDevice>, entry: &PartitionEntry) -> Result<Self, BlockError> {
+        let end = entry.start_lba.checked_add(entry.block_count);
+        if end.is_none_or(|end| end > disk.block_count()) {
+            return Err(BlockError::OutOfRange);
+        }
+
+        Ok(Self {
+            name: partition_name(disk.name(), entry.number),
+            disk,
+            start_lba: entry.start_lba,
+            block_count: entry.block_count,
+        })
+    }
+
+    pub fn start_lba(&self) -> u64 {
+        self.start_lba
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        self.disk.read_blocks(self.start_lba + lba, buffer)
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        self.disk.write_blocks(self.start_lba + lba, buffer)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.disk.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::{crc32, scan, Guid, Partition, PartitionError, PartitionKind};
+    use crate::dev::block::ramdisk::RamDisk;
+    use crate::dev::block::{BlockDevice, BlockError};
+
+    const BLOCKS: u64 = 128;
+
+    fn mbr_entry(disk: &mut [u8], slot: usize, system_id: u8, start: u32, count: u32) {
+        let entry = &mut disk[446 + slot * 16..446 + (slot + 1) * 16];
+        entry[4] = system_id;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+        disk[510] = 0x55;
+        disk[511] = 0xaa;
+    }
+
+    /// Write a GPT header at `lba` describing a 4-entry table at `entries_lba`.
+    fn gpt_header(disk: &mut [u8], lba: u64, entries_lba: u64) {
+        let entries = &disk[entries_lba as usize * 512..][..4 * 128];
+        let entries_crc = crc32(entries);
+
+        let header = &mut disk[lba as usize * 512..][..92];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32(header);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+    }
+
+    fn gpt_disk() -> RamDisk {
+        let mut image = vec![0u8; BLOCKS as usize * 512];
+        mbr_entry(&mut image, 0, 0xee, 1, BLOCKS as u32 - 1);
+
+        let entry = &mut image[2 * 512..2 * 512 + 128];
+        entry[0] = 0xaf;
+        entry[16] = 0x01;
+        entry[32..40].copy_from_slice(&34u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&63u64.to_le_bytes());
+        for (index, unit) in "root".encode_utf16().enumerate() {
+            entry[56 + index * 2..58 + index * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        // The backup table sits just before the backup header in the last block.
+        image.copy_within(2 * 512..3 * 512, (BLOCKS as usize - 2) * 512);
+
+        gpt_header(&mut image, 1, 2);
+        gpt_header(&mut image, BLOCKS - 1, BLOCKS - 2);
+        RamDisk::from_bytes("vda", 512, image)
+    }
+
+    #[kunit]
+    fn crc32_matches_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[kunit]
+    fn guid_displays_in_mixed_endian_form() {
+        let guid = Guid([
+            0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+            0xc9, 0x3b,
+        ]);
+        assert_eq!(guid.to_string(), "c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+    }
+
+    #[kunit]
+    fn parses_primary_mbr_entries() {
+        let mut image = vec![0u8; BLOCKS as usize * 512];
+        mbr_entry(&mut image, 0, 0x83, 2048 / 512, 32);
+        mbr_entry(&mut image, 2, 0x0c, 64, 16);
+        let disk = RamDisk::from_bytes("sda", 512, image);
+
+        let entries = scan(&disk).expect("mbr should parse");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].number, 3);
+        assert_eq!(entries[1].start_lba, 64);
+        assert_eq!(entries[1].kind, PartitionKind::Mbr { system_id: 0x0c });
+    }
+
+    #[kunit]
+    fn blank_disks_have_no_table() {
+        let disk = RamDisk::new("sda", 512, BLOCKS);
+        assert_eq!(scan(&disk), Err(PartitionError::NoPartitionTable));
+    }
+
+    #[kunit]
+    fn protective_mbr_leads_to_gpt() {
+        let entries = scan(&gpt_disk()).expect("gpt should parse");
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].start_lba, 34);
+        assert_eq!(entries[0].block_count, 30);
+        match &entries[0].kind {
+            PartitionKind::Gpt { name, .. } => assert_eq!(name, "root"),
+            kind => panic!("unexpected partition kind {kind:?}"),
+        }
+    }
+
+    #[kunit]
+    fn corrupt_primary_header_falls_back_to_backup() {
+        let disk = gpt_disk();
+        disk.with_contents(|image| image[512 + 40] ^= 0xff);
+        assert_eq!(scan(&disk).map(|entries| entries.len()), Ok(1));
+
+        disk.with_contents(|image| image[(BLOCKS as usize - 1) * 512 + 40] ^= 0xff);
+        assert_eq!(scan(&disk), Err(PartitionError::InvalidGpt));
+    }
+
+    #[kunit]
+    fn partitions_translate_and_bound_requests() {
+        let disk = Arc::new(gpt_disk());
+        let entries = scan(disk.as_ref()).expect("gpt should parse");
+        let partition = Partition::new(disk.clone(), &entries[0]).expect("entry fits the disk");
+        assert_eq!(partition.name(), "vda1");
+
+        partition
+            .write_blocks(1, &[0x5a; 512])
+            .expect("write inside the partition");
+        disk.with_contents(|image| assert_eq!(image[35 * 512], 0x5a));
+        assert_eq!(
+            partition.read_blocks(30, &mut [0; 512]),
+            Err(BlockError::OutOfRange)
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError};
+
+/// A block device backed by kernel memory.
+pub struct RamDisk {
+    name: String,
+    block_size: usize,
+    data: Mutex<Vec<u8>>,
+    read_only: bool,
+}
+
+impl RamDisk {
+    /// Create a zero-filled disk of `block_count` blocks.
+    pub fn new(name: &str, block_size: usize, block_count: u64) -> Self {
+        Self::from_bytes(name, block_size, vec![0; block_size * block_count as usize])
+    }
+
+    /// Wrap an existing image, padding it to a whole number of blocks.
+    pub fn from_bytes(name: &str, block_size: usize, mut data: Vec<u8>) -> Self {
+        data.resize(data.len().next_multiple_of(block_size), 0);
+        Self {
+            name: name.into(),
+            block_size,
+            data: Mutex::new(data),
+            read_only: false,
+        }
+    }
+
+    /// Reject writes from now on.
+    pub fn into_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Run `f` over the raw contents of the disk.
+    pub fn with_contents<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.data.lock())
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+        let start = lba as usize * self.block_size;
+        buffer.copy_from_slice(&self.data.lock()[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, lba, buffer.len())?;
+        let start = lba as usize * self.block_size;
+        self.data.lock()[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
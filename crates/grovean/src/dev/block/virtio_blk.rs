@@ -0,0 +1,222 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError};
+use crate::dev::virtio::queue::{QueueBuffer, VirtQueue};
+use crate::dev::virtio::{self, DeviceType, Transport, VirtioError};
+use crate::memory::dma::DmaBuffer;
+
+const REQUEST_QUEUE: u16 = 0;
+
+/// Feature bits (virtio 1.2, section 5.2.3).
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+/// Request types (virtio 1.2, section 5.2.6).
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+
+/// Capacity is always counted in 512-byte sectors, whatever the device's preferred block size.
+const SECTOR_SIZE: usize = 512;
+
+/// The request header and status byte share the first page of the bounce buffer; data follows.
+const HEADER_SIZE: usize = 16;
+const STATUS_OFFSET: usize = HEADER_SIZE;
+const DATA_OFFSET: usize = 4096;
+const DATA_SIZE: usize = 64 * 1024;
+
+/// Bounds the wait for the device to complete one request.
+const REQUEST_SPIN_LIMIT: usize = 10_000_000;
+
+struct Channel {
+    transport: Box<dyn Transport>,
+    queue: VirtQueue,
+    buffer: DmaBuffer,
+}
+
+/// A virtio block device, driven one polled request at a time through a bounce buffer.
+pub struct VirtioBlk {
+    name: String,
+    sectors: u64,
+    read_only: bool,
+    flush_supported: bool,
+    channel: Mutex<Channel>,
+}
+
+impl VirtioBlk {
+    pub fn new(name: String, mut transport: Box<dyn Transport>) -> Result<Self, VirtioError> {
+        let features = virtio::negotiate(transport.as_mut(), F_RO | F_FLUSH)?;
+
+        let setup = |transport: &mut dyn Transport| -> Result<_, VirtioError> {
+            let queue = VirtQueue::setup(transport, REQUEST_QUEUE)?;
+            let buffer =
+                DmaBuffer::new(DATA_OFFSET + DATA_SIZE).map_err(|_| VirtioError::OutOfMemory)?;
+            Ok((queue, buffer))
+        };
+        let (queue, buffer) = match setup(transport.as_mut()) {
+            Ok(parts) => parts,
+            Err(error) => {
+                virtio::fail(transport.as_mut());
+                return Err(error);
+            }
+        };
+
+        let sectors = read_capacity(transport.as_ref());
+        let mut channel = Channel {
+            transport,
+            queue,
+            buffer,
+        };
+        virtio::finish_init(channel.transport.as_mut());
+
+        Ok(Self {
+            name,
+            sectors,
+            read_only: features & F_RO != 0,
+            flush_supported: features & F_FLUSH != 0,
+            channel: Mutex::new(channel),
+        })
+    }
+}
+
+/// Read the 64-bit capacity, retrying while the device changes its configuration underneath.
+fn read_capacity(transport: &dyn Transport) -> u64 {
+    loop {
+        let generation = transport.config_generation();
+        let low = transport.read_config_u32(0) as u64;
+        let high = transport.read_config_u32(4) as u64;
+        if transport.config_generation() == generation {
+            return high << 32 | low;
+        }
+    }
+}
+
+impl Channel {
+    /// Submit one request of `length` data bytes and wait for its status.
+    fn submit(&mut self, kind: u32, sector: u64, length: usize) -> Result<(), BlockError> {
+        let header = &mut self.buffer.as_mut_slice()[..HEADER_SIZE];
+        header[0..4].copy_from_slice(&kind.to_le_bytes());
+        header[4..8].fill(0);
+        header[8..16].copy_from_slice(&sector.to_le_bytes());
+        self.buffer.as_mut_slice()[STATUS_OFFSET] = 0xff;
+
+        let base = self.buffer.phys_addr();
+        let header = QueueBuffer::readable(base, HEADER_SIZE as u32);
+        let status = QueueBuffer::writable(base + STATUS_OFFSET as u64, 1);
+        let data = if kind == REQUEST_IN {
+            QueueBuffer::writable(base + DATA_OFFSET as u64, length as u32)
+        } else {
+            QueueBuffer::readable(base + DATA_OFFSET as u64, length as u32)
+        };
+        let added = if length == 0 {
+            self.queue.add(&[header, status])
+        } else {
+            self.queue.add(&[header, data, status])
+        };
+        added.map_err(|_| BlockError::DeviceError)?;
+        self.queue.notify(self.transport.as_mut());
+
+        let mut spins = 0;
+        while self.queue.pop_used().is_none() {
+            spins += 1;
+            if spins >= REQUEST_SPIN_LIMIT {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        self.transport.ack_interrupt();
+
+        match self.buffer.as_slice()[STATUS_OFFSET] {
+            STATUS_OK => Ok(()),
+            _ => Err(BlockError::DeviceError),
+        }
+    }
+
+    fn data(&mut self, length: usize) -> &mut [u8] {
+        &mut self.buffer.as_mut_slice()[DATA_OFFSET..DATA_OFFSET + length]
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buffer.len())?;
+
+        let mut channel = self.channel.lock();
+        for (index, chunk) in buffer.chunks_mut(DATA_SIZE).enumerate() {
+            let sector = lba + (index * DATA_SIZE / SECTOR_SIZE) as u64;
+            channel.submit(REQUEST_IN, sector, chunk.len())?;
+            chunk.copy_from_slice(channel.data(chunk.len()));
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, lba, buffer.len())?;
+
+        let mut channel = self.channel.lock();
+        for (index, chunk) in buffer.chunks(DATA_SIZE).enumerate() {
+            let sector = lba + (index * DATA_SIZE / SECTOR_SIZE) as u64;
+            channel.data(chunk.len()).copy_from_slice(chunk);
+            channel.submit(REQUEST_OUT, sector, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.flush_supported {
+            return Ok(());
+        }
+        self.channel.lock().submit(REQUEST_FLUSH, 0, 0)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Probe for virtio block devices and register them as `vda`, `vdb` and so on.
+pub fn init() {
+    let transports = virtio::find_transports(DeviceType::Block);
+    for (letter, transport) in (b'a'..=b'z').zip(transports) {
+        let name = format!("vd{}", letter as char);
+        match VirtioBlk::new(name.clone(), transport) {
+            Ok(disk) => {
+                crate::info_ln!(
+                    "{}: virtio-blk {} sectors{}",
+                    name,
+                    disk.block_count(),
+                    if disk.is_read_only() {
+                        " (read-only)"
+                    } else {
+                        ""
+                    }
+                );
+                super::register(Arc::new(disk));
+            }
+            Err(error) => {
+                crate::warn_ln!("{}: virtio-blk initialization failed: {:?}", name, error);
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+pub mod virtio;
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::fs::devfs::{self, Device};
+use crate::fs::vfs::FsError;
+use crate::task;
+
+pub use virtio::{ConsoleError, PortInfo, VirtioConsole};
+
+/// Port names the host can assign (`-device virtserialport,name=...`) to split kernel logs
+/// from the interactive shell. Without them both share the console port.
+pub const LOG_PORT_NAME: &str = "grovean.log";
+pub const SHELL_PORT_NAME: &str = "grovean.shell";
+
+/// The first port, which is the console port when the device is not multiport.
+const CONSOLE_PORT: u32 = 0;
+/// How often a reader of `/dev/hvc0` checks for input while there is none.
+const SHELL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static CONSOLE: Mutex<Option<ConsoleState>> = Mutex::new(None);
+
+struct ConsoleState {
+    device: VirtioConsole,
+}
+
+impl ConsoleState {
+    /// The ports are looked up on every use, since the host may name them after `init`.
+    fn log_port(&self) -> u32 {
+        self.device.find_port(LOG_PORT_NAME).unwrap_or(CONSOLE_PORT)
+    }
+
+    fn shell_port(&self) -> u32 {
+        self.device
+            .find_port(SHELL_PORT_NAME)
+            .unwrap_or(CONSOLE_PORT)
+    }
+}
+
+/// The shell channel as a byte stream.
+struct ShellDevice;
+
+impl Device for ShellDevice {
+    fn mode(&self) -> u32 {
+        0o660
+    }
+
+    /// Block until some input arrives, then return what there is, up to `buffer.len()` bytes.
+    fn read(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let count = read_shell(buffer).map_err(|_| FsError::NotSupported)?;
+            if count > 0 {
+                return Ok(count);
+            }
+            task::sleep(SHELL_POLL_INTERVAL);
+        }
+    }
+
+    fn write(&self, _offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        write_shell(buffer).map_err(|_| FsError::NotSupported)?;
+        Ok(buffer.len())
+    }
+}
+
+/// Probe for a virtio console, start mirroring kernel logs to it and make its shell channel
+/// available as `/dev/hvc0`.
+pub fn init() {
+    let transports = crate::dev::virtio::find_transports(crate::dev::virtio::DeviceType::Console);
+    let Some(transport) = transports.into_iter().next() else {
+        return;
+    };
+
+    match VirtioConsole::new(transport) {
+        Ok(device) => {
+            let state = ConsoleState { device };
+            let (log_port, shell_port) = (state.log_port(), state.shell_port());
+            let ports = state.device.port_count();
+            *CONSOLE.lock() = Some(state);
+            if let Err(error) = devfs::register("hvc0", Arc::new(ShellDevice)) {
+                crate::warn_ln!("virtio-console: failed to register hvc0: {:?}", error);
+            }
+
+            crate::info_ln!(
+                "hvc0: virtio-console ports={} log=port{} shell=port{}",
+                ports,
+                log_port,
+                shell_port
+            );
+        }
+        Err(error) => {
+            crate::warn_ln!("virtio-console: initialization failed: {:?}", error);
+        }
+    }
+}
+
+pub fn with_console<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut VirtioConsole) -> R,
+{
+    CONSOLE.lock().as_mut().map(|state| f(&mut state.device))
+}
+
+/// Read pending input from the shell channel. Reading polls the device, which also picks up
+/// control messages that name ports late.
+pub fn read_shell(buffer: &mut [u8]) -> Result<usize, ConsoleError> {
+    let mut console = CONSOLE.lock();
+    let state = console.as_mut().ok_or(ConsoleError::NoSuchPort)?;
+    let port = state.shell_port();
+    state.device.read(port, buffer)
+}
+
+/// Write to the shell channel.
+pub fn write_shell(data: &[u8]) -> Result<(), ConsoleError> {
+    let mut console = CONSOLE.lock();
+    let state = console.as_mut().ok_or(ConsoleError::NoSuchPort)?;
+    let port = state.shell_port();
+    state.device.write(port, data)
+}
+
+struct LogWriter<'a> {
+    state: &'a mut ConsoleState,
+}
+
+impl fmt::Write for LogWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let port = self.state.log_port();
+        self.state
+            .device
+            .write(port, s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: ::core::fmt::Arguments) {
+    // Skip rather than deadlock when a log line is emitted while the console is busy.
+    let Some(mut console) = CONSOLE.try_lock() else {
+        return;
+    };
+    if let Some(state) = console.as_mut() {
+        let _ = fmt::write(&mut LogWriter { state }, args);
+    }
+}
+
+/// Print to the virtio console log channel.
+#[macro_export]
+macro_rules! console_print {
+  ($($arg:tt)*) => {
+    $crate::dev::console::_print(format_args!($($arg)*));
+  };
+}
+
+/// Print INFO to the virtio console.
+#[macro_export]
+macro_rules! console_info {
+  ($fmt:expr) => ($crate::console_print!(concat!("INFO: ", $fmt)));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!($fmt, $($arg)*));
+}
+
+/// Print INFO to the virtio console followed by a newline.
+#[macro_export]
+macro_rules! console_info_ln {
+  () => ($crate::console_print!("\n"));
+  ($fmt:expr) => ($crate::console_print!(concat!("INFO: ", $fmt, "\n")));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Print DEBUG to the virtio console.
+#[macro_export]
+macro_rules! console_debug {
+  ($fmt:expr) => ($crate::console_print!(concat!("DEBUG: ", $fmt)));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!($fmt, $($arg)*));
+}
+
+/// Print DEBUG to the virtio console followed by a newline.
+#[macro_export]
+macro_rules! console_debug_ln {
+  () => ($crate::console_print!("\n"));
+  ($fmt:expr) => ($crate::console_print!(concat!("DEBUG: ", $fmt, "\n")));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Print WARN to the virtio console.
+#[macro_export]
+macro_rules! console_warn {
+  ($fmt:expr) => ($crate::console_print!(concat!("WARN: ", $fmt)));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!($fmt, $($arg)*));
+}
+
+/// Print WARN to the virtio console followed by a newline.
+#[macro_export]
+macro_rules! console_warn_ln {
+  () => ($crate::console_print!("\n"));
+  ($fmt:expr) => ($crate::console_print!(concat!("WARN: ", $fmt, "\n")));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Print DANGER to the virtio console.
+#[macro_export]
+macro_rules! console_danger {
+  ($fmt:expr) => ($crate::console_print!(concat!("DANGER: ", $fmt)));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!($fmt, $($arg)*));
+}
+
+/// Print DANGER to the virtio console followed by a newline.
+#[macro_export]
+macro_rules! console_danger_ln {
+  () => ($crate::console_print!("\n"));
+  ($fmt:expr) => ($crate::console_print!(concat!("DANGER: ", $fmt, "\n")));
+  ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(concat!($fmt, "\n"), $($arg)*));
+}
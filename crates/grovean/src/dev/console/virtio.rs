@@ -0,0 +1,552 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::dev::virtio::queue::{QueueBuffer, VirtQueue};
+use crate::dev::virtio::{self, Transport, VirtioError};
+use crate::memory::dma::DmaBuffer;
+use crate::time;
+
+/// Feature bits (virtio 1.2, section 5.3.3).
+const F_SIZE: u64 = 1 << 0;
+const F_MULTIPORT: u64 = 1 << 1;
+
+const SUPPORTED_FEATURES: u64 = F_SIZE | F_MULTIPORT;
+
+/// Device configuration layout.
+const CONFIG_MAX_NR_PORTS: usize = 4;
+
+/// Queues 2 and 3 carry control messages once `VIRTIO_CONSOLE_F_MULTIPORT` is negotiated.
+const CONTROL_RX_QUEUE: u16 = 2;
+const CONTROL_TX_QUEUE: u16 = 3;
+
+/// Control message events.
+const EVENT_DEVICE_READY: u16 = 0;
+const EVENT_DEVICE_ADD: u16 = 1;
+const EVENT_DEVICE_REMOVE: u16 = 2;
+const EVENT_PORT_READY: u16 = 3;
+const EVENT_CONSOLE_PORT: u16 = 4;
+const EVENT_PORT_OPEN: u16 = 6;
+const EVENT_PORT_NAME: u16 = 7;
+
+/// Size of `struct virtio_console_control`.
+const CONTROL_HEADER_SIZE: usize = 8;
+
+/// We never drive more ports than this, whatever the device offers.
+const MAX_PORTS: u32 = 8;
+
+const RX_SLOT_SIZE: usize = 256;
+const RX_SLOTS: usize = 4;
+const TX_BUFFER_SIZE: usize = 4096;
+const CONTROL_SLOT_SIZE: usize = 128;
+const CONTROL_SLOTS: usize = 8;
+
+/// Bounds the wait for the device to consume a transmit buffer.
+const TX_SPIN_LIMIT: usize = 1_000_000;
+/// Bounds the wait in `new` for the host to name and open the ports it added, which it only
+/// does once each is reported ready.
+const CONTROL_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    NoSuchPort,
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ControlMessage {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+impl ControlMessage {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            id: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            event: u16::from_le_bytes([bytes[4], bytes[5]]),
+            value: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; CONTROL_HEADER_SIZE] {
+        let mut bytes = [0; CONTROL_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.id.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.event.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+}
+
+/// Receive and transmit queue indices of `port`.
+fn port_queues(port: u32) -> (u16, u16) {
+    let receive = if port == 0 { 0 } else { 2 * (port as u16 + 1) };
+    (receive, receive + 1)
+}
+
+/// Receive buffers posted as fixed slots of one DMA region, indexed by descriptor head.
+struct RxRing {
+    queue: VirtQueue,
+    memory: DmaBuffer,
+    slot_size: usize,
+    slot_of_head: Vec<Option<usize>>,
+}
+
+impl RxRing {
+    fn new(queue: VirtQueue, slots: usize, slot_size: usize) -> Result<Self, VirtioError> {
+        let memory = DmaBuffer::new(slots * slot_size).map_err(|_| VirtioError::OutOfMemory)?;
+        let mut ring = Self {
+            slot_of_head: (0..queue.size()).map(|_| None).collect(),
+            queue,
+            memory,
+            slot_size,
+        };
+        for slot in 0..slots {
+            ring.post(slot)?;
+        }
+        Ok(ring)
+    }
+
+    fn post(&mut self, slot: usize) -> Result<(), VirtioError> {
+        let phys = self.memory.phys_addr() + (slot * self.slot_size) as u64;
+        let head = self
+            .queue
+            .add(&[QueueBuffer::writable(phys, self.slot_size as u32)])?;
+        self.slot_of_head[head as usize] = Some(slot);
+        Ok(())
+    }
+
+    /// Hand each completed buffer's contents to `f`, then give the buffer back to the device.
+    fn drain<F: FnMut(&[u8])>(&mut self, transport: &mut dyn Transport, mut f: F) {
+        let mut reposted = false;
+        while let Some(used) = self.queue.pop_used() {
+            let Some(slot) = self.slot_of_head[used.head as usize].take() else {
+                continue;
+            };
+            let start = slot * self.slot_size;
+            let len = (used.len as usize).min(self.slot_size);
+            f(&self.memory.as_slice()[start..start + len]);
+            reposted |= self.post(slot).is_ok();
+        }
+        if reposted {
+            self.queue.notify(transport);
+        }
+    }
+}
+
+/// A single bounce buffer; each transmission waits for the previous one to complete.
+struct TxRing {
+    queue: VirtQueue,
+    memory: DmaBuffer,
+}
+
+impl TxRing {
+    fn new(queue: VirtQueue, size: usize) -> Result<Self, VirtioError> {
+        let memory = DmaBuffer::new(size).map_err(|_| VirtioError::OutOfMemory)?;
+        Ok(Self { queue, memory })
+    }
+
+    fn send(&mut self, transport: &mut dyn Transport, data: &[u8]) -> Result<(), ConsoleError> {
+        for chunk in data.chunks(self.memory.len()) {
+            self.memory.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.queue
+                .add(&[QueueBuffer::readable(
+                    self.memory.phys_addr(),
+                    chunk.len() as u32,
+                )])
+                .map_err(|_| ConsoleError::Timeout)?;
+            self.queue.notify(transport);
+
+            let mut spins = 0;
+            while self.queue.pop_used().is_none() {
+                spins += 1;
+                if spins >= TX_SPIN_LIMIT {
+                    return Err(ConsoleError::Timeout);
+                }
+                core::hint::spin_loop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Host-side state of a port, as reported through control messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortInfo {
+    pub name: Option<String>,
+    pub is_console: bool,
+    /// Whether a host program is connected to the port.
+    pub host_connected: bool,
+}
+
+/// What the control queue has said about a port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PortState {
+    info: PortInfo,
+    added: bool,
+}
+
+impl PortState {
+    /// Whether the host has said all it will about an added port: what it is and that a
+    /// program is connected to it.
+    fn is_settled(&self) -> bool {
+        self.added && (self.info.name.is_some() || self.info.is_console) && self.info.host_connected
+    }
+}
+
+struct Port {
+    rx: RxRing,
+    tx: TxRing,
+    state: PortState,
+    pending: VecDeque<u8>,
+}
+
+/// A virtio console device, optionally exposing several named ports.
+pub struct VirtioConsole {
+    transport: Box<dyn Transport>,
+    ports: Vec<Option<Port>>,
+    control: Option<(RxRing, TxRing)>,
+}
+
+impl VirtioConsole {
+    pub fn new(mut transport: Box<dyn Transport>) -> Result<Self, VirtioError> {
+        let features = virtio::negotiate(transport.as_mut(), SUPPORTED_FEATURES)?;
+        let multiport = features & F_MULTIPORT != 0;
+
+        let port_count = if multiport {
+            transport
+                .read_config_u32(CONFIG_MAX_NR_PORTS)
+                .clamp(1, MAX_PORTS)
+        } else {
+            1
+        };
+
+        let setup = |transport: &mut dyn Transport| -> Result<_, VirtioError> {
+            let mut ports = Vec::new();
+            for port in 0..port_count {
+                let (receive, transmit) = port_queues(port);
+                ports.push(Some(Port {
+                    rx: RxRing::new(
+                        VirtQueue::setup(transport, receive)?,
+                        RX_SLOTS,
+                        RX_SLOT_SIZE,
+                    )?,
+                    tx: TxRing::new(VirtQueue::setup(transport, transmit)?, TX_BUFFER_SIZE)?,
+                    state: PortState {
+                        info: PortInfo::default(),
+                        // Without multiport, port 0 exists and is connected from the start.
+                        added: !multiport,
+                    },
+                    pending: VecDeque::new(),
+                }));
+            }
+
+            let control = if multiport {
+                Some((
+                    RxRing::new(
+                        VirtQueue::setup(transport, CONTROL_RX_QUEUE)?,
+                        CONTROL_SLOTS,
+                        CONTROL_SLOT_SIZE,
+                    )?,
+                    TxRing::new(
+                        VirtQueue::setup(transport, CONTROL_TX_QUEUE)?,
+                        CONTROL_SLOT_SIZE,
+                    )?,
+                ))
+            } else {
+                None
+            };
+            Ok((ports, control))
+        };
+
+        let (ports, control) = match setup(transport.as_mut()) {
+            Ok(parts) => parts,
+            Err(error) => {
+                virtio::fail(transport.as_mut());
+                return Err(error);
+            }
+        };
+
+        let mut console = Self {
+            transport,
+            ports,
+            control,
+        };
+
+        virtio::finish_init(console.transport.as_mut());
+        for port in console.ports.iter_mut().flatten() {
+            port.rx.queue.notify(console.transport.as_mut());
+        }
+
+        if console.control.is_some() {
+            console.send_control(ControlMessage {
+                id: 0,
+                event: EVENT_DEVICE_READY,
+                value: 1,
+            });
+            console.settle_control();
+        } else if let Some(Some(port)) = console.ports.first_mut() {
+            port.state.info.is_console = true;
+            port.state.info.host_connected = true;
+        }
+
+        Ok(console)
+    }
+
+    pub fn port_count(&self) -> usize {
+        self.ports.len()
+    }
+
+    pub fn port_info(&self, port: u32) -> Option<&PortInfo> {
+        self.port(port).ok().map(|port| &port.state.info)
+    }
+
+    /// Find the port the host registered under `name` (QEMU's `virtserialport,name=...`).
+    pub fn find_port(&self, name: &str) -> Option<u32> {
+        find_port(
+            self.ports
+                .iter()
+                .map(|port| port.as_ref().map(|port| &port.state)),
+            name,
+        )
+    }
+
+    /// Send `data` on `port`, blocking until the device has consumed it.
+    pub fn write(&mut self, port: u32, data: &[u8]) -> Result<(), ConsoleError> {
+        let transport = self.transport.as_mut();
+        let port = self
+            .ports
+            .get_mut(port as usize)
+            .and_then(Option::as_mut)
+            .filter(|port| port.state.added)
+            .ok_or(ConsoleError::NoSuchPort)?;
+        port.tx.send(transport, data)
+    }
+
+    /// Copy pending input from `port` into `buffer`, returning the number of bytes read.
+    pub fn read(&mut self, port: u32, buffer: &mut [u8]) -> Result<usize, ConsoleError> {
+        self.poll();
+        let port = self
+            .ports
+            .get_mut(port as usize)
+            .and_then(Option::as_mut)
+            .ok_or(ConsoleError::NoSuchPort)?;
+
+        let count = buffer.len().min(port.pending.len());
+        for (slot, byte) in buffer.iter_mut().zip(port.pending.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+
+    /// Collect received data and process control messages.
+    pub fn poll(&mut self) {
+        self.transport.ack_interrupt();
+        let transport = self.transport.as_mut();
+        for port in self.ports.iter_mut().flatten() {
+            let pending = &mut port.pending;
+            port.rx.drain(transport, |data| pending.extend(data));
+        }
+        self.poll_control();
+    }
+
+    fn port(&self, port: u32) -> Result<&Port, ConsoleError> {
+        self.ports
+            .get(port as usize)
+            .and_then(Option::as_ref)
+            .filter(|port| port.state.added)
+            .ok_or(ConsoleError::NoSuchPort)
+    }
+
+    fn send_control(&mut self, message: ControlMessage) {
+        if let Some((_, tx)) = self.control.as_mut() {
+            // A lost control reply only leaves the port unusable, so there is nothing to unwind.
+            let _ = tx.send(self.transport.as_mut(), &message.to_bytes());
+        }
+    }
+
+    /// Process control messages until every port the host added is named and open, or until
+    /// `CONTROL_SETTLE_TIME` passes. The replies draw further messages, so one pass is not
+    /// enough. Whatever arrives later is picked up by `poll`.
+    fn settle_control(&mut self) {
+        let deadline = time::uptime() + CONTROL_SETTLE_TIME;
+        loop {
+            self.poll_control();
+            let mut added = self
+                .ports
+                .iter()
+                .flatten()
+                .filter(|port| port.state.added)
+                .peekable();
+            let settled = added.peek().is_some() && added.all(|port| port.state.is_settled());
+            if settled || time::uptime() >= deadline {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn poll_control(&mut self) {
+        let mut messages = Vec::new();
+        if let Some((rx, _)) = self.control.as_mut() {
+            rx.drain(self.transport.as_mut(), |data| {
+                if data.len() >= CONTROL_HEADER_SIZE {
+                    let name = core::str::from_utf8(&data[CONTROL_HEADER_SIZE..])
+                        .ok()
+                        .map(|name| String::from(name.trim_end_matches('\0')));
+                    messages.push((ControlMessage::from_bytes(data), name));
+                }
+            });
+        }
+
+        for (message, name) in messages {
+            self.handle_control(message, name);
+        }
+    }
+
+    fn handle_control(&mut self, message: ControlMessage, name: Option<String>) {
+        let port = self
+            .ports
+            .get_mut(message.id as usize)
+            .and_then(Option::as_mut)
+            .map(|port| &mut port.state);
+        if let Some(reply) = handle_control(port, message, name) {
+            self.send_control(reply);
+        }
+    }
+}
+
+/// Apply `message` to the state of the port it is about, `None` if it is not one we set up,
+/// returning the reply to send, if any.
+fn handle_control(
+    port: Option<&mut PortState>,
+    message: ControlMessage,
+    name: Option<String>,
+) -> Option<ControlMessage> {
+    let Some(port) = port else {
+        // Ports beyond what we set up are refused.
+        return (message.event == EVENT_DEVICE_ADD).then_some(ControlMessage {
+            id: message.id,
+            event: EVENT_PORT_READY,
+            value: 0,
+        });
+    };
+
+    match message.event {
+        EVENT_DEVICE_ADD => {
+            port.added = true;
+            return Some(ControlMessage {
+                id: message.id,
+                event: EVENT_PORT_READY,
+                value: 1,
+            });
+        }
+        EVENT_DEVICE_REMOVE => *port = PortState::default(),
+        EVENT_CONSOLE_PORT => {
+            port.info.is_console = true;
+            return Some(ControlMessage {
+                id: message.id,
+                event: EVENT_PORT_OPEN,
+                value: 1,
+            });
+        }
+        EVENT_PORT_OPEN => port.info.host_connected = message.value != 0,
+        EVENT_PORT_NAME => port.info.name = name,
+        _ => {}
+    }
+    None
+}
+
+/// The index of the added port named `name` among `ports`.
+fn find_port<'a>(ports: impl Iterator<Item = Option<&'a PortState>>, name: &str) -> Option<u32> {
+    ports
+        .enumerate()
+        .find(|(_, port)| {
+            port.filter(|port| port.added)
+                .and_then(|port| port.info.name.as_deref())
+                .is_some_and(|port_name| port_name == name)
+        })
+        .map(|(index, _)| index as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::{
+        find_port, handle_control, port_queues, ControlMessage, PortState, EVENT_CONSOLE_PORT,
+        EVENT_DEVICE_ADD, EVENT_PORT_NAME, EVENT_PORT_OPEN, EVENT_PORT_READY,
+    };
+    use crate::dev::console::{LOG_PORT_NAME, SHELL_PORT_NAME};
+
+    #[kunit]
+    fn maps_ports_to_queue_pairs() {
+        assert_eq!(port_queues(0), (0, 1));
+        assert_eq!(port_queues(1), (4, 5));
+        assert_eq!(port_queues(2), (6, 7));
+    }
+
+    #[kunit]
+    fn control_messages_round_trip_through_bytes() {
+        let message = ControlMessage {
+            id: 3,
+            event: EVENT_PORT_READY,
+            value: 1,
+        };
+        let bytes = message.to_bytes();
+
+        assert_eq!(bytes, [3, 0, 0, 0, 3, 0, 1, 0]);
+        assert_eq!(ControlMessage::from_bytes(&bytes), message);
+    }
+
+    #[kunit]
+    fn named_ports_are_found_once_added_named_and_opened() {
+        let mut ports: Vec<PortState> = (0..3).map(|_| PortState::default()).collect();
+        let mut feed = |id: u32, event: u16, value: u16, name: Option<&str>| {
+            let message = ControlMessage { id, event, value };
+            handle_control(ports.get_mut(id as usize), message, name.map(String::from))
+        };
+
+        let ready = |id| {
+            Some(ControlMessage {
+                id,
+                event: EVENT_PORT_READY,
+                value: 1,
+            })
+        };
+        assert_eq!(feed(0, EVENT_DEVICE_ADD, 0, None), ready(0));
+        assert_eq!(
+            feed(0, EVENT_CONSOLE_PORT, 1, None),
+            Some(ControlMessage {
+                id: 0,
+                event: EVENT_PORT_OPEN,
+                value: 1
+            })
+        );
+        for (id, name) in [(1, LOG_PORT_NAME), (2, SHELL_PORT_NAME)] {
+            assert_eq!(feed(id, EVENT_DEVICE_ADD, 0, None), ready(id));
+            assert_eq!(feed(id, EVENT_PORT_NAME, 0, Some(name)), None);
+            assert_eq!(feed(id, EVENT_PORT_OPEN, 1, None), None);
+        }
+        assert_eq!(
+            feed(5, EVENT_DEVICE_ADD, 0, None),
+            Some(ControlMessage {
+                id: 5,
+                event: EVENT_PORT_READY,
+                value: 0
+            })
+        );
+
+        let find = |name| find_port(ports.iter().map(Some), name);
+        assert_eq!(find(LOG_PORT_NAME), Some(1));
+        assert_eq!(find(SHELL_PORT_NAME), Some(2));
+        assert_eq!(find("missing"), None);
+        assert!(ports[1].is_settled() && ports[2].is_settled());
+        assert!(!ports[0].is_settled());
+    }
+}
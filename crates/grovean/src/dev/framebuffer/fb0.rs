@@ -1,6 +1,9 @@
-use crate::dev::framebuffer::Framebuffer;
+use crate::dev::block::BlockError;
+use crate::dev::framebuffer::{Framebuffer, FramebufferError};
+use crate::fs::devfs::{self, Device};
+use crate::fs::vfs::FsError;
+use alloc::sync::Arc;
 use core::fmt;
-#[cfg(not(test))]
 use font8x8::legacy::BASIC_LEGACY as FONT;
 #[cfg(not(test))]
 use limine::request::FramebufferRequest;
@@ -28,26 +31,139 @@ where
 /// Initialize the framebuffer terminal
 #[cfg(not(test))]
 pub fn init() {
-    if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-        if let Some(framebuffer_metadata) = framebuffer_response.framebuffers().next() {
-            let framebuffer = Framebuffer::new(
-                framebuffer_metadata.addr() as *mut u8,
-                framebuffer_metadata.width(),
-                framebuffer_metadata.height(),
-                framebuffer_metadata.pitch(),
-                framebuffer_metadata.bpp(),
-            );
-            unsafe {
-                FRONT_BUFFER = Some(Framebufferterminal::new(
+    if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response()
+        && let Some(framebuffer_metadata) = framebuffer_response.framebuffers().next()
+    {
+        let framebuffer = Framebuffer::new(
+            framebuffer_metadata.addr(),
+            framebuffer_metadata.width(),
+            framebuffer_metadata.height(),
+            framebuffer_metadata.pitch(),
+            framebuffer_metadata.bpp(),
+        );
+        unsafe {
+            FRONT_BUFFER = Some(Framebufferterminal::new(
+                framebuffer,
+                crate::dat::terminal::BACKGROUND,
+                FONT,
+            ));
+        }
+    }
+}
+
+/// Move the terminal onto a new framebuffer, such as one driven by a GPU that replaced the
+/// firmware framebuffer.
+pub fn attach(framebuffer: Framebuffer) {
+    let front_buffer = core::ptr::addr_of_mut!(FRONT_BUFFER);
+    unsafe {
+        match (*front_buffer).as_mut() {
+            Some(terminal) => terminal.replace_framebuffer(framebuffer),
+            None => {
+                *front_buffer = Some(Framebufferterminal::new(
                     framebuffer,
                     crate::dat::terminal::BACKGROUND,
                     FONT,
-                ));
+                ))
             }
         }
     }
 }
 
+/// `ioctl` requests understood by `/dev/fb0`.
+pub const IOCTL_GET_WIDTH: u32 = 0x4600;
+pub const IOCTL_GET_HEIGHT: u32 = 0x4601;
+pub const IOCTL_GET_PITCH: u32 = 0x4602;
+pub const IOCTL_GET_BPP: u32 = 0x4603;
+/// Switch resolution; the argument is `width << 32 | height`.
+pub const IOCTL_SET_MODE: u32 = 0x4604;
+/// Push raw writes made since the last flush to the display.
+pub const IOCTL_FLUSH: u32 = 0x4605;
+
+/// Raw access to the memory of whichever framebuffer the terminal currently draws on.
+struct Fb0Device;
+
+impl Fb0Device {
+    fn with_terminal<T>(
+        &self,
+        f: impl FnOnce(&mut Framebufferterminal) -> T,
+    ) -> Result<T, FsError> {
+        let mut result = None;
+        with_front_buffer(|terminal| result = Some(f(terminal)));
+        result.ok_or(FsError::NotSupported)
+    }
+}
+
+impl Device for Fb0Device {
+    fn mode(&self) -> u32 {
+        0o660
+    }
+
+    fn size(&self) -> u64 {
+        self.with_terminal(|terminal| terminal.framebuffer().size())
+            .unwrap_or(0)
+    }
+
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        self.with_terminal(|terminal| terminal.framebuffer().read_bytes(offset, buffer))
+    }
+
+    fn write(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        self.with_terminal(|terminal| {
+            let count = terminal.framebuffer().write_bytes(offset, buffer);
+            terminal.flush();
+            count
+        })
+    }
+
+    fn ioctl(&self, request: u32, argument: usize) -> Result<usize, FsError> {
+        self.with_terminal(|terminal| {
+            let framebuffer = terminal.framebuffer();
+            match request {
+                IOCTL_GET_WIDTH => Ok(framebuffer.get_width() as usize),
+                IOCTL_GET_HEIGHT => Ok(framebuffer.get_height() as usize),
+                IOCTL_GET_PITCH => Ok(framebuffer.get_pitch() as usize),
+                IOCTL_GET_BPP => Ok(framebuffer.get_bpp() as usize),
+                IOCTL_SET_MODE => {
+                    let width = (argument as u64) >> 32;
+                    let height = argument as u64 & 0xffff_ffff;
+                    match terminal.set_mode(width, height) {
+                        Ok(()) => Ok(0),
+                        Err(FramebufferError::ModeSwitchUnsupported) => Err(FsError::NotSupported),
+                        Err(FramebufferError::InvalidMode) => Err(FsError::InvalidArgument),
+                        Err(FramebufferError::DeviceError) => {
+                            Err(FsError::Io(BlockError::DeviceError))
+                        }
+                    }
+                }
+                IOCTL_FLUSH => {
+                    terminal.flush();
+                    Ok(0)
+                }
+                _ => Err(FsError::InvalidArgument),
+            }
+        })?
+    }
+}
+
+/// Make the terminal's framebuffer available as `/dev/fb0`, if there is one.
+pub fn register_device() {
+    let mut present = false;
+    with_front_buffer(|_| present = true);
+    if !present {
+        return;
+    }
+    if let Err(error) = devfs::register("fb0", Arc::new(Fb0Device)) {
+        crate::warn_ln!("fb0: failed to register the device: {:?}", error);
+    }
+}
+
+/// Switch the framebuffer terminal to a new resolution
+pub fn set_mode(width: u64, height: u64) -> Result<(), FramebufferError> {
+    let mut result = Err(FramebufferError::ModeSwitchUnsupported);
+    with_front_buffer(|terminal| result = terminal.set_mode(width, height));
+    result
+}
+
 pub struct Framebufferterminal {
     framebuffer: Framebuffer,
     background_color: u32,
@@ -64,7 +180,7 @@ impl Framebufferterminal {
         let cell_width = 8;
         let cell_height = 8;
 
-        let terminal = Self {
+        let mut terminal = Self {
             framebuffer,
             background_color,
             font,
@@ -74,9 +190,40 @@ impl Framebufferterminal {
             cell_height,
         };
         terminal.clear_screen();
+        terminal.flush();
         terminal
     }
 
+    /// Push everything drawn since the last flush to the display
+    pub fn flush(&mut self) {
+        self.framebuffer.flush();
+    }
+
+    /// Get the framebuffer the terminal draws on
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Switch to a new resolution and start over from a blank screen
+    pub fn set_mode(&mut self, width: u64, height: u64) -> Result<(), FramebufferError> {
+        self.framebuffer.set_mode(width, height)?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Draw onto a different framebuffer from now on, starting from a blank screen
+    pub fn replace_framebuffer(&mut self, framebuffer: Framebuffer) {
+        self.framebuffer = framebuffer;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.clear_screen();
+        self.flush();
+    }
+
     /// Clear the framebuffer
     pub fn clear_screen(&self) {
         self.framebuffer.set_background(self.background_color);
@@ -98,6 +245,11 @@ impl Framebufferterminal {
 
     /// Write a string to the framebuffer
     pub fn write_string(&mut self, s: &str, color: u32) {
+        self.draw_string(s, color);
+        self.flush();
+    }
+
+    fn draw_string(&mut self, s: &str, color: u32) {
         for ch in s.chars() {
             if ch == '\n' {
                 self.cursor_x = 0;
@@ -133,6 +285,7 @@ impl Framebufferterminal {
             color,
         };
         let _ = fmt::write(&mut writer, args);
+        self.flush();
     }
 
     pub fn write_fmt_line_with_color(&mut self, args: fmt::Arguments<'_>, color: u32) {
@@ -142,6 +295,7 @@ impl Framebufferterminal {
 
     /// Scroll the framebuffer up by one cell height
     fn scroll(&mut self) {
+        self.framebuffer.mark_all_damaged();
         let row_size = self.framebuffer.pitch as usize * self.cell_height as usize;
         unsafe {
             core::ptr::copy(
@@ -166,7 +320,7 @@ struct ColorWriter<'a> {
 
 impl fmt::Write for ColorWriter<'_> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.terminal.write_string(s, self.color);
+        self.terminal.draw_string(s, self.color);
         Ok(())
     }
 }
@@ -1,4 +1,63 @@
 pub mod fb0;
+pub mod virtio_gpu;
+
+use alloc::boxed::Box;
+use core::cell::Cell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferError {
+    ModeSwitchUnsupported,
+    InvalidMode,
+    DeviceError,
+}
+
+/// A rectangle in pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u64,
+    pub y: u64,
+    pub width: u64,
+    pub height: u64,
+}
+
+impl Rect {
+    pub const fn new(x: u64, y: u64, width: u64, height: u64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Get the smallest rectangle covering both `self` and `other`.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Memory layout of a framebuffer after a mode switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferMode {
+    pub address: *mut u8,
+    pub width: u64,
+    pub height: u64,
+    pub pitch: u64,
+    pub bpp: u16,
+}
+
+/// A device that scans out framebuffer memory only when told to, such as virtio-gpu.
+pub trait FramebufferBackend: Send {
+    /// Make the pixels inside `rect` visible on the display.
+    fn flush(&mut self, rect: Rect);
+
+    /// Switch the display resolution, returning the layout of the new framebuffer memory.
+    fn set_mode(&mut self, width: u64, height: u64) -> Result<FramebufferMode, FramebufferError>;
+}
 
 pub struct Framebuffer {
     address: *mut u8,
@@ -6,6 +65,8 @@ pub struct Framebuffer {
     height: u64,
     pitch: u64,
     bpp: u16,
+    backend: Option<Box<dyn FramebufferBackend>>,
+    damage: Cell<Option<Rect>>,
 }
 
 impl Framebuffer {
@@ -17,11 +78,72 @@ impl Framebuffer {
             height,
             pitch,
             bpp,
+            backend: None,
+            damage: Cell::new(None),
         }
     }
 
+    /// Create a framebuffer whose contents reach the display through `backend`
+    pub fn with_backend(mode: FramebufferMode, backend: Box<dyn FramebufferBackend>) -> Self {
+        let mut framebuffer =
+            Self::new(mode.address, mode.width, mode.height, mode.pitch, mode.bpp);
+        framebuffer.backend = Some(backend);
+        framebuffer
+    }
+
+    /// Record that the pixels inside `rect` changed and need flushing
+    pub fn mark_damaged(&self, rect: Rect) {
+        let damage = match self.damage.get() {
+            Some(damage) => damage.union(&rect),
+            None => rect,
+        };
+        self.damage.set(Some(damage));
+    }
+
+    /// Record that the whole framebuffer changed
+    pub fn mark_all_damaged(&self) {
+        self.mark_damaged(Rect::new(0, 0, self.width, self.height));
+    }
+
+    /// Push all changes since the last flush to the display
+    pub fn flush(&mut self) {
+        let Some(damage) = self.damage.take() else {
+            return;
+        };
+        if let Some(backend) = self.backend.as_mut() {
+            let right = (damage.x + damage.width).min(self.width);
+            let bottom = (damage.y + damage.height).min(self.height);
+            if damage.x < right && damage.y < bottom {
+                backend.flush(Rect::new(
+                    damage.x,
+                    damage.y,
+                    right - damage.x,
+                    bottom - damage.y,
+                ));
+            }
+        }
+    }
+
+    /// Switch to a new resolution, if the backend supports it
+    pub fn set_mode(&mut self, width: u64, height: u64) -> Result<(), FramebufferError> {
+        let backend = self
+            .backend
+            .as_mut()
+            .ok_or(FramebufferError::ModeSwitchUnsupported)?;
+        let mode = backend.set_mode(width, height)?;
+
+        self.address = mode.address;
+        self.width = mode.width;
+        self.height = mode.height;
+        self.pitch = mode.pitch;
+        self.bpp = mode.bpp;
+        self.damage.set(None);
+        Ok(())
+    }
+
     /// Set the background color of the framebuffer
     pub fn set_background(&self, color: u32) {
+        self.mark_all_damaged();
         for j in 0..self.height {
             let offset = (j * self.pitch) as isize;
             unsafe {
@@ -35,6 +157,7 @@ impl Framebuffer {
 
     /// Draw a pixel on the framebuffer
     pub fn draw_pixel(&self, x: u64, y: u64, color: u32) {
+        self.mark_damaged(Rect::new(x, y, 1, 1));
         let offset = (y * self.pitch + x * (self.bpp as u64 / 8)) as isize;
         unsafe {
             let pixel = self.address.offset(offset) as *mut u32;
@@ -44,6 +167,7 @@ impl Framebuffer {
 
     /// Draw a rectangle on the framebuffer
     pub fn draw_rect(&self, x: u64, y: u64, width: u64, height: u64, color: u32) {
+        self.mark_damaged(Rect::new(x, y, width, height));
         let bytes_per_pixel = self.bpp as usize / 8;
         for j in 0..height {
             let offset = ((y + j) * self.pitch + x * bytes_per_pixel as u64) as isize;
@@ -76,16 +200,16 @@ impl Framebuffer {
             self.draw_pixel(x as u64, y as u64, 0xFFFFFFFF);
             for _i in 0..dx1 {
                 if px < 0 {
-                    px = px + 2 * dy1;
+                    px += 2 * dy1;
                 } else {
                     if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
-                        y = y + 1;
+                        y += 1;
                     } else {
-                        y = y - 1;
+                        y -= 1;
                     }
-                    px = px + 2 * (dy1 - dx1);
+                    px += 2 * (dy1 - dx1);
                 }
-                x = x + 1;
+                x += 1;
                 self.draw_pixel(x as u64, y as u64, 0xFFFFFFFF);
             }
         } else {
@@ -99,16 +223,17 @@ impl Framebuffer {
             self.draw_pixel(x as u64, y as u64, 0xFFFFFFFF);
             for _i in 0..dy1 {
                 if py <= 0 {
-                    py = py + 2 * dx1;
+                    py += 2 * dx1;
                 } else {
                     if (dx < 0 && dy < 0) || (dx > 0 && dy > 0) {
-                        x = x + 1;
+                        x += 1;
                     } else {
-                        x = x - 1;
+                        x -= 1;
                     }
-                    py = py + 2 * (dx1 - dy1);
+                    py += 2 * (dx1 - dy1);
                 }
-                y = y + 1;
+                y += 1;
+                self.draw_pixel(x as u64, y as u64, 0xFFFFFFFF);
             }
         }
     }
@@ -137,4 +262,137 @@ impl Framebuffer {
     pub fn get_bpp(&self) -> u16 {
         self.bpp
     }
+
+    /// Get the size of the framebuffer memory in bytes
+    pub fn size(&self) -> u64 {
+        self.pitch * self.height
+    }
+
+    /// Copy raw framebuffer memory starting at byte `offset`, returning the byte count
+    pub fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> usize {
+        let count = (self.size().saturating_sub(offset) as usize).min(buffer.len());
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.address.add(offset as usize),
+                buffer.as_mut_ptr(),
+                count,
+            );
+        }
+        count
+    }
+
+    /// Overwrite raw framebuffer memory starting at byte `offset`, returning the byte count
+    ///
+    /// Every row the write touches is marked damaged.
+    pub fn write_bytes(&self, offset: u64, buffer: &[u8]) -> usize {
+        let count = (self.size().saturating_sub(offset) as usize).min(buffer.len());
+        if count == 0 {
+            return 0;
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                buffer.as_ptr(),
+                self.address.add(offset as usize),
+                count,
+            );
+        }
+
+        let first_row = offset / self.pitch;
+        let last_row = (offset + count as u64 - 1) / self.pitch;
+        self.mark_damaged(Rect::new(
+            0,
+            first_row,
+            self.width,
+            last_row - first_row + 1,
+        ));
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+    use spin::Mutex;
+
+    use super::{Framebuffer, FramebufferBackend, FramebufferError, FramebufferMode, Rect};
+
+    struct RecordingBackend {
+        flushed: Arc<Mutex<Vec<Rect>>>,
+    }
+
+    impl FramebufferBackend for RecordingBackend {
+        fn flush(&mut self, rect: Rect) {
+            self.flushed.lock().push(rect);
+        }
+
+        fn set_mode(&mut self, _: u64, _: u64) -> Result<FramebufferMode, FramebufferError> {
+            Err(FramebufferError::InvalidMode)
+        }
+    }
+
+    fn framebuffer_with_backend(pixels: &mut [u32]) -> (Framebuffer, Arc<Mutex<Vec<Rect>>>) {
+        let flushed = Arc::new(Mutex::new(Vec::new()));
+        let mode = FramebufferMode {
+            address: pixels.as_mut_ptr() as *mut u8,
+            width: 8,
+            height: 8,
+            pitch: 32,
+            bpp: 32,
+        };
+        let backend = RecordingBackend {
+            flushed: flushed.clone(),
+        };
+        (Framebuffer::with_backend(mode, Box::new(backend)), flushed)
+    }
+
+    #[kunit]
+    fn union_covers_both_rectangles() {
+        let merged = Rect::new(1, 2, 2, 2).union(&Rect::new(5, 0, 1, 1));
+        assert_eq!(merged, Rect::new(1, 0, 5, 4));
+    }
+
+    #[kunit]
+    fn flush_sends_accumulated_damage_once() {
+        let mut pixels = vec![0u32; 64];
+        let (mut framebuffer, flushed) = framebuffer_with_backend(&mut pixels);
+
+        framebuffer.draw_pixel(1, 1, 0xffffff);
+        framebuffer.draw_rect(4, 2, 2, 3, 0xffffff);
+        framebuffer.flush();
+        framebuffer.flush();
+
+        assert_eq!(flushed.lock().as_slice(), &[Rect::new(1, 1, 5, 4)]);
+        assert_eq!(pixels[8 + 1], 0xffffff);
+    }
+
+    #[kunit]
+    fn linear_framebuffers_cannot_switch_modes() {
+        let mut pixels = vec![0u32; 64];
+        let mut framebuffer = Framebuffer::new(pixels.as_mut_ptr() as *mut u8, 8, 8, 32, 32);
+
+        assert_eq!(
+            framebuffer.set_mode(16, 16),
+            Err(FramebufferError::ModeSwitchUnsupported)
+        );
+    }
+
+    #[kunit]
+    fn raw_writes_damage_the_rows_they_touch() {
+        let mut pixels = vec![0u32; 64];
+        let (mut framebuffer, flushed) = framebuffer_with_backend(&mut pixels);
+
+        assert_eq!(framebuffer.write_bytes(30, &[0xff; 8]), 8);
+        assert_eq!(framebuffer.write_bytes(250, &[0xff; 8]), 6);
+        framebuffer.flush();
+
+        assert_eq!(flushed.lock().as_slice(), &[Rect::new(0, 0, 8, 8)]);
+        let mut read = [0u8; 4];
+        assert_eq!(framebuffer.read_bytes(32, &mut read), 4);
+        assert_eq!(read, [0xff; 4]);
+        assert_eq!(framebuffer.read_bytes(256, &mut read), 0);
+    }
 }
@@ -0,0 +1,434 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::{fb0, Framebuffer, FramebufferBackend, FramebufferError, FramebufferMode, Rect};
+use crate::dev::virtio::queue::{QueueBuffer, VirtQueue};
+use crate::dev::virtio::{self, DeviceType, Transport, VirtioError};
+use crate::memory::dma::DmaBuffer;
+
+const CONTROL_QUEUE: u16 = 0;
+
+/// Device configuration layout (virtio 1.2, section 5.7.4).
+const CONFIG_NUM_SCANOUTS: usize = 8;
+
+/// Control command types (virtio 1.2, section 5.7.6.7).
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_RESOURCE_UNREF: u32 = 0x0102;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Matches the 0x00RRGGBB little-endian pixels the terminal draws.
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+/// Size of `struct virtio_gpu_ctrl_hdr`.
+const CONTROL_HEADER_SIZE: usize = 24;
+const MAX_SCANOUTS: usize = 16;
+/// Size of one `struct virtio_gpu_display_one` in the display info response.
+const DISPLAY_ONE_SIZE: usize = 24;
+
+/// Requests are written at the start of the command page and responses right after them.
+const COMMAND_BUFFER_SIZE: usize = 4096;
+const RESPONSE_OFFSET: usize = 2048;
+
+const BYTES_PER_PIXEL: u64 = 4;
+const DEFAULT_WIDTH: u64 = 1024;
+const DEFAULT_HEIGHT: u64 = 768;
+const MAX_WIDTH: u64 = 3840;
+const MAX_HEIGHT: u64 = 2160;
+
+/// Bounds the wait for the device to answer a control command.
+const COMMAND_SPIN_LIMIT: usize = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuError {
+    Virtio(VirtioError),
+    OutOfMemory,
+    Timeout,
+    /// The device answered a command with an error response type.
+    CommandFailed(u32),
+}
+
+impl From<VirtioError> for GpuError {
+    fn from(error: VirtioError) -> Self {
+        GpuError::Virtio(error)
+    }
+}
+
+/// The preferred size of a scanout as reported by `GET_DISPLAY_INFO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayMode {
+    pub scanout: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Builds a control request in the little-endian layout the device expects.
+struct Command {
+    bytes: Vec<u8>,
+}
+
+impl Command {
+    fn new(kind: u32) -> Self {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.resize(CONTROL_HEADER_SIZE, 0);
+        Self { bytes }
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn rect(self, rect: Rect) -> Self {
+        self.u32(rect.x as u32)
+            .u32(rect.y as u32)
+            .u32(rect.width as u32)
+            .u32(rect.height as u32)
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Parse the enabled scanouts out of a `RESP_OK_DISPLAY_INFO` response.
+fn parse_display_info(response: &[u8]) -> Vec<DisplayMode> {
+    (0..MAX_SCANOUTS)
+        .filter_map(|scanout| {
+            let entry = CONTROL_HEADER_SIZE + scanout * DISPLAY_ONE_SIZE;
+            let enabled = u32_at(response, entry + 16) != 0;
+            let width = u32_at(response, entry + 8);
+            let height = u32_at(response, entry + 12);
+            (enabled && width != 0 && height != 0).then_some(DisplayMode {
+                scanout: scanout as u32,
+                width,
+                height,
+            })
+        })
+        .collect()
+}
+
+/// A host-side 2D resource together with the guest pages backing it.
+struct Resource {
+    id: u32,
+    width: u64,
+    height: u64,
+    backing: DmaBuffer,
+}
+
+impl Resource {
+    fn pitch(&self) -> u64 {
+        self.width * BYTES_PER_PIXEL
+    }
+
+    fn mode(&self) -> FramebufferMode {
+        FramebufferMode {
+            address: self.backing.as_ptr(),
+            width: self.width,
+            height: self.height,
+            pitch: self.pitch(),
+            bpp: (BYTES_PER_PIXEL * 8) as u16,
+        }
+    }
+}
+
+/// A virtio-gpu device driven through 2D commands on a single scanout.
+pub struct VirtioGpu {
+    transport: Box<dyn Transport>,
+    control: VirtQueue,
+    commands: DmaBuffer,
+    scanout: u32,
+    resource: Option<Resource>,
+    next_resource_id: u32,
+}
+
+impl VirtioGpu {
+    pub fn new(mut transport: Box<dyn Transport>) -> Result<Self, VirtioError> {
+        virtio::negotiate(transport.as_mut(), 0)?;
+
+        let setup = |transport: &mut dyn Transport| -> Result<_, VirtioError> {
+            let control = VirtQueue::setup(transport, CONTROL_QUEUE)?;
+            let commands =
+                DmaBuffer::new(COMMAND_BUFFER_SIZE).map_err(|_| VirtioError::OutOfMemory)?;
+            Ok((control, commands))
+        };
+        let (control, commands) = match setup(transport.as_mut()) {
+            Ok(parts) => parts,
+            Err(error) => {
+                virtio::fail(transport.as_mut());
+                return Err(error);
+            }
+        };
+
+        let mut device = Self {
+            transport,
+            control,
+            commands,
+            scanout: 0,
+            resource: None,
+            next_resource_id: 1,
+        };
+        virtio::finish_init(device.transport.as_mut());
+
+        Ok(device)
+    }
+
+    pub fn scanout_count(&self) -> u32 {
+        self.transport.read_config_u32(CONFIG_NUM_SCANOUTS)
+    }
+
+    /// Ask the host for the preferred size of every enabled scanout.
+    pub fn display_modes(&mut self) -> Result<Vec<DisplayMode>, GpuError> {
+        let response = self.submit(Command::new(CMD_GET_DISPLAY_INFO), RESP_OK_DISPLAY_INFO)?;
+        Ok(parse_display_info(response))
+    }
+
+    /// Create a resource of the given size, back it with guest memory and show it on the
+    /// scanout, releasing the previous one. On failure the new resource is released again and
+    /// the previous one stays on the scanout.
+    pub fn switch_mode(&mut self, width: u64, height: u64) -> Result<FramebufferMode, GpuError> {
+        let id = self.next_resource_id;
+        self.next_resource_id += 1;
+
+        let backing = DmaBuffer::new((width * height * BYTES_PER_PIXEL) as usize)
+            .map_err(|_| GpuError::OutOfMemory)?;
+        let resource = Resource {
+            id,
+            width,
+            height,
+            backing,
+        };
+
+        self.submit_ok(
+            Command::new(CMD_RESOURCE_CREATE_2D)
+                .u32(id)
+                .u32(FORMAT_B8G8R8X8_UNORM)
+                .u32(width as u32)
+                .u32(height as u32),
+        )?;
+        let shown = self
+            .submit_ok(
+                Command::new(CMD_RESOURCE_ATTACH_BACKING)
+                    .u32(id)
+                    .u32(1)
+                    .u64(resource.backing.phys_addr())
+                    .u32(resource.backing.len() as u32)
+                    .u32(0),
+            )
+            .and_then(|()| {
+                self.submit_ok(
+                    Command::new(CMD_SET_SCANOUT)
+                        .rect(Rect::new(0, 0, width, height))
+                        .u32(self.scanout)
+                        .u32(id),
+                )
+            });
+        if let Err(error) = shown {
+            // The host may hold the backing already, so it goes through `release` too.
+            if let Err(release_error) = self.release(resource) {
+                crate::serial_warn_ln!("virtio-gpu: release failed: {:?}", release_error);
+            }
+            return Err(error);
+        }
+
+        let mode = resource.mode();
+        // The new mode is on screen whatever becomes of the old resource.
+        if let Some(previous) = self.resource.replace(resource)
+            && let Err(error) = self.release(previous)
+        {
+            crate::serial_warn_ln!("virtio-gpu: release failed: {:?}", error);
+        }
+        Ok(mode)
+    }
+
+    /// Copy `rect` from guest memory into the host resource and update the display.
+    pub fn flush_rect(&mut self, rect: Rect) -> Result<(), GpuError> {
+        let Some(resource) = self.resource.as_ref() else {
+            return Ok(());
+        };
+        let id = resource.id;
+        let offset = rect.y * resource.pitch() + rect.x * BYTES_PER_PIXEL;
+
+        self.submit_ok(
+            Command::new(CMD_TRANSFER_TO_HOST_2D)
+                .rect(rect)
+                .u64(offset)
+                .u32(id)
+                .u32(0),
+        )?;
+        self.submit_ok(Command::new(CMD_RESOURCE_FLUSH).rect(rect).u32(id).u32(0))
+    }
+
+    /// Detach the backing of `resource` and destroy it on the host. The backing pages are only
+    /// freed once the device has let go of them; if detaching fails they are leaked instead,
+    /// since the host may still read them.
+    fn release(&mut self, resource: Resource) -> Result<(), GpuError> {
+        let Resource { id, backing, .. } = resource;
+        let detached = self.submit_ok(Command::new(CMD_RESOURCE_DETACH_BACKING).u32(id).u32(0));
+        match detached {
+            Ok(()) => drop(backing),
+            Err(_) => core::mem::forget(backing),
+        }
+        let unreferenced = self.submit_ok(Command::new(CMD_RESOURCE_UNREF).u32(id).u32(0));
+        detached.and(unreferenced)
+    }
+
+    fn submit_ok(&mut self, command: Command) -> Result<(), GpuError> {
+        self.submit(command, RESP_OK_NODATA).map(|_| ())
+    }
+
+    /// Send one request and spin until the device has written its response.
+    fn submit(&mut self, command: Command, expected: u32) -> Result<&[u8], GpuError> {
+        let request_len = command.bytes.len();
+        let response_len = COMMAND_BUFFER_SIZE - RESPONSE_OFFSET;
+        let memory = self.commands.as_mut_slice();
+        memory[..request_len].copy_from_slice(&command.bytes);
+        memory[RESPONSE_OFFSET..].fill(0);
+
+        let phys = self.commands.phys_addr();
+        self.control.add(&[
+            QueueBuffer::readable(phys, request_len as u32),
+            QueueBuffer::writable(phys + RESPONSE_OFFSET as u64, response_len as u32),
+        ])?;
+        self.control.notify(self.transport.as_mut());
+
+        let mut spins = 0;
+        while self.control.pop_used().is_none() {
+            spins += 1;
+            if spins >= COMMAND_SPIN_LIMIT {
+                return Err(GpuError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+
+        let response = &self.commands.as_slice()[RESPONSE_OFFSET..];
+        match u32_at(response, 0) {
+            kind if kind == expected => Ok(response),
+            kind => Err(GpuError::CommandFailed(kind)),
+        }
+    }
+}
+
+impl FramebufferBackend for VirtioGpu {
+    fn flush(&mut self, rect: Rect) {
+        if let Err(error) = self.flush_rect(rect) {
+            // The terminal is mid-write here, so only report to serial.
+            crate::serial_warn_ln!("virtio-gpu: flush failed: {:?}", error);
+        }
+    }
+
+    fn set_mode(&mut self, width: u64, height: u64) -> Result<FramebufferMode, FramebufferError> {
+        if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err(FramebufferError::InvalidMode);
+        }
+        self.switch_mode(width, height)
+            .map_err(|_| FramebufferError::DeviceError)
+    }
+}
+
+/// Probe for a virtio-gpu and move the framebuffer terminal onto it.
+pub fn init() {
+    let transports = virtio::find_transports(DeviceType::Gpu);
+    let Some(transport) = transports.into_iter().next() else {
+        return;
+    };
+
+    let mut device = match VirtioGpu::new(transport) {
+        Ok(device) => device,
+        Err(error) => {
+            crate::warn_ln!("virtio-gpu: initialization failed: {:?}", error);
+            return;
+        }
+    };
+
+    let preferred = device
+        .display_modes()
+        .ok()
+        .and_then(|modes| modes.into_iter().next());
+    let (width, height) = match preferred {
+        Some(mode) => {
+            device.scanout = mode.scanout;
+            (mode.width as u64, mode.height as u64)
+        }
+        None => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+    };
+
+    match device.switch_mode(width, height) {
+        Ok(mode) => {
+            let scanouts = device.scanout_count();
+            fb0::attach(Framebuffer::with_backend(mode, Box::new(device)));
+            crate::info_ln!("fb0: virtio-gpu {}x{} scanouts={}", width, height, scanouts);
+        }
+        Err(error) => {
+            crate::warn_ln!("virtio-gpu: mode setup failed: {:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::{
+        parse_display_info, Command, DisplayMode, CMD_TRANSFER_TO_HOST_2D, CONTROL_HEADER_SIZE,
+        DISPLAY_ONE_SIZE, MAX_SCANOUTS,
+    };
+    use crate::dev::framebuffer::Rect;
+
+    #[kunit]
+    fn commands_start_with_a_zeroed_header() {
+        let command = Command::new(CMD_TRANSFER_TO_HOST_2D)
+            .rect(Rect::new(1, 2, 3, 4))
+            .u64(0x10)
+            .u32(7)
+            .u32(0);
+        let bytes = command.bytes;
+
+        assert_eq!(bytes.len(), CONTROL_HEADER_SIZE + 32);
+        assert_eq!(&bytes[..4], &[0x05, 0x01, 0, 0]);
+        assert!(bytes[4..CONTROL_HEADER_SIZE].iter().all(|&byte| byte == 0));
+        assert_eq!(
+            &bytes[CONTROL_HEADER_SIZE..CONTROL_HEADER_SIZE + 4],
+            &[1, 0, 0, 0]
+        );
+        assert_eq!(bytes[CONTROL_HEADER_SIZE + 16], 0x10);
+        assert_eq!(bytes[CONTROL_HEADER_SIZE + 24], 7);
+    }
+
+    #[kunit]
+    fn display_info_skips_disabled_scanouts() {
+        let mut response = vec![0u8; CONTROL_HEADER_SIZE + MAX_SCANOUTS * DISPLAY_ONE_SIZE];
+        let entry = CONTROL_HEADER_SIZE + DISPLAY_ONE_SIZE;
+        response[entry + 8..entry + 12].copy_from_slice(&1280u32.to_le_bytes());
+        response[entry + 12..entry + 16].copy_from_slice(&800u32.to_le_bytes());
+        response[entry + 16] = 1;
+
+        assert_eq!(
+            parse_display_info(&response),
+            vec![DisplayMode {
+                scanout: 1,
+                width: 1280,
+                height: 800,
+            }]
+        );
+    }
+}
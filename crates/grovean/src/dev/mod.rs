@@ -1,2 +1,19 @@
+pub mod block;
+pub mod console;
 pub mod framebuffer;
+pub mod net;
+pub mod pci;
+pub mod rng;
 pub mod serial;
+pub mod virtio;
+
+pub fn init() {
+    serial::init();
+    pci::init();
+    console::init();
+    framebuffer::virtio_gpu::init();
+    framebuffer::fb0::register_device();
+    net::init();
+    rng::init();
+    block::init();
+}
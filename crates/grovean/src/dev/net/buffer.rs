@@ -0,0 +1,217 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::NetError;
+use crate::memory::dma::DmaBuffer;
+
+/// Checksum state carried alongside a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// Nothing is known about the checksum, or the frame carries a complete one.
+    None,
+    /// The checksum covering `start..` still has to be computed and stored at `start + offset`.
+    Partial { start: u16, offset: u16 },
+    /// The device has already validated the frame's checksums.
+    Validated,
+}
+
+struct PoolShared {
+    memory: DmaBuffer,
+    slot_size: usize,
+    headroom: usize,
+    slot_count: usize,
+    free: Mutex<Vec<usize>>,
+}
+
+/// A fixed set of equally sized DMA-capable packet buffers.
+///
+/// Every buffer reserves `headroom` bytes in front of the frame for device headers.
+#[derive(Clone)]
+pub struct PacketPool {
+    shared: Arc<PoolShared>,
+}
+
+impl PacketPool {
+    pub fn new(slot_count: usize, slot_size: usize, headroom: usize) -> Result<Self, NetError> {
+        assert!(headroom < slot_size);
+        let memory = DmaBuffer::new(slot_count * slot_size).map_err(|_| NetError::OutOfMemory)?;
+
+        Ok(Self {
+            shared: Arc::new(PoolShared {
+                memory,
+                slot_size,
+                headroom,
+                slot_count,
+                free: Mutex::new((0..slot_count).rev().collect()),
+            }),
+        })
+    }
+
+    /// Take an empty buffer from the pool.
+    pub fn alloc(&self) -> Option<PacketBuffer> {
+        let slot = self.shared.free.lock().pop()?;
+        Some(PacketBuffer {
+            shared: self.shared.clone(),
+            slot,
+            len: 0,
+            checksum: ChecksumStatus::None,
+        })
+    }
+
+    pub fn available(&self) -> usize {
+        self.shared.free.lock().len()
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.shared.slot_count
+    }
+}
+
+/// A frame living in a [`PacketPool`] slot; dropping it returns the slot to the pool.
+pub struct PacketBuffer {
+    shared: Arc<PoolShared>,
+    slot: usize,
+    len: usize,
+    checksum: ChecksumStatus,
+}
+
+impl PacketBuffer {
+    /// Get the frame bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.slot_bytes()[self.shared.headroom..self.shared.headroom + self.len]
+    }
+
+    /// Get the frame bytes mutably.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        let (start, end) = (self.shared.headroom, self.shared.headroom + self.len);
+        &mut self.slot_bytes_mut()[start..end]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the largest frame the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.shared.slot_size - self.shared.headroom
+    }
+
+    pub fn set_len(&mut self, len: usize) -> Result<(), NetError> {
+        if len > self.capacity() {
+            return Err(NetError::FrameTooLarge);
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Replace the buffer contents with `frame`.
+    pub fn copy_from(&mut self, frame: &[u8]) -> Result<(), NetError> {
+        self.set_len(frame.len())?;
+        self.data_mut().copy_from_slice(frame);
+        Ok(())
+    }
+
+    pub fn checksum(&self) -> ChecksumStatus {
+        self.checksum
+    }
+
+    pub fn set_checksum(&mut self, checksum: ChecksumStatus) {
+        self.checksum = checksum;
+    }
+
+    /// Get the bytes reserved in front of the frame for device headers.
+    pub(crate) fn headroom_mut(&mut self) -> &mut [u8] {
+        let headroom = self.shared.headroom;
+        &mut self.slot_bytes_mut()[..headroom]
+    }
+
+    /// Get the physical address of the slot, headroom included.
+    pub(crate) fn phys_addr(&self) -> u64 {
+        self.shared.memory.phys_addr() + (self.slot * self.shared.slot_size) as u64
+    }
+
+    /// Get the slot size, headroom included.
+    pub(crate) fn slot_size(&self) -> usize {
+        self.shared.slot_size
+    }
+
+    /// Get the headroom and frame length together, as the device sees them.
+    pub(crate) fn wire_len(&self) -> usize {
+        self.shared.headroom + self.len
+    }
+
+    fn slot_bytes(&self) -> &[u8] {
+        let start = self.slot * self.shared.slot_size;
+        unsafe {
+            core::slice::from_raw_parts(
+                self.shared.memory.as_ptr().add(start),
+                self.shared.slot_size,
+            )
+        }
+    }
+
+    fn slot_bytes_mut(&mut self) -> &mut [u8] {
+        let start = self.slot * self.shared.slot_size;
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.shared.memory.as_ptr().add(start),
+                self.shared.slot_size,
+            )
+        }
+    }
+}
+
+impl Drop for PacketBuffer {
+    fn drop(&mut self) {
+        self.shared.free.lock().push(self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{ChecksumStatus, PacketPool};
+
+    #[kunit]
+    fn buffers_return_to_the_pool_on_drop() {
+        let pool = PacketPool::new(2, 256, 16).expect("pool should allocate");
+
+        let first = pool.alloc().expect("buffer should be free");
+        let second = pool.alloc().expect("buffer should be free");
+        assert!(pool.alloc().is_none());
+
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        drop(second);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[kunit]
+    fn frames_live_after_the_headroom() {
+        let pool = PacketPool::new(1, 256, 16).expect("pool should allocate");
+        let mut buffer = pool.alloc().expect("buffer should be free");
+
+        buffer.copy_from(&[1, 2, 3]).expect("frame should fit");
+        buffer.headroom_mut().fill(0xaa);
+
+        assert_eq!(buffer.capacity(), 240);
+        assert_eq!(buffer.data(), &[1, 2, 3]);
+        assert_eq!(buffer.wire_len(), 19);
+    }
+
+    #[kunit]
+    fn oversized_frames_are_rejected() {
+        let pool = PacketPool::new(1, 64, 16).expect("pool should allocate");
+        let mut buffer = pool.alloc().expect("buffer should be free");
+
+        assert!(buffer.set_len(49).is_err());
+        assert!(buffer.set_len(48).is_ok());
+        assert_eq!(buffer.checksum(), ChecksumStatus::None);
+    }
+}
@@ -0,0 +1,202 @@
+pub mod buffer;
+pub mod virtio_net;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use crate::interrupts::softirq::{self, Softirq};
+use crate::sync::IrqSpinLock;
+use crate::time;
+
+pub use buffer::{ChecksumStatus, PacketBuffer, PacketPool};
+
+/// Largest Ethernet frame we send or receive, excluding the frame check sequence.
+pub const MAX_FRAME_SIZE: usize = 1514;
+
+/// How often the net softirq polls devices while their interrupts are not routed.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Locked with interrupts disabled, since the net softirq polls the devices.
+static NET_DEVICES: IrqSpinLock<Vec<Box<dyn NetDevice>>> = IrqSpinLock::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// No free descriptors or buffers; call [`NetDevice::poll`] to reclaim completions.
+    Busy,
+    FrameTooLarge,
+    InvalidChecksumRequest,
+    OutOfMemory,
+    DeviceError,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// Checksum work a device can take over from the network stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChecksumOffload(u32);
+
+impl ChecksumOffload {
+    pub const NONE: Self = Self(0);
+    /// The device completes partial checksums on transmit (see [`ChecksumStatus::Partial`]).
+    pub const TX_PARTIAL: Self = Self(1 << 0);
+    /// The device validates checksums on receive and reports [`ChecksumStatus::Validated`].
+    pub const RX_VALIDATE: Self = Self(1 << 1);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// A network interface exchanging Ethernet frames through pooled buffers.
+pub trait NetDevice: Send {
+    fn mac_address(&self) -> MacAddress;
+
+    fn link_status(&self) -> LinkStatus;
+
+    fn checksum_offload(&self) -> ChecksumOffload;
+
+    fn mtu(&self) -> usize;
+
+    /// Take an empty buffer from the transmit pool to fill with a frame.
+    fn alloc_tx_buffer(&mut self) -> Option<PacketBuffer>;
+
+    /// Queue a frame for transmission; the buffer returns to its pool once the device is done.
+    fn transmit(&mut self, packet: PacketBuffer) -> Result<(), NetError>;
+
+    /// Take the next received frame, if any.
+    fn receive(&mut self) -> Option<PacketBuffer>;
+
+    /// Reclaim completed transmissions and replenish receive buffers.
+    fn poll(&mut self);
+}
+
+/// Probe for network devices and register them, then start polling them from the net
+/// softirq.
+pub fn init() {
+    for transport in crate::dev::virtio::find_transports(crate::dev::virtio::DeviceType::Network) {
+        match virtio_net::VirtioNet::new(transport) {
+            Ok(device) => {
+                crate::info_ln!(
+                    "net{}: virtio-net mac={} link={:?}",
+                    device_count(),
+                    device.mac_address(),
+                    device.link_status()
+                );
+                register(Box::new(device));
+            }
+            Err(error) => {
+                crate::warn_ln!("virtio-net: initialization failed: {:?}", error);
+            }
+        }
+    }
+    if device_count() > 0 {
+        softirq::set_handler(Softirq::Net, poll_devices);
+        schedule_poll();
+    }
+}
+
+/// Raise the net softirq after `POLL_INTERVAL`, and again after every interval. A timer
+/// stands in for the devices' interrupts until those are routed.
+fn schedule_poll() {
+    time::timer::add(POLL_INTERVAL, || {
+        softirq::raise(Softirq::Net);
+        schedule_poll();
+    });
+}
+
+/// The net softirq: reclaim completed transmissions and replenish receive buffers on every
+/// device, outside the interrupt that raised it.
+fn poll_devices() {
+    for device in NET_DEVICES.lock().iter_mut() {
+        device.poll();
+    }
+}
+
+/// Register a network device, returning its index.
+pub fn register(device: Box<dyn NetDevice>) -> usize {
+    let mut devices = NET_DEVICES.lock();
+    devices.push(device);
+    devices.len() - 1
+}
+
+pub fn device_count() -> usize {
+    NET_DEVICES.lock().len()
+}
+
+pub fn with_net_device<F, R>(index: usize, f: F) -> Option<R>
+where
+    F: FnOnce(&mut dyn NetDevice) -> R,
+{
+    let mut devices = NET_DEVICES.lock();
+    devices.get_mut(index).map(|device| f(device.as_mut()))
+}
+
+/// Compute the 16-bit ones' complement Internet checksum of `data`.
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let (words, remainder) = data.as_chunks::<2>();
+    for word in words {
+        sum += u16::from_be_bytes(*word) as u32;
+    }
+    if let [last] = remainder {
+        sum += (*last as u32) << 8;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use kunit::kunit;
+
+    use super::{internet_checksum, ChecksumOffload, MacAddress};
+
+    #[kunit]
+    fn formats_mac_addresses() {
+        let mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        assert_eq!(format!("{}", mac), "52:54:00:12:34:56");
+    }
+
+    #[kunit]
+    fn checksum_offload_flags_combine() {
+        let both = ChecksumOffload::TX_PARTIAL.union(ChecksumOffload::RX_VALIDATE);
+
+        assert!(both.contains(ChecksumOffload::TX_PARTIAL));
+        assert!(both.contains(ChecksumOffload::RX_VALIDATE));
+        assert!(!ChecksumOffload::NONE.contains(ChecksumOffload::TX_PARTIAL));
+    }
+
+    #[kunit]
+    fn computes_internet_checksums() {
+        // An IPv4 header with its checksum field zeroed.
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(internet_checksum(&header), 0xb861);
+        assert_eq!(internet_checksum(&[0x01]), !0x0100);
+    }
+}
@@ -0,0 +1,367 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::{
+    internet_checksum, ChecksumOffload, ChecksumStatus, LinkStatus, MacAddress, NetDevice,
+    NetError, PacketBuffer, PacketPool, MAX_FRAME_SIZE,
+};
+use crate::dev::virtio::queue::{QueueBuffer, VirtQueue};
+use crate::dev::virtio::{self, Transport, VirtioError};
+
+/// Feature bits (virtio 1.2, section 5.1.3).
+const F_CSUM: u64 = 1 << 0;
+const F_GUEST_CSUM: u64 = 1 << 1;
+const F_MAC: u64 = 1 << 5;
+const F_STATUS: u64 = 1 << 16;
+
+const SUPPORTED_FEATURES: u64 = F_CSUM | F_GUEST_CSUM | F_MAC | F_STATUS;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// Device configuration layout.
+const CONFIG_MAC: usize = 0;
+const CONFIG_STATUS: usize = 6;
+const STATUS_LINK_UP: u16 = 1;
+
+/// `struct virtio_net_hdr` flags.
+const HDR_F_NEEDS_CSUM: u8 = 1;
+const HDR_F_DATA_VALID: u8 = 2;
+
+/// Size of `struct virtio_net_hdr` once `VIRTIO_F_VERSION_1` is negotiated.
+const NET_HDR_SIZE: usize = 12;
+
+const BUFFER_SIZE: usize = 2048;
+const POOL_SIZE: usize = 128;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct NetHeader {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+impl NetHeader {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        Self {
+            flags: bytes[0],
+            gso_type: bytes[1],
+            hdr_len: u16_at(2),
+            gso_size: u16_at(4),
+            csum_start: u16_at(6),
+            csum_offset: u16_at(8),
+            num_buffers: u16_at(10),
+        }
+    }
+
+    fn write_to(&self, bytes: &mut [u8]) {
+        bytes[0] = self.flags;
+        bytes[1] = self.gso_type;
+        bytes[2..4].copy_from_slice(&self.hdr_len.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.gso_size.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.csum_start.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.csum_offset.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.num_buffers.to_le_bytes());
+    }
+
+    fn rx_checksum(&self) -> ChecksumStatus {
+        if self.flags & HDR_F_DATA_VALID != 0 {
+            ChecksumStatus::Validated
+        } else if self.flags & HDR_F_NEEDS_CSUM != 0 {
+            ChecksumStatus::Partial {
+                start: self.csum_start,
+                offset: self.csum_offset,
+            }
+        } else {
+            ChecksumStatus::None
+        }
+    }
+}
+
+/// A virtio network device with one receive and one transmit queue.
+pub struct VirtioNet {
+    transport: Box<dyn Transport>,
+    features: u64,
+    mac: MacAddress,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    rx_pool: PacketPool,
+    tx_pool: PacketPool,
+    /// Buffers owned by the device, indexed by the head descriptor of their chain.
+    rx_in_flight: Vec<Option<PacketBuffer>>,
+    tx_in_flight: Vec<Option<PacketBuffer>>,
+}
+
+impl VirtioNet {
+    pub fn new(mut transport: Box<dyn Transport>) -> Result<Self, VirtioError> {
+        let features = virtio::negotiate(transport.as_mut(), SUPPORTED_FEATURES)?;
+
+        let setup = |transport: &mut dyn Transport| -> Result<_, VirtioError> {
+            let rx = VirtQueue::setup(transport, RX_QUEUE)?;
+            let tx = VirtQueue::setup(transport, TX_QUEUE)?;
+            let rx_pool = PacketPool::new(POOL_SIZE, BUFFER_SIZE, NET_HDR_SIZE)
+                .map_err(|_| VirtioError::OutOfMemory)?;
+            let tx_pool = PacketPool::new(POOL_SIZE, BUFFER_SIZE, NET_HDR_SIZE)
+                .map_err(|_| VirtioError::OutOfMemory)?;
+            Ok((rx, tx, rx_pool, tx_pool))
+        };
+        let (rx, tx, rx_pool, tx_pool) = match setup(transport.as_mut()) {
+            Ok(parts) => parts,
+            Err(error) => {
+                virtio::fail(transport.as_mut());
+                return Err(error);
+            }
+        };
+
+        let mac = if features & F_MAC != 0 {
+            read_mac(transport.as_ref())
+        } else {
+            // Locally administered fallback when the device does not provide an address.
+            MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01])
+        };
+
+        let mut device = Self {
+            rx_in_flight: (0..rx.size()).map(|_| None).collect(),
+            tx_in_flight: (0..tx.size()).map(|_| None).collect(),
+            transport,
+            features,
+            mac,
+            rx,
+            tx,
+            rx_pool,
+            tx_pool,
+        };
+
+        virtio::finish_init(device.transport.as_mut());
+        device.replenish_rx();
+
+        Ok(device)
+    }
+
+    /// Post free pool buffers to the receive queue until either runs out.
+    fn replenish_rx(&mut self) {
+        let mut posted = false;
+        while self.rx.num_free() > 0 {
+            let Some(buffer) = self.rx_pool.alloc() else {
+                break;
+            };
+
+            let chain = [QueueBuffer::writable(
+                buffer.phys_addr(),
+                buffer.slot_size() as u32,
+            )];
+            match self.rx.add(&chain) {
+                Ok(head) => {
+                    self.rx_in_flight[head as usize] = Some(buffer);
+                    posted = true;
+                }
+                Err(_) => break,
+            }
+        }
+
+        if posted {
+            self.rx.notify(self.transport.as_mut());
+        }
+    }
+
+    /// Drop transmitted buffers the device has finished with, returning them to the pool.
+    fn reclaim_tx(&mut self) {
+        while let Some(used) = self.tx.pop_used() {
+            self.tx_in_flight[used.head as usize] = None;
+        }
+    }
+
+    /// Fill in the device header, falling back to software checksums when offload is off.
+    fn prepare_tx(&self, packet: &mut PacketBuffer) -> Result<(), NetError> {
+        let mut header = NetHeader::default();
+
+        if let ChecksumStatus::Partial { start, offset } = packet.checksum() {
+            let (start, offset) = (start as usize, offset as usize);
+            if start + offset + 2 > packet.len() {
+                return Err(NetError::InvalidChecksumRequest);
+            }
+
+            if self.features & F_CSUM != 0 {
+                header.flags = HDR_F_NEEDS_CSUM;
+                header.csum_start = start as u16;
+                header.csum_offset = offset as u16;
+            } else {
+                let data = packet.data_mut();
+                // The partial sum left in the field by the stack is folded in by summing over it.
+                let checksum = internet_checksum(&data[start..]);
+                data[start + offset..start + offset + 2].copy_from_slice(&checksum.to_be_bytes());
+                packet.set_checksum(ChecksumStatus::None);
+            }
+        }
+
+        header.write_to(packet.headroom_mut());
+        Ok(())
+    }
+}
+
+fn read_mac(transport: &dyn Transport) -> MacAddress {
+    // Re-read until the configuration generation is stable across the whole read.
+    loop {
+        let generation = transport.config_generation();
+        let mut mac = [0u8; 6];
+        for (index, byte) in mac.iter_mut().enumerate() {
+            *byte = transport.read_config_u8(CONFIG_MAC + index);
+        }
+        if transport.config_generation() == generation {
+            return MacAddress(mac);
+        }
+    }
+}
+
+impl NetDevice for VirtioNet {
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn link_status(&self) -> LinkStatus {
+        if self.features & F_STATUS == 0 {
+            // Without the status field the link is always considered up.
+            return LinkStatus::Up;
+        }
+
+        if self.transport.read_config_u16(CONFIG_STATUS) & STATUS_LINK_UP != 0 {
+            LinkStatus::Up
+        } else {
+            LinkStatus::Down
+        }
+    }
+
+    fn checksum_offload(&self) -> ChecksumOffload {
+        let mut offload = ChecksumOffload::NONE;
+        if self.features & F_CSUM != 0 {
+            offload = offload.union(ChecksumOffload::TX_PARTIAL);
+        }
+        if self.features & F_GUEST_CSUM != 0 {
+            offload = offload.union(ChecksumOffload::RX_VALIDATE);
+        }
+        offload
+    }
+
+    fn mtu(&self) -> usize {
+        MAX_FRAME_SIZE - 14
+    }
+
+    fn alloc_tx_buffer(&mut self) -> Option<PacketBuffer> {
+        if self.tx_pool.available() == 0 {
+            self.reclaim_tx();
+        }
+        self.tx_pool.alloc()
+    }
+
+    fn transmit(&mut self, mut packet: PacketBuffer) -> Result<(), NetError> {
+        if packet.len() > MAX_FRAME_SIZE {
+            return Err(NetError::FrameTooLarge);
+        }
+
+        self.prepare_tx(&mut packet)?;
+
+        if self.tx.num_free() == 0 {
+            self.reclaim_tx();
+        }
+
+        let chain = [QueueBuffer::readable(
+            packet.phys_addr(),
+            packet.wire_len() as u32,
+        )];
+        let head = self.tx.add(&chain).map_err(|_| NetError::Busy)?;
+        self.tx_in_flight[head as usize] = Some(packet);
+        self.tx.notify(self.transport.as_mut());
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<PacketBuffer> {
+        loop {
+            let used = self.rx.pop_used()?;
+            let Some(mut packet) = self.rx_in_flight[used.head as usize].take() else {
+                continue;
+            };
+
+            // A runt with no frame after the header, or a frame too long for the buffer, is
+            // dropped, which gives its buffer back to the pool for `replenish_rx` to post again.
+            let written = used.len as usize;
+            let Some(frame_len) = written.checked_sub(NET_HDR_SIZE).filter(|&len| len > 0) else {
+                drop(packet);
+                self.replenish_rx();
+                continue;
+            };
+            let header = NetHeader::from_bytes(packet.headroom_mut());
+            if packet.set_len(frame_len).is_err() {
+                drop(packet);
+                self.replenish_rx();
+                continue;
+            }
+            packet.set_checksum(if self.features & F_GUEST_CSUM != 0 {
+                header.rx_checksum()
+            } else {
+                ChecksumStatus::None
+            });
+
+            self.replenish_rx();
+            return Some(packet);
+        }
+    }
+
+    fn poll(&mut self) {
+        self.transport.ack_interrupt();
+        self.reclaim_tx();
+        self.replenish_rx();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{NetHeader, HDR_F_DATA_VALID, HDR_F_NEEDS_CSUM, NET_HDR_SIZE};
+    use crate::dev::net::ChecksumStatus;
+
+    #[kunit]
+    fn header_round_trips_through_bytes() {
+        let header = NetHeader {
+            flags: HDR_F_NEEDS_CSUM,
+            gso_type: 0,
+            hdr_len: 0,
+            gso_size: 0,
+            csum_start: 34,
+            csum_offset: 16,
+            num_buffers: 1,
+        };
+        let mut bytes = [0u8; NET_HDR_SIZE];
+        header.write_to(&mut bytes);
+
+        assert_eq!(NetHeader::from_bytes(&bytes), header);
+        assert_eq!(&bytes[6..10], &[34, 0, 16, 0]);
+    }
+
+    #[kunit]
+    fn receive_flags_map_to_checksum_status() {
+        let mut header = NetHeader::default();
+        assert_eq!(header.rx_checksum(), ChecksumStatus::None);
+
+        header.flags = HDR_F_DATA_VALID;
+        assert_eq!(header.rx_checksum(), ChecksumStatus::Validated);
+
+        header.flags = HDR_F_NEEDS_CSUM;
+        header.csum_start = 34;
+        header.csum_offset = 6;
+        assert_eq!(
+            header.rx_checksum(),
+            ChecksumStatus::Partial {
+                start: 34,
+                offset: 6
+            }
+        );
+    }
+}
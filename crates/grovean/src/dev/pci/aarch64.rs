@@ -0,0 +1,80 @@
+use spin::Mutex;
+
+use super::PciAddress;
+use crate::memory::paging;
+
+#[derive(Clone, Copy)]
+struct EcamConfig {
+    base: u64,
+    bus_count: usize,
+}
+
+const DEFAULT_ECAM_CONFIG: EcamConfig = EcamConfig {
+    // QEMU virt platform high-memory PCIe ECAM window.
+    base: 0x40_1000_0000,
+    // The high ECAM window decodes all 256 buses.
+    bus_count: 256,
+};
+
+/// Each bus decodes 32 devices * 8 functions * 4 KiB of configuration space.
+const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+/// Virtual base of each bus' configuration space, mapped the first time the bus is touched.
+static BUS_MAPPINGS: Mutex<[u64; 256]> = Mutex::new([0; 256]);
+
+fn ecam_offset(address: PciAddress, offset: u16) -> u64 {
+    ((address.device as u64) << 15) | ((address.function as u64) << 12) | (offset as u64 & 0xffc)
+}
+
+fn bus_base(bus: u8) -> Option<u64> {
+    if bus as usize >= DEFAULT_ECAM_CONFIG.bus_count {
+        return None;
+    }
+
+    let mut mappings = BUS_MAPPINGS.lock();
+    if mappings[bus as usize] == 0 {
+        let phys = DEFAULT_ECAM_CONFIG.base + bus as u64 * ECAM_BUS_SIZE;
+        mappings[bus as usize] = paging::map_mmio(phys, ECAM_BUS_SIZE).ok()?;
+    }
+    Some(mappings[bus as usize])
+}
+
+pub(super) fn read_config(address: PciAddress, offset: u16) -> u32 {
+    match bus_base(address.bus) {
+        Some(base) => unsafe {
+            core::ptr::read_volatile((base + ecam_offset(address, offset)) as *const u32)
+        },
+        None => u32::MAX,
+    }
+}
+
+pub(super) fn write_config(address: PciAddress, offset: u16, value: u32) {
+    if let Some(base) = bus_base(address.bus) {
+        unsafe {
+            core::ptr::write_volatile((base + ecam_offset(address, offset)) as *mut u32, value);
+        }
+    }
+}
+
+pub(super) fn write_config_u16(address: PciAddress, offset: u16, value: u16) {
+    if let Some(base) = bus_base(address.bus) {
+        let register = base + ecam_offset(address, offset) + (offset & 0b10) as u64;
+        unsafe {
+            core::ptr::write_volatile(register as *mut u16, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::ecam_offset;
+    use crate::dev::pci::PciAddress;
+
+    #[kunit]
+    fn encodes_ecam_offsets() {
+        let address = PciAddress::new(0, 2, 3);
+        assert_eq!(ecam_offset(address, 0x10), 0x13010);
+    }
+}
@@ -0,0 +1,398 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Configuration space register offsets shared by every header type.
+const REG_VENDOR_ID: u16 = 0x00;
+const REG_COMMAND: u16 = 0x04;
+const REG_STATUS: u16 = 0x06;
+const REG_CLASS: u16 = 0x08;
+const REG_HEADER_TYPE: u16 = 0x0e;
+const REG_BAR0: u16 = 0x10;
+const REG_SECONDARY_BUS: u16 = 0x19;
+const REG_SUBSYSTEM_ID: u16 = 0x2e;
+const REG_CAPABILITIES: u16 = 0x34;
+const REG_INTERRUPT_LINE: u16 = 0x3c;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+const INVALID_VENDOR_ID: u16 = 0xffff;
+
+static PCI_DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// The bus/device/function triple addressing a PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        read_config(*self, offset & !0b11)
+    }
+
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        (self.read_u32(offset) >> ((offset & 0b10) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read_u32(offset) >> ((offset & 0b11) * 8)) as u8
+    }
+
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        write_config(*self, offset & !0b11, value);
+    }
+
+    /// Write the 16-bit register at `offset` alone. Writing the whole dword back would also
+    /// hit its other half, such as the status register next to the command register, whose
+    /// bits clear when written as one.
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        write_config_u16(*self, offset & !0b1, value);
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+/// A PCI function discovered during enumeration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<Self> {
+        let id = address.read_u32(REG_VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == INVALID_VENDOR_ID {
+            return None;
+        }
+
+        let class = address.read_u32(REG_CLASS);
+        Some(Self {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: address.read_u8(REG_HEADER_TYPE),
+        })
+    }
+
+    pub fn subsystem_id(&self) -> u16 {
+        self.address.read_u16(REG_SUBSYSTEM_ID)
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        self.address.read_u8(REG_INTERRUPT_LINE)
+    }
+
+    /// Decode base address register `index`, sizing it by probing with all ones.
+    ///
+    /// Returns `None` for unimplemented BARs and for the upper half of a 64-bit BAR.
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        if self.header_type & HEADER_TYPE_MASK != 0 || index >= 6 {
+            return None;
+        }
+
+        let offset = REG_BAR0 + index as u16 * 4;
+        let low = self.address.read_u32(offset);
+        let is_io = low & 0b1 != 0;
+        let is_64bit = !is_io && (low >> 1) & 0b11 == 0b10;
+        if is_64bit && index == 5 {
+            return None;
+        }
+
+        // Decoding stays disabled while the BAR temporarily holds the sizing pattern.
+        let command = self.address.read_u16(REG_COMMAND);
+        self.address.write_u16(
+            REG_COMMAND,
+            command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+        );
+
+        self.address.write_u32(offset, u32::MAX);
+        let low_mask = self.address.read_u32(offset);
+        self.address.write_u32(offset, low);
+
+        let high = if is_64bit {
+            let high = self.address.read_u32(offset + 4);
+            self.address.write_u32(offset + 4, u32::MAX);
+            let high_mask = self.address.read_u32(offset + 4);
+            self.address.write_u32(offset + 4, high);
+            Some((high, high_mask))
+        } else {
+            None
+        };
+
+        self.address.write_u16(REG_COMMAND, command);
+
+        if is_io {
+            let size = bar_size((low_mask & !0b11) as u64 | 0xffff_ffff_0000_0000) as u32;
+            if size == 0 {
+                return None;
+            }
+            return Some(Bar::Io {
+                port: low & !0b11,
+                size,
+            });
+        }
+
+        let (address, mask) = match high {
+            Some((high, high_mask)) => (
+                ((high as u64) << 32) | (low & !0xf) as u64,
+                ((high_mask as u64) << 32) | (low_mask & !0xf) as u64,
+            ),
+            None => (
+                (low & !0xf) as u64,
+                (low_mask & !0xf) as u64 | 0xffff_ffff_0000_0000,
+            ),
+        };
+
+        let size = bar_size(mask);
+        if size == 0 {
+            return None;
+        }
+
+        Some(Bar::Memory {
+            address,
+            size,
+            prefetchable: low & (1 << 3) != 0,
+        })
+    }
+
+    /// Iterate over the (id, offset) pairs of the capability list.
+    pub fn capabilities(&self) -> CapabilityIter {
+        let next = if self.address.read_u16(REG_STATUS) & STATUS_CAPABILITIES_LIST != 0 {
+            self.address.read_u8(REG_CAPABILITIES) & !0b11
+        } else {
+            0
+        };
+
+        CapabilityIter {
+            address: self.address,
+            next,
+            remaining: 48,
+        }
+    }
+
+    /// Enable memory decoding and bus mastering so the function can perform DMA.
+    pub fn enable_bus_master(&self) {
+        let command = self.address.read_u16(REG_COMMAND);
+        self.address.write_u16(
+            REG_COMMAND,
+            command | COMMAND_MEMORY_SPACE | COMMAND_IO_SPACE | COMMAND_BUS_MASTER,
+        );
+    }
+
+    /// Mask the legacy INTx pin, for drivers that poll or use message-signalled interrupts.
+    pub fn disable_legacy_interrupts(&self) {
+        let command = self.address.read_u16(REG_COMMAND);
+        self.address
+            .write_u16(REG_COMMAND, command | COMMAND_INTERRUPT_DISABLE);
+    }
+}
+
+pub struct CapabilityIter {
+    address: PciAddress,
+    next: u8,
+    // Bounds the walk in case a malformed list loops back on itself.
+    remaining: usize,
+}
+
+impl Iterator for CapabilityIter {
+    type Item = (u8, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 || self.remaining == 0 {
+            return None;
+        }
+
+        let offset = self.next as u16;
+        let header = self.address.read_u16(offset);
+        self.next = (header >> 8) as u8 & !0b11;
+        self.remaining -= 1;
+
+        Some((header as u8, offset))
+    }
+}
+
+/// Compute a BAR size from the value read back after writing all ones.
+fn bar_size(mask: u64) -> u64 {
+    if mask == 0 || mask == 0xffff_ffff_0000_0000 {
+        return 0;
+    }
+    (!mask).wrapping_add(1)
+}
+
+fn scan_bus(bus: u8, devices: &mut Vec<PciDevice>) {
+    for device in 0..32 {
+        let Some(first) = PciDevice::probe(PciAddress::new(bus, device, 0)) else {
+            continue;
+        };
+
+        let functions = if first.header_type & HEADER_TYPE_MULTIFUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+
+        for function in 0..functions {
+            let Some(found) = PciDevice::probe(PciAddress::new(bus, device, function)) else {
+                continue;
+            };
+
+            devices.push(found);
+            if found.header_type & HEADER_TYPE_MASK == HEADER_TYPE_BRIDGE {
+                let secondary = found.address.read_u8(REG_SECONDARY_BUS);
+                if secondary > bus {
+                    scan_bus(secondary, devices);
+                }
+            }
+        }
+    }
+}
+
+fn read_config(address: PciAddress, offset: u16) -> u32 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        aarch64::read_config(address, offset)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        x86_64::read_config(address, offset)
+    }
+}
+
+fn write_config(address: PciAddress, offset: u16, value: u32) {
+    #[cfg(target_arch = "aarch64")]
+    aarch64::write_config(address, offset, value);
+
+    #[cfg(target_arch = "x86_64")]
+    x86_64::write_config(address, offset, value);
+}
+
+fn write_config_u16(address: PciAddress, offset: u16, value: u16) {
+    #[cfg(target_arch = "aarch64")]
+    aarch64::write_config_u16(address, offset, value);
+
+    #[cfg(target_arch = "x86_64")]
+    x86_64::write_config_u16(address, offset, value);
+}
+
+/// Enumerate every function reachable from bus 0, following PCI-to-PCI bridges.
+pub fn init() {
+    let mut devices = Vec::new();
+    scan_bus(0, &mut devices);
+
+    crate::info_ln!("pci: {} functions", devices.len());
+    for device in &devices {
+        crate::debug_ln!(
+            "  {} {:04x}:{:04x} class={:02x}:{:02x}:{:02x}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if
+        );
+    }
+
+    *PCI_DEVICES.lock() = devices;
+}
+
+pub fn with_devices<F, R>(f: F) -> R
+where
+    F: FnOnce(&[PciDevice]) -> R,
+{
+    let devices = PCI_DEVICES.lock();
+    f(&devices)
+}
+
+/// Collect every enumerated function matching `predicate`.
+pub fn find<P>(predicate: P) -> Vec<PciDevice>
+where
+    P: Fn(&PciDevice) -> bool,
+{
+    with_devices(|devices| devices.iter().copied().filter(|d| predicate(d)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{bar_size, PciAddress};
+
+    #[kunit]
+    fn sizes_32bit_memory_bars() {
+        assert_eq!(bar_size(0xffff_ffff_fff0_0000), 0x10_0000);
+        assert_eq!(bar_size(0xffff_ffff_ffff_f000), 0x1000);
+    }
+
+    #[kunit]
+    fn sizes_64bit_memory_bars() {
+        assert_eq!(bar_size(0xffff_fffc_0000_0000), 0x4_0000_0000);
+    }
+
+    #[kunit]
+    fn unimplemented_bars_have_no_size() {
+        assert_eq!(bar_size(0), 0);
+        assert_eq!(bar_size(0xffff_ffff_0000_0000), 0);
+    }
+
+    #[kunit]
+    fn formats_addresses_as_bus_device_function() {
+        use alloc::format;
+
+        assert_eq!(format!("{}", PciAddress::new(0, 0x1f, 2)), "00:1f.2");
+    }
+}
@@ -0,0 +1,64 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::PciAddress;
+
+/// Legacy configuration mechanism #1 address and data ports.
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// The address/data port pair is shared state, so accesses must not interleave.
+static CONFIG_PORTS: Mutex<()> = Mutex::new(());
+
+fn config_address(address: PciAddress, offset: u16) -> u32 {
+    (1 << 31)
+        | ((address.bus as u32) << 16)
+        | ((address.device as u32) << 11)
+        | ((address.function as u32) << 8)
+        | (offset as u32 & 0xfc)
+}
+
+pub(super) fn read_config(address: PciAddress, offset: u16) -> u32 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _guard = CONFIG_PORTS.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(config_address(address, offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    })
+}
+
+pub(super) fn write_config(address: PciAddress, offset: u16, value: u32) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _guard = CONFIG_PORTS.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(config_address(address, offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    });
+}
+
+/// Write only the 16-bit register at `offset`, through the matching half of the data port.
+pub(super) fn write_config_u16(address: PciAddress, offset: u16, value: u16) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _guard = CONFIG_PORTS.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(config_address(address, offset));
+            Port::<u16>::new(CONFIG_DATA + (offset & 0b10)).write(value);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::config_address;
+    use crate::dev::pci::PciAddress;
+
+    #[kunit]
+    fn encodes_mechanism_one_addresses() {
+        let address = PciAddress::new(1, 2, 3);
+        assert_eq!(config_address(address, 0x10), 0x8001_1310);
+    }
+}
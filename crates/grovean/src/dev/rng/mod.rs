@@ -0,0 +1,42 @@
+pub mod virtio_rng;
+
+use spin::Mutex;
+
+pub use virtio_rng::{RngError, VirtioRng};
+
+static RNG_DEVICE: Mutex<Option<VirtioRng>> = Mutex::new(None);
+
+/// Probe for a virtio entropy device.
+pub fn init() {
+    let transports = crate::dev::virtio::find_transports(crate::dev::virtio::DeviceType::Entropy);
+    let Some(transport) = transports.into_iter().next() else {
+        return;
+    };
+
+    match VirtioRng::new(transport) {
+        Ok(device) => {
+            *RNG_DEVICE.lock() = Some(device);
+            crate::info_ln!("hwrng: virtio-rng");
+        }
+        Err(error) => {
+            crate::warn_ln!("virtio-rng: initialization failed: {:?}", error);
+        }
+    }
+}
+
+/// Fill `dest` from the entropy device, returning how many bytes were written. Returns zero
+/// when there is no device or it stops answering.
+pub fn read_entropy(dest: &mut [u8]) -> usize {
+    let mut device = RNG_DEVICE.lock();
+    let Some(device) = device.as_mut() else {
+        return 0;
+    };
+
+    match device.read_exact(dest) {
+        Ok(()) => dest.len(),
+        Err(error) => {
+            crate::warn_ln!("virtio-rng: read failed: {:?}", error);
+            0
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use alloc::boxed::Box;
+
+use crate::dev::virtio::queue::{QueueBuffer, VirtQueue};
+use crate::dev::virtio::{self, Transport, VirtioError};
+use crate::memory::dma::DmaBuffer;
+
+const REQUEST_QUEUE: u16 = 0;
+
+const BUFFER_SIZE: usize = 256;
+
+/// Bounds the wait for the host to supply entropy.
+const REQUEST_SPIN_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngError {
+    Virtio(VirtioError),
+    Timeout,
+}
+
+impl From<VirtioError> for RngError {
+    fn from(error: VirtioError) -> Self {
+        RngError::Virtio(error)
+    }
+}
+
+/// A virtio entropy device, which fills any device-writable buffer with random bytes.
+pub struct VirtioRng {
+    transport: Box<dyn Transport>,
+    queue: VirtQueue,
+    buffer: DmaBuffer,
+}
+
+impl VirtioRng {
+    pub fn new(mut transport: Box<dyn Transport>) -> Result<Self, VirtioError> {
+        virtio::negotiate(transport.as_mut(), 0)?;
+
+        let setup = |transport: &mut dyn Transport| -> Result<_, VirtioError> {
+            let queue = VirtQueue::setup(transport, REQUEST_QUEUE)?;
+            let buffer = DmaBuffer::new(BUFFER_SIZE).map_err(|_| VirtioError::OutOfMemory)?;
+            Ok((queue, buffer))
+        };
+        let (queue, buffer) = match setup(transport.as_mut()) {
+            Ok(parts) => parts,
+            Err(error) => {
+                virtio::fail(transport.as_mut());
+                return Err(error);
+            }
+        };
+
+        let mut device = Self {
+            transport,
+            queue,
+            buffer,
+        };
+        virtio::finish_init(device.transport.as_mut());
+
+        Ok(device)
+    }
+
+    /// Fill as much of `dest` as the host provides in one request, returning the byte count.
+    pub fn read(&mut self, dest: &mut [u8]) -> Result<usize, RngError> {
+        let requested = dest.len().min(BUFFER_SIZE);
+        self.queue.add(&[QueueBuffer::writable(
+            self.buffer.phys_addr(),
+            requested as u32,
+        )])?;
+        self.queue.notify(self.transport.as_mut());
+
+        let mut spins = 0;
+        let used = loop {
+            if let Some(used) = self.queue.pop_used() {
+                break used;
+            }
+            spins += 1;
+            if spins >= REQUEST_SPIN_LIMIT {
+                return Err(RngError::Timeout);
+            }
+            core::hint::spin_loop();
+        };
+
+        let count = (used.len as usize).min(requested);
+        dest[..count].copy_from_slice(&self.buffer.as_slice()[..count]);
+        // Random bytes should not linger in memory the device can see.
+        self.buffer.as_mut_slice()[..count].fill(0);
+        Ok(count)
+    }
+
+    /// Fill all of `dest`, issuing as many requests as needed.
+    pub fn read_exact(&mut self, dest: &mut [u8]) -> Result<(), RngError> {
+        let mut filled = 0;
+        while filled < dest.len() {
+            filled += self.read(&mut dest[filled..])?;
+        }
+        Ok(())
+    }
+}
@@ -2,14 +2,16 @@ use core::fmt;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::MmioSerialPort;
 
+use crate::sync::IrqSpinLock;
+
 #[derive(Clone, Copy)]
 struct SerialConfig {
     base: usize,
     line_status_offset: usize,
     output_empty_mask: u8,
+    data_ready_mask: u8,
     write_spin_limit: usize,
 }
 
@@ -20,10 +22,16 @@ const DEFAULT_SERIAL_CONFIG: SerialConfig = SerialConfig {
     line_status_offset: 5,
     // 16550 LSR bit 5: transmitter holding register empty.
     output_empty_mask: 1 << 5,
+    // 16550 LSR bit 0: receiver data ready.
+    data_ready_mask: 1 << 0,
     // Best-effort write bound to prevent indefinite lockup.
     write_spin_limit: 100_000,
 };
 
+/// The QEMU virt UART's shared peripheral interrupt, with its receive interrupt enabled by
+/// `MmioSerialPort::init`.
+pub(super) const RECEIVE_LINE: usize = 1;
+
 struct SerialRuntimeState {
     disabled: AtomicBool,
 }
@@ -46,31 +54,47 @@ impl SerialRuntimeState {
 
 struct Aarch64SerialPort {
     config: SerialConfig,
+    base: usize,
     _mmio: MmioSerialPort,
 }
 
 impl Aarch64SerialPort {
     fn new(config: SerialConfig) -> Self {
-        let mut mmio = unsafe { MmioSerialPort::new(config.base) };
+        // The bootloader does not identity map devices, so go through the MMIO window once
+        // paging is available and fall back to the physical address for very early output.
+        let base = crate::memory::paging::map_mmio(config.base as u64, 8)
+            .map(|virt| virt as usize)
+            .unwrap_or(config.base);
+
+        let mut mmio = unsafe { MmioSerialPort::new(base) };
         mmio.init();
         Self {
             config,
+            base,
             _mmio: mmio,
         }
     }
 
     fn data_addr(&self) -> *mut u8 {
-        self.config.base as *mut u8
+        self.base as *mut u8
     }
 
     fn line_status_addr(&self) -> *const u8 {
-        (self.config.base + self.config.line_status_offset) as *const u8
+        (self.base + self.config.line_status_offset) as *const u8
     }
 
     fn is_output_empty(&self, status: u8) -> bool {
         status & self.config.output_empty_mask != 0
     }
 
+    fn read_byte_non_blocking(&mut self) -> Option<u8> {
+        let status = unsafe { core::ptr::read_volatile(self.line_status_addr()) };
+        if status & self.config.data_ready_mask == 0 {
+            return None;
+        }
+        Some(unsafe { core::ptr::read_volatile(self.data_addr()) })
+    }
+
     fn write_byte_non_blocking(&mut self, byte: u8) -> Result<(), fmt::Error> {
         let line_status = self.line_status_addr();
         let data = self.data_addr();
@@ -103,7 +127,7 @@ static SERIAL_STATE: SerialRuntimeState = SerialRuntimeState::new();
 
 lazy_static! {
     /// A static instance of the aarch64 memory-mapped serial port interface.
-    static ref SERIAL1: Mutex<Aarch64SerialPort> = Mutex::new(Aarch64SerialPort::new(DEFAULT_SERIAL_CONFIG));
+    static ref SERIAL1: IrqSpinLock<Aarch64SerialPort> = IrqSpinLock::new(Aarch64SerialPort::new(DEFAULT_SERIAL_CONFIG));
 }
 
 pub(super) fn _print(args: ::core::fmt::Arguments) {
@@ -118,6 +142,24 @@ pub(super) fn _print(args: ::core::fmt::Arguments) {
     }
 }
 
+pub(super) fn write_bytes(bytes: &[u8]) {
+    if SERIAL_STATE.is_disabled() {
+        return;
+    }
+
+    let mut port = SERIAL1.lock();
+    for &byte in bytes {
+        if port.write_byte_non_blocking(byte).is_err() {
+            SERIAL_STATE.disable();
+            return;
+        }
+    }
+}
+
+pub(super) fn read_byte() -> Option<u8> {
+    SERIAL1.lock().read_byte_non_blocking()
+}
+
 #[cfg(test)]
 mod tests {
     use kunit::kunit;
@@ -137,6 +179,7 @@ mod tests {
             base: 0x1000,
             line_status_offset: 5,
             output_empty_mask: 1 << 5,
+            data_ready_mask: 1 << 0,
             write_spin_limit: 10,
         };
 
@@ -3,6 +3,128 @@ mod aarch64;
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::sync::Arc;
+
+use crate::fs::devfs::{self, Device};
+use crate::fs::vfs::FsError;
+use crate::interrupts;
+use crate::sync::IrqSpinLock;
+use crate::task::WaitQueue;
+
+/// Bytes kept for readers before further input is dropped.
+const INPUT_CAPACITY: usize = 4096;
+
+/// Bytes taken from the port that no reader has had yet. The port's receive FIFO is small,
+/// so the receive interrupt empties it into here.
+static INPUT: IrqSpinLock<InputBuffer> = IrqSpinLock::new(InputBuffer::new());
+/// Readers waiting for input, woken by the receive interrupt.
+static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+
+/// A ring of received bytes.
+struct InputBuffer {
+    bytes: [u8; INPUT_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl InputBuffer {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; INPUT_CAPACITY],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append `byte`, dropping it if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if self.len == INPUT_CAPACITY {
+            return;
+        }
+        self.bytes[(self.start + self.len) % INPUT_CAPACITY] = byte;
+        self.len += 1;
+    }
+
+    /// Move the oldest bytes into `buffer`, returning how many there were.
+    fn take(&mut self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len().min(self.len);
+        for slot in &mut buffer[..count] {
+            *slot = self.bytes[self.start];
+            self.start = (self.start + 1) % INPUT_CAPACITY;
+        }
+        self.len -= count;
+        count
+    }
+
+    /// Take every byte waiting in the port.
+    fn fill(&mut self) {
+        while let Some(byte) = read_byte() {
+            self.push(byte);
+        }
+    }
+}
+
+/// The first serial port as a byte stream.
+struct SerialDevice;
+
+impl Device for SerialDevice {
+    fn mode(&self) -> u32 {
+        0o660
+    }
+
+    /// Block until some input arrives, then return what there is, up to `buffer.len()` bytes.
+    fn read(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let count = {
+                let mut input = INPUT.lock();
+                input.fill();
+                input.take(buffer)
+            };
+            if count > 0 {
+                return Ok(count);
+            }
+            INPUT_WAITERS.wait_until(|| !INPUT.lock().is_empty());
+        }
+    }
+
+    fn write(&self, _offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        write_bytes(buffer);
+        Ok(buffer.len())
+    }
+}
+
+/// Make the first serial port available as `/dev/ttyS0`, with reads woken by its receive
+/// interrupt.
+pub fn init() {
+    match interrupts::enable_line(arch::RECEIVE_LINE) {
+        Some(vector) => interrupts::set_handler(vector, "serial", received),
+        None => {
+            crate::warn_ln!("serial: no receive interrupt, reads will not wake");
+        }
+    }
+    if let Err(error) = devfs::register("ttyS0", Arc::new(SerialDevice)) {
+        crate::warn_ln!("serial: failed to register ttyS0: {:?}", error);
+    }
+}
+
+/// The receive interrupt: empty the port, which also quiets the interrupt, and wake readers.
+fn received() {
+    INPUT.lock().fill();
+    INPUT_WAITERS.wake_all();
+}
+
 #[doc(hidden)]
 #[cfg(target_arch = "aarch64")]
 pub fn _print(args: ::core::fmt::Arguments) {
@@ -15,6 +137,16 @@ pub fn _print(args: ::core::fmt::Arguments) {
     x86_64::_print(args);
 }
 
+/// Write raw bytes to the first serial port.
+pub fn write_bytes(bytes: &[u8]) {
+    arch::write_bytes(bytes);
+}
+
+/// Take one received byte from the first serial port, if one is waiting.
+pub fn read_byte() -> Option<u8> {
+    arch::read_byte()
+}
+
 /// Print to the serial port.
 #[macro_export]
 macro_rules! serial_print {
@@ -82,3 +214,39 @@ macro_rules! serial_danger_ln {
   ($fmt:expr) => ($crate::serial_print!(concat!("DANGER: ", $fmt, "\n")));
   ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{InputBuffer, INPUT_CAPACITY};
+
+    #[kunit]
+    fn input_wraps_around_and_drops_overflow() {
+        let mut input = InputBuffer::new();
+        let mut buffer = [0; 3];
+        for byte in 0..INPUT_CAPACITY - 1 {
+            input.push(byte as u8);
+        }
+        let mut drained = [0; INPUT_CAPACITY];
+        assert_eq!(
+            input.take(&mut drained[..INPUT_CAPACITY - 2]),
+            INPUT_CAPACITY - 2
+        );
+
+        for &byte in b"abc" {
+            input.push(byte);
+        }
+        assert_eq!(input.take(&mut buffer), 3);
+        assert_eq!(buffer, [(INPUT_CAPACITY - 2) as u8, b'a', b'b']);
+        assert_eq!(input.take(&mut buffer), 1);
+        assert_eq!(buffer[0], b'c');
+        assert!(input.is_empty());
+
+        for _ in 0..INPUT_CAPACITY + 1 {
+            input.push(b'x');
+        }
+        let mut overflowed = [0; INPUT_CAPACITY + 1];
+        assert_eq!(input.take(&mut overflowed), INPUT_CAPACITY);
+    }
+}
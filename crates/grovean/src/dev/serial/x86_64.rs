@@ -1,24 +1,53 @@
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::PortReadOnly;
+
+use crate::sync::IrqSpinLock;
+
+/// I/O port base of COM1.
+const COM1: u16 = 0x3F8;
+/// The ISA IRQ COM1 raises, with its receive interrupt enabled by `SerialPort::init`.
+pub(super) const RECEIVE_LINE: usize = 4;
+
+/// 16550 line status register offset and its data-ready bit.
+const LINE_STATUS_OFFSET: u16 = 5;
+const DATA_READY: u8 = 1 << 0;
 
 lazy_static! {
-    /// A static instance of the serial port interface.
-    static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    /// A static instance of the serial port interface. Interrupt handlers print too, so it
+    /// is held with interrupts disabled.
+    static ref SERIAL1: IrqSpinLock<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinLock::new(serial_port)
     };
 }
 
 pub(super) fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
+
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("Printing to serial failed");
+}
+
+pub(super) fn write_bytes(bytes: &[u8]) {
+    let mut port = SERIAL1.lock();
+    for &byte in bytes {
+        port.send(byte);
+    }
+}
+
+pub(super) fn read_byte() -> Option<u8> {
+    // Hold the lock so the status check and the data read are not split by a writer.
+    let _port = SERIAL1.lock();
+    let mut line_status = PortReadOnly::<u8>::new(COM1 + LINE_STATUS_OFFSET);
+    let mut data = PortReadOnly::<u8>::new(COM1);
+    unsafe {
+        if line_status.read() & DATA_READY == 0 {
+            return None;
+        }
+        Some(data.read())
+    }
 }
@@ -0,0 +1,198 @@
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::{DeviceType, Transport};
+use crate::memory::paging;
+
+#[derive(Clone, Copy)]
+struct MmioConfig {
+    base: u64,
+    slot_size: u64,
+    slot_count: usize,
+}
+
+#[cfg(target_arch = "aarch64")]
+const DEFAULT_MMIO_CONFIG: MmioConfig = MmioConfig {
+    // QEMU virt platform virtio-mmio transports.
+    base: 0x0a00_0000,
+    slot_size: 0x200,
+    slot_count: 32,
+};
+
+// The x86_64 machines we boot on (pc, q35) only attach virtio over PCI.
+#[cfg(target_arch = "x86_64")]
+const DEFAULT_MMIO_CONFIG: MmioConfig = MmioConfig {
+    base: 0,
+    slot_size: 0,
+    slot_count: 0,
+};
+
+/// "virt" in little-endian ASCII.
+const MAGIC_VALUE: u32 = 0x7472_6976;
+const MODERN_VERSION: u32 = 2;
+
+/// Virtual base of the transport slots, mapped on first probe.
+static MMIO_WINDOW: Once<Option<u64>> = Once::new();
+
+/// virtio-mmio register offsets (virtio 1.2, section 4.2.2).
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const REG_CONFIG_GENERATION: usize = 0x0fc;
+const REG_CONFIG: usize = 0x100;
+
+/// A modern (version 2) virtio-mmio device.
+///
+/// QEMU exposes legacy version 1 registers by default; run it with
+/// `-global virtio-mmio.force-legacy=false` for these slots to be picked up.
+pub struct MmioTransport {
+    base: u64,
+    device_type: DeviceType,
+}
+
+impl MmioTransport {
+    /// Probe a mapped register window, returning a transport if a modern device of
+    /// `device_type` sits there.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point at a mapped virtio-mmio register window.
+    pub unsafe fn probe_slot(base: u64, device_type: DeviceType) -> Option<Self> {
+        let transport = Self { base, device_type };
+
+        if transport.read(REG_MAGIC) != MAGIC_VALUE {
+            return None;
+        }
+
+        if DeviceType::from(transport.read(REG_DEVICE_ID)) != device_type {
+            return None;
+        }
+
+        if transport.read(REG_VERSION) != MODERN_VERSION {
+            crate::warn_ln!("virtio-mmio: skipping legacy device at {:#x}", base);
+            return None;
+        }
+
+        Some(transport)
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset as u64) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset as u64) as *mut u32, value) }
+    }
+}
+
+impl Transport for MmioTransport {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn device_features(&mut self) -> u64 {
+        self.write(REG_DEVICE_FEATURES_SEL, 0);
+        let low = self.read(REG_DEVICE_FEATURES) as u64;
+        self.write(REG_DEVICE_FEATURES_SEL, 1);
+        let high = self.read(REG_DEVICE_FEATURES) as u64;
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        self.write(REG_DRIVER_FEATURES_SEL, 0);
+        self.write(REG_DRIVER_FEATURES, features as u32);
+        self.write(REG_DRIVER_FEATURES_SEL, 1);
+        self.write(REG_DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read(REG_STATUS) as u8
+    }
+
+    fn set_status(&mut self, status: u8) {
+        self.write(REG_STATUS, status as u32);
+    }
+
+    fn max_queue_size(&mut self, queue: u16) -> u16 {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        if self.read(REG_QUEUE_READY) != 0 {
+            return 0;
+        }
+        self.read(REG_QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    fn setup_queue(&mut self, queue: u16, size: u16, desc: u64, driver: u64, device: u64) {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        self.write(REG_QUEUE_NUM, size as u32);
+        self.write(REG_QUEUE_DESC_LOW, desc as u32);
+        self.write(REG_QUEUE_DESC_HIGH, (desc >> 32) as u32);
+        self.write(REG_QUEUE_DRIVER_LOW, driver as u32);
+        self.write(REG_QUEUE_DRIVER_HIGH, (driver >> 32) as u32);
+        self.write(REG_QUEUE_DEVICE_LOW, device as u32);
+        self.write(REG_QUEUE_DEVICE_HIGH, (device >> 32) as u32);
+        self.write(REG_QUEUE_READY, 1);
+    }
+
+    fn notify(&mut self, queue: u16) {
+        self.write(REG_QUEUE_NOTIFY, queue as u32);
+    }
+
+    fn ack_interrupt(&mut self) -> u8 {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        self.write(REG_INTERRUPT_ACK, status);
+        status as u8
+    }
+
+    fn config_generation(&self) -> u32 {
+        self.read(REG_CONFIG_GENERATION)
+    }
+
+    fn read_config_u8(&self, offset: usize) -> u8 {
+        unsafe { core::ptr::read_volatile((self.base + (REG_CONFIG + offset) as u64) as *const u8) }
+    }
+
+    fn write_config_u8(&mut self, offset: usize, value: u8) {
+        unsafe {
+            core::ptr::write_volatile((self.base + (REG_CONFIG + offset) as u64) as *mut u8, value)
+        }
+    }
+}
+
+/// Find every modern virtio-mmio device of `device_type` on the platform's transport slots.
+pub fn probe(device_type: DeviceType) -> Vec<MmioTransport> {
+    let config = DEFAULT_MMIO_CONFIG;
+    if config.slot_count == 0 {
+        return Vec::new();
+    }
+
+    let window = MMIO_WINDOW.call_once(|| {
+        paging::map_mmio(config.base, config.slot_size * config.slot_count as u64).ok()
+    });
+    let Some(window) = *window else {
+        return Vec::new();
+    };
+
+    (0..config.slot_count)
+        .filter_map(|slot| unsafe {
+            MmioTransport::probe_slot(window + slot as u64 * config.slot_size, device_type)
+        })
+        .collect()
+}
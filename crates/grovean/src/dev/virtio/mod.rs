@@ -0,0 +1,181 @@
+pub mod mmio;
+pub mod pci;
+pub mod queue;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Device status bits (virtio 1.2, section 2.1).
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 128;
+
+/// Device-independent feature bits.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// Bounds the wait for a device to acknowledge a reset.
+const RESET_SPIN_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device does not offer `VIRTIO_F_VERSION_1` and only speaks the legacy interface.
+    LegacyDevice,
+    /// The device did not keep `FEATURES_OK` set after feature negotiation.
+    FeaturesRejected,
+    ResetTimeout,
+    QueueUnavailable,
+    QueueFull,
+    OutOfMemory,
+    MissingCapability,
+    MappingFailed,
+}
+
+/// Device types as numbered by the virtio specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Gpu,
+    Unknown(u32),
+}
+
+impl From<u32> for DeviceType {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => DeviceType::Network,
+            2 => DeviceType::Block,
+            3 => DeviceType::Console,
+            4 => DeviceType::Entropy,
+            16 => DeviceType::Gpu,
+            other => DeviceType::Unknown(other),
+        }
+    }
+}
+
+/// Register-level access to a virtio device, independent of how it is attached.
+pub trait Transport: Send {
+    fn device_type(&self) -> DeviceType;
+
+    fn device_features(&mut self) -> u64;
+
+    fn set_driver_features(&mut self, features: u64);
+
+    fn status(&self) -> u8;
+
+    fn set_status(&mut self, status: u8);
+
+    /// Get the largest size the device supports for `queue`, or zero if it does not exist.
+    fn max_queue_size(&mut self, queue: u16) -> u16;
+
+    /// Program the ring addresses of `queue` and hand it to the device.
+    fn setup_queue(&mut self, queue: u16, size: u16, desc: u64, driver: u64, device: u64);
+
+    fn notify(&mut self, queue: u16);
+
+    /// Read and acknowledge the interrupt status (bit 0: used ring, bit 1: configuration).
+    fn ack_interrupt(&mut self) -> u8;
+
+    fn config_generation(&self) -> u32;
+
+    fn read_config_u8(&self, offset: usize) -> u8;
+
+    fn write_config_u8(&mut self, offset: usize, value: u8);
+
+    fn read_config_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.read_config_u8(offset), self.read_config_u8(offset + 1)])
+    }
+
+    fn read_config_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([
+            self.read_config_u8(offset),
+            self.read_config_u8(offset + 1),
+            self.read_config_u8(offset + 2),
+            self.read_config_u8(offset + 3),
+        ])
+    }
+
+    fn write_config_u32(&mut self, offset: usize, value: u32) {
+        for (index, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_config_u8(offset + index, byte);
+        }
+    }
+}
+
+/// Reset the device and negotiate the intersection of its features with `supported`.
+///
+/// On success the device is left in the `FEATURES_OK` state, ready for queue setup.
+pub fn negotiate(transport: &mut dyn Transport, supported: u64) -> Result<u64, VirtioError> {
+    transport.set_status(0);
+    let mut spins = 0;
+    while transport.status() != 0 {
+        spins += 1;
+        if spins >= RESET_SPIN_LIMIT {
+            return Err(VirtioError::ResetTimeout);
+        }
+        core::hint::spin_loop();
+    }
+
+    transport.set_status(STATUS_ACKNOWLEDGE);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let offered = transport.device_features();
+    if offered & F_VERSION_1 == 0 {
+        transport.set_status(STATUS_FAILED);
+        return Err(VirtioError::LegacyDevice);
+    }
+
+    let negotiated = offered & (supported | F_VERSION_1);
+    transport.set_driver_features(negotiated);
+    transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+    if transport.status() & STATUS_FEATURES_OK == 0 {
+        transport.set_status(STATUS_FAILED);
+        return Err(VirtioError::FeaturesRejected);
+    }
+
+    Ok(negotiated)
+}
+
+/// Tell the device the driver is fully set up.
+pub fn finish_init(transport: &mut dyn Transport) {
+    let status = transport.status();
+    transport.set_status(status | STATUS_DRIVER_OK);
+}
+
+/// Mark the device as failed, for drivers that give up after negotiation.
+pub fn fail(transport: &mut dyn Transport) {
+    let status = transport.status();
+    transport.set_status(status | STATUS_FAILED);
+}
+
+/// Find every virtio device of `device_type`, whether attached over PCI or MMIO.
+pub fn find_transports(device_type: DeviceType) -> Vec<Box<dyn Transport>> {
+    let mut transports: Vec<Box<dyn Transport>> = Vec::new();
+
+    for transport in pci::probe(device_type) {
+        transports.push(Box::new(transport));
+    }
+    for transport in mmio::probe(device_type) {
+        transports.push(Box::new(transport));
+    }
+
+    transports
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::DeviceType;
+
+    #[kunit]
+    fn maps_device_ids_to_types() {
+        assert_eq!(DeviceType::from(1), DeviceType::Network);
+        assert_eq!(DeviceType::from(4), DeviceType::Entropy);
+        assert_eq!(DeviceType::from(16), DeviceType::Gpu);
+        assert_eq!(DeviceType::from(42), DeviceType::Unknown(42));
+    }
+}
@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+
+use super::{DeviceType, Transport, VirtioError};
+use crate::dev::pci::{self, Bar, PciDevice};
+use crate::memory::paging;
+
+const VIRTIO_VENDOR_ID: u16 = 0x1af4;
+/// Transitional devices use ids 0x1000..=0x103f, modern devices 0x1040 + device type.
+const TRANSITIONAL_DEVICE_IDS: core::ops::RangeInclusive<u16> = 0x1000..=0x103f;
+const MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+const CAP_ID_VENDOR: u8 = 0x09;
+
+/// `cfg_type` values of the vendor-specific virtio capabilities.
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_ISR_CFG: u8 = 3;
+const CAP_DEVICE_CFG: u8 = 4;
+
+/// Byte offsets inside `struct virtio_pci_cap`.
+const CAP_CFG_TYPE: u16 = 3;
+const CAP_BAR: u16 = 4;
+const CAP_OFFSET: u16 = 8;
+const CAP_LENGTH: u16 = 12;
+const CAP_NOTIFY_MULTIPLIER: u16 = 16;
+
+/// Register offsets inside `struct virtio_pci_common_cfg`.
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0c;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_CONFIG_GENERATION: usize = 0x15;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// A modern (virtio 1.x) device attached over PCI, configured through vendor capabilities.
+pub struct PciTransport {
+    device: PciDevice,
+    device_type: DeviceType,
+    common: u64,
+    notify: u64,
+    notify_multiplier: u32,
+    isr: u64,
+    device_config: u64,
+}
+
+impl PciTransport {
+    pub fn new(device: PciDevice) -> Result<Self, VirtioError> {
+        let device_type = device_type_of(&device).ok_or(VirtioError::MissingCapability)?;
+
+        let mut common = None;
+        let mut notify = None;
+        let mut notify_multiplier = 0;
+        let mut isr = None;
+        let mut device_config = None;
+
+        for (id, offset) in device.capabilities() {
+            if id != CAP_ID_VENDOR {
+                continue;
+            }
+
+            let cfg_type = device.address.read_u8(offset + CAP_CFG_TYPE);
+            let region = || -> Result<u64, VirtioError> {
+                let bar = device.address.read_u8(offset + CAP_BAR);
+                let region_offset = device.address.read_u32(offset + CAP_OFFSET) as u64;
+                let length = device.address.read_u32(offset + CAP_LENGTH) as u64;
+                let Some(Bar::Memory { address, .. }) = device.bar(bar) else {
+                    return Err(VirtioError::MissingCapability);
+                };
+                paging::map_mmio(address + region_offset, length)
+                    .map_err(|_| VirtioError::MappingFailed)
+            };
+
+            match cfg_type {
+                CAP_COMMON_CFG if common.is_none() => common = Some(region()?),
+                CAP_NOTIFY_CFG if notify.is_none() => {
+                    notify = Some(region()?);
+                    notify_multiplier = device.address.read_u32(offset + CAP_NOTIFY_MULTIPLIER);
+                }
+                CAP_ISR_CFG if isr.is_none() => isr = Some(region()?),
+                CAP_DEVICE_CFG if device_config.is_none() => device_config = Some(region()?),
+                _ => {}
+            }
+        }
+
+        device.enable_bus_master();
+
+        Ok(Self {
+            device,
+            device_type,
+            common: common.ok_or(VirtioError::MissingCapability)?,
+            notify: notify.ok_or(VirtioError::MissingCapability)?,
+            notify_multiplier,
+            isr: isr.ok_or(VirtioError::MissingCapability)?,
+            // Devices without configuration fields (such as entropy) omit this capability.
+            device_config: device_config.unwrap_or(0),
+        })
+    }
+
+    pub fn pci_device(&self) -> &PciDevice {
+        &self.device
+    }
+
+    fn read_common<T: Copy>(&self, offset: usize) -> T {
+        unsafe { core::ptr::read_volatile((self.common + offset as u64) as *const T) }
+    }
+
+    fn write_common<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { core::ptr::write_volatile((self.common + offset as u64) as *mut T, value) }
+    }
+}
+
+fn device_type_of(device: &PciDevice) -> Option<DeviceType> {
+    if device.vendor_id != VIRTIO_VENDOR_ID {
+        return None;
+    }
+
+    if device.device_id >= MODERN_DEVICE_ID_BASE {
+        Some(DeviceType::from(
+            (device.device_id - MODERN_DEVICE_ID_BASE) as u32,
+        ))
+    } else if TRANSITIONAL_DEVICE_IDS.contains(&device.device_id) {
+        // Transitional devices carry the virtio device type in the subsystem id.
+        Some(DeviceType::from(device.subsystem_id() as u32))
+    } else {
+        None
+    }
+}
+
+impl Transport for PciTransport {
+    fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    fn device_features(&mut self) -> u64 {
+        self.write_common::<u32>(COMMON_DEVICE_FEATURE_SELECT, 0);
+        let low = self.read_common::<u32>(COMMON_DEVICE_FEATURE) as u64;
+        self.write_common::<u32>(COMMON_DEVICE_FEATURE_SELECT, 1);
+        let high = self.read_common::<u32>(COMMON_DEVICE_FEATURE) as u64;
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE, features as u32);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    fn status(&self) -> u8 {
+        self.read_common(COMMON_DEVICE_STATUS)
+    }
+
+    fn set_status(&mut self, status: u8) {
+        self.write_common(COMMON_DEVICE_STATUS, status);
+    }
+
+    fn max_queue_size(&mut self, queue: u16) -> u16 {
+        self.write_common(COMMON_QUEUE_SELECT, queue);
+        self.read_common(COMMON_QUEUE_SIZE)
+    }
+
+    fn setup_queue(&mut self, queue: u16, size: u16, desc: u64, driver: u64, device: u64) {
+        self.write_common(COMMON_QUEUE_SELECT, queue);
+        self.write_common(COMMON_QUEUE_SIZE, size);
+        self.write_common(COMMON_QUEUE_DESC, desc);
+        self.write_common(COMMON_QUEUE_DRIVER, driver);
+        self.write_common(COMMON_QUEUE_DEVICE, device);
+        self.write_common::<u16>(COMMON_QUEUE_ENABLE, 1);
+    }
+
+    fn notify(&mut self, queue: u16) {
+        self.write_common(COMMON_QUEUE_SELECT, queue);
+        let notify_offset = self.read_common::<u16>(COMMON_QUEUE_NOTIFY_OFF) as u64;
+        let address = self.notify + notify_offset * self.notify_multiplier as u64;
+        unsafe { core::ptr::write_volatile(address as *mut u16, queue) }
+    }
+
+    fn ack_interrupt(&mut self) -> u8 {
+        // Reading the ISR status register also clears it.
+        unsafe { core::ptr::read_volatile(self.isr as *const u8) }
+    }
+
+    fn config_generation(&self) -> u32 {
+        self.read_common::<u8>(COMMON_CONFIG_GENERATION) as u32
+    }
+
+    fn read_config_u8(&self, offset: usize) -> u8 {
+        unsafe { core::ptr::read_volatile((self.device_config + offset as u64) as *const u8) }
+    }
+
+    fn write_config_u8(&mut self, offset: usize, value: u8) {
+        unsafe { core::ptr::write_volatile((self.device_config + offset as u64) as *mut u8, value) }
+    }
+}
+
+/// Find every virtio PCI function of `device_type` exposing the modern interface.
+pub fn probe(device_type: DeviceType) -> Vec<PciTransport> {
+    pci::find(|device| device_type_of(device) == Some(device_type))
+        .into_iter()
+        .filter_map(|device| match PciTransport::new(device) {
+            Ok(transport) => Some(transport),
+            Err(error) => {
+                crate::warn_ln!("virtio-pci: skipping {}: {:?}", device.address, error);
+                None
+            }
+        })
+        .collect()
+}
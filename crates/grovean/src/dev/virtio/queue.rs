@@ -0,0 +1,392 @@
+use core::sync::atomic::{fence, Ordering};
+
+use super::{Transport, VirtioError};
+use crate::memory::dma::DmaBuffer;
+
+/// Largest queue we allocate, even if the device offers more.
+pub const MAX_QUEUE_SIZE: u16 = 256;
+
+/// Descriptor flags.
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Marks the end of the free descriptor list.
+const NO_DESCRIPTOR: u16 = u16::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// One physically contiguous piece of a request handed to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueBuffer {
+    pub phys: u64,
+    pub len: u32,
+    /// The device writes into this buffer rather than reading from it.
+    pub device_writable: bool,
+}
+
+impl QueueBuffer {
+    pub const fn readable(phys: u64, len: u32) -> Self {
+        Self {
+            phys,
+            len,
+            device_writable: false,
+        }
+    }
+
+    pub const fn writable(phys: u64, len: u32) -> Self {
+        Self {
+            phys,
+            len,
+            device_writable: true,
+        }
+    }
+}
+
+/// A chain the device has finished with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsedElement {
+    /// Head descriptor index returned by [`VirtQueue::add`].
+    pub head: u16,
+    /// Number of bytes the device wrote into the chain.
+    pub len: u32,
+}
+
+/// A split virtqueue: descriptor table, driver (available) ring and device (used) ring.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    memory: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Allocate ring memory for a queue of `size` entries (a power of two).
+    pub fn new(index: u16, size: u16) -> Result<Self, VirtioError> {
+        if size == 0 || !size.is_power_of_two() {
+            return Err(VirtioError::QueueUnavailable);
+        }
+
+        let entries = size as usize;
+        let avail_offset = entries * size_of::<Descriptor>();
+        // flags, idx, ring[size], used_event
+        let avail_len = 2 * (3 + entries);
+        let used_offset = (avail_offset + avail_len).next_multiple_of(4);
+        // flags, idx, ring[size] of (id, len), avail_event
+        let used_len = 4 + 8 * entries + 2;
+
+        let memory =
+            DmaBuffer::new(used_offset + used_len).map_err(|_| VirtioError::OutOfMemory)?;
+
+        let mut queue = Self {
+            index,
+            size,
+            memory,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+
+        for descriptor in 0..size {
+            let next = if descriptor + 1 == size {
+                NO_DESCRIPTOR
+            } else {
+                descriptor + 1
+            };
+            queue.write_descriptor(
+                descriptor,
+                Descriptor {
+                    addr: 0,
+                    len: 0,
+                    flags: 0,
+                    next,
+                },
+            );
+        }
+
+        Ok(queue)
+    }
+
+    /// Negotiate a size with the device, allocate the rings and enable the queue.
+    pub fn setup(transport: &mut dyn Transport, index: u16) -> Result<Self, VirtioError> {
+        let max = transport.max_queue_size(index);
+        if max == 0 {
+            return Err(VirtioError::QueueUnavailable);
+        }
+
+        let size = max.min(MAX_QUEUE_SIZE);
+        // Round down to a power of two so ring indices wrap cleanly.
+        let size = 1 << (u16::BITS - 1 - size.leading_zeros());
+        let queue = Self::new(index, size)?;
+        transport.setup_queue(
+            index,
+            size,
+            queue.desc_addr(),
+            queue.driver_addr(),
+            queue.device_addr(),
+        );
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    pub fn desc_addr(&self) -> u64 {
+        self.memory.phys_addr()
+    }
+
+    pub fn driver_addr(&self) -> u64 {
+        self.memory.phys_addr() + self.avail_offset as u64
+    }
+
+    pub fn device_addr(&self) -> u64 {
+        self.memory.phys_addr() + self.used_offset as u64
+    }
+
+    /// Chain `buffers` onto free descriptors and publish the chain to the device.
+    ///
+    /// Returns the head descriptor index, which identifies the chain once it is used.
+    pub fn add(&mut self, buffers: &[QueueBuffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut current = head;
+        for (position, buffer) in buffers.iter().enumerate() {
+            let mut descriptor = self.read_descriptor(current);
+            let next_free = descriptor.next;
+
+            descriptor.addr = buffer.phys;
+            descriptor.len = buffer.len;
+            descriptor.flags = if buffer.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if position + 1 < buffers.len() {
+                descriptor.flags |= DESC_F_NEXT;
+            }
+            self.write_descriptor(current, descriptor);
+
+            if position + 1 < buffers.len() {
+                current = next_free;
+            } else {
+                self.free_head = next_free;
+            }
+        }
+        self.num_free -= buffers.len() as u16;
+
+        let slot = self.avail_idx % self.size;
+        self.write_avail_ring(slot, head);
+        // The ring entry must be visible before the device can observe the new index.
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        self.write_avail_idx(self.avail_idx);
+        fence(Ordering::SeqCst);
+
+        Ok(head)
+    }
+
+    /// Tell the device new chains are available.
+    pub fn notify(&self, transport: &mut dyn Transport) {
+        transport.notify(self.index);
+    }
+
+    /// Check whether the device has returned chains not yet collected by [`Self::pop_used`].
+    pub fn has_used(&self) -> bool {
+        self.read_used_idx() != self.last_used_idx
+    }
+
+    /// Collect the next chain the device has finished with, returning its descriptors to the
+    /// free list.
+    pub fn pop_used(&mut self) -> Option<UsedElement> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = self.last_used_idx % self.size;
+        let (id, len) = self.read_used_ring(slot);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        let head = id as u16;
+        self.recycle_chain(head);
+
+        Some(UsedElement { head, len })
+    }
+
+    fn recycle_chain(&mut self, head: u16) {
+        let mut current = head;
+        loop {
+            let mut descriptor = self.read_descriptor(current);
+            let has_next = descriptor.flags & DESC_F_NEXT != 0;
+            let next = descriptor.next;
+            self.num_free += 1;
+
+            if !has_next {
+                descriptor.next = self.free_head;
+                descriptor.flags = 0;
+                self.write_descriptor(current, descriptor);
+                break;
+            }
+
+            descriptor.flags = 0;
+            self.write_descriptor(current, descriptor);
+            current = next;
+        }
+        self.free_head = head;
+    }
+
+    fn descriptor_ptr(&self, index: u16) -> *mut Descriptor {
+        unsafe { (self.memory.as_ptr() as *mut Descriptor).add(index as usize) }
+    }
+
+    fn read_descriptor(&self, index: u16) -> Descriptor {
+        unsafe { core::ptr::read_volatile(self.descriptor_ptr(index)) }
+    }
+
+    fn write_descriptor(&mut self, index: u16, descriptor: Descriptor) {
+        unsafe { core::ptr::write_volatile(self.descriptor_ptr(index), descriptor) }
+    }
+
+    fn avail_ptr(&self) -> *mut u16 {
+        unsafe { self.memory.as_ptr().add(self.avail_offset) as *mut u16 }
+    }
+
+    fn write_avail_idx(&mut self, idx: u16) {
+        unsafe { core::ptr::write_volatile(self.avail_ptr().add(1), idx) }
+    }
+
+    fn write_avail_ring(&mut self, slot: u16, head: u16) {
+        unsafe { core::ptr::write_volatile(self.avail_ptr().add(2 + slot as usize), head) }
+    }
+
+    fn used_ptr(&self) -> *mut u8 {
+        unsafe { self.memory.as_ptr().add(self.used_offset) }
+    }
+
+    fn read_used_idx(&self) -> u16 {
+        unsafe { core::ptr::read_volatile((self.used_ptr() as *const u16).add(1)) }
+    }
+
+    fn read_used_ring(&self, slot: u16) -> (u32, u32) {
+        unsafe {
+            let element = self.used_ptr().add(4 + 8 * slot as usize) as *const u32;
+            (
+                core::ptr::read_volatile(element),
+                core::ptr::read_volatile(element.add(1)),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{QueueBuffer, UsedElement, VirtQueue};
+
+    /// Play the device side: consume the next available chain and report it as used.
+    fn complete_next(queue: &mut VirtQueue, device_avail_idx: &mut u16, written: u32) {
+        unsafe {
+            let avail = queue.avail_ptr();
+            let head =
+                core::ptr::read_volatile(avail.add(2 + (*device_avail_idx % queue.size) as usize));
+            *device_avail_idx = device_avail_idx.wrapping_add(1);
+
+            let used = queue.used_ptr();
+            let used_idx = core::ptr::read_volatile((used as *const u16).add(1));
+            let element = used.add(4 + 8 * (used_idx % queue.size) as usize) as *mut u32;
+            core::ptr::write_volatile(element, head as u32);
+            core::ptr::write_volatile(element.add(1), written);
+            core::ptr::write_volatile((used as *mut u16).add(1), used_idx.wrapping_add(1));
+        }
+    }
+
+    #[kunit]
+    fn rejects_sizes_that_are_not_powers_of_two() {
+        assert!(VirtQueue::new(0, 0).is_err());
+        assert!(VirtQueue::new(0, 12).is_err());
+    }
+
+    #[kunit]
+    fn rings_are_laid_out_after_the_descriptor_table() {
+        let queue = VirtQueue::new(0, 8).expect("queue should allocate");
+
+        assert_eq!(queue.driver_addr() - queue.desc_addr(), 8 * 16);
+        assert_eq!(queue.device_addr() % 4, 0);
+        assert!(queue.device_addr() >= queue.driver_addr() + 2 * (3 + 8));
+    }
+
+    #[kunit]
+    fn chains_consume_and_return_descriptors() {
+        let mut queue = VirtQueue::new(0, 8).expect("queue should allocate");
+        let mut device_avail_idx = 0;
+
+        let head = queue
+            .add(&[
+                QueueBuffer::readable(0x1000, 16),
+                QueueBuffer::writable(0x2000, 64),
+            ])
+            .expect("chain should fit");
+        assert_eq!(queue.num_free(), 6);
+        assert!(!queue.has_used());
+
+        complete_next(&mut queue, &mut device_avail_idx, 64);
+        assert_eq!(queue.pop_used(), Some(UsedElement { head, len: 64 }));
+        assert_eq!(queue.num_free(), 8);
+        assert_eq!(queue.pop_used(), None);
+    }
+
+    #[kunit]
+    fn full_queue_rejects_new_chains() {
+        let mut queue = VirtQueue::new(0, 2).expect("queue should allocate");
+
+        queue
+            .add(&[
+                QueueBuffer::readable(0x1000, 1),
+                QueueBuffer::readable(0x2000, 1),
+            ])
+            .expect("chain should fit");
+        assert!(queue.add(&[QueueBuffer::readable(0x3000, 1)]).is_err());
+    }
+
+    #[kunit]
+    fn indices_wrap_around_the_ring() {
+        let mut queue = VirtQueue::new(0, 4).expect("queue should allocate");
+        let mut device_avail_idx = 0;
+
+        for round in 0..10u32 {
+            let head = queue
+                .add(&[QueueBuffer::writable(0x1000, 32)])
+                .expect("descriptor should be free");
+            complete_next(&mut queue, &mut device_avail_idx, round);
+            assert_eq!(queue.pop_used(), Some(UsedElement { head, len: round }));
+        }
+        assert_eq!(queue.num_free(), 4);
+    }
+}
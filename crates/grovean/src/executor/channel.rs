@@ -0,0 +1,233 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use crate::sync::IrqSpinLock;
+
+/// The receiver is gone; the value that could not be sent comes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel holds as many values as it can.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and no values are left.
+    Closed,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    /// The receiver, while it waits for a value.
+    receiver: Option<Waker>,
+    /// Senders waiting for room.
+    blocked: VecDeque<Waker>,
+}
+
+/// Locked with interrupts disabled, so interrupt handlers can `try_send`.
+type Shared<T> = Arc<IrqSpinLock<State<T>>>;
+
+/// Sends values into a channel. Clone it for more senders.
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+/// Takes the values out of a channel in the order they were sent.
+pub struct Receiver<T> {
+    shared: Shared<T>,
+}
+
+/// A channel holding at most `capacity` values, at least one, between any number of senders
+/// and one receiver.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let capacity = capacity.max(1);
+    let shared = Arc::new(IrqSpinLock::new(State {
+        queue: VecDeque::with_capacity(capacity),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        receiver: None,
+        blocked: VecDeque::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Send `value` if there is room. Never blocks, so interrupt handlers may call it.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let receiver = {
+            let mut state = self.shared.lock();
+            if !state.receiver_alive {
+                return Err(TrySendError::Closed(value));
+            }
+            if state.queue.len() >= state.capacity {
+                return Err(TrySendError::Full(value));
+            }
+            state.queue.push_back(value);
+            state.receiver.take()
+        };
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+        Ok(())
+    }
+
+    /// Send `value`, waiting for room.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let Some(item) = value.take() else {
+                return Poll::Ready(Ok(()));
+            };
+            match self.try_send(item) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(item)) => Poll::Ready(Err(SendError(item))),
+                Err(TrySendError::Full(item)) => {
+                    value = Some(item);
+                    self.shared.lock().blocked.push_back(cx.waker().clone());
+                    // Room may have appeared before the waker was queued.
+                    if self.has_room() {
+                        cx.waker().wake_by_ref();
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn has_room(&self) -> bool {
+        let state = self.shared.lock();
+        !state.receiver_alive || state.queue.len() < state.capacity
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let receiver = {
+            let mut state = self.shared.lock();
+            state.senders -= 1;
+            if state.senders > 0 {
+                return;
+            }
+            state.receiver.take()
+        };
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Take the next value if one is waiting.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let (value, sender) = {
+            let mut state = self.shared.lock();
+            match state.queue.pop_front() {
+                Some(value) => (value, state.blocked.pop_front()),
+                None if state.senders == 0 => return Err(TryRecvError::Closed),
+                None => return Err(TryRecvError::Empty),
+            }
+        };
+        if let Some(sender) = sender {
+            sender.wake();
+        }
+        Ok(value)
+    }
+
+    /// Wait for the next value, or `None` once every sender is gone and no values are left.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                let mut state = self.shared.lock();
+                // Checked again locked, since a value or the last sender's drop may have come
+                // in between.
+                if !state.queue.is_empty() || state.senders == 0 {
+                    cx.waker().wake_by_ref();
+                } else {
+                    state.receiver = Some(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let (values, senders) = {
+            let mut state = self.shared.lock();
+            state.receiver_alive = false;
+            let values: Vec<T> = state.queue.drain(..).collect();
+            (values, core::mem::take(&mut state.blocked))
+        };
+        // Dropped unlocked, since values and wakers may run code of their own when dropped.
+        drop(values);
+        for sender in senders {
+            sender.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{channel, SendError, TryRecvError, TrySendError};
+    use crate::executor::block_on;
+
+    #[kunit]
+    fn values_arrive_in_order_and_full_channels_refuse_more() {
+        let (sender, mut receiver) = channel(2);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        sender.try_send(1).unwrap();
+        sender.clone().try_send(2).unwrap();
+        assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+
+        assert_eq!(block_on(receiver.recv()), Some(1));
+        block_on(sender.send(3)).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.recv()), Some(2));
+        assert_eq!(block_on(receiver.recv()), Some(3));
+        assert_eq!(block_on(receiver.recv()), None);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[kunit]
+    fn dropping_the_receiver_closes_the_channel() {
+        let (sender, receiver) = channel(1);
+        sender.try_send(1).unwrap();
+        drop(receiver);
+        assert_eq!(sender.try_send(2), Err(TrySendError::Closed(2)));
+        assert_eq!(block_on(sender.send(3)), Err(SendError(3)));
+    }
+}
@@ -0,0 +1,267 @@
+pub mod channel;
+mod timer;
+mod waker;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+use crate::sync::IrqSpinLock;
+use crate::task::{self, Priority, TaskError, WaitQueue};
+
+pub use channel::channel;
+pub use timer::{sleep, sleep_until, timeout, Elapsed, Sleep};
+pub use waker::AtomicWaker;
+
+/// The executor `spawn` uses, run by the `async` task.
+static EXECUTOR: Executor = Executor::new();
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runs futures on a kernel task of its own, polling each again once its waker is woken.
+/// Wakers may be woken from interrupt handlers and softirqs. Executors are statics; `start`
+/// gives each its task, and futures spawned before that wait for it.
+pub struct Executor {
+    /// Futures woken and not yet polled again.
+    ready: IrqSpinLock<VecDeque<Arc<AsyncTask>>>,
+    /// The executor's task, while nothing is ready.
+    worker: WaitQueue,
+    /// Futures spawned and not yet complete.
+    live: AtomicUsize,
+}
+
+/// A spawned future and what its waker needs to queue it again.
+struct AsyncTask {
+    /// Only polled on the executor's task; `None` once complete.
+    future: Mutex<Option<BoxFuture>>,
+    executor: &'static Executor,
+    /// Set while the task sits in the ready queue, so waking it again does not queue it twice.
+    queued: AtomicBool,
+}
+
+impl Executor {
+    pub const fn new() -> Self {
+        Self {
+            ready: IrqSpinLock::new(VecDeque::new()),
+            worker: WaitQueue::new(),
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    /// Spawn the task polling this executor's futures.
+    pub fn start(&'static self, name: &str, priority: Priority) -> Result<(), TaskError> {
+        task::spawn(name, priority, move || self.run())?;
+        Ok(())
+    }
+
+    /// Run `future` on this executor, returning a handle that completes with its output.
+    pub fn spawn<F>(&'static self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let join = Arc::new(IrqSpinLock::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let result = join.clone();
+        let task = Arc::new(AsyncTask {
+            future: Mutex::new(Some(Box::pin(async move {
+                let output = future.await;
+                let waker = {
+                    let mut join = result.lock();
+                    join.output = Some(output);
+                    join.waker.take()
+                };
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }))),
+            executor: self,
+            queued: AtomicBool::new(false),
+        });
+        self.live.fetch_add(1, Ordering::Relaxed);
+        task.wake_by_ref();
+        JoinHandle { state: join }
+    }
+
+    /// Futures spawned on this executor that have not completed.
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// The worker's loop, polling ready futures with the queue unlocked.
+    fn run(&self) {
+        loop {
+            self.worker.wait_until(|| !self.ready.lock().is_empty());
+            while let Some(task) = self.pop() {
+                task.poll();
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<Arc<AsyncTask>> {
+        self.ready.lock().pop_front()
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncTask {
+    fn poll(self: &Arc<Self>) {
+        // Cleared first, so a wake during the poll queues the task again.
+        self.queued.store(false, Ordering::Release);
+        let mut slot = self.future.lock();
+        let Some(future) = slot.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(self.clone());
+        if future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            *slot = None;
+            self.executor.live.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.executor.ready.lock().push_back(self.clone());
+        self.executor.worker.wake_one();
+    }
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    /// Whoever awaits the handle.
+    waker: Option<Waker>,
+}
+
+/// A future completing with the output of a spawned future. Dropping it lets the future run
+/// on without anyone taking its output.
+pub struct JoinHandle<T> {
+    state: Arc<IrqSpinLock<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().output.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wakes the kernel task blocked in `block_on`.
+struct ThreadWaker {
+    woken: AtomicBool,
+    waiters: WaitQueue,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+/// Run `future` on the spawned executor.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    EXECUTOR.spawn(future)
+}
+
+/// Run `future` to completion on the calling kernel task, blocking it between polls. Lets
+/// thread code wait on async code; must not be called from a future.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let thread = Arc::new(ThreadWaker {
+        woken: AtomicBool::new(false),
+        waiters: WaitQueue::new(),
+    });
+    let waker = Waker::from(thread.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread
+            .waiters
+            .wait_until(|| thread.woken.swap(false, Ordering::Acquire));
+    }
+}
+
+/// Start the `async` task polling spawned futures.
+pub fn init() {
+    EXECUTOR
+        .start("async", Priority::Normal)
+        .expect("failed to allocate the async task stack");
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+    use kunit::kunit;
+
+    use super::{block_on, Executor};
+
+    static EXECUTOR: Executor = Executor::new();
+
+    #[kunit]
+    fn spawned_futures_run_when_polled_and_hand_over_their_output() {
+        let handle = EXECUTOR.spawn(async { 6 * 7 });
+        assert_eq!(EXECUTOR.live(), 1);
+        assert!(!handle.is_finished());
+
+        let task = EXECUTOR.pop().expect("spawning queues the future");
+        assert!(EXECUTOR.pop().is_none());
+        assert!(task.queued.load(Ordering::Relaxed));
+        task.poll();
+        assert_eq!(EXECUTOR.live(), 0);
+        assert_eq!(block_on(handle), 42);
+    }
+
+    #[kunit]
+    fn block_on_returns_what_the_future_does() {
+        assert_eq!(block_on(async { "done" }), "done");
+    }
+}
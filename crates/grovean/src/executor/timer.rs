@@ -0,0 +1,103 @@
+use alloc::sync::Arc;
+use core::future::{poll_fn, Future};
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use super::AtomicWaker;
+use crate::time::{self, timer::Timer};
+
+/// The future `timeout` gave up on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Shared between a `Sleep` and its kernel timer.
+struct Alarm {
+    fired: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// A future completing once a point in time since boot has passed, to the resolution of the
+/// timer tick. Dropping it cancels its timer.
+pub struct Sleep {
+    deadline: Duration,
+    alarm: Arc<Alarm>,
+    timer: Option<Timer>,
+}
+
+/// Complete after `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::uptime() + duration)
+}
+
+/// Complete once `deadline`, counted from boot like `time::uptime`, has passed.
+pub fn sleep_until(deadline: Duration) -> Sleep {
+    Sleep {
+        deadline,
+        alarm: Arc::new(Alarm {
+            fired: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }),
+        timer: None,
+    }
+}
+
+/// Run `future` until it completes or `duration` passes, whichever comes first.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let mut future = pin!(future);
+    let mut expiry = sleep(duration);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut expiry).poll(cx).map(|()| Err(Elapsed))
+    })
+    .await
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let now = time::uptime();
+        if self.alarm.fired.load(Ordering::Acquire) || now >= self.deadline {
+            return Poll::Ready(());
+        }
+        self.alarm.waker.register(cx.waker());
+        if self.timer.is_none() {
+            let alarm = self.alarm.clone();
+            self.timer = Some(time::timer::add(self.deadline - now, move || {
+                alarm.fired.store(true, Ordering::Release);
+                alarm.waker.wake();
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use kunit::kunit;
+
+    use super::{sleep, timeout};
+    use crate::executor::block_on;
+
+    #[kunit]
+    fn past_deadlines_complete_at_once() {
+        block_on(sleep(Duration::ZERO));
+        assert_eq!(
+            block_on(timeout(Duration::from_secs(1), async { 3 })),
+            Ok(3)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use core::task::Waker;
+
+use crate::sync::IrqSpinLock;
+
+/// Holds the waker of the future waiting on some event, so whatever signals the event,
+/// including an interrupt handler, can wake it. Only the most recently registered waker is
+/// kept.
+pub struct AtomicWaker {
+    waker: IrqSpinLock<Option<Waker>>,
+}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            waker: IrqSpinLock::new(None),
+        }
+    }
+
+    /// Wake `waker` on the next `wake`, replacing whatever was registered before.
+    pub fn register(&self, waker: &Waker) {
+        let replaced = {
+            let mut slot = self.waker.lock();
+            if slot.as_ref().is_some_and(|held| held.will_wake(waker)) {
+                return;
+            }
+            slot.replace(waker.clone())
+        };
+        // Dropped unlocked, since dropping a waker may free its task.
+        drop(replaced);
+    }
+
+    /// Wake the registered waker, if any, and forget it.
+    pub fn wake(&self) {
+        let waker = self.waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::Waker;
+    use kunit::kunit;
+
+    use super::AtomicWaker;
+
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[kunit]
+    fn a_registered_waker_is_woken_once() {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let slot = AtomicWaker::new();
+
+        slot.wake();
+        slot.register(&waker);
+        slot.register(&waker);
+        slot.wake();
+        slot.wake();
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+    }
+}
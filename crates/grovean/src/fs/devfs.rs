@@ -0,0 +1,410 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::fs::vfs::{DirEntry, FileType, Filesystem, FsError, Inode, Metadata};
+use crate::time;
+
+const ROOT_INODE: u64 = 1;
+
+/// A driver's file interface, reachable as `/dev/<name>` once registered.
+///
+/// Offsets are only meaningful to devices with a size; streams such as serial ports ignore
+/// them. Reads from a stream with nothing pending return zero bytes rather than waiting.
+pub trait Device: Send + Sync {
+    fn file_type(&self) -> FileType {
+        FileType::CharDevice
+    }
+
+    /// Permission bits.
+    fn mode(&self) -> u32 {
+        0o600
+    }
+
+    /// Size in bytes of seekable devices, zero for streams.
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn read(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn write(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Device-specific control request, with request numbers defined by each driver.
+    fn ioctl(&self, _request: u32, _argument: usize) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+struct Registry {
+    next_inode: u64,
+    devices: BTreeMap<String, Arc<DeviceInode>>,
+}
+
+/// A flat directory of device files. Every mount of one instance shows the same devices.
+pub struct DevFs {
+    root: Arc<RootInode>,
+}
+
+impl DevFs {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(RootInode {
+                created: time::now(),
+                registry: Mutex::new(Registry {
+                    next_inode: ROOT_INODE + 1,
+                    devices: BTreeMap::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Add `device` under `name`, which must be a single path component not yet taken.
+    pub fn register(&self, name: &str, device: Arc<dyn Device>) -> Result<(), FsError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut registry = self.root.registry.lock();
+        if registry.devices.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let inode = registry.next_inode;
+        registry.next_inode += 1;
+        registry.devices.insert(
+            name.to_string(),
+            Arc::new(DeviceInode {
+                inode,
+                registered: time::now(),
+                device,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Remove the device called `name`. Files already open on it keep working.
+    pub fn unregister(&self, name: &str) -> Result<(), FsError> {
+        self.root
+            .registry
+            .lock()
+            .devices
+            .remove(name)
+            .map(|_| ())
+            .ok_or(FsError::NotFound)
+    }
+
+    pub fn device(&self, name: &str) -> Option<Arc<dyn Device>> {
+        self.root
+            .registry
+            .lock()
+            .devices
+            .get(name)
+            .map(|inode| inode.device.clone())
+    }
+}
+
+impl Default for DevFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filesystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct RootInode {
+    created: u64,
+    registry: Mutex<Registry>,
+}
+
+impl Inode for RootInode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: ROOT_INODE,
+            file_type: FileType::Directory,
+            mode: 0o755,
+            size: 0,
+            links: 2,
+            accessed: self.created,
+            modified: self.created,
+            changed: self.created,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match self.registry.lock().devices.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .registry
+            .lock()
+            .devices
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                inode: inode.inode,
+                file_type: inode.device.file_type(),
+            })
+            .collect())
+    }
+
+    fn cache_lookups(&self) -> bool {
+        false
+    }
+
+    fn create(&self, _: &str, _: FileType, _: u32) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn remove(&self, _: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+}
+
+struct DeviceInode {
+    inode: u64,
+    registered: u64,
+    device: Arc<dyn Device>,
+}
+
+impl Inode for DeviceInode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: self.inode,
+            file_type: self.device.file_type(),
+            mode: self.device.mode(),
+            size: self.device.size(),
+            links: 1,
+            accessed: self.registered,
+            modified: self.registered,
+            changed: self.registered,
+        }
+    }
+
+    fn lookup(&self, _: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        self.device.read(offset, buffer)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        self.device.write(offset, buffer)
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn ioctl(&self, request: u32, argument: usize) -> Result<usize, FsError> {
+        self.device.ioctl(request, argument)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.device.sync()
+    }
+}
+
+lazy_static! {
+    /// The instance drivers register into, mounted on `/dev`.
+    static ref DEVFS: Arc<DevFs> = Arc::new(DevFs::new());
+}
+
+pub fn devfs() -> Arc<DevFs> {
+    DEVFS.clone()
+}
+
+/// Make `device` available as `/dev/<name>`.
+pub fn register(name: &str, device: Arc<dyn Device>) -> Result<(), FsError> {
+    DEVFS.register(name, device)
+}
+
+pub fn unregister(name: &str) -> Result<(), FsError> {
+    DEVFS.unregister(name)
+}
+
+pub fn device(name: &str) -> Option<Arc<dyn Device>> {
+    DEVFS.device(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+    use spin::Mutex;
+
+    use super::{DevFs, Device};
+    use crate::fs::tmpfs::Tmpfs;
+    use crate::fs::vfs::{FileType, FsError, OpenFlags, Vfs};
+
+    const IOCTL_LENGTH: u32 = 1;
+
+    /// A device that records writes and reads back what was last written.
+    struct Echo {
+        written: Mutex<Vec<u8>>,
+    }
+
+    impl Device for Echo {
+        fn read(&self, _: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+            let written = self.written.lock();
+            let count = written.len().min(buffer.len());
+            buffer[..count].copy_from_slice(&written[..count]);
+            Ok(count)
+        }
+
+        fn write(&self, _: u64, buffer: &[u8]) -> Result<usize, FsError> {
+            *self.written.lock() = buffer.to_vec();
+            Ok(buffer.len())
+        }
+
+        fn ioctl(&self, request: u32, _: usize) -> Result<usize, FsError> {
+            match request {
+                IOCTL_LENGTH => Ok(self.written.lock().len()),
+                _ => Err(FsError::InvalidArgument),
+            }
+        }
+    }
+
+    struct Disk;
+
+    impl Device for Disk {
+        fn file_type(&self) -> FileType {
+            FileType::BlockDevice
+        }
+
+        fn size(&self) -> u64 {
+            4096
+        }
+    }
+
+    fn mounted(devfs: &Arc<DevFs>) -> Vfs {
+        let vfs = Vfs::new();
+        vfs.mount("/", Arc::new(Tmpfs::new())).expect("root");
+        vfs.create_dir("/dev", 0o755).expect("mkdir");
+        vfs.mount("/dev", devfs.clone()).expect("mount");
+        vfs
+    }
+
+    fn echo() -> Arc<Echo> {
+        Arc::new(Echo {
+            written: Mutex::new(Vec::new()),
+        })
+    }
+
+    #[kunit]
+    fn registered_devices_are_listed_with_their_types() {
+        let devfs = Arc::new(DevFs::new());
+        devfs.register("tty0", echo()).expect("tty0");
+        devfs.register("disk0", Arc::new(Disk)).expect("disk0");
+        let vfs = mounted(&devfs);
+
+        let entries = vfs.read_dir("/dev").expect("read_dir");
+        let listed: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.file_type))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("disk0", FileType::BlockDevice),
+                ("tty0", FileType::CharDevice)
+            ]
+        );
+
+        let metadata = vfs.metadata("/dev/disk0").expect("metadata");
+        assert_eq!(metadata.size, 4096);
+        assert_eq!(metadata.mode, 0o600);
+    }
+
+    #[kunit]
+    fn open_files_reach_the_driver() {
+        let devfs = Arc::new(DevFs::new());
+        devfs.register("echo", echo()).expect("register");
+        let vfs = mounted(&devfs);
+
+        let file = vfs
+            .open("/dev/echo", OpenFlags::READ.union(OpenFlags::WRITE))
+            .expect("open");
+        assert_eq!(file.write(b"ping").expect("write"), 4);
+        assert_eq!(file.ioctl(IOCTL_LENGTH, 0), Ok(4));
+        assert_eq!(file.ioctl(99, 0), Err(FsError::InvalidArgument));
+
+        let mut buffer = [0u8; 8];
+        let reread = vfs.open("/dev/echo", OpenFlags::READ).expect("reopen");
+        assert_eq!(reread.read(&mut buffer).expect("read"), 4);
+        assert_eq!(&buffer[..4], b"ping");
+    }
+
+    #[kunit]
+    fn names_are_unique_and_single_components() {
+        let devfs = DevFs::new();
+        devfs.register("null", echo()).expect("register");
+
+        assert_eq!(devfs.register("null", echo()), Err(FsError::AlreadyExists));
+        assert_eq!(devfs.register("a/b", echo()), Err(FsError::InvalidPath));
+        assert_eq!(devfs.register("..", echo()), Err(FsError::InvalidPath));
+    }
+
+    #[kunit]
+    fn unregistered_devices_disappear_but_open_files_survive() {
+        let devfs = Arc::new(DevFs::new());
+        devfs.register("echo", echo()).expect("register");
+        let vfs = mounted(&devfs);
+        let file = vfs.open("/dev/echo", OpenFlags::WRITE).expect("open");
+
+        devfs.unregister("echo").expect("unregister");
+
+        assert!(devfs.device("echo").is_none());
+        assert_eq!(
+            vfs.open("/dev/echo", OpenFlags::READ).err(),
+            Some(FsError::NotFound)
+        );
+        assert_eq!(file.write(b"x"), Ok(1));
+        assert_eq!(devfs.unregister("echo"), Err(FsError::NotFound));
+    }
+
+    #[kunit]
+    fn the_directory_refuses_new_entries() {
+        let devfs = Arc::new(DevFs::new());
+        let vfs = mounted(&devfs);
+
+        assert_eq!(
+            vfs.create_dir("/dev/sub", 0o755).err(),
+            Some(FsError::NotSupported)
+        );
+        assert_eq!(
+            vfs.open("/dev/new", OpenFlags::WRITE.union(OpenFlags::CREATE))
+                .err(),
+            Some(FsError::NotSupported)
+        );
+    }
+}
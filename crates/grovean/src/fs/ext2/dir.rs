@@ -0,0 +1,234 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::fs::vfs::{FileType, FsError};
+
+const HEADER_SIZE: usize = 8;
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// Inode type codes stored in entries when the volume has the file type feature.
+pub const TYPE_UNKNOWN: u8 = 0;
+pub const TYPE_REGULAR: u8 = 1;
+pub const TYPE_DIRECTORY: u8 = 2;
+pub const TYPE_CHAR_DEVICE: u8 = 3;
+pub const TYPE_BLOCK_DEVICE: u8 = 4;
+pub const TYPE_SYMLINK: u8 = 7;
+
+/// A record of a directory block. Records with inode zero are unused space.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub offset: usize,
+    pub inode: u32,
+    pub length: usize,
+    pub name: String,
+    pub file_type: u8,
+}
+
+impl Record {
+    /// Bytes the record needs for its name, leaving the rest free for another entry.
+    fn used_length(&self) -> usize {
+        if self.inode == 0 {
+            0
+        } else {
+            record_size(self.name.len())
+        }
+    }
+}
+
+pub fn type_code(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Regular => TYPE_REGULAR,
+        FileType::Directory => TYPE_DIRECTORY,
+        FileType::Symlink => TYPE_SYMLINK,
+        FileType::CharDevice => TYPE_CHAR_DEVICE,
+        FileType::BlockDevice => TYPE_BLOCK_DEVICE,
+    }
+}
+
+pub fn file_type(code: u8) -> Option<FileType> {
+    match code {
+        TYPE_REGULAR => Some(FileType::Regular),
+        TYPE_DIRECTORY => Some(FileType::Directory),
+        TYPE_SYMLINK => Some(FileType::Symlink),
+        TYPE_CHAR_DEVICE => Some(FileType::CharDevice),
+        TYPE_BLOCK_DEVICE => Some(FileType::BlockDevice),
+        _ => None,
+    }
+}
+
+/// Smallest record holding a name of `name_length` bytes; records are four-byte aligned.
+pub fn record_size(name_length: usize) -> usize {
+    (HEADER_SIZE + name_length).next_multiple_of(4)
+}
+
+/// Split a directory block into its records, checking that they tile the block exactly.
+pub fn parse_block(block: &[u8]) -> Result<Vec<Record>, FsError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < block.len() {
+        if block.len() - offset < HEADER_SIZE {
+            return Err(FsError::Corrupt);
+        }
+        let header = &block[offset..offset + HEADER_SIZE];
+        let inode = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let name_length = header[6] as usize;
+        if length < HEADER_SIZE
+            || !length.is_multiple_of(4)
+            || offset + length > block.len()
+            || HEADER_SIZE + name_length > length
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let name = &block[offset + HEADER_SIZE..offset + HEADER_SIZE + name_length];
+        records.push(Record {
+            offset,
+            inode,
+            length,
+            name: String::from_utf8_lossy(name).into(),
+            file_type: header[7],
+        });
+        offset += length;
+    }
+    Ok(records)
+}
+
+fn write_record(
+    block: &mut [u8],
+    offset: usize,
+    length: usize,
+    inode: u32,
+    name: &str,
+    file_type: u8,
+) {
+    let record = &mut block[offset..offset + length];
+    record[0..4].copy_from_slice(&inode.to_le_bytes());
+    record[4..6].copy_from_slice(&(length as u16).to_le_bytes());
+    record[6] = name.len() as u8;
+    record[7] = file_type;
+    record[HEADER_SIZE..HEADER_SIZE + name.len()].copy_from_slice(name.as_bytes());
+}
+
+/// Mark a whole block as one unused record.
+pub fn init_block(block: &mut [u8]) {
+    block.fill(0);
+    let length = block.len();
+    write_record(block, 0, length, 0, "", TYPE_UNKNOWN);
+}
+
+/// Add an entry to the block if it has room, splitting the slack off an existing record.
+pub fn insert(block: &mut [u8], name: &str, inode: u32, file_type: u8) -> Result<bool, FsError> {
+    let needed = record_size(name.len());
+    let Some(record) = parse_block(block)?
+        .into_iter()
+        .find(|record| record.length - record.used_length() >= needed)
+    else {
+        return Ok(false);
+    };
+
+    if record.inode == 0 {
+        write_record(block, record.offset, record.length, inode, name, file_type);
+    } else {
+        let used = record.used_length();
+        let (offset, length) = (record.offset, record.length);
+        block[offset + 4..offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+        write_record(block, offset + used, length - used, inode, name, file_type);
+    }
+    Ok(true)
+}
+
+/// Remove the entry called `name`, returning its inode. The space joins the previous record,
+/// or becomes an unused record at the start of the block.
+pub fn remove(block: &mut [u8], name: &str) -> Result<Option<u32>, FsError> {
+    let records = parse_block(block)?;
+    let Some(index) = records
+        .iter()
+        .position(|record| record.inode != 0 && record.name == name)
+    else {
+        return Ok(None);
+    };
+
+    let record = &records[index];
+    match index.checked_sub(1).map(|previous| &records[previous]) {
+        Some(previous) => {
+            let length = (previous.length + record.length) as u16;
+            block[previous.offset + 4..previous.offset + 6].copy_from_slice(&length.to_le_bytes());
+        }
+        None => block[record.offset..record.offset + 4].fill(0),
+    }
+    Ok(Some(record.inode))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{init_block, insert, parse_block, remove, TYPE_DIRECTORY, TYPE_REGULAR};
+    use crate::fs::vfs::FsError;
+
+    fn names(block: &[u8]) -> Vec<(usize, u32, usize)> {
+        parse_block(block)
+            .expect("parse")
+            .into_iter()
+            .map(|record| (record.offset, record.inode, record.length))
+            .collect()
+    }
+
+    #[kunit]
+    fn entries_split_the_slack_of_earlier_records() {
+        let mut block = vec![0u8; 1024];
+        init_block(&mut block);
+        assert!(insert(&mut block, ".", 2, TYPE_DIRECTORY).unwrap());
+        assert!(insert(&mut block, "..", 2, TYPE_DIRECTORY).unwrap());
+        assert!(insert(&mut block, "hello.txt", 12, TYPE_REGULAR).unwrap());
+
+        assert_eq!(names(&block), [(0, 2, 12), (12, 2, 12), (24, 12, 1000)]);
+        let records = parse_block(&block).unwrap();
+        assert_eq!(records[2].name, "hello.txt");
+        assert_eq!(records[2].file_type, TYPE_REGULAR);
+    }
+
+    #[kunit]
+    fn full_blocks_refuse_new_entries() {
+        let mut block = vec![0u8; 1024];
+        init_block(&mut block);
+        let name = "x".repeat(200);
+        for inode in 11..15 {
+            assert!(insert(&mut block, &name, inode, TYPE_REGULAR).unwrap());
+        }
+        assert!(!insert(&mut block, &name, 15, TYPE_REGULAR).unwrap());
+    }
+
+    #[kunit]
+    fn removal_merges_space_into_the_previous_record() {
+        let mut block = vec![0u8; 1024];
+        init_block(&mut block);
+        for (name, inode) in [("a", 11), ("b", 12), ("c", 13)] {
+            insert(&mut block, name, inode, TYPE_REGULAR).unwrap();
+        }
+
+        assert_eq!(remove(&mut block, "b").unwrap(), Some(12));
+        assert_eq!(names(&block), [(0, 11, 24), (24, 13, 1000)]);
+        assert_eq!(remove(&mut block, "a").unwrap(), Some(11));
+        assert_eq!(names(&block), [(0, 0, 24), (24, 13, 1000)]);
+        assert_eq!(remove(&mut block, "a").unwrap(), None);
+
+        // The unused first record is reused when a name fits.
+        insert(&mut block, "d", 14, TYPE_REGULAR).unwrap();
+        assert_eq!(names(&block), [(0, 14, 24), (24, 13, 1000)]);
+    }
+
+    #[kunit]
+    fn records_must_tile_the_block() {
+        let mut block = vec![0u8; 1024];
+        init_block(&mut block);
+        block[4..6].copy_from_slice(&1020u16.to_le_bytes());
+        assert_eq!(parse_block(&block), Err(FsError::Corrupt));
+
+        block[4..6].copy_from_slice(&2048u16.to_le_bytes());
+        assert_eq!(parse_block(&block), Err(FsError::Corrupt));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::fs::vfs::FileType;
+
+/// Bytes of an inode the driver reads and writes; larger inodes keep their extra fields.
+pub const INODE_RECORD_SIZE: usize = 128;
+pub const BLOCK_POINTERS: usize = 15;
+pub const DIRECT_BLOCKS: usize = 12;
+pub const SINGLE_INDIRECT: usize = 12;
+pub const DOUBLE_INDIRECT: usize = 13;
+pub const TRIPLE_INDIRECT: usize = 14;
+/// Symlink targets this short are kept in the block pointers rather than a data block.
+pub const FAST_SYMLINK_SIZE: usize = BLOCK_POINTERS * 4;
+
+const TYPE_MASK: u16 = 0xf000;
+pub const TYPE_FIFO: u16 = 0x1000;
+pub const TYPE_CHAR_DEVICE: u16 = 0x2000;
+pub const TYPE_DIRECTORY: u16 = 0x4000;
+pub const TYPE_BLOCK_DEVICE: u16 = 0x6000;
+pub const TYPE_REGULAR: u16 = 0x8000;
+pub const TYPE_SYMLINK: u16 = 0xa000;
+pub const TYPE_SOCKET: u16 = 0xc000;
+pub const PERMISSION_MASK: u16 = 0o7777;
+
+/// The fields of an on-disk inode the driver uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskInode {
+    /// File type in the top four bits, permissions below.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub accessed: u32,
+    pub changed: u32,
+    pub modified: u32,
+    pub deleted: u32,
+    pub links: u16,
+    /// Space charged to the file, in 512-byte units whatever the block size.
+    pub sectors: u32,
+    pub flags: u32,
+    pub block: [u32; BLOCK_POINTERS],
+}
+
+impl DiskInode {
+    pub fn new(mode: u16, now: u32) -> Self {
+        Self {
+            mode,
+            accessed: now,
+            changed: now,
+            modified: now,
+            ..Self::default()
+        }
+    }
+
+    pub fn parse(raw: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        let mode = u16_at(0);
+        // The directory ACL field holds the upper size word of regular files.
+        let size_high = if mode & TYPE_MASK == TYPE_REGULAR {
+            u32_at(108)
+        } else {
+            0
+        };
+        let mut block = [0u32; BLOCK_POINTERS];
+        for (index, pointer) in block.iter_mut().enumerate() {
+            *pointer = u32_at(40 + index * 4);
+        }
+        Self {
+            mode,
+            uid: u16_at(2) as u32 | (u16_at(120) as u32) << 16,
+            gid: u16_at(24) as u32 | (u16_at(122) as u32) << 16,
+            size: u32_at(4) as u64 | (size_high as u64) << 32,
+            accessed: u32_at(8),
+            changed: u32_at(12),
+            modified: u32_at(16),
+            deleted: u32_at(20),
+            links: u16_at(26),
+            sectors: u32_at(28),
+            flags: u32_at(32),
+            block,
+        }
+    }
+
+    /// Overwrite the known fields of a raw inode, keeping the rest.
+    pub fn write_to(&self, raw: &mut [u8]) {
+        raw[0..2].copy_from_slice(&self.mode.to_le_bytes());
+        raw[2..4].copy_from_slice(&(self.uid as u16).to_le_bytes());
+        raw[4..8].copy_from_slice(&(self.size as u32).to_le_bytes());
+        raw[8..12].copy_from_slice(&self.accessed.to_le_bytes());
+        raw[12..16].copy_from_slice(&self.changed.to_le_bytes());
+        raw[16..20].copy_from_slice(&self.modified.to_le_bytes());
+        raw[20..24].copy_from_slice(&self.deleted.to_le_bytes());
+        raw[24..26].copy_from_slice(&(self.gid as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&self.links.to_le_bytes());
+        raw[28..32].copy_from_slice(&self.sectors.to_le_bytes());
+        raw[32..36].copy_from_slice(&self.flags.to_le_bytes());
+        for (index, pointer) in self.block.iter().enumerate() {
+            raw[40 + index * 4..44 + index * 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        if self.is_regular() {
+            raw[108..112].copy_from_slice(&((self.size >> 32) as u32).to_le_bytes());
+        }
+        raw[120..122].copy_from_slice(&((self.uid >> 16) as u16).to_le_bytes());
+        raw[122..124].copy_from_slice(&((self.gid >> 16) as u16).to_le_bytes());
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode & TYPE_MASK {
+            TYPE_DIRECTORY => FileType::Directory,
+            TYPE_SYMLINK => FileType::Symlink,
+            TYPE_CHAR_DEVICE => FileType::CharDevice,
+            TYPE_BLOCK_DEVICE => FileType::BlockDevice,
+            // FIFOs and sockets have no VFS type of their own and no data either.
+            _ => FileType::Regular,
+        }
+    }
+
+    pub fn is_regular(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_REGULAR
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_DIRECTORY
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_SYMLINK
+    }
+
+    /// Whether the block pointers hold a symlink target instead of block numbers.
+    pub fn is_fast_symlink(&self) -> bool {
+        self.is_symlink() && self.sectors == 0
+    }
+
+    /// Whether the block pointers name data blocks, as opposed to a target or device number.
+    pub fn has_data_blocks(&self) -> bool {
+        (self.is_regular() || self.is_directory() || self.is_symlink()) && !self.is_fast_symlink()
+    }
+
+    pub fn permissions(&self) -> u32 {
+        (self.mode & PERMISSION_MASK) as u32
+    }
+
+    pub fn block_bytes(&self) -> [u8; FAST_SYMLINK_SIZE] {
+        let mut bytes = [0u8; FAST_SYMLINK_SIZE];
+        for (chunk, pointer) in bytes.as_chunks_mut::<4>().0.iter_mut().zip(self.block) {
+            *chunk = pointer.to_le_bytes();
+        }
+        bytes
+    }
+
+    pub fn set_block_bytes(&mut self, data: &[u8]) {
+        let mut bytes = [0u8; FAST_SYMLINK_SIZE];
+        bytes[..data.len()].copy_from_slice(data);
+        for (pointer, chunk) in self.block.iter_mut().zip(bytes.as_chunks::<4>().0) {
+            *pointer = u32::from_le_bytes(*chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{DiskInode, INODE_RECORD_SIZE, TYPE_DIRECTORY, TYPE_REGULAR, TYPE_SYMLINK};
+    use crate::fs::vfs::FileType;
+
+    #[kunit]
+    fn inodes_round_trip_and_keep_unknown_fields() {
+        let mut inode = DiskInode::new(TYPE_REGULAR | 0o640, 1_700_000_000);
+        inode.uid = 0x0001_0002;
+        inode.size = 0x1_2345_6789;
+        inode.links = 1;
+        inode.block[14] = 99;
+
+        let mut raw = [0xaau8; INODE_RECORD_SIZE];
+        inode.write_to(&mut raw);
+        assert_eq!(DiskInode::parse(&raw), inode);
+        // The generation number is not ours to change.
+        assert_eq!(raw[100..104], [0xaa; 4]);
+        assert_eq!(inode.permissions(), 0o640);
+        assert_eq!(inode.file_type(), FileType::Regular);
+    }
+
+    #[kunit]
+    fn only_regular_files_have_a_high_size_word() {
+        let mut raw = [0u8; INODE_RECORD_SIZE];
+        raw[0..2].copy_from_slice(&(TYPE_DIRECTORY | 0o755).to_le_bytes());
+        raw[4..8].copy_from_slice(&1024u32.to_le_bytes());
+        raw[108..112].copy_from_slice(&7u32.to_le_bytes());
+
+        let inode = DiskInode::parse(&raw);
+        assert_eq!(inode.size, 1024);
+        assert!(inode.is_directory());
+    }
+
+    #[kunit]
+    fn fast_symlinks_store_the_target_in_the_pointers() {
+        let mut inode = DiskInode::new(TYPE_SYMLINK | 0o777, 0);
+        inode.set_block_bytes(b"../lib/libc.so");
+
+        assert!(inode.is_fast_symlink());
+        assert!(!inode.has_data_blocks());
+        assert_eq!(&inode.block_bytes()[..14], b"../lib/libc.so");
+        assert_eq!(inode.block[0], u32::from_le_bytes(*b"../l"));
+    }
+}
@@ -0,0 +1,877 @@
+pub mod dir;
+pub mod inode;
+pub mod superblock;
+pub mod volume;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::dev::block::BlockDevice;
+use crate::fs::vfs::{DirEntry, FileType, Filesystem, FsError, Inode, Metadata};
+use crate::time;
+use inode::{DiskInode, FAST_SYMLINK_SIZE, PERMISSION_MASK};
+use volume::Volume;
+
+const ROOT_INODE: u32 = 2;
+
+/// An ext2 filesystem on a block device.
+///
+/// Inodes are read through the block cache on every operation rather than kept in memory.
+/// Reads leave access times alone, and unlinking the last name of a file frees it at once,
+/// which invalidates handles that are still open.
+pub struct Ext2Fs {
+    volume: Arc<Mutex<Volume>>,
+}
+
+impl Ext2Fs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        Ok(Self {
+            volume: Arc::new(Mutex::new(Volume::open(device)?)),
+        })
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        let volume = self.volume.lock();
+        volume.superblock().free_blocks as u64 * volume.block_size() as u64
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        self.volume.lock().superblock().free_inodes
+    }
+
+    /// Whether the device or an unknown feature of the volume prevents writing.
+    pub fn is_read_only(&self) -> bool {
+        self.volume.lock().is_read_only()
+    }
+}
+
+impl Filesystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(Ext2Inode {
+            volume: self.volume.clone(),
+            number: ROOT_INODE,
+        })
+    }
+
+    fn uses_page_cache(&self) -> bool {
+        true
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.volume.lock().sync()
+    }
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.len() > dir::MAX_NAME_LENGTH {
+        return Err(FsError::NameTooLong);
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+fn now() -> u32 {
+    time::now() as u32
+}
+
+struct Ext2Inode {
+    volume: Arc<Mutex<Volume>>,
+    number: u32,
+}
+
+impl Ext2Inode {
+    fn child(&self, number: u32) -> Arc<dyn Inode> {
+        Arc::new(Ext2Inode {
+            volume: self.volume.clone(),
+            number,
+        })
+    }
+
+    fn directory(volume: &Volume, number: u32) -> Result<DiskInode, FsError> {
+        let inode = volume.read_inode(number)?;
+        if !inode.is_directory() {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(inode)
+    }
+
+    /// The live entries of a directory, each with the index of the block holding it.
+    fn records(volume: &Volume, directory: &DiskInode) -> Result<Vec<(u64, dir::Record)>, FsError> {
+        let mut block = vec![0u8; volume.block_size()];
+        let mut records = Vec::new();
+        for index in 0..directory.size.div_ceil(volume.block_size() as u64) {
+            let number = volume
+                .map_block(directory, index)?
+                .ok_or(FsError::Corrupt)?;
+            volume.read_block(number, 0, &mut block)?;
+            records.extend(
+                dir::parse_block(&block)?
+                    .into_iter()
+                    .filter(|record| record.inode != 0)
+                    .map(|record| (index, record)),
+            );
+        }
+        Ok(records)
+    }
+
+    fn find(
+        volume: &Volume,
+        directory: &DiskInode,
+        name: &str,
+    ) -> Result<Option<(u64, dir::Record)>, FsError> {
+        Ok(Self::records(volume, directory)?
+            .into_iter()
+            .find(|(_, record)| record.name == name))
+    }
+
+    /// Link `child` into directory `number`, growing the directory by a block when no block has
+    /// room. Writes the directory inode back.
+    fn add_entry(
+        volume: &mut Volume,
+        number: u32,
+        directory: &mut DiskInode,
+        name: &str,
+        child: u32,
+        file_type: FileType,
+    ) -> Result<(), FsError> {
+        let code = match volume.superblock().has_file_types() {
+            true => dir::type_code(file_type),
+            false => dir::TYPE_UNKNOWN,
+        };
+        let block_size = volume.block_size();
+        let mut block = vec![0u8; block_size];
+        directory.modified = now();
+        directory.changed = directory.modified;
+
+        let blocks = directory.size.div_ceil(block_size as u64);
+        for index in 0..blocks {
+            let physical = volume
+                .map_block(directory, index)?
+                .ok_or(FsError::Corrupt)?;
+            volume.read_block(physical, 0, &mut block)?;
+            if dir::insert(&mut block, name, child, code)? {
+                volume.write_block(physical, 0, &block)?;
+                return volume.write_inode(number, directory);
+            }
+        }
+
+        let result = volume.map_block_allocating(directory, blocks);
+        if let Ok(physical) = result {
+            dir::init_block(&mut block);
+            dir::insert(&mut block, name, child, code)?;
+            volume.write_block(physical, 0, &block)?;
+            directory.size += block_size as u64;
+        }
+        // Blocks allocated before a failure still belong to the directory.
+        volume.write_inode(number, directory)?;
+        result.map(|_| ())
+    }
+
+    /// Free the blocks and the inode of a file that lost its last link.
+    fn release(volume: &mut Volume, number: u32, inode: &mut DiskInode) -> Result<(), FsError> {
+        if inode.has_data_blocks() {
+            volume.free_blocks_from(inode, 0)?;
+        }
+        inode.links = 0;
+        inode.size = 0;
+        inode.deleted = now();
+        volume.write_inode(number, inode)?;
+        volume.free_inode(number, inode.is_directory())
+    }
+
+    /// Allocate an inode in the group of this directory and fill it through `init`, then link
+    /// it under `name`. Everything is undone if a step fails.
+    fn create_with<F>(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u16,
+        init: F,
+    ) -> Result<u32, FsError>
+    where
+        F: FnOnce(&mut Volume, u32, &mut DiskInode) -> Result<(), FsError>,
+    {
+        check_name(name)?;
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        let mut parent = Self::directory(&volume, self.number)?;
+        if Self::find(&volume, &parent, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let is_directory = file_type == FileType::Directory;
+        let number = volume.allocate_inode(self.number, is_directory)?;
+        let mut inode = DiskInode::new(mode, now());
+        inode.links = if is_directory { 2 } else { 1 };
+        let result = init(&mut volume, number, &mut inode)
+            .and_then(|()| volume.write_inode(number, &inode))
+            .and_then(|()| {
+                Self::add_entry(
+                    &mut volume,
+                    self.number,
+                    &mut parent,
+                    name,
+                    number,
+                    file_type,
+                )
+            });
+        if let Err(error) = result {
+            Self::release(&mut volume, number, &mut inode)?;
+            return Err(error);
+        }
+
+        if is_directory {
+            parent.links += 1;
+            volume.write_inode(self.number, &parent)?;
+        }
+        Ok(number)
+    }
+
+    fn regular_file(volume: &Volume, number: u32) -> Result<DiskInode, FsError> {
+        let inode = volume.read_inode(number)?;
+        if inode.is_directory() {
+            return Err(FsError::IsADirectory);
+        }
+        if !inode.is_regular() {
+            return Err(FsError::InvalidArgument);
+        }
+        Ok(inode)
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        let inode = self
+            .volume
+            .lock()
+            .read_inode(self.number)
+            .unwrap_or_default();
+        Metadata {
+            inode: self.number as u64,
+            file_type: inode.file_type(),
+            mode: inode.permissions(),
+            size: inode.size,
+            links: inode.links as u32,
+            accessed: inode.accessed as u64,
+            modified: inode.modified as u64,
+            changed: inode.changed as u64,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let volume = self.volume.lock();
+        let directory = Self::directory(&volume, self.number)?;
+        let (_, record) = Self::find(&volume, &directory, name)?.ok_or(FsError::NotFound)?;
+        Ok(self.child(record.inode))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let volume = self.volume.lock();
+        let directory = Self::directory(&volume, self.number)?;
+        Self::records(&volume, &directory)?
+            .into_iter()
+            .filter(|(_, record)| record.name != "." && record.name != "..")
+            .map(|(_, record)| {
+                let file_type = match dir::file_type(record.file_type) {
+                    Some(file_type) => file_type,
+                    None => volume.read_inode(record.inode)?.file_type(),
+                };
+                Ok(DirEntry {
+                    name: record.name,
+                    inode: record.inode as u64,
+                    file_type,
+                })
+            })
+            .collect()
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+    ) -> Result<Arc<dyn Inode>, FsError> {
+        let permissions = mode as u16 & PERMISSION_MASK;
+        let number = match file_type {
+            FileType::Regular => self.create_with(
+                name,
+                file_type,
+                inode::TYPE_REGULAR | permissions,
+                |_, _, _| Ok(()),
+            )?,
+            FileType::Directory => {
+                let parent = self.number;
+                let mode = inode::TYPE_DIRECTORY | permissions;
+                self.create_with(name, file_type, mode, |volume, number, inode| {
+                    let physical = volume.map_block_allocating(inode, 0)?;
+                    let mut block = vec![0u8; volume.block_size()];
+                    dir::init_block(&mut block);
+                    let code = match volume.superblock().has_file_types() {
+                        true => dir::TYPE_DIRECTORY,
+                        false => dir::TYPE_UNKNOWN,
+                    };
+                    dir::insert(&mut block, ".", number, code)?;
+                    dir::insert(&mut block, "..", parent, code)?;
+                    volume.write_block(physical, 0, &block)?;
+                    inode.size = volume.block_size() as u64;
+                    Ok(())
+                })?
+            }
+            _ => return Err(FsError::NotSupported),
+        };
+        Ok(self.child(number))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if target.is_empty() {
+            return Err(FsError::InvalidArgument);
+        }
+        let mode = inode::TYPE_SYMLINK | 0o777;
+        let number = self.create_with(name, FileType::Symlink, mode, |volume, _, inode| {
+            if target.len() >= volume.block_size() {
+                return Err(FsError::NameTooLong);
+            }
+            inode.size = target.len() as u64;
+            if target.len() < FAST_SYMLINK_SIZE {
+                inode.set_block_bytes(target.as_bytes());
+                Ok(())
+            } else {
+                let physical = volume.map_block_allocating(inode, 0)?;
+                volume.write_block(physical, 0, target.as_bytes())
+            }
+        })?;
+        Ok(self.child(number))
+    }
+
+    fn remove(&self, name: &str) -> Result<(), FsError> {
+        check_name(name)?;
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        let mut parent = Self::directory(&volume, self.number)?;
+        let (index, record) = Self::find(&volume, &parent, name)?.ok_or(FsError::NotFound)?;
+
+        let mut child = volume.read_inode(record.inode)?;
+        if child.is_directory()
+            && Self::records(&volume, &child)?
+                .iter()
+                .any(|(_, record)| record.name != "." && record.name != "..")
+        {
+            return Err(FsError::NotEmpty);
+        }
+
+        let physical = volume.map_block(&parent, index)?.ok_or(FsError::Corrupt)?;
+        let mut block = vec![0u8; volume.block_size()];
+        volume.read_block(physical, 0, &mut block)?;
+        dir::remove(&mut block, name)?;
+        volume.write_block(physical, 0, &block)?;
+
+        let now = now();
+        parent.modified = now;
+        parent.changed = now;
+        child.changed = now;
+        if child.is_directory() {
+            // The child's `..` entry no longer links the parent.
+            parent.links = parent.links.saturating_sub(1);
+            child.links = 0;
+        } else {
+            child.links = child.links.saturating_sub(1);
+        }
+        volume.write_inode(self.number, &parent)?;
+
+        if child.links == 0 {
+            Self::release(&mut volume, record.inode, &mut child)
+        } else {
+            volume.write_inode(record.inode, &child)
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let volume = self.volume.lock();
+        let inode = Self::regular_file(&volume, self.number)?;
+        let block_size = volume.block_size();
+
+        let length = buffer.len().min(inode.size.saturating_sub(offset) as usize);
+        let mut done = 0;
+        while done < length {
+            let position = offset + done as u64;
+            let start = (position % block_size as u64) as usize;
+            let chunk = (block_size - start).min(length - done);
+            let target = &mut buffer[done..done + chunk];
+            match volume.map_block(&inode, position / block_size as u64)? {
+                Some(physical) => volume.read_block(physical, start, target)?,
+                None => target.fill(0),
+            }
+            done += chunk;
+        }
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        let mut inode = Self::regular_file(&volume, self.number)?;
+        if offset.checked_add(buffer.len() as u64).is_none() {
+            return Err(FsError::InvalidArgument);
+        }
+        let max_size = volume.max_file_size();
+        if offset >= max_size && !buffer.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+
+        let block_size = volume.block_size();
+        let length = buffer.len().min((max_size - offset.min(max_size)) as usize);
+        let mut done = 0;
+        let mut failure = None;
+        while done < length {
+            let position = offset + done as u64;
+            let start = (position % block_size as u64) as usize;
+            let chunk = (block_size - start).min(length - done);
+            let physical =
+                match volume.map_block_allocating(&mut inode, position / block_size as u64) {
+                    Ok(physical) => physical,
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    }
+                };
+            volume.write_block(physical, start, &buffer[done..done + chunk])?;
+            done += chunk;
+        }
+
+        if done > 0 {
+            inode.size = inode.size.max(offset + done as u64);
+            inode.modified = now();
+            inode.changed = inode.modified;
+        }
+        // Write the inode even after a failure, as it may own newly allocated blocks.
+        volume.write_inode(self.number, &inode)?;
+        match failure {
+            // Report a short write if part of the data made it.
+            Some(error) if done == 0 => Err(error),
+            _ => Ok(done),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let mut volume = self.volume.lock();
+        volume.check_writable()?;
+        let mut inode = Self::regular_file(&volume, self.number)?;
+        if size > volume.max_file_size() {
+            return Err(FsError::NoSpace);
+        }
+
+        if size < inode.size {
+            let block_size = volume.block_size() as u64;
+            // Zero the tail of a partial last block so growing the file again reads zeros.
+            let tail = (size % block_size) as usize;
+            if tail != 0
+                && let Some(physical) = volume.map_block(&inode, size / block_size)?
+            {
+                volume.write_block(physical, tail, &vec![0u8; block_size as usize - tail])?;
+            }
+            volume.free_blocks_from(&mut inode, size.div_ceil(block_size))?;
+        }
+        inode.size = size;
+        inode.modified = now();
+        inode.changed = inode.modified;
+        volume.write_inode(self.number, &inode)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        let volume = self.volume.lock();
+        let inode = volume.read_inode(self.number)?;
+        if !inode.is_symlink() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let length = inode.size as usize;
+        let target = if inode.is_fast_symlink() {
+            inode
+                .block_bytes()
+                .get(..length)
+                .ok_or(FsError::Corrupt)?
+                .to_vec()
+        } else {
+            if length >= volume.block_size() {
+                return Err(FsError::Corrupt);
+            }
+            let mut target = vec![0u8; length];
+            let physical = volume.map_block(&inode, 0)?.ok_or(FsError::Corrupt)?;
+            volume.read_block(physical, 0, &mut target)?;
+            target
+        };
+        String::from_utf8(target).map_err(|_| FsError::Corrupt)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.volume.lock().sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::superblock::{INCOMPAT_FILETYPE, SUPERBLOCK_OFFSET};
+    use super::Ext2Fs;
+    use crate::dev::block::ramdisk::RamDisk;
+    use crate::fs::vfs::{FileType, FsError, OpenFlags, SeekFrom, Vfs};
+
+    const BLOCK_SIZE: usize = 1024;
+    const BLOCKS: u32 = 1536;
+    const BLOCKS_PER_GROUP: u32 = 512;
+    const INODES_PER_GROUP: u32 = 64;
+    const INODE_SIZE: usize = 128;
+    const FIRST_INODE: u32 = 11;
+    const ROOT_TIME: u32 = 1_700_000_000;
+
+    fn block(image: &mut [u8], number: u32) -> &mut [u8] {
+        &mut image[number as usize * BLOCK_SIZE..][..BLOCK_SIZE]
+    }
+
+    /// Format a volume the way mke2fs does with 1 KiB blocks and three groups, each starting
+    /// with a superblock copy, the descriptor table, both bitmaps and the inode table.
+    fn format(incompatible: u32, read_only_compatible: u32) -> Arc<RamDisk> {
+        let disk = Arc::new(RamDisk::new("ram0", 512, BLOCKS as u64 * 2));
+        let groups = (BLOCKS - 1).div_ceil(BLOCKS_PER_GROUP);
+        let table_blocks = INODES_PER_GROUP * INODE_SIZE as u32 / BLOCK_SIZE as u32;
+        let metadata_blocks = 4 + table_blocks;
+        let root_block = 1 + metadata_blocks;
+
+        disk.with_contents(|image| {
+            let mut free_blocks = 0;
+            let mut descriptors = Vec::new();
+            for group in 0..groups {
+                let start = 1 + group * BLOCKS_PER_GROUP;
+                let size = (BLOCKS - start).min(BLOCKS_PER_GROUP);
+                let used = metadata_blocks + if group == 0 { 1 } else { 0 };
+                let bitmap = block(image, start + 2);
+                for bit in (0..used).chain(size..8 * BLOCK_SIZE as u32) {
+                    bitmap[bit as usize / 8] |= 1 << (bit % 8);
+                }
+                let reserved = if group == 0 { FIRST_INODE - 1 } else { 0 };
+                let bitmap = block(image, start + 3);
+                for bit in (0..reserved).chain(INODES_PER_GROUP..8 * BLOCK_SIZE as u32) {
+                    bitmap[bit as usize / 8] |= 1 << (bit % 8);
+                }
+
+                free_blocks += size - used;
+                let mut descriptor = [0u8; 32];
+                descriptor[0..4].copy_from_slice(&(start + 2).to_le_bytes());
+                descriptor[4..8].copy_from_slice(&(start + 3).to_le_bytes());
+                descriptor[8..12].copy_from_slice(&(start + 4).to_le_bytes());
+                descriptor[12..14].copy_from_slice(&((size - used) as u16).to_le_bytes());
+                descriptor[14..16]
+                    .copy_from_slice(&((INODES_PER_GROUP - reserved) as u16).to_le_bytes());
+                descriptor[16..18].copy_from_slice(&u16::from(group == 0).to_le_bytes());
+                descriptors.extend_from_slice(&descriptor);
+            }
+
+            let mut superblock = [0u8; 1024];
+            let inodes = groups * INODES_PER_GROUP;
+            for (offset, value) in [
+                (0, inodes),
+                (4, BLOCKS),
+                (12, free_blocks),
+                (16, inodes - (FIRST_INODE - 1)),
+                (20, 1),
+                (32, BLOCKS_PER_GROUP),
+                (36, BLOCKS_PER_GROUP),
+                (40, INODES_PER_GROUP),
+                (76, 1),
+                (84, FIRST_INODE),
+                (96, incompatible),
+                (100, read_only_compatible),
+            ] {
+                superblock[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            superblock[56..58].copy_from_slice(&0xef53u16.to_le_bytes());
+            superblock[58..60].copy_from_slice(&1u16.to_le_bytes());
+            superblock[88..90].copy_from_slice(&(INODE_SIZE as u16).to_le_bytes());
+            for group in 0..groups {
+                let start = 1 + group * BLOCKS_PER_GROUP;
+                block(image, start)[..1024].copy_from_slice(&superblock);
+                block(image, start + 1)[..descriptors.len()].copy_from_slice(&descriptors);
+            }
+
+            let mut root = [0u8; INODE_SIZE];
+            root[0..2].copy_from_slice(&0o40755u16.to_le_bytes());
+            root[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+            for offset in [8, 12, 16] {
+                root[offset..offset + 4].copy_from_slice(&ROOT_TIME.to_le_bytes());
+            }
+            root[26..28].copy_from_slice(&2u16.to_le_bytes());
+            root[28..32].copy_from_slice(&2u32.to_le_bytes());
+            root[40..44].copy_from_slice(&root_block.to_le_bytes());
+            block(image, 5)[INODE_SIZE..2 * INODE_SIZE].copy_from_slice(&root);
+
+            let directory = block(image, root_block);
+            directory[0..12].copy_from_slice(&[2, 0, 0, 0, 12, 0, 1, 2, b'.', 0, 0, 0]);
+            directory[12..20].copy_from_slice(&[2, 0, 0, 0, 0xf4, 0x03, 2, 2]);
+            directory[20..22].copy_from_slice(b"..");
+        });
+        disk
+    }
+
+    fn blank() -> Arc<RamDisk> {
+        format(INCOMPAT_FILETYPE, 0)
+    }
+
+    fn mount(disk: &Arc<RamDisk>) -> (Arc<Ext2Fs>, Vfs) {
+        let ext2 = Arc::new(Ext2Fs::new(disk.clone()).expect("mount"));
+        let vfs = Vfs::new();
+        vfs.mount("/", ext2.clone()).expect("vfs mount");
+        (ext2, vfs)
+    }
+
+    fn write_file(vfs: &Vfs, path: &str, data: &[u8]) {
+        let file = vfs
+            .open(path, OpenFlags::WRITE.union(OpenFlags::CREATE))
+            .expect("create");
+        assert_eq!(file.write(data).expect("write"), data.len());
+    }
+
+    fn read_file(vfs: &Vfs, path: &str) -> Result<Vec<u8>, FsError> {
+        let file = vfs.open(path, OpenFlags::READ)?;
+        let mut data = vec![0u8; file.metadata().size as usize];
+        let length = file.read(&mut data)?;
+        data.truncate(length);
+        Ok(data)
+    }
+
+    fn pattern(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 13 / 7) as u8).collect()
+    }
+
+    #[kunit]
+    fn root_metadata_comes_from_the_inode() {
+        let (_, vfs) = mount(&blank());
+        let metadata = vfs.metadata("/").expect("metadata");
+
+        assert_eq!(metadata.inode, 2);
+        assert_eq!(metadata.file_type, FileType::Directory);
+        assert_eq!(metadata.mode, 0o755);
+        assert_eq!(metadata.links, 2);
+        assert_eq!(metadata.modified, ROOT_TIME as u64);
+        assert_eq!(metadata.accessed, ROOT_TIME as u64);
+        assert!(vfs.read_dir("/").expect("list").is_empty());
+    }
+
+    #[kunit]
+    fn files_directories_and_links_survive_a_remount() {
+        let disk = blank();
+        let long_target = format!("/{}", "deep/".repeat(20));
+        {
+            let (_, vfs) = mount(&disk);
+            vfs.create_dir("/home", 0o750).expect("mkdir");
+            let file = vfs
+                .open("/home/notes.txt", OpenFlags::WRITE.union(OpenFlags::CREATE))
+                .expect("create");
+            file.write(b"remember the milk").expect("write");
+            vfs.symlink("home/notes.txt", "/short")
+                .expect("fast symlink");
+            vfs.symlink(&long_target, "/long").expect("slow symlink");
+            vfs.sync().expect("sync");
+        }
+
+        let (_, vfs) = mount(&disk);
+        assert_eq!(
+            read_file(&vfs, "/home/notes.txt").expect("read"),
+            b"remember the milk"
+        );
+        assert_eq!(
+            read_file(&vfs, "/short").expect("follow"),
+            b"remember the milk"
+        );
+        let long = vfs
+            .resolve_at(&vfs.root().expect("root"), "/long", false)
+            .expect("resolve link");
+        assert_eq!(
+            long.dentry().inode().read_link().expect("read link"),
+            long_target
+        );
+
+        let home = vfs.metadata("/home").expect("metadata");
+        assert_eq!(
+            (home.file_type, home.mode, home.links),
+            (FileType::Directory, 0o750, 2)
+        );
+        assert_eq!(vfs.metadata("/").expect("root").links, 3);
+        let names: Vec<_> = vfs
+            .read_dir("/")
+            .expect("list")
+            .into_iter()
+            .map(|entry| (entry.name, entry.file_type))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("home".into(), FileType::Directory),
+                ("short".into(), FileType::Symlink),
+                ("long".into(), FileType::Symlink)
+            ]
+        );
+    }
+
+    #[kunit]
+    fn large_files_use_indirect_blocks_across_groups() {
+        let disk = blank();
+        let (ext2, vfs) = mount(&disk);
+        let free = ext2.free_bytes();
+        // 300 blocks reach the double indirect range; two files do not fit in one group.
+        let data = pattern(300 * BLOCK_SIZE + 100);
+        write_file(&vfs, "/first", &data);
+        write_file(&vfs, "/second", &data);
+        vfs.sync().expect("sync");
+
+        let (ext2, vfs) = mount(&disk);
+        assert_eq!(read_file(&vfs, "/first").expect("read"), data);
+        assert_eq!(read_file(&vfs, "/second").expect("read"), data);
+        vfs.remove("/first").expect("remove");
+        vfs.remove("/second").expect("remove");
+        assert_eq!(ext2.free_bytes(), free);
+    }
+
+    #[kunit]
+    fn truncation_and_sparse_writes_read_zeros() {
+        let (ext2, vfs) = mount(&blank());
+        let free = ext2.free_bytes();
+        write_file(&vfs, "/file", &[0xaa; 3 * BLOCK_SIZE]);
+
+        let file = vfs.open("/file", OpenFlags::READ_WRITE).expect("open");
+        file.truncate(10).expect("shrink");
+        file.sync().expect("sync");
+        assert_eq!(ext2.free_bytes(), free - BLOCK_SIZE as u64);
+        file.truncate(20).expect("grow");
+        file.seek(SeekFrom::Start(20 * BLOCK_SIZE as u64))
+            .expect("seek");
+        file.write(b"end").expect("write past the end");
+        file.sync().expect("sync");
+        assert_eq!(ext2.free_bytes(), free - 3 * BLOCK_SIZE as u64);
+
+        let data = read_file(&vfs, "/file").expect("read");
+        assert_eq!(data.len(), 20 * BLOCK_SIZE + 3);
+        assert!(data[..10].iter().all(|&byte| byte == 0xaa));
+        assert!(data[10..20 * BLOCK_SIZE].iter().all(|&byte| byte == 0));
+        assert_eq!(&data[20 * BLOCK_SIZE..], b"end");
+
+        file.truncate(0).expect("empty");
+        assert_eq!(ext2.free_bytes(), free);
+    }
+
+    #[kunit]
+    fn unlink_frees_inodes_and_checks_emptiness() {
+        let (ext2, vfs) = mount(&blank());
+        let (free_bytes, free_inodes) = (ext2.free_bytes(), ext2.free_inodes());
+        vfs.create_dir("/dir", 0o755).expect("mkdir");
+        write_file(&vfs, "/dir/file", &pattern(5000));
+        assert_eq!(ext2.free_inodes(), free_inodes - 2);
+
+        assert_eq!(vfs.remove("/dir").err(), Some(FsError::NotEmpty));
+        vfs.remove("/dir/file").expect("remove file");
+        vfs.remove("/dir").expect("remove directory");
+        assert_eq!(ext2.free_inodes(), free_inodes);
+        assert_eq!(ext2.free_bytes(), free_bytes);
+        assert_eq!(vfs.metadata("/").expect("root").links, 2);
+        assert_eq!(vfs.resolve("/dir").err(), Some(FsError::NotFound));
+    }
+
+    #[kunit]
+    fn directories_grow_and_reuse_freed_space() {
+        let (_, vfs) = mount(&blank());
+        vfs.create_dir("/many", 0o755).expect("mkdir");
+        for index in 0..60 {
+            write_file(
+                &vfs,
+                &format!("/many/a rather long file name {index:03}"),
+                b"x",
+            );
+        }
+        assert_eq!(
+            vfs.metadata("/many").expect("metadata").size,
+            3 * BLOCK_SIZE as u64
+        );
+        assert_eq!(vfs.read_dir("/many").expect("list").len(), 60);
+
+        for index in 0..10 {
+            vfs.remove(&format!("/many/a rather long file name {index:03}"))
+                .expect("remove");
+        }
+        for index in 0..10 {
+            write_file(
+                &vfs,
+                &format!("/many/another long file name {index:03}"),
+                b"y",
+            );
+        }
+        assert_eq!(
+            vfs.metadata("/many").expect("metadata").size,
+            3 * BLOCK_SIZE as u64
+        );
+        assert_eq!(
+            read_file(&vfs, "/many/another long file name 009").expect("read"),
+            b"y"
+        );
+    }
+
+    #[kunit]
+    fn names_are_case_sensitive_and_validated() {
+        let (_, vfs) = mount(&blank());
+        write_file(&vfs, "/Makefile", b"all:");
+
+        assert_eq!(vfs.resolve("/makefile").err(), Some(FsError::NotFound));
+        write_file(&vfs, "/makefile", b"");
+        assert_eq!(
+            vfs.create_dir("/Makefile", 0o755).err(),
+            Some(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            vfs.create_dir(&format!("/{}", "n".repeat(256)), 0o755)
+                .err(),
+            Some(FsError::NameTooLong)
+        );
+    }
+
+    #[kunit]
+    fn unknown_features_refuse_or_restrict_the_mount() {
+        assert_eq!(
+            Ext2Fs::new(format(INCOMPAT_FILETYPE | 0x40, 0)).err(),
+            Some(FsError::NotSupported)
+        );
+
+        let (ext2, vfs) = mount(&format(INCOMPAT_FILETYPE, 0x8));
+        assert!(ext2.is_read_only());
+        assert_eq!(vfs.create_dir("/dir", 0o755).err(), Some(FsError::ReadOnly));
+
+        let disk = blank();
+        disk.with_contents(|image| image[SUPERBLOCK_OFFSET as usize + 56] = 0);
+        assert_eq!(Ext2Fs::new(disk).err(), Some(FsError::Corrupt));
+    }
+
+    #[kunit]
+    fn works_without_file_types_in_entries() {
+        let (_, vfs) = mount(&format(0, 0));
+        vfs.create_dir("/dir", 0o755).expect("mkdir");
+        write_file(&vfs, "/dir/file", b"data");
+
+        let entries = vfs.read_dir("/dir").expect("list");
+        assert_eq!(entries[0].file_type, FileType::Regular);
+        assert_eq!(
+            vfs.read_dir("/").expect("list")[0].file_type,
+            FileType::Directory
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::fs::vfs::FsError;
+
+/// The superblock always starts 1024 bytes into the volume, whatever the block size.
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+const MAGIC: u16 = 0xef53;
+const MIN_BLOCK_SIZE: usize = 1024;
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+/// Revision 0 volumes have fixed inode sizes and reserved inodes.
+const GOOD_OLD_REVISION: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+
+/// Directory entries record the type of the inode they point to.
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+
+/// Superblock and descriptor backups live only in groups 0, 1 and powers of 3, 5 and 7.
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Regular files may be 2 GiB or larger, using the upper size word of the inode.
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+
+/// The fields of the superblock the driver uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub free_blocks: u32,
+    pub free_inodes: u32,
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    /// First inode number available to files; those below are reserved.
+    pub first_inode: u32,
+    pub inode_size: usize,
+    pub incompatible: u32,
+    pub read_only_compatible: u32,
+}
+
+impl Superblock {
+    pub fn parse(raw: &[u8]) -> Result<Self, FsError> {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        if u16_at(56) != MAGIC {
+            return Err(FsError::Corrupt);
+        }
+        let log_block_size = u32_at(24);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            return Err(FsError::Corrupt);
+        }
+        let block_size = MIN_BLOCK_SIZE << log_block_size;
+
+        let revision = u32_at(76);
+        let (first_inode, inode_size, incompatible, read_only_compatible) =
+            if revision == GOOD_OLD_REVISION {
+                (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE, 0, 0)
+            } else {
+                (u32_at(84), u16_at(88) as usize, u32_at(96), u32_at(100))
+            };
+        if incompatible & !INCOMPAT_SUPPORTED != 0 {
+            return Err(FsError::NotSupported);
+        }
+
+        let superblock = Self {
+            inodes_count: u32_at(0),
+            blocks_count: u32_at(4),
+            free_blocks: u32_at(12),
+            free_inodes: u32_at(16),
+            first_data_block: u32_at(20),
+            block_size,
+            blocks_per_group: u32_at(32),
+            inodes_per_group: u32_at(40),
+            first_inode,
+            inode_size,
+            incompatible,
+            read_only_compatible,
+        };
+
+        let bits_per_block = 8 * block_size as u32;
+        let valid = superblock.blocks_per_group != 0
+            && superblock.blocks_per_group <= bits_per_block
+            && superblock.inodes_per_group != 0
+            && superblock.inodes_per_group <= bits_per_block
+            && superblock.first_data_block < superblock.blocks_count
+            && inode_size.is_power_of_two()
+            && (GOOD_OLD_INODE_SIZE..=block_size).contains(&inode_size)
+            && superblock.inodes_count <= superblock.group_count() * superblock.inodes_per_group;
+        if !valid {
+            return Err(FsError::Corrupt);
+        }
+        Ok(superblock)
+    }
+
+    /// Store the free counts and the write time into a raw superblock.
+    pub fn write_counts(&self, raw: &mut [u8], now: u32) {
+        raw[12..16].copy_from_slice(&self.free_blocks.to_le_bytes());
+        raw[16..20].copy_from_slice(&self.free_inodes.to_le_bytes());
+        raw[48..52].copy_from_slice(&now.to_le_bytes());
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    /// Whether every feature that constrains writers is understood.
+    pub fn is_writable(&self) -> bool {
+        self.read_only_compatible & !RO_COMPAT_SUPPORTED == 0
+    }
+
+    pub fn has_file_types(&self) -> bool {
+        self.incompatible & INCOMPAT_FILETYPE != 0
+    }
+
+    pub fn has_large_files(&self) -> bool {
+        self.read_only_compatible & RO_COMPAT_LARGE_FILE != 0
+    }
+
+    /// Byte offset of the group descriptor table, in the block after the superblock.
+    pub fn descriptor_table_offset(&self) -> u64 {
+        (self.first_data_block as u64 + 1) * self.block_size as u64
+    }
+}
+
+/// Where a block group keeps its bitmaps and inode table, and how much of it is free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks: u16,
+    pub free_inodes: u16,
+    pub used_directories: u16,
+}
+
+impl GroupDescriptor {
+    pub fn parse(raw: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        Self {
+            block_bitmap: u32_at(0),
+            inode_bitmap: u32_at(4),
+            inode_table: u32_at(8),
+            free_blocks: u16_at(12),
+            free_inodes: u16_at(14),
+            used_directories: u16_at(16),
+        }
+    }
+
+    /// Overwrite the known fields of a raw descriptor, keeping the reserved bytes.
+    pub fn write_to(&self, raw: &mut [u8]) {
+        raw[0..4].copy_from_slice(&self.block_bitmap.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.inode_bitmap.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.inode_table.to_le_bytes());
+        raw[12..14].copy_from_slice(&self.free_blocks.to_le_bytes());
+        raw[14..16].copy_from_slice(&self.free_inodes.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.used_directories.to_le_bytes());
+    }
+}
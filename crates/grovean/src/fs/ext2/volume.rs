@@ -0,0 +1,503 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::inode::{
+    DiskInode, DIRECT_BLOCKS, DOUBLE_INDIRECT, INODE_RECORD_SIZE, SINGLE_INDIRECT, TRIPLE_INDIRECT,
+};
+use super::superblock::{
+    GroupDescriptor, Superblock, GROUP_DESCRIPTOR_SIZE, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE,
+};
+use crate::dev::block::cache::BlockCache;
+use crate::dev::block::BlockDevice;
+use crate::fs::vfs::FsError;
+use crate::time;
+
+/// Blocks of the device kept in the block cache.
+const CACHE_BLOCKS: usize = 256;
+/// Largest regular file on volumes without the large file feature.
+const SMALL_FILE_LIMIT: u64 = i32::MAX as u64;
+
+/// Where block `index` of a file is found: the slot in the inode's pointers, and the entries
+/// to follow through `depth` levels of indirect blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockPath {
+    slot: usize,
+    entries: [usize; 3],
+    depth: usize,
+}
+
+fn block_path(index: u64, per_block: u64) -> Option<BlockPath> {
+    let direct = DIRECT_BLOCKS as u64;
+    if index < direct {
+        return Some(BlockPath {
+            slot: index as usize,
+            entries: [0; 3],
+            depth: 0,
+        });
+    }
+
+    let mut index = index - direct;
+    let mut span = per_block;
+    for (depth, slot) in [
+        (1, SINGLE_INDIRECT),
+        (2, DOUBLE_INDIRECT),
+        (3, TRIPLE_INDIRECT),
+    ] {
+        if index < span {
+            let mut entries = [0; 3];
+            let mut rest = index;
+            for level in (0..depth).rev() {
+                entries[level] = (rest % per_block) as usize;
+                rest /= per_block;
+            }
+            return Some(BlockPath {
+                slot,
+                entries,
+                depth,
+            });
+        }
+        index -= span;
+        span *= per_block;
+    }
+    None
+}
+
+/// A mounted ext2 volume: the block cache, superblock and group descriptors.
+pub struct Volume {
+    device: BlockCache,
+    superblock: Superblock,
+    groups: Vec<GroupDescriptor>,
+    read_only: bool,
+    /// Whether free counts changed since the superblock and descriptors were last written.
+    dirty: bool,
+}
+
+impl Volume {
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let read_only = device.is_read_only();
+        let device = BlockCache::new(device, CACHE_BLOCKS);
+        let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Superblock::parse(&raw)?;
+
+        let size = superblock.blocks_count as u64 * superblock.block_size as u64;
+        if size > device.block_count() * device.block_size() as u64 {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut table = vec![0u8; superblock.group_count() as usize * GROUP_DESCRIPTOR_SIZE];
+        device.read_bytes(superblock.descriptor_table_offset(), &mut table)?;
+        let groups = table
+            .as_chunks::<GROUP_DESCRIPTOR_SIZE>()
+            .0
+            .iter()
+            .map(|raw| GroupDescriptor::parse(raw))
+            .collect();
+
+        Ok(Self {
+            device,
+            read_only: read_only || !superblock.is_writable(),
+            superblock,
+            groups,
+            dirty: false,
+        })
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn check_writable(&self) -> Result<(), FsError> {
+        if self.read_only {
+            Err(FsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Largest size a regular file may reach.
+    pub fn max_file_size(&self) -> u64 {
+        let per_block = (self.block_size() / 4) as u64;
+        let blocks = DIRECT_BLOCKS as u64 + per_block + per_block.pow(2) + per_block.pow(3);
+        let mapped = blocks * self.block_size() as u64;
+        if self.superblock.has_large_files() {
+            mapped
+        } else {
+            mapped.min(SMALL_FILE_LIMIT)
+        }
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size() as u64
+    }
+
+    /// Read `buffer.len()` bytes starting at `offset` within `block`.
+    pub fn read_block(&self, block: u32, offset: usize, buffer: &mut [u8]) -> Result<(), FsError> {
+        Ok(self
+            .device
+            .read_bytes(self.block_offset(block) + offset as u64, buffer)?)
+    }
+
+    pub fn write_block(&self, block: u32, offset: usize, buffer: &[u8]) -> Result<(), FsError> {
+        Ok(self
+            .device
+            .write_bytes(self.block_offset(block) + offset as u64, buffer)?)
+    }
+
+    fn read_pointer(&self, block: u32, entry: usize) -> Result<u32, FsError> {
+        let mut raw = [0u8; 4];
+        self.read_block(block, entry * 4, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn write_pointer(&self, block: u32, entry: usize, value: u32) -> Result<(), FsError> {
+        self.write_block(block, entry * 4, &value.to_le_bytes())
+    }
+
+    fn inode_offset(&self, number: u32) -> Result<u64, FsError> {
+        if number == 0 || number > self.superblock.inodes_count {
+            return Err(FsError::Corrupt);
+        }
+        let group = (number - 1) / self.superblock.inodes_per_group;
+        let index = (number - 1) % self.superblock.inodes_per_group;
+        let table = self.groups[group as usize].inode_table;
+        Ok(self.block_offset(table) + index as u64 * self.superblock.inode_size as u64)
+    }
+
+    pub fn read_inode(&self, number: u32) -> Result<DiskInode, FsError> {
+        let mut raw = [0u8; INODE_RECORD_SIZE];
+        self.device
+            .read_bytes(self.inode_offset(number)?, &mut raw)?;
+        Ok(DiskInode::parse(&raw))
+    }
+
+    pub fn write_inode(&self, number: u32, inode: &DiskInode) -> Result<(), FsError> {
+        let offset = self.inode_offset(number)?;
+        let mut raw = [0u8; INODE_RECORD_SIZE];
+        self.device.read_bytes(offset, &mut raw)?;
+        inode.write_to(&mut raw);
+        Ok(self.device.write_bytes(offset, &raw)?)
+    }
+
+    /// Find a clear bit among the first `count` of a bitmap block and set it.
+    fn claim_bit(&self, bitmap: u32, count: u32) -> Result<Option<u32>, FsError> {
+        let mut bits = vec![0u8; count.div_ceil(8) as usize];
+        self.read_block(bitmap, 0, &mut bits)?;
+        let Some(bit) = (0..count).find(|&bit| bits[bit as usize / 8] & (1 << (bit % 8)) == 0)
+        else {
+            return Ok(None);
+        };
+        let byte = bits[bit as usize / 8] | 1 << (bit % 8);
+        self.write_block(bitmap, bit as usize / 8, &[byte])?;
+        Ok(Some(bit))
+    }
+
+    fn release_bit(&self, bitmap: u32, bit: u32) -> Result<(), FsError> {
+        let mut byte = [0u8];
+        self.read_block(bitmap, bit as usize / 8, &mut byte)?;
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::Corrupt);
+        }
+        byte[0] &= !(1 << (bit % 8));
+        self.write_block(bitmap, bit as usize / 8, &byte)
+    }
+
+    /// Blocks in `group`, which is less than a full group for the last one.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let start = self.superblock.first_data_block + group * self.superblock.blocks_per_group;
+        (self.superblock.blocks_count - start).min(self.superblock.blocks_per_group)
+    }
+
+    fn group_of_block(&self, block: u32) -> u32 {
+        (block.saturating_sub(self.superblock.first_data_block) / self.superblock.blocks_per_group)
+            .min(self.superblock.group_count() - 1)
+    }
+
+    /// Allocate a zeroed block, preferring the group of `goal` to keep files together.
+    pub fn allocate_block(&mut self, goal: u32) -> Result<u32, FsError> {
+        self.check_writable()?;
+        let count = self.superblock.group_count();
+        let first = self.group_of_block(goal);
+        for group in (0..count).map(|offset| (first + offset) % count) {
+            let descriptor = self.groups[group as usize];
+            if descriptor.free_blocks == 0 {
+                continue;
+            }
+            let Some(bit) = self.claim_bit(descriptor.block_bitmap, self.blocks_in_group(group))?
+            else {
+                continue;
+            };
+
+            self.groups[group as usize].free_blocks -= 1;
+            self.superblock.free_blocks = self.superblock.free_blocks.saturating_sub(1);
+            self.dirty = true;
+            let block =
+                self.superblock.first_data_block + group * self.superblock.blocks_per_group + bit;
+            self.write_block(block, 0, &vec![0u8; self.block_size()])?;
+            return Ok(block);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    pub fn free_block(&mut self, block: u32) -> Result<(), FsError> {
+        if block < self.superblock.first_data_block || block >= self.superblock.blocks_count {
+            return Err(FsError::Corrupt);
+        }
+        let group = self.group_of_block(block);
+        let bit =
+            block - self.superblock.first_data_block - group * self.superblock.blocks_per_group;
+        self.release_bit(self.groups[group as usize].block_bitmap, bit)?;
+        self.groups[group as usize].free_blocks += 1;
+        self.superblock.free_blocks += 1;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Allocate an inode with a zeroed record, preferring the group of `near`.
+    pub fn allocate_inode(&mut self, near: u32, directory: bool) -> Result<u32, FsError> {
+        self.check_writable()?;
+        let count = self.superblock.group_count();
+        let first = (near.max(1) - 1) / self.superblock.inodes_per_group;
+        for group in (0..count).map(|offset| (first + offset) % count) {
+            let descriptor = self.groups[group as usize];
+            if descriptor.free_inodes == 0 {
+                continue;
+            }
+            let Some(bit) =
+                self.claim_bit(descriptor.inode_bitmap, self.superblock.inodes_per_group)?
+            else {
+                continue;
+            };
+            let number = group * self.superblock.inodes_per_group + bit + 1;
+            if number < self.superblock.first_inode || number > self.superblock.inodes_count {
+                // Reserved inodes should already be marked in use.
+                return Err(FsError::Corrupt);
+            }
+
+            let descriptor = &mut self.groups[group as usize];
+            descriptor.free_inodes -= 1;
+            if directory {
+                descriptor.used_directories += 1;
+            }
+            self.superblock.free_inodes = self.superblock.free_inodes.saturating_sub(1);
+            self.dirty = true;
+            let offset = self.inode_offset(number)?;
+            self.device
+                .write_bytes(offset, &vec![0u8; self.superblock.inode_size])?;
+            return Ok(number);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    pub fn free_inode(&mut self, number: u32, directory: bool) -> Result<(), FsError> {
+        if number < self.superblock.first_inode || number > self.superblock.inodes_count {
+            return Err(FsError::Corrupt);
+        }
+        let group = (number - 1) / self.superblock.inodes_per_group;
+        let bit = (number - 1) % self.superblock.inodes_per_group;
+        self.release_bit(self.groups[group as usize].inode_bitmap, bit)?;
+
+        let descriptor = &mut self.groups[group as usize];
+        descriptor.free_inodes += 1;
+        if directory {
+            descriptor.used_directories = descriptor.used_directories.saturating_sub(1);
+        }
+        self.superblock.free_inodes += 1;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn per_block(&self) -> u64 {
+        (self.block_size() / 4) as u64
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size() / 512) as u32
+    }
+
+    /// The block holding block `index` of a file, or `None` for a hole.
+    pub fn map_block(&self, inode: &DiskInode, index: u64) -> Result<Option<u32>, FsError> {
+        let path = block_path(index, self.per_block()).ok_or(FsError::InvalidArgument)?;
+        let mut block = inode.block[path.slot];
+        for &entry in &path.entries[..path.depth] {
+            if block == 0 {
+                return Ok(None);
+            }
+            block = self.read_pointer(block, entry)?;
+        }
+        Ok((block != 0).then_some(block))
+    }
+
+    /// Like `map_block`, but allocating the data block and any indirect blocks on the way.
+    /// The caller writes the inode back.
+    pub fn map_block_allocating(
+        &mut self,
+        inode: &mut DiskInode,
+        index: u64,
+    ) -> Result<u32, FsError> {
+        let path = block_path(index, self.per_block()).ok_or(FsError::NoSpace)?;
+        let goal = inode.block[0];
+        if inode.block[path.slot] == 0 {
+            inode.block[path.slot] = self.allocate_block(goal)?;
+            inode.sectors += self.sectors_per_block();
+        }
+
+        let mut block = inode.block[path.slot];
+        for &entry in &path.entries[..path.depth] {
+            let mut next = self.read_pointer(block, entry)?;
+            if next == 0 {
+                next = self.allocate_block(block)?;
+                inode.sectors += self.sectors_per_block();
+                self.write_pointer(block, entry, next)?;
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Free every block of a file from block `first` on, along with indirect blocks that no
+    /// longer map anything. The caller writes the inode back.
+    pub fn free_blocks_from(&mut self, inode: &mut DiskInode, first: u64) -> Result<(), FsError> {
+        for slot in (first.min(DIRECT_BLOCKS as u64) as usize)..DIRECT_BLOCKS {
+            if inode.block[slot] != 0 {
+                self.free_block(inode.block[slot])?;
+                inode.block[slot] = 0;
+                inode.sectors = inode.sectors.saturating_sub(self.sectors_per_block());
+            }
+        }
+
+        let mut base = DIRECT_BLOCKS as u64;
+        let mut span = self.per_block();
+        for (depth, slot) in [
+            (1, SINGLE_INDIRECT),
+            (2, DOUBLE_INDIRECT),
+            (3, TRIPLE_INDIRECT),
+        ] {
+            let block = inode.block[slot];
+            if block != 0
+                && first < base + span
+                && self.free_tree(inode, block, depth, first.saturating_sub(base))?
+            {
+                inode.block[slot] = 0;
+            }
+            base += span;
+            span *= self.per_block();
+        }
+        Ok(())
+    }
+
+    /// Free the blocks mapped by an indirect block from relative index `first` on, and the
+    /// indirect block itself when that leaves it empty. Returns whether it was freed.
+    fn free_tree(
+        &mut self,
+        inode: &mut DiskInode,
+        block: u32,
+        depth: u32,
+        first: u64,
+    ) -> Result<bool, FsError> {
+        let per_block = self.per_block();
+        let span = per_block.pow(depth - 1);
+        let mut raw = vec![0u8; self.block_size()];
+        self.read_block(block, 0, &mut raw)?;
+
+        let mut entries: Vec<u32> = raw
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|chunk| u32::from_le_bytes(*chunk))
+            .collect();
+        for (index, entry) in entries.iter_mut().enumerate() {
+            let start = index as u64 * span;
+            if *entry == 0 || start + span <= first {
+                continue;
+            }
+            let freed = if depth == 1 {
+                self.free_block(*entry)?;
+                inode.sectors = inode.sectors.saturating_sub(self.sectors_per_block());
+                true
+            } else {
+                self.free_tree(inode, *entry, depth - 1, first.saturating_sub(start))?
+            };
+            if freed {
+                *entry = 0;
+            }
+        }
+
+        if first == 0 {
+            self.free_block(block)?;
+            inode.sectors = inode.sectors.saturating_sub(self.sectors_per_block());
+            return Ok(true);
+        }
+        for (chunk, entry) in raw.as_chunks_mut::<4>().0.iter_mut().zip(entries) {
+            *chunk = entry.to_le_bytes();
+        }
+        self.write_block(block, 0, &raw)?;
+        Ok(false)
+    }
+
+    /// Write the free counts to the superblock and group descriptors, then flush the cache.
+    pub fn sync(&mut self) -> Result<(), FsError> {
+        if self.dirty {
+            let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+            self.device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+            self.superblock.write_counts(&mut raw, time::now() as u32);
+            self.device.write_bytes(SUPERBLOCK_OFFSET, &raw)?;
+
+            let offset = self.superblock.descriptor_table_offset();
+            let mut table = vec![0u8; self.groups.len() * GROUP_DESCRIPTOR_SIZE];
+            self.device.read_bytes(offset, &mut table)?;
+            for (raw, group) in table
+                .as_chunks_mut::<GROUP_DESCRIPTOR_SIZE>()
+                .0
+                .iter_mut()
+                .zip(&self.groups)
+            {
+                group.write_to(raw);
+            }
+            self.device.write_bytes(offset, &table)?;
+            self.dirty = false;
+        }
+        self.device.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{block_path, BlockPath};
+
+    #[kunit]
+    fn block_paths_walk_each_level_of_indirection() {
+        let path = |index| block_path(index, 256).expect("in range");
+        assert_eq!(
+            path(11),
+            BlockPath {
+                slot: 11,
+                entries: [0; 3],
+                depth: 0
+            }
+        );
+        assert_eq!(path(12 + 255).entries, [255, 0, 0]);
+        assert_eq!(path(12 + 256).slot, 13);
+        assert_eq!(path(12 + 256 + 257).entries, [1, 1, 0]);
+
+        let triple = path(12 + 256 + 256 * 256 + 256 * 256 + 2);
+        assert_eq!((triple.slot, triple.depth), (14, 3));
+        assert_eq!(triple.entries, [1, 0, 2]);
+        assert_eq!(
+            block_path(12 + 256 + 256 * 256 + 256 * 256 * 256, 256),
+            None
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::fs::vfs::FsError;
+
+/// FAT12 volumes have fewer clusters than this, FAT16 volumes fewer than [`FAT16_MAX_CLUSTERS`].
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+
+/// The first data cluster; entries 0 and 1 of the FAT are reserved.
+pub const FIRST_CLUSTER: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// The BIOS parameter block of the boot sector, with the layout derived from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BiosParameterBlock {
+    pub fat_type: FatType,
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub fat_count: u32,
+    pub root_entry_count: u32,
+    pub total_sectors: u32,
+    pub sectors_per_fat: u32,
+    /// First cluster of the root directory on FAT32; zero on FAT12/16, whose root directory
+    /// is a fixed region before the data area.
+    pub root_cluster: u32,
+    /// Sector of the FSInfo structure on FAT32, zero when absent.
+    pub fs_info_sector: u32,
+    pub volume_id: u32,
+}
+
+impl BiosParameterBlock {
+    pub fn parse(sector: &[u8]) -> Result<Self, FsError> {
+        let u16_at =
+            |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]) as u32;
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if sector.len() < 512 || sector[510..512] != [0x55, 0xaa] {
+            return Err(FsError::Corrupt);
+        }
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved_sectors = u16_at(14);
+        let fat_count = sector[16] as u32;
+        let root_entry_count = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            count => count,
+        };
+        let sectors_per_fat = match u16_at(22) {
+            0 => u32_at(36),
+            count => count,
+        };
+
+        if !(512..=4096).contains(&bytes_per_sector)
+            || !bytes_per_sector.is_power_of_two()
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || sectors_per_fat == 0
+        {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut bpb = Self {
+            fat_type: FatType::Fat12,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            root_entry_count,
+            total_sectors,
+            sectors_per_fat,
+            root_cluster: 0,
+            fs_info_sector: 0,
+            volume_id: 0,
+        };
+        if bpb.first_data_sector() >= total_sectors {
+            return Err(FsError::Corrupt);
+        }
+
+        // The FAT type follows from the cluster count alone.
+        let clusters = bpb.cluster_count();
+        bpb.fat_type = if clusters < FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if clusters < FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        if bpb.fat_type == FatType::Fat32 {
+            bpb.root_cluster = u32_at(44);
+            bpb.fs_info_sector = u16_at(48);
+            bpb.volume_id = u32_at(67);
+            if root_entry_count != 0 || !bpb.is_data_cluster(bpb.root_cluster) {
+                return Err(FsError::Corrupt);
+            }
+        } else {
+            bpb.volume_id = u32_at(39);
+            if root_entry_count == 0 {
+                return Err(FsError::Corrupt);
+            }
+        }
+        Ok(bpb)
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    pub fn first_fat_sector(&self) -> u32 {
+        self.reserved_sectors
+    }
+
+    pub fn root_dir_sector(&self) -> u32 {
+        self.reserved_sectors + self.fat_count * self.sectors_per_fat
+    }
+
+    pub fn root_dir_sectors(&self) -> u32 {
+        (self.root_entry_count * 32).div_ceil(self.bytes_per_sector)
+    }
+
+    pub fn first_data_sector(&self) -> u32 {
+        self.root_dir_sector() + self.root_dir_sectors()
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.first_data_sector()) / self.sectors_per_cluster
+    }
+
+    /// Whether `cluster` lies inside the data area.
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.cluster_count()).contains(&cluster)
+    }
+
+    /// Byte offset of `cluster` from the start of the volume.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.first_data_sector() as u64
+            + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64;
+        sector * self.bytes_per_sector as u64
+    }
+}
@@ -0,0 +1,485 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::fs::vfs::FsError;
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Long name entries claim every one of the low four attributes.
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// First name byte of an unused entry; every entry after it is unused too.
+const END_MARKER: u8 = 0x00;
+pub const DELETED_MARKER: u8 = 0xe5;
+/// Stands in for a real 0xe5 first character, which would read as deleted.
+const KANJI_E5: u8 = 0x05;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_SEQUENCE_MASK: u8 = 0x1f;
+const LFN_CHARS: usize = 13;
+/// Byte offsets of the UTF-16 units stored in a long name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_UNITS: usize = 255;
+
+/// Windows NT flags in the reserved byte marking the base or extension of a short name as
+/// lowercase, which spares a long name for names like `readme.txt`.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXTENSION: u8 = 0x10;
+
+/// 1980-01-01, the earliest FAT date, used until the kernel has a wall clock.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+/// The 8.3 entry that every file and directory has, possibly preceded by long name entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortEntry {
+    pub name: [u8; 11],
+    pub attributes: u8,
+    pub case: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn parse(entry: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]) as u32;
+        Self {
+            name: entry[..11].try_into().unwrap(),
+            attributes: entry[11],
+            case: entry[12],
+            first_cluster: u16_at(20) << 16 | u16_at(26),
+            size: u32::from_le_bytes(entry[28..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write_to(&self, entry: &mut [u8]) {
+        entry[..ENTRY_SIZE].fill(0);
+        entry[..11].copy_from_slice(&self.name);
+        entry[11] = self.attributes;
+        entry[12] = self.case;
+        for offset in [16, 18, 24] {
+            entry[offset..offset + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+        }
+        entry[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&self.size.to_le_bytes());
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// The short name as `BASE.EXT`, lowercased where the case flags ask for it.
+    pub fn display_name(&self) -> String {
+        let mut base = self.name[..8].to_vec();
+        if base[0] == KANJI_E5 {
+            base[0] = DELETED_MARKER;
+        }
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let text = String::from_utf8_lossy(bytes);
+            let text = text.trim_end_matches(' ');
+            if lower {
+                text.to_ascii_lowercase()
+            } else {
+                text.into()
+            }
+        };
+
+        let mut name = part(&base, self.case & CASE_LOWER_BASE != 0);
+        let extension = part(&self.name[8..], self.case & CASE_LOWER_EXTENSION != 0);
+        if !extension.is_empty() {
+            name.push('.');
+            name.push_str(&extension);
+        }
+        name
+    }
+}
+
+/// A live directory entry with the slots it occupies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirRecord {
+    /// The long name when one is present and intact, otherwise the short name.
+    pub name: String,
+    pub entry: ShortEntry,
+    /// Slot of the first long name entry, or of the short entry when there is no long name.
+    pub first_slot: usize,
+    /// Slot of the short entry.
+    pub slot: usize,
+}
+
+impl DirRecord {
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.entry.display_name().eq_ignore_ascii_case(name)
+    }
+}
+
+/// Decode the raw contents of a directory, skipping the volume label, `.` and `..`.
+pub fn parse_directory(data: &[u8]) -> Vec<DirRecord> {
+    let mut records = Vec::new();
+    let mut long_name: Vec<u16> = Vec::new();
+    // Sequence number expected next, the checksum the long name entries agree on, and the slot
+    // of the first of them.
+    let mut pending: Option<(u8, u8, usize)> = None;
+
+    for (slot, entry) in data.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+        match entry[0] {
+            END_MARKER => break,
+            DELETED_MARKER => {
+                pending = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        if entry[11] & 0x3f == ATTR_LONG_NAME {
+            let sequence = entry[0] & LFN_SEQUENCE_MASK;
+            let checksum = entry[13];
+            if entry[0] & LFN_LAST != 0 {
+                long_name = vec![0; sequence as usize * LFN_CHARS];
+                pending = Some((sequence, checksum, slot));
+            }
+            match pending {
+                Some((expected, expected_checksum, first_slot))
+                    if sequence == expected && checksum == expected_checksum && sequence > 0 =>
+                {
+                    let start = (sequence as usize - 1) * LFN_CHARS;
+                    for (index, offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                        long_name[start + index] =
+                            u16::from_le_bytes([entry[*offset], entry[offset + 1]]);
+                    }
+                    pending = Some((sequence - 1, checksum, first_slot));
+                }
+                _ => pending = None,
+            }
+            continue;
+        }
+
+        let short = ShortEntry::parse(entry);
+        let first_slot;
+        let name = match pending.take() {
+            Some((0, checksum, start)) if checksum == short_name_checksum(&short.name) => {
+                let length = long_name
+                    .iter()
+                    .position(|&unit| unit == 0)
+                    .unwrap_or(long_name.len());
+                first_slot = start;
+                String::from_utf16_lossy(&long_name[..length])
+            }
+            _ => {
+                first_slot = slot;
+                short.display_name()
+            }
+        };
+
+        if short.attributes & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+            continue;
+        }
+        records.push(DirRecord {
+            name,
+            entry: short,
+            first_slot,
+            slot,
+        });
+    }
+    records
+}
+
+/// Find `count` consecutive unused slots, returning the first.
+pub fn free_run(data: &[u8], count: usize) -> Option<usize> {
+    let mut run = 0;
+    for (slot, entry) in data.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+        match entry[0] {
+            // Everything from the end marker onwards is free.
+            END_MARKER if data.len() / ENTRY_SIZE - slot + run >= count => return Some(slot - run),
+            END_MARKER => return None,
+            DELETED_MARKER => run += 1,
+            _ => run = 0,
+        }
+        if run == count {
+            return Some(slot + 1 - run);
+        }
+    }
+    None
+}
+
+pub fn short_name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Check that `name` can be stored as a long name.
+pub fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.encode_utf16().count() > MAX_NAME_UNITS {
+        return Err(FsError::NameTooLong);
+    }
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.ends_with(['.', ' ']) || name.chars().any(invalid) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok(())
+}
+
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// Lowercase flag for one part of a name, or `None` if it mixes cases and needs a long name.
+fn part_case(part: &str, flag: u8) -> Option<u8> {
+    let lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+    let upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+    match (lower, upper) {
+        (true, true) => None,
+        (true, false) => Some(flag),
+        _ => Some(0),
+    }
+}
+
+fn pad(part: &[u8], width: usize) -> Vec<u8> {
+    let mut padded = part[..part.len().min(width)].to_vec();
+    padded.resize(width, b' ');
+    padded
+}
+
+/// Choose the short entry name for `name`.
+///
+/// Returns the 8.3 name, the case flags, and whether long name entries must accompany it.
+/// `taken` reports whether a short name is already used in the directory.
+pub fn short_name_for(name: &str, taken: impl Fn(&[u8; 11]) -> bool) -> ([u8; 11], u8, bool) {
+    let (base, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+
+    // Names that already are 8.3 in a single case per part need no long name.
+    let fits = (1..=8).contains(&base.len())
+        && extension.len() <= 3
+        && base
+            .bytes()
+            .chain(extension.bytes())
+            .all(|byte| is_short_char(byte.to_ascii_uppercase()));
+    if fits
+        && let (Some(base_case), Some(extension_case)) = (
+            part_case(base, CASE_LOWER_BASE),
+            part_case(extension, CASE_LOWER_EXTENSION),
+        )
+    {
+        let mut short = [b' '; 11];
+        short[..8].copy_from_slice(&pad(base.to_ascii_uppercase().as_bytes(), 8));
+        short[8..].copy_from_slice(&pad(extension.to_ascii_uppercase().as_bytes(), 3));
+        if !taken(&short) {
+            return (short, base_case | extension_case, false);
+        }
+    }
+
+    // Leading dots cannot start a short name.
+    let mut lossy = base.starts_with('.');
+    let mut convert = |part: &str| -> Vec<u8> {
+        let mut converted = Vec::new();
+        for c in part.chars() {
+            let byte = c.to_ascii_uppercase();
+            if c == ' ' || c == '.' {
+                lossy = true;
+            } else if byte.is_ascii() && is_short_char(byte as u8) {
+                converted.push(byte as u8);
+            } else {
+                lossy = true;
+                converted.push(b'_');
+            }
+        }
+        converted
+    };
+    let mut basis = convert(base.trim_start_matches('.'));
+    let extension = convert(extension);
+    if basis.is_empty() {
+        basis.push(b'_');
+    }
+    lossy |= basis.len() > 8 || extension.len() > 3;
+
+    let mut short = [b' '; 11];
+    short[8..].copy_from_slice(&pad(&extension, 3));
+    if !lossy {
+        short[..8].copy_from_slice(&pad(&basis, 8));
+        if !taken(&short) {
+            return (short, 0, true);
+        }
+    }
+
+    // Append a numeric tail, shortening the basis to make room for it.
+    for number in 1u32.. {
+        let tail = format!("~{number}");
+        let keep = (8 - tail.len()).min(basis.len());
+        let mut candidate = basis[..keep].to_vec();
+        candidate.extend_from_slice(tail.as_bytes());
+        short[..8].copy_from_slice(&pad(&candidate, 8));
+        if !taken(&short) {
+            break;
+        }
+    }
+    (short, 0, true)
+}
+
+/// Encode the long name entries for `name`, in the order they precede the short entry.
+pub fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+
+    (1..=count)
+        .rev()
+        .map(|sequence| {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = sequence as u8 | if sequence == count { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (index, offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                // The name is terminated by a NUL and the rest padded with 0xffff.
+                let position = (sequence - 1) * LFN_CHARS + index;
+                let unit = match position.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[position],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                entry[*offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entry
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{
+        free_run, long_name_entries, parse_directory, short_name_checksum, short_name_for,
+        validate_name, ShortEntry, ATTR_ARCHIVE, DELETED_MARKER, ENTRY_SIZE,
+    };
+    use crate::fs::vfs::FsError;
+
+    fn short(name: &[u8; 11], case: u8) -> [u8; ENTRY_SIZE] {
+        let mut raw = [0u8; ENTRY_SIZE];
+        ShortEntry {
+            name: *name,
+            attributes: ATTR_ARCHIVE,
+            case,
+            first_cluster: 0x0012_0034,
+            size: 99,
+        }
+        .write_to(&mut raw);
+        raw
+    }
+
+    fn directory(name: &str, short_name: &[u8; 11]) -> Vec<u8> {
+        let mut data: Vec<u8> = long_name_entries(name, short_name_checksum(short_name)).concat();
+        data.extend_from_slice(&short(short_name, 0));
+        data
+    }
+
+    #[kunit]
+    fn short_entries_round_trip() {
+        let raw = short(b"README  TXT", 0x18);
+        let entry = ShortEntry::parse(&raw);
+
+        assert_eq!(entry.first_cluster, 0x0012_0034);
+        assert_eq!(entry.size, 99);
+        assert_eq!(entry.display_name(), "readme.txt");
+    }
+
+    #[kunit]
+    fn long_names_are_laid_out_last_entry_first() {
+        let entries = long_name_entries("A long file name.txt", 0x5a);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0][0], 0x42);
+        assert_eq!(entries[1][0], 0x01);
+        assert_eq!(entries[0][11], 0x0f);
+        assert_eq!(entries[0][13], 0x5a);
+        // "A long file name.txt" has 20 units: 13 in the first entry, then 7, a NUL and padding.
+        assert_eq!(entries[0][1..3], [b'a', 0]);
+        assert_eq!(entries[0][18..20], [0, 0]);
+        assert_eq!(entries[0][20..22], [0xff, 0xff]);
+    }
+
+    #[kunit]
+    fn long_names_are_read_back() {
+        let data = directory("A long file name.txt", b"ALONGF~1TXT");
+        let records = parse_directory(&data);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "A long file name.txt");
+        assert_eq!(records[0].first_slot, 0);
+        assert_eq!(records[0].slot, 2);
+        assert!(records[0].matches("a LONG file name.TXT"));
+        assert!(records[0].matches("alongf~1.txt"));
+    }
+
+    #[kunit]
+    fn orphaned_long_names_fall_back_to_the_short_name() {
+        let mut data = directory("A long file name.txt", b"ALONGF~1TXT");
+        // A short entry whose checksum no longer matches, as left by an old driver.
+        data[2 * ENTRY_SIZE] = b'B';
+        let records = parse_directory(&data);
+
+        assert_eq!(records[0].name, "BLONGF~1.TXT");
+        assert_eq!(records[0].first_slot, 2);
+    }
+
+    #[kunit]
+    fn deleted_entries_are_skipped_and_reused() {
+        let mut data = directory("A long file name.txt", b"ALONGF~1TXT");
+        data.extend_from_slice(&short(b"KEEP       ", 0));
+        data.resize(data.len() + 4 * ENTRY_SIZE, 0);
+        for slot in 0..3 {
+            data[slot * ENTRY_SIZE] = DELETED_MARKER;
+        }
+
+        let records = parse_directory(&data);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "KEEP");
+        assert_eq!(free_run(&data, 3), Some(0));
+        assert_eq!(free_run(&data, 4), Some(4));
+        assert_eq!(free_run(&data, 9), None);
+    }
+
+    #[kunit]
+    fn short_names_follow_the_usual_rules() {
+        let none = |_: &[u8; 11]| false;
+
+        assert_eq!(
+            short_name_for("readme.txt", none),
+            (*b"README  TXT", 0x18, false)
+        );
+        assert_eq!(
+            short_name_for("MAKEFILE", none),
+            (*b"MAKEFILE   ", 0, false)
+        );
+        assert_eq!(
+            short_name_for("Readme.txt", none),
+            (*b"README  TXT", 0, true)
+        );
+        assert_eq!(
+            short_name_for("a long name.html", none),
+            (*b"ALONGN~1HTM", 0, true)
+        );
+        assert_eq!(short_name_for(".bashrc", none), (*b"BASHRC~1   ", 0, true));
+        assert_eq!(short_name_for("x+y.c", none), (*b"X_Y~1   C  ", 0, true));
+
+        let taken = |name: &[u8; 11]| name == b"ALONGN~1HTM";
+        assert_eq!(short_name_for("a long name.html", taken).0, *b"ALONGN~2HTM");
+    }
+
+    #[kunit]
+    fn invalid_names_are_rejected() {
+        assert_eq!(validate_name("a:b"), Err(FsError::InvalidPath));
+        assert_eq!(validate_name("trailing."), Err(FsError::InvalidPath));
+        assert_eq!(validate_name(&"x".repeat(256)), Err(FsError::NameTooLong));
+        assert_eq!(validate_name("ok name (1).txt"), Ok(()));
+    }
+}
@@ -0,0 +1,884 @@
+pub mod bpb;
+pub mod dir;
+pub mod volume;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::dev::block::BlockDevice;
+use crate::fs::vfs::{DirEntry, FileType, Filesystem, FsError, Inode, Metadata};
+use bpb::FatType;
+use dir::{DirRecord, ShortEntry, ENTRY_SIZE};
+use volume::{Directory, Volume};
+
+const ROOT_INODE: u64 = 1;
+/// File sizes are stored in 32 bits.
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+
+/// A FAT12, FAT16 or FAT32 filesystem on a block device.
+///
+/// FAT has no inodes, so inodes here name the slot of a file's short directory entry and read
+/// it afresh for every operation. Removing a file therefore invalidates open handles to it.
+pub struct FatFs {
+    volume: Arc<Mutex<Volume>>,
+}
+
+impl FatFs {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        Ok(Self {
+            volume: Arc::new(Mutex::new(Volume::open(device)?)),
+        })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.volume.lock().bpb().fat_type
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        let volume = self.volume.lock();
+        volume.free_clusters() as u64 * volume.bpb().cluster_size() as u64
+    }
+}
+
+impl Filesystem for FatFs {
+    fn name(&self) -> &str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(FatInode {
+            volume: self.volume.clone(),
+            node: Node::Root,
+            inode: ROOT_INODE,
+            is_directory: true,
+        })
+    }
+
+    fn uses_page_cache(&self) -> bool {
+        true
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.volume.lock().sync()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    /// The short entry at `slot` of `parent`.
+    Entry {
+        parent: Directory,
+        slot: usize,
+    },
+}
+
+struct FatInode {
+    volume: Arc<Mutex<Volume>>,
+    node: Node,
+    inode: u64,
+    is_directory: bool,
+}
+
+fn root_directory(volume: &Volume) -> Directory {
+    match volume.bpb().fat_type {
+        FatType::Fat32 => Directory::Clusters(volume.bpb().root_cluster),
+        _ => Directory::FixedRoot,
+    }
+}
+
+fn read_entry(volume: &Volume, parent: Directory, slot: usize) -> Result<ShortEntry, FsError> {
+    let mut raw = [0u8; ENTRY_SIZE];
+    volume.read_bytes(volume.slot_offset(parent, slot)?, &mut raw)?;
+    // The file was removed while this inode was held.
+    if raw[0] == 0 || raw[0] == dir::DELETED_MARKER {
+        return Err(FsError::NotFound);
+    }
+    Ok(ShortEntry::parse(&raw))
+}
+
+fn write_entry(
+    volume: &Volume,
+    parent: Directory,
+    slot: usize,
+    entry: &ShortEntry,
+) -> Result<(), FsError> {
+    let mut raw = [0u8; ENTRY_SIZE];
+    entry.write_to(&mut raw);
+    volume.write_bytes(volume.slot_offset(parent, slot)?, &raw)
+}
+
+/// Write `data` at byte `offset` of the file stored in `chain`.
+fn write_chain(volume: &Volume, chain: &[u32], offset: u64, data: &[u8]) -> Result<(), FsError> {
+    let cluster_size = volume.bpb().cluster_size() as u64;
+    let mut done = 0;
+    while done < data.len() {
+        let position = offset + done as u64;
+        let cluster = chain[(position / cluster_size) as usize];
+        let start = position % cluster_size;
+        let chunk = ((cluster_size - start) as usize).min(data.len() - done);
+        volume.write_bytes(
+            volume.bpb().cluster_offset(cluster) + start,
+            &data[done..done + chunk],
+        )?;
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// Zero bytes `start..end` of the file stored in `chain`.
+fn zero_chain(volume: &Volume, chain: &[u32], start: u64, end: u64) -> Result<(), FsError> {
+    let zeros = vec![0u8; volume.bpb().cluster_size() as usize];
+    let mut position = start;
+    while position < end {
+        let chunk = (end - position).min(zeros.len() as u64);
+        write_chain(volume, chain, position, &zeros[..chunk as usize])?;
+        position += chunk;
+    }
+    Ok(())
+}
+
+/// Grow the chain of `entry` to hold `size` bytes, returning the whole chain. On failure the
+/// chain is restored to its previous length.
+fn ensure_clusters(
+    volume: &mut Volume,
+    entry: &mut ShortEntry,
+    size: u64,
+) -> Result<Vec<u32>, FsError> {
+    let needed = size.div_ceil(volume.bpb().cluster_size() as u64) as usize;
+    let mut chain = match entry.first_cluster {
+        0 => Vec::new(),
+        first => volume.chain(first)?,
+    };
+    let original = chain.len();
+
+    while chain.len() < needed {
+        match volume.allocate(chain.last().copied()) {
+            Ok(cluster) => chain.push(cluster),
+            Err(error) => {
+                match original {
+                    0 if !chain.is_empty() => volume.free_chain(chain[0])?,
+                    0 => {}
+                    _ => volume.truncate_chain(chain[original - 1])?,
+                }
+                return Err(error);
+            }
+        }
+    }
+    if original == 0 && !chain.is_empty() {
+        entry.first_cluster = chain[0];
+    }
+    Ok(chain)
+}
+
+impl FatInode {
+    fn child(
+        &self,
+        volume: &Volume,
+        parent: Directory,
+        record: &DirRecord,
+    ) -> Result<Self, FsError> {
+        Ok(Self {
+            volume: self.volume.clone(),
+            node: Node::Entry {
+                parent,
+                slot: record.slot,
+            },
+            inode: volume.slot_offset(parent, record.slot)? / ENTRY_SIZE as u64,
+            is_directory: record.entry.is_directory(),
+        })
+    }
+
+    /// Where this directory keeps its entries.
+    fn directory(&self, volume: &Volume) -> Result<Directory, FsError> {
+        match self.node {
+            Node::Root => Ok(root_directory(volume)),
+            Node::Entry { parent, slot } => {
+                let entry = read_entry(volume, parent, slot)?;
+                if !entry.is_directory() {
+                    return Err(FsError::NotADirectory);
+                }
+                match entry.first_cluster {
+                    0 => Err(FsError::Corrupt),
+                    cluster => Ok(Directory::Clusters(cluster)),
+                }
+            }
+        }
+    }
+
+    /// The directory entry of this regular file with where it lives.
+    fn file_entry(&self, volume: &Volume) -> Result<(Directory, usize, ShortEntry), FsError> {
+        match self.node {
+            Node::Root => Err(FsError::IsADirectory),
+            Node::Entry { parent, slot } => {
+                let entry = read_entry(volume, parent, slot)?;
+                if entry.is_directory() {
+                    return Err(FsError::IsADirectory);
+                }
+                Ok((parent, slot, entry))
+            }
+        }
+    }
+
+    fn records(volume: &Volume, directory: Directory) -> Result<Vec<DirRecord>, FsError> {
+        Ok(dir::parse_directory(&volume.read_directory(directory)?))
+    }
+
+    /// Write the `.` and `..` entries of a new directory at `cluster`.
+    fn write_dot_entries(volume: &Volume, cluster: u32, parent: Directory) -> Result<(), FsError> {
+        // `..` points at cluster zero when the parent is the root, even on FAT32.
+        let parent_cluster = match parent {
+            Directory::Clusters(cluster) if cluster != volume.bpb().root_cluster => cluster,
+            _ => 0,
+        };
+        let mut entries = [0u8; 2 * ENTRY_SIZE];
+        for (index, (name, first_cluster)) in [
+            (*b".          ", cluster),
+            (*b"..         ", parent_cluster),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            ShortEntry {
+                name,
+                attributes: dir::ATTR_DIRECTORY,
+                case: 0,
+                first_cluster,
+                size: 0,
+            }
+            .write_to(&mut entries[index * ENTRY_SIZE..]);
+        }
+        volume.write_bytes(volume.bpb().cluster_offset(cluster), &entries)
+    }
+
+    /// Find room for `entries` in `directory`, growing it if needed, and write them there.
+    /// Returns the slot of the last entry.
+    fn insert_entries(
+        volume: &mut Volume,
+        directory: Directory,
+        entries: &[[u8; ENTRY_SIZE]],
+    ) -> Result<usize, FsError> {
+        let slot = loop {
+            let data = volume.read_directory(directory)?;
+            if let Some(slot) = dir::free_run(&data, entries.len()) {
+                break slot;
+            }
+            volume.extend_directory(directory)?;
+        };
+        for (index, entry) in entries.iter().enumerate() {
+            volume.write_bytes(volume.slot_offset(directory, slot + index)?, entry)?;
+        }
+        Ok(slot + entries.len() - 1)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let volume = self.volume.lock();
+        let entry = match self.node {
+            Node::Root => None,
+            Node::Entry { parent, slot } => read_entry(&volume, parent, slot).ok(),
+        };
+        let read_only = entry.is_some_and(|entry| entry.attributes & dir::ATTR_READ_ONLY != 0);
+        let (file_type, mode) = match (self.is_directory, read_only) {
+            (true, false) => (FileType::Directory, 0o755),
+            (true, true) => (FileType::Directory, 0o555),
+            (false, false) => (FileType::Regular, 0o644),
+            (false, true) => (FileType::Regular, 0o444),
+        };
+        Metadata {
+            inode: self.inode,
+            file_type,
+            mode,
+            size: entry.map_or(0, |entry| entry.size as u64),
+            links: 1,
+            accessed: 0,
+            modified: 0,
+            changed: 0,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let volume = self.volume.lock();
+        let directory = self.directory(&volume)?;
+        let record = Self::records(&volume, directory)?
+            .into_iter()
+            .find(|record| record.matches(name))
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(self.child(&volume, directory, &record)?))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let volume = self.volume.lock();
+        let directory = self.directory(&volume)?;
+        Self::records(&volume, directory)?
+            .into_iter()
+            .map(|record| {
+                Ok(DirEntry {
+                    inode: volume.slot_offset(directory, record.slot)? / ENTRY_SIZE as u64,
+                    file_type: if record.entry.is_directory() {
+                        FileType::Directory
+                    } else {
+                        FileType::Regular
+                    },
+                    name: record.name,
+                })
+            })
+            .collect()
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+    ) -> Result<Arc<dyn Inode>, FsError> {
+        dir::validate_name(name)?;
+        let mut volume = self.volume.lock();
+        let directory = self.directory(&volume)?;
+        let records = Self::records(&volume, directory)?;
+        if records.iter().any(|record| record.matches(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short_name, case, needs_long_name) = dir::short_name_for(name, |short| {
+            records.iter().any(|record| &record.entry.name == short)
+        });
+        let mut entries = match needs_long_name {
+            true => dir::long_name_entries(name, dir::short_name_checksum(&short_name)),
+            false => Vec::new(),
+        };
+
+        let mut entry = ShortEntry {
+            name: short_name,
+            attributes: dir::ATTR_ARCHIVE,
+            case,
+            first_cluster: 0,
+            size: 0,
+        };
+        if mode & 0o222 == 0 {
+            entry.attributes |= dir::ATTR_READ_ONLY;
+        }
+        match file_type {
+            FileType::Regular => {}
+            FileType::Directory => {
+                let cluster = volume.allocate(None)?;
+                if let Err(error) = Self::write_dot_entries(&volume, cluster, directory) {
+                    volume.free_chain(cluster)?;
+                    return Err(error);
+                }
+                entry.attributes = entry.attributes & dir::ATTR_READ_ONLY | dir::ATTR_DIRECTORY;
+                entry.first_cluster = cluster;
+            }
+            _ => return Err(FsError::NotSupported),
+        }
+
+        let mut raw = [0u8; ENTRY_SIZE];
+        entry.write_to(&mut raw);
+        entries.push(raw);
+        let slot = match Self::insert_entries(&mut volume, directory, &entries) {
+            Ok(slot) => slot,
+            Err(error) => {
+                if entry.first_cluster != 0 {
+                    volume.free_chain(entry.first_cluster)?;
+                }
+                return Err(error);
+            }
+        };
+
+        let record = DirRecord {
+            name: name.into(),
+            entry,
+            first_slot: slot + 1 - entries.len(),
+            slot,
+        };
+        Ok(Arc::new(self.child(&volume, directory, &record)?))
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.directory(&self.volume.lock())?;
+        Err(FsError::NotSupported)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), FsError> {
+        let mut volume = self.volume.lock();
+        let directory = self.directory(&volume)?;
+        let record = Self::records(&volume, directory)?
+            .into_iter()
+            .find(|record| record.matches(name))
+            .ok_or(FsError::NotFound)?;
+
+        let first_cluster = record.entry.first_cluster;
+        if record.entry.is_directory()
+            && first_cluster != 0
+            && !Self::records(&volume, Directory::Clusters(first_cluster))?.is_empty()
+        {
+            return Err(FsError::NotEmpty);
+        }
+
+        // Unlink the entry before freeing its clusters so an interrupted removal leaks space
+        // rather than leaving an entry pointing at free clusters.
+        for slot in record.first_slot..=record.slot {
+            volume.write_bytes(volume.slot_offset(directory, slot)?, &[dir::DELETED_MARKER])?;
+        }
+        if first_cluster != 0 {
+            volume.free_chain(first_cluster)?;
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let volume = self.volume.lock();
+        let (_, _, entry) = self.file_entry(&volume)?;
+        let length = buffer
+            .len()
+            .min((entry.size as u64).saturating_sub(offset) as usize);
+        if length == 0 {
+            return Ok(0);
+        }
+
+        let cluster_size = volume.bpb().cluster_size() as u64;
+        let mut cluster = entry.first_cluster;
+        for _ in 0..offset / cluster_size {
+            cluster = volume.next_cluster(cluster)?.ok_or(FsError::Corrupt)?;
+        }
+
+        let mut done = 0;
+        while done < length {
+            let position = offset + done as u64;
+            let start = position % cluster_size;
+            let chunk = ((cluster_size - start) as usize).min(length - done);
+            volume.read_bytes(
+                volume.bpb().cluster_offset(cluster) + start,
+                &mut buffer[done..done + chunk],
+            )?;
+            done += chunk;
+            if done < length {
+                cluster = volume.next_cluster(cluster)?.ok_or(FsError::Corrupt)?;
+            }
+        }
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        let mut volume = self.volume.lock();
+        let (parent, slot, mut entry) = self.file_entry(&volume)?;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(FsError::NoSpace)?;
+
+        let cluster_size = volume.bpb().cluster_size() as u64;
+        let allocated = (entry.size as u64).div_ceil(cluster_size) * cluster_size;
+        let chain = ensure_clusters(&mut volume, &mut entry, end)?;
+        // New clusters come zeroed, but the slack of the old last cluster may hold stale data.
+        let size = entry.size as u64;
+        if offset > size {
+            zero_chain(&volume, &chain, size, offset.min(allocated))?;
+        }
+        write_chain(&volume, &chain, offset, buffer)?;
+
+        entry.size = entry.size.max(end as u32);
+        entry.attributes |= dir::ATTR_ARCHIVE;
+        write_entry(&volume, parent, slot, &entry)?;
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, new_size: u64) -> Result<(), FsError> {
+        let mut volume = self.volume.lock();
+        let (parent, slot, mut entry) = self.file_entry(&volume)?;
+        if new_size > MAX_FILE_SIZE {
+            return Err(FsError::NoSpace);
+        }
+
+        let cluster_size = volume.bpb().cluster_size() as u64;
+        let size = entry.size as u64;
+        if new_size > size {
+            let allocated = size.div_ceil(cluster_size) * cluster_size;
+            let chain = ensure_clusters(&mut volume, &mut entry, new_size)?;
+            zero_chain(&volume, &chain, size, new_size.min(allocated))?;
+        } else if entry.first_cluster != 0 {
+            let keep = new_size.div_ceil(cluster_size) as usize;
+            if keep == 0 {
+                volume.free_chain(entry.first_cluster)?;
+                entry.first_cluster = 0;
+            } else {
+                let chain = volume.chain(entry.first_cluster)?;
+                if chain.len() > keep {
+                    volume.truncate_chain(chain[keep - 1])?;
+                }
+            }
+        }
+
+        entry.size = new_size as u32;
+        entry.attributes |= dir::ATTR_ARCHIVE;
+        write_entry(&volume, parent, slot, &entry)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        self.volume.lock().sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::format;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+    use spin::Mutex;
+
+    use super::bpb::FatType;
+    use super::FatFs;
+    use crate::dev::block::{check_request, BlockDevice, BlockError};
+    use crate::fs::vfs::{FileType, FsError, OpenFlags, SeekFrom, Vfs};
+
+    const SECTOR_SIZE: usize = 512;
+
+    /// A disk that only stores the sectors written to it, so FAT32 fixtures fit the test heap.
+    struct SparseDisk {
+        sectors: Mutex<BTreeMap<u64, Vec<u8>>>,
+        sector_count: u64,
+    }
+
+    impl SparseDisk {
+        fn sector(&self, lba: u64) -> Vec<u8> {
+            self.sectors
+                .lock()
+                .get(&lba)
+                .cloned()
+                .unwrap_or_else(|| vec![0; SECTOR_SIZE])
+        }
+
+        fn write_at(&self, offset: usize, data: &[u8]) {
+            let lba = (offset / SECTOR_SIZE) as u64;
+            let mut sector = self.sector(lba);
+            sector[offset % SECTOR_SIZE..][..data.len()].copy_from_slice(data);
+            self.sectors.lock().insert(lba, sector);
+        }
+    }
+
+    impl BlockDevice for SparseDisk {
+        fn name(&self) -> &str {
+            "sparse0"
+        }
+
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn block_count(&self) -> u64 {
+            self.sector_count
+        }
+
+        fn read_blocks(&self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+            check_request(self, lba, buffer.len())?;
+            for (index, chunk) in buffer.chunks_mut(SECTOR_SIZE).enumerate() {
+                chunk.copy_from_slice(&self.sector(lba + index as u64));
+            }
+            Ok(())
+        }
+
+        fn write_blocks(&self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+            check_request(self, lba, buffer.len())?;
+            let mut sectors = self.sectors.lock();
+            for (index, chunk) in buffer.chunks(SECTOR_SIZE).enumerate() {
+                sectors.insert(lba + index as u64, chunk.to_vec());
+            }
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<(), BlockError> {
+            Ok(())
+        }
+    }
+
+    /// Format a blank volume the way mkfs.fat would, with two FATs.
+    fn format(
+        fat_type: FatType,
+        total_sectors: u32,
+        sectors_per_cluster: u32,
+        root_entries: u32,
+    ) -> Arc<SparseDisk> {
+        let disk = Arc::new(SparseDisk {
+            sectors: Mutex::new(BTreeMap::new()),
+            sector_count: total_sectors as u64,
+        });
+        let (reserved, entry_bits, root_entries) = match fat_type {
+            FatType::Fat12 => (1, 12, root_entries),
+            FatType::Fat16 => (1, 16, root_entries),
+            FatType::Fat32 => (32, 32, 0),
+        };
+        let root_sectors = (root_entries * 32).div_ceil(SECTOR_SIZE as u32);
+
+        let mut sectors_per_fat = 1;
+        let clusters = loop {
+            let data = total_sectors - reserved - 2 * sectors_per_fat - root_sectors;
+            let clusters = data / sectors_per_cluster;
+            let needed = ((clusters + 2) * entry_bits).div_ceil(8 * SECTOR_SIZE as u32);
+            if needed <= sectors_per_fat {
+                break clusters;
+            }
+            sectors_per_fat = needed;
+        };
+
+        let mut boot = [0u8; SECTOR_SIZE];
+        boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"GROVEAN ");
+        boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[13] = sectors_per_cluster as u8;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+        boot[21] = 0xf8;
+        boot[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let mut fat = vec![0u8; 12];
+        match fat_type {
+            FatType::Fat12 | FatType::Fat16 => {
+                boot[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes());
+                boot[39..43].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+                fat[..4].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff]);
+                if fat_type == FatType::Fat12 {
+                    fat[3] = 0;
+                }
+            }
+            FatType::Fat32 => {
+                boot[36..40].copy_from_slice(&sectors_per_fat.to_le_bytes());
+                boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+                boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+                boot[67..71].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+                // Reserved entries, then the end of the single-cluster root directory.
+                fat.copy_from_slice(&[
+                    0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+                ]);
+
+                let mut fs_info = [0u8; SECTOR_SIZE];
+                fs_info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+                fs_info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+                fs_info[488..492].copy_from_slice(&(clusters - 1).to_le_bytes());
+                fs_info[492..496].copy_from_slice(&3u32.to_le_bytes());
+                fs_info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+                disk.write_at(SECTOR_SIZE, &fs_info);
+            }
+        }
+        disk.write_at(0, &boot);
+        for copy in 0..2 {
+            disk.write_at(
+                (reserved + copy * sectors_per_fat) as usize * SECTOR_SIZE,
+                &fat,
+            );
+        }
+        disk
+    }
+
+    fn fat12() -> Arc<SparseDisk> {
+        format(FatType::Fat12, 2880, 1, 16)
+    }
+
+    fn fat16() -> Arc<SparseDisk> {
+        format(FatType::Fat16, 8400, 1, 512)
+    }
+
+    fn fat32() -> Arc<SparseDisk> {
+        format(FatType::Fat32, 68000, 1, 0)
+    }
+
+    fn mount(disk: &Arc<SparseDisk>) -> (Arc<FatFs>, Vfs) {
+        let fat = Arc::new(FatFs::new(disk.clone()).expect("mount"));
+        let vfs = Vfs::new();
+        vfs.mount("/", fat.clone()).expect("vfs mount");
+        (fat, vfs)
+    }
+
+    fn write_file(vfs: &Vfs, path: &str, data: &[u8]) {
+        let file = vfs
+            .open(path, OpenFlags::WRITE.union(OpenFlags::CREATE))
+            .expect("create");
+        assert_eq!(file.write(data).expect("write"), data.len());
+    }
+
+    fn read_file(vfs: &Vfs, path: &str) -> Result<Vec<u8>, FsError> {
+        let file = vfs.open(path, OpenFlags::READ)?;
+        let mut data = vec![0u8; file.metadata().size as usize];
+        let length = file.read(&mut data)?;
+        data.truncate(length);
+        Ok(data)
+    }
+
+    fn pattern(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 7) as u8).collect()
+    }
+
+    #[kunit]
+    fn detects_the_fat_type_from_the_cluster_count() {
+        for (disk, fat_type) in [
+            (fat12(), FatType::Fat12),
+            (fat16(), FatType::Fat16),
+            (fat32(), FatType::Fat32),
+        ] {
+            assert_eq!(FatFs::new(disk).expect("mount").fat_type(), fat_type);
+        }
+    }
+
+    #[kunit]
+    fn files_survive_a_remount_on_every_fat_type() {
+        for disk in [fat12(), fat16(), fat32()] {
+            let data = pattern(3 * SECTOR_SIZE + 17);
+            {
+                let (_, vfs) = mount(&disk);
+                vfs.create_dir("/Boot Logs", 0o755).expect("mkdir");
+                write_file(&vfs, "/Boot Logs/Crash report.txt", &data);
+                write_file(&vfs, "/config.txt", b"timeout=5\n");
+                vfs.sync().expect("sync");
+            }
+
+            let (_, vfs) = mount(&disk);
+            assert_eq!(
+                read_file(&vfs, "/boot logs/CRASH REPORT.TXT").expect("read"),
+                data
+            );
+            assert_eq!(
+                read_file(&vfs, "/CONFIG.TXT").expect("read"),
+                b"timeout=5\n"
+            );
+
+            let names: Vec<_> = vfs
+                .read_dir("/")
+                .expect("list")
+                .into_iter()
+                .map(|entry| (entry.name, entry.file_type))
+                .collect();
+            assert_eq!(
+                names,
+                [
+                    ("Boot Logs".into(), FileType::Directory),
+                    ("config.txt".into(), FileType::Regular)
+                ]
+            );
+        }
+    }
+
+    #[kunit]
+    fn fat12_entries_straddling_sectors_are_kept_intact() {
+        let disk = fat12();
+        let (_, vfs) = mount(&disk);
+        // 400 single-sector clusters cover FAT12 entries whose bytes cross sector boundaries.
+        let data = pattern(400 * SECTOR_SIZE);
+        write_file(&vfs, "/big.bin", &data);
+        vfs.sync().expect("sync");
+
+        let (_, vfs) = mount(&disk);
+        assert_eq!(read_file(&vfs, "/big.bin").expect("read"), data);
+    }
+
+    #[kunit]
+    fn removal_frees_clusters_and_checks_emptiness() {
+        let (fat, vfs) = mount(&fat16());
+        let free = fat.free_bytes();
+        vfs.create_dir("/dir", 0o755).expect("mkdir");
+        write_file(&vfs, "/dir/file", &pattern(5000));
+
+        assert_eq!(vfs.remove("/dir").err(), Some(FsError::NotEmpty));
+        vfs.remove("/dir/file").expect("remove file");
+        vfs.remove("/dir").expect("remove directory");
+        assert_eq!(fat.free_bytes(), free);
+        assert_eq!(vfs.resolve("/dir").err(), Some(FsError::NotFound));
+    }
+
+    #[kunit]
+    fn directories_grow_but_the_fixed_root_does_not() {
+        let (_, vfs) = mount(&fat12());
+        for index in 0..16 {
+            write_file(&vfs, &format!("/F{index}"), b"");
+        }
+        assert_eq!(
+            vfs.open("/ONEMORE", OpenFlags::WRITE.union(OpenFlags::CREATE))
+                .err(),
+            Some(FsError::NoSpace)
+        );
+
+        let (_, vfs) = mount(&fat16());
+        vfs.create_dir("/many", 0o755).expect("mkdir");
+        for index in 0..40 {
+            write_file(&vfs, &format!("/many/file number {index}"), b"x");
+        }
+        assert_eq!(vfs.read_dir("/many").expect("list").len(), 40);
+        assert_eq!(read_file(&vfs, "/many/file number 39").expect("read"), b"x");
+    }
+
+    #[kunit]
+    fn truncation_and_sparse_writes_read_zeros() {
+        let (fat, vfs) = mount(&fat16());
+        let free = fat.free_bytes();
+        write_file(&vfs, "/file", &[0xaa; 3 * SECTOR_SIZE]);
+
+        let file = vfs.open("/file", OpenFlags::READ_WRITE).expect("open");
+        file.truncate(10).expect("shrink");
+        assert_eq!(fat.free_bytes(), free - SECTOR_SIZE as u64);
+        file.seek(SeekFrom::Start(600)).expect("seek");
+        file.write(b"end").expect("write past the end");
+
+        let data = read_file(&vfs, "/file").expect("read");
+        assert_eq!(data.len(), 603);
+        assert!(data[..10].iter().all(|&byte| byte == 0xaa));
+        assert!(data[10..600].iter().all(|&byte| byte == 0));
+        assert_eq!(&data[600..], b"end");
+
+        file.truncate(0).expect("empty");
+        assert_eq!(fat.free_bytes(), free);
+    }
+
+    #[kunit]
+    fn fs_info_tracks_free_clusters() {
+        let disk = fat32();
+        let free = {
+            let (fat, vfs) = mount(&disk);
+            let free = fat.free_bytes();
+            write_file(&vfs, "/log.txt", &pattern(4 * SECTOR_SIZE));
+            vfs.sync().expect("sync");
+            free
+        };
+
+        let fs_info = disk.sector(1);
+        let free_clusters = u32::from_le_bytes(fs_info[488..492].try_into().unwrap());
+        assert_eq!(
+            free_clusters as u64 * SECTOR_SIZE as u64,
+            free - 4 * SECTOR_SIZE as u64
+        );
+        let (fat, _) = mount(&disk);
+        assert_eq!(fat.free_bytes(), free - 4 * SECTOR_SIZE as u64);
+    }
+
+    #[kunit]
+    fn names_are_case_insensitive_and_unique() {
+        let (_, vfs) = mount(&fat32());
+        write_file(&vfs, "/ReadMe.md", b"hi");
+
+        assert_eq!(read_file(&vfs, "/readme.md").expect("read"), b"hi");
+        assert_eq!(
+            vfs.create_dir("/README.MD", 0o755).err(),
+            Some(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            vfs.symlink("/x", "/link").err(),
+            Some(FsError::NotSupported)
+        );
+    }
+
+    #[kunit]
+    fn rejects_devices_without_a_fat() {
+        let disk = Arc::new(SparseDisk {
+            sectors: Mutex::new(BTreeMap::new()),
+            sector_count: 100,
+        });
+        assert_eq!(FatFs::new(disk).err(), Some(FsError::Corrupt));
+    }
+}
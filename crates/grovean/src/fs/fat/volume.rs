@@ -0,0 +1,360 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::bpb::{BiosParameterBlock, FatType, FIRST_CLUSTER};
+use super::dir::ENTRY_SIZE;
+use crate::dev::block::cache::BlockCache;
+use crate::dev::block::BlockDevice;
+use crate::fs::vfs::FsError;
+
+/// Blocks of the device kept in the sector cache.
+const CACHE_BLOCKS: usize = 256;
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+/// Free count and next free hint when FSInfo does not know them.
+const FS_INFO_UNKNOWN: u32 = 0xffff_ffff;
+
+const CLUSTER_FREE: u32 = 0;
+const FAT32_ENTRY_MASK: u32 = 0x0fff_ffff;
+
+/// Where the entries of a directory are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directory {
+    /// The fixed root directory region of FAT12 and FAT16.
+    FixedRoot,
+    /// A cluster chain, as for every other directory.
+    Clusters(u32),
+}
+
+/// A mounted FAT volume: byte access through the sector cache, the allocation table and the
+/// FSInfo counters.
+pub struct Volume {
+    device: BlockCache,
+    bpb: BiosParameterBlock,
+    free_clusters: u32,
+    next_free: u32,
+    fs_info_dirty: bool,
+}
+
+impl Volume {
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Self, FsError> {
+        let device = BlockCache::new(device, CACHE_BLOCKS);
+        let mut boot_sector = vec![0u8; 512.max(device.block_size())];
+        device.read_bytes(0, &mut boot_sector)?;
+        let bpb = BiosParameterBlock::parse(&boot_sector)?;
+
+        let size = bpb.total_sectors as u64 * bpb.bytes_per_sector as u64;
+        if size > device.block_count() * device.block_size() as u64 {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut volume = Self {
+            device,
+            bpb,
+            free_clusters: 0,
+            next_free: FIRST_CLUSTER,
+            fs_info_dirty: false,
+        };
+        match volume.read_fs_info()? {
+            Some((free, next)) => {
+                volume.free_clusters = free;
+                volume.next_free = next;
+            }
+            None => volume.free_clusters = volume.count_free_clusters()?,
+        }
+        Ok(volume)
+    }
+
+    pub fn bpb(&self) -> &BiosParameterBlock {
+        &self.bpb
+    }
+
+    pub fn free_clusters(&self) -> u32 {
+        self.free_clusters
+    }
+
+    pub fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        Ok(self.device.read_bytes(offset, buffer)?)
+    }
+
+    pub fn write_bytes(&self, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+        Ok(self.device.write_bytes(offset, buffer)?)
+    }
+
+    /// Read the FSInfo counters, if the volume has valid ones.
+    fn read_fs_info(&self) -> Result<Option<(u32, u32)>, FsError> {
+        let Some(offset) = self.fs_info_offset() else {
+            return Ok(None);
+        };
+        let mut sector = [0u8; 512];
+        self.read_bytes(offset, &mut sector)?;
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if u32_at(0) != FS_INFO_LEAD_SIGNATURE
+            || u32_at(484) != FS_INFO_STRUCT_SIGNATURE
+            || u32_at(508) != FS_INFO_TRAIL_SIGNATURE
+            || u32_at(488) == FS_INFO_UNKNOWN
+            || u32_at(488) > self.bpb.cluster_count()
+        {
+            return Ok(None);
+        }
+        let next = match u32_at(492) {
+            next if self.bpb.is_data_cluster(next) => next,
+            _ => FIRST_CLUSTER,
+        };
+        Ok(Some((u32_at(488), next)))
+    }
+
+    fn fs_info_offset(&self) -> Option<u64> {
+        let sector = self.bpb.fs_info_sector;
+        (self.bpb.fat_type == FatType::Fat32 && sector != 0 && sector < self.bpb.reserved_sectors)
+            .then_some(sector as u64 * self.bpb.bytes_per_sector as u64)
+    }
+
+    fn count_free_clusters(&self) -> Result<u32, FsError> {
+        let mut fat = vec![0u8; (self.bpb.sectors_per_fat * self.bpb.bytes_per_sector) as usize];
+        self.read_bytes(self.fat_offset(0), &mut fat)?;
+        let last = FIRST_CLUSTER + self.bpb.cluster_count();
+        Ok((FIRST_CLUSTER..last)
+            .filter(|&cluster| decode_entry(self.bpb.fat_type, &fat, cluster) == CLUSTER_FREE)
+            .count() as u32)
+    }
+
+    /// Byte offset of the start of FAT copy `copy`.
+    fn fat_offset(&self, copy: u32) -> u64 {
+        (self.bpb.first_fat_sector() + copy * self.bpb.sectors_per_fat) as u64
+            * self.bpb.bytes_per_sector as u64
+    }
+
+    fn entry_position(&self, cluster: u32) -> (u64, usize) {
+        match self.bpb.fat_type {
+            FatType::Fat12 => ((cluster + cluster / 2) as u64, 2),
+            FatType::Fat16 => (cluster as u64 * 2, 2),
+            FatType::Fat32 => (cluster as u64 * 4, 4),
+        }
+    }
+
+    /// Read the allocation table entry of `cluster`.
+    pub fn entry(&self, cluster: u32) -> Result<u32, FsError> {
+        let (position, width) = self.entry_position(cluster);
+        let mut bytes = [0u8; 4];
+        self.read_bytes(self.fat_offset(0) + position, &mut bytes[..width])?;
+        let value = u32::from_le_bytes(bytes);
+        Ok(match self.bpb.fat_type {
+            FatType::Fat12 if cluster % 2 == 1 => value >> 4,
+            FatType::Fat12 => value & 0xfff,
+            FatType::Fat16 => value,
+            FatType::Fat32 => value & FAT32_ENTRY_MASK,
+        })
+    }
+
+    /// Update the entry of `cluster` in every copy of the table.
+    fn set_entry(&mut self, cluster: u32, value: u32) -> Result<(), FsError> {
+        let (position, width) = self.entry_position(cluster);
+        for copy in 0..self.bpb.fat_count {
+            let offset = self.fat_offset(copy) + position;
+            let mut bytes = [0u8; 4];
+            self.read_bytes(offset, &mut bytes[..width])?;
+            let old = u32::from_le_bytes(bytes);
+            let new = match self.bpb.fat_type {
+                FatType::Fat12 if cluster % 2 == 1 => old & 0x000f | value << 4,
+                FatType::Fat12 => old & 0xf000 | value & 0xfff,
+                FatType::Fat16 => value & 0xffff,
+                // The top four bits are reserved and must be preserved.
+                FatType::Fat32 => old & !FAT32_ENTRY_MASK | value & FAT32_ENTRY_MASK,
+            };
+            self.write_bytes(offset, &new.to_le_bytes()[..width])?;
+        }
+        Ok(())
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.bpb.fat_type {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => FAT32_ENTRY_MASK,
+        }
+    }
+
+    /// The cluster after `cluster` in its chain, or `None` at the end.
+    pub fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let value = self.entry(cluster)?;
+        // Values from 0xff8 (or 0xfff8, 0x0ffffff8) up mark the end of a chain.
+        if value >= self.end_of_chain() & !0x7 {
+            Ok(None)
+        } else if self.bpb.is_data_cluster(value) {
+            Ok(Some(value))
+        } else {
+            Err(FsError::Corrupt)
+        }
+    }
+
+    /// Collect the clusters of the chain starting at `start`.
+    pub fn chain(&self, start: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = Some(start);
+        while let Some(current) = cluster {
+            // A chain longer than the volume has clusters must loop.
+            if !self.bpb.is_data_cluster(current)
+                || chain.len() >= self.bpb.cluster_count() as usize
+            {
+                return Err(FsError::Corrupt);
+            }
+            chain.push(current);
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(chain)
+    }
+
+    /// Allocate a zeroed cluster, appending it to the chain ending at `previous` if given.
+    pub fn allocate(&mut self, previous: Option<u32>) -> Result<u32, FsError> {
+        if self.free_clusters == 0 {
+            return Err(FsError::NoSpace);
+        }
+        let count = self.bpb.cluster_count();
+        let start = self
+            .next_free
+            .clamp(FIRST_CLUSTER, FIRST_CLUSTER + count - 1);
+        let mut cluster = start;
+        loop {
+            if self.entry(cluster)? == CLUSTER_FREE {
+                break;
+            }
+            cluster = if cluster + 1 == FIRST_CLUSTER + count {
+                FIRST_CLUSTER
+            } else {
+                cluster + 1
+            };
+            if cluster == start {
+                self.free_clusters = 0;
+                return Err(FsError::NoSpace);
+            }
+        }
+
+        let zeros = vec![0u8; self.bpb.cluster_size() as usize];
+        self.write_bytes(self.bpb.cluster_offset(cluster), &zeros)?;
+        self.set_entry(cluster, self.end_of_chain())?;
+        if let Some(previous) = previous {
+            self.set_entry(previous, cluster)?;
+        }
+
+        self.free_clusters -= 1;
+        self.next_free = cluster + 1;
+        self.fs_info_dirty = true;
+        Ok(cluster)
+    }
+
+    /// Free every cluster of the chain starting at `start`.
+    pub fn free_chain(&mut self, start: u32) -> Result<(), FsError> {
+        for cluster in self.chain(start)? {
+            self.set_entry(cluster, CLUSTER_FREE)?;
+            self.free_clusters += 1;
+        }
+        self.fs_info_dirty = true;
+        Ok(())
+    }
+
+    /// Cut the chain after `last`, freeing the clusters that followed it.
+    pub fn truncate_chain(&mut self, last: u32) -> Result<(), FsError> {
+        if let Some(next) = self.next_cluster(last)? {
+            self.set_entry(last, self.end_of_chain())?;
+            self.free_chain(next)?;
+        }
+        Ok(())
+    }
+
+    /// Read every entry slot of a directory.
+    pub fn read_directory(&self, directory: Directory) -> Result<Vec<u8>, FsError> {
+        match directory {
+            Directory::FixedRoot => {
+                let mut data = vec![0u8; self.bpb.root_entry_count as usize * ENTRY_SIZE];
+                let offset = self.bpb.root_dir_sector() as u64 * self.bpb.bytes_per_sector as u64;
+                self.read_bytes(offset, &mut data)?;
+                Ok(data)
+            }
+            Directory::Clusters(start) => {
+                let cluster_size = self.bpb.cluster_size() as usize;
+                let chain = self.chain(start)?;
+                let mut data = vec![0u8; chain.len() * cluster_size];
+                for (cluster, chunk) in chain.iter().zip(data.chunks_mut(cluster_size)) {
+                    self.read_bytes(self.bpb.cluster_offset(*cluster), chunk)?;
+                }
+                Ok(data)
+            }
+        }
+    }
+
+    /// Byte offset of entry `slot` of a directory.
+    pub fn slot_offset(&self, directory: Directory, slot: usize) -> Result<u64, FsError> {
+        let position = (slot * ENTRY_SIZE) as u64;
+        match directory {
+            Directory::FixedRoot if slot < self.bpb.root_entry_count as usize => {
+                Ok(self.bpb.root_dir_sector() as u64 * self.bpb.bytes_per_sector as u64 + position)
+            }
+            Directory::FixedRoot => Err(FsError::Corrupt),
+            Directory::Clusters(start) => {
+                let cluster_size = self.bpb.cluster_size() as u64;
+                let mut cluster = start;
+                for _ in 0..position / cluster_size {
+                    cluster = self.next_cluster(cluster)?.ok_or(FsError::Corrupt)?;
+                }
+                Ok(self.bpb.cluster_offset(cluster) + position % cluster_size)
+            }
+        }
+    }
+
+    /// Grow a directory by one zeroed cluster. The fixed root directory cannot grow.
+    pub fn extend_directory(&mut self, directory: Directory) -> Result<(), FsError> {
+        match directory {
+            Directory::FixedRoot => Err(FsError::NoSpace),
+            Directory::Clusters(start) => {
+                let last = *self.chain(start)?.last().ok_or(FsError::Corrupt)?;
+                self.allocate(Some(last)).map(|_| ())
+            }
+        }
+    }
+
+    /// Write the FSInfo counters if they changed, then flush the sector cache.
+    pub fn sync(&mut self) -> Result<(), FsError> {
+        if self.fs_info_dirty
+            && let Some(offset) = self.fs_info_offset()
+        {
+            let mut sector = [0u8; 512];
+            self.read_bytes(offset, &mut sector)?;
+            sector[0..4].copy_from_slice(&FS_INFO_LEAD_SIGNATURE.to_le_bytes());
+            sector[484..488].copy_from_slice(&FS_INFO_STRUCT_SIGNATURE.to_le_bytes());
+            sector[488..492].copy_from_slice(&self.free_clusters.to_le_bytes());
+            sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+            sector[508..512].copy_from_slice(&FS_INFO_TRAIL_SIGNATURE.to_le_bytes());
+            self.write_bytes(offset, &sector)?;
+        }
+        self.fs_info_dirty = false;
+        self.device.flush()?;
+        Ok(())
+    }
+}
+
+/// Decode the entry of `cluster` from an in-memory copy of the table.
+fn decode_entry(fat_type: FatType, fat: &[u8], cluster: u32) -> u32 {
+    let cluster = cluster as usize;
+    match fat_type {
+        FatType::Fat12 => {
+            let offset = cluster + cluster / 2;
+            let value = u16::from_le_bytes([fat[offset], fat[offset + 1]]) as u32;
+            if cluster % 2 == 1 {
+                value >> 4
+            } else {
+                value & 0xfff
+            }
+        }
+        FatType::Fat16 => u16::from_le_bytes([fat[cluster * 2], fat[cluster * 2 + 1]]) as u32,
+        FatType::Fat32 => {
+            u32::from_le_bytes(fat[cluster * 4..cluster * 4 + 4].try_into().unwrap())
+                & FAT32_ENTRY_MASK
+        }
+    }
+}
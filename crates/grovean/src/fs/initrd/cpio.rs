@@ -0,0 +1,88 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{normalize_path, Entry, EntryKind, InitrdError};
+
+const HEADER_SIZE: usize = 110;
+const MAGIC: &[u8; 6] = b"070701";
+/// The same layout with a checksum of the file data, which is not verified.
+const MAGIC_CRC: &[u8; 6] = b"070702";
+const TRAILER: &str = "TRAILER!!!";
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_FILE: u32 = 0o100000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+/// Header fields, in the order they appear after the magic.
+const FIELD_MODE: usize = 1;
+const FIELD_FILE_SIZE: usize = 6;
+const FIELD_NAME_SIZE: usize = 11;
+
+/// Check for the "new ASCII" cpio magic in the first header.
+pub fn is_newc(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE && (&data[..6] == MAGIC || &data[..6] == MAGIC_CRC)
+}
+
+/// Parse a cpio archive in the "new ASCII" (newc) format, as produced by `cpio -H newc`.
+pub fn parse(data: &'static [u8]) -> Result<Vec<Entry>, InitrdError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let header = data
+            .get(offset..offset + HEADER_SIZE)
+            .ok_or(InitrdError::Truncated)?;
+        if !is_newc(header) {
+            return Err(InitrdError::InvalidHeader);
+        }
+
+        let mode = hex_field(header, FIELD_MODE)?;
+        let file_size = hex_field(header, FIELD_FILE_SIZE)? as usize;
+        let name_size = hex_field(header, FIELD_NAME_SIZE)? as usize;
+
+        // The name includes its NUL terminator, and both name and data are padded to four bytes
+        // from the start of the header.
+        let name_start = offset + HEADER_SIZE;
+        let name = data
+            .get(name_start..name_start + name_size)
+            .ok_or(InitrdError::Truncated)?;
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+        let name = String::from_utf8_lossy(name);
+        if name == TRAILER {
+            return Ok(entries);
+        }
+
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let contents = data
+            .get(data_start..data_start + file_size)
+            .ok_or(InitrdError::Truncated)?;
+
+        let kind = match mode & MODE_TYPE_MASK {
+            MODE_FILE => Some(EntryKind::File),
+            MODE_DIRECTORY => Some(EntryKind::Directory),
+            MODE_SYMLINK => Some(EntryKind::Symlink),
+            _ => None,
+        };
+        let path = normalize_path(&name);
+        if let Some(kind) = kind
+            && !path.is_empty()
+        {
+            entries.push(Entry {
+                path,
+                kind,
+                mode: mode & 0o7777,
+                data: contents,
+            });
+        }
+
+        offset = (data_start + file_size).next_multiple_of(4);
+    }
+}
+
+fn hex_field(header: &[u8], index: usize) -> Result<u32, InitrdError> {
+    let start = MAGIC.len() + index * 8;
+    let digits =
+        core::str::from_utf8(&header[start..start + 8]).map_err(|_| InitrdError::InvalidHeader)?;
+    u32::from_str_radix(digits, 16).map_err(|_| InitrdError::InvalidHeader)
+}
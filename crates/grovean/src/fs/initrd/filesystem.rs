@@ -0,0 +1,237 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{Archive, Entry, EntryKind};
+use crate::fs::vfs::{DirEntry, FileType, Filesystem, FsError, Inode, Metadata};
+
+const ROOT_INODE: u64 = 1;
+
+/// Read-only view of an archive for the VFS. Inode numbers follow the archive order.
+pub struct InitrdFs {
+    archive: Arc<Archive>,
+}
+
+impl InitrdFs {
+    pub fn new(archive: Arc<Archive>) -> Self {
+        Self { archive }
+    }
+}
+
+impl Filesystem for InitrdFs {
+    fn name(&self) -> &str {
+        "initrd"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(InitrdInode {
+            archive: self.archive.clone(),
+            index: None,
+        })
+    }
+}
+
+fn file_type(kind: EntryKind) -> FileType {
+    match kind {
+        EntryKind::File => FileType::Regular,
+        EntryKind::Directory => FileType::Directory,
+        EntryKind::Symlink => FileType::Symlink,
+    }
+}
+
+struct InitrdInode {
+    archive: Arc<Archive>,
+    /// Position in the archive, or `None` for the root directory.
+    index: Option<usize>,
+}
+
+impl InitrdInode {
+    fn entry(&self) -> Option<&Entry> {
+        self.index.map(|index| &self.archive.entries()[index])
+    }
+
+    fn path(&self) -> &str {
+        self.entry().map_or("", |entry| &entry.path)
+    }
+
+    fn file_type(&self) -> FileType {
+        self.entry()
+            .map_or(FileType::Directory, |entry| file_type(entry.kind))
+    }
+
+    fn inode_number(index: usize) -> u64 {
+        ROOT_INODE + 1 + index as u64
+    }
+
+    /// Fail unless this is a directory, which any modification of it then runs into.
+    fn check_directory(&self) -> Result<(), FsError> {
+        match self.file_type() {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+impl Inode for InitrdInode {
+    fn metadata(&self) -> Metadata {
+        let file_type = self.file_type();
+        Metadata {
+            inode: self.index.map_or(ROOT_INODE, Self::inode_number),
+            file_type,
+            mode: self.entry().map_or(0o755, |entry| entry.mode),
+            size: match file_type {
+                FileType::Directory => 0,
+                _ => self.entry().map_or(0, |entry| entry.data.len() as u64),
+            },
+            links: if file_type == FileType::Directory {
+                2
+            } else {
+                1
+            },
+            accessed: 0,
+            modified: 0,
+            changed: 0,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+        let index = self
+            .archive
+            .entries()
+            .iter()
+            .position(|entry| entry.parent() == self.path() && entry.name() == name)
+            .ok_or(FsError::NotFound)?;
+        Ok(Arc::new(InitrdInode {
+            archive: self.archive.clone(),
+            index: Some(index),
+        }))
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_directory()?;
+        Ok(self
+            .archive
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.parent() == self.path())
+            .map(|(index, entry)| DirEntry {
+                name: entry.name().into(),
+                inode: Self::inode_number(index),
+                file_type: file_type(entry.kind),
+            })
+            .collect())
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _file_type: FileType,
+        _mode: u32,
+    ) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_directory()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&self, _name: &str) -> Result<(), FsError> {
+        self.check_directory()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let entry = match self.entry() {
+            Some(entry) if entry.kind == EntryKind::File => entry,
+            Some(entry) if entry.kind == EntryKind::Symlink => {
+                return Err(FsError::InvalidArgument)
+            }
+            _ => return Err(FsError::IsADirectory),
+        };
+        let start = (offset as usize).min(entry.data.len());
+        let length = buffer.len().min(entry.data.len() - start);
+        buffer[..length].copy_from_slice(&entry.data[start..start + length]);
+        Ok(length)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        match self.entry() {
+            Some(entry) if entry.kind == EntryKind::Symlink => {
+                Ok(String::from_utf8_lossy(entry.data).into())
+            }
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::InitrdFs;
+    use crate::fs::initrd::{Archive, Entry, EntryKind};
+    use crate::fs::vfs::{FileType, FsError, OpenFlags, Vfs};
+
+    fn entry(path: &str, kind: EntryKind, data: &'static [u8]) -> Entry {
+        Entry {
+            path: path.into(),
+            kind,
+            mode: 0o644,
+            data,
+        }
+    }
+
+    fn mounted() -> Vfs {
+        let archive = Archive::new(vec![
+            entry("etc/motd", EntryKind::File, b"welcome"),
+            entry("motd", EntryKind::Symlink, b"etc/motd"),
+        ]);
+        let vfs = Vfs::new();
+        vfs.mount("/", Arc::new(InitrdFs::new(Arc::new(archive))))
+            .expect("mount");
+        vfs
+    }
+
+    #[kunit]
+    fn files_are_readable_through_the_vfs() {
+        let vfs = mounted();
+        let file = vfs.open("/motd", OpenFlags::READ).expect("open");
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(file.read(&mut buffer).expect("read"), 7);
+        assert_eq!(&buffer[..7], b"welcome");
+        assert_eq!(
+            vfs.metadata("/etc").expect("stat").file_type,
+            FileType::Directory
+        );
+        assert_eq!(vfs.read_dir("/").expect("list").len(), 2);
+    }
+
+    #[kunit]
+    fn modifications_are_rejected() {
+        let vfs = mounted();
+
+        assert_eq!(
+            vfs.open("/new", OpenFlags::WRITE.union(OpenFlags::CREATE))
+                .err(),
+            Some(FsError::ReadOnly)
+        );
+        let file = vfs.open("/etc/motd", OpenFlags::WRITE).expect("open");
+        assert_eq!(file.write(b"x").err(), Some(FsError::ReadOnly));
+        assert_eq!(vfs.remove("/etc/motd").err(), Some(FsError::ReadOnly));
+    }
+}
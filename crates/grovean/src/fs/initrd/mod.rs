@@ -0,0 +1,313 @@
+pub mod cpio;
+pub mod filesystem;
+pub mod tar;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(not(test))]
+use limine::request::ModuleRequest;
+use spin::Mutex;
+
+pub use filesystem::InitrdFs;
+
+#[cfg(not(test))]
+#[used]
+#[unsafe(link_section = ".requests")]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+/// The `module_string` that marks a module as the initial ramdisk when several are loaded.
+pub const MODULE_STRING: &str = "initrd";
+
+static INITRD: Mutex<Option<Arc<Archive>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitrdError {
+    ModuleUnavailable,
+    UnknownFormat,
+    InvalidHeader,
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A file in the archive. The data of a symbolic link is its target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path relative to the archive root, without leading or trailing slashes.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits.
+    pub mode: u32,
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    /// The final component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// The path of the containing directory, empty for top-level entries.
+    pub fn parent(&self) -> &str {
+        self.path.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+}
+
+/// A parsed archive whose file data still lives in the loaded module.
+#[derive(Debug)]
+pub struct Archive {
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    /// Build an archive from parsed entries, adding the directories archivers leave implicit.
+    /// Later entries replace earlier ones with the same path.
+    fn new(parsed: Vec<Entry>) -> Self {
+        let mut entries: Vec<Entry> = Vec::with_capacity(parsed.len());
+        for entry in parsed {
+            let mut parent = entry.parent();
+            while !parent.is_empty() && !entries.iter().any(|existing| existing.path == parent) {
+                entries.push(Entry {
+                    path: parent.into(),
+                    kind: EntryKind::Directory,
+                    mode: 0o755,
+                    data: &[],
+                });
+                parent = parent.rsplit_once('/').map_or("", |(parent, _)| parent);
+            }
+
+            match entries
+                .iter_mut()
+                .find(|existing| existing.path == entry.path)
+            {
+                Some(existing) => *existing = entry,
+                None => entries.push(entry),
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Find an entry by path. Leading slashes and `.` components are ignored.
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        let path = normalize_path(path);
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// List the entries directly inside the directory at `path`; the root is `""` or `"/"`.
+    pub fn children<'a>(&'a self, path: &str) -> impl Iterator<Item = &'a Entry> {
+        let path = normalize_path(path);
+        self.entries
+            .iter()
+            .filter(move |entry| entry.parent() == path)
+    }
+}
+
+/// Parse a ustar or cpio newc archive, detected by its magic.
+pub fn parse(data: &'static [u8]) -> Result<Archive, InitrdError> {
+    let entries = if tar::is_ustar(data) {
+        tar::parse(data)?
+    } else if cpio::is_newc(data) {
+        cpio::parse(data)?
+    } else {
+        return Err(InitrdError::UnknownFormat);
+    };
+    Ok(Archive::new(entries))
+}
+
+/// Strip leading `/`, `./` and empty or `.` components, which archivers emit inconsistently.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    for component in path
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+    {
+        if !normalized.is_empty() {
+            normalized.push('/');
+        }
+        normalized.push_str(component);
+    }
+    normalized
+}
+
+/// The archive loaded at boot, if any.
+pub fn archive() -> Option<Arc<Archive>> {
+    INITRD.lock().clone()
+}
+
+/// Parse the initial ramdisk handed over by Limine.
+pub fn init() {
+    #[cfg(not(test))]
+    match load() {
+        Ok(archive) => {
+            crate::info_ln!("initrd: {} entries", archive.entries().len());
+            *INITRD.lock() = Some(Arc::new(archive));
+        }
+        Err(InitrdError::ModuleUnavailable) => {}
+        Err(error) => {
+            crate::warn_ln!("initrd: failed to parse module: {:?}", error);
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn load() -> Result<Archive, InitrdError> {
+    let modules = MODULE_REQUEST
+        .get_response()
+        .map(|response| response.modules())
+        .unwrap_or_default();
+
+    // Prefer the module tagged as the initrd, falling back to the first one.
+    let module = modules
+        .iter()
+        .find(|module| module.string().to_bytes() == MODULE_STRING.as_bytes())
+        .or(modules.first())
+        .ok_or(InitrdError::ModuleUnavailable)?;
+
+    // Limine reports modules through the direct map, and their memory is never reclaimed.
+    let data =
+        unsafe { core::slice::from_raw_parts(module.addr() as *const u8, module.size() as usize) };
+    parse(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{normalize_path, parse, EntryKind, InitrdError};
+
+    fn tar_header(path: &str, type_flag: u8, size: usize, link: &str) -> [u8; 512] {
+        let mut header = [0u8; 512];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+        header[156] = type_flag;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+        header
+    }
+
+    fn tar_image() -> &'static [u8] {
+        let mut image = Vec::new();
+        image.extend_from_slice(&tar_header("./etc/", b'5', 0, ""));
+        image.extend_from_slice(&tar_header("./etc/motd", b'0', 5, ""));
+        let mut data = vec![0u8; 512];
+        data[..5].copy_from_slice(b"hello");
+        image.extend_from_slice(&data);
+        image.extend_from_slice(&tar_header("./bin/sh", b'2', 0, "/bin/init"));
+        image.extend_from_slice(&[0; 1024]);
+        image.leak()
+    }
+
+    fn cpio_entry(image: &mut Vec<u8>, path: &str, mode: u32, data: &[u8]) {
+        image.extend_from_slice(b"070701");
+        let fields = [
+            1,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            data.len() as u32,
+            0,
+            0,
+            0,
+            0,
+            path.len() as u32 + 1,
+            0,
+        ];
+        for field in fields {
+            image.extend_from_slice(format!("{field:08x}").as_bytes());
+        }
+        image.extend_from_slice(path.as_bytes());
+        image.push(0);
+        image.resize(image.len().next_multiple_of(4), 0);
+        image.extend_from_slice(data);
+        image.resize(image.len().next_multiple_of(4), 0);
+    }
+
+    fn cpio_image() -> &'static [u8] {
+        let mut image = Vec::new();
+        cpio_entry(&mut image, ".", 0o040755, &[]);
+        cpio_entry(&mut image, "bin/init", 0o100755, b"\x7fELF");
+        cpio_entry(&mut image, "etc/hostname", 0o100644, b"grovean\n");
+        cpio_entry(&mut image, "TRAILER!!!", 0, &[]);
+        image.leak()
+    }
+
+    #[kunit]
+    fn parses_ustar_files_directories_and_links() {
+        let archive = parse(tar_image()).expect("valid tar");
+
+        let motd = archive.find("/etc/motd").expect("motd");
+        assert_eq!(motd.kind, EntryKind::File);
+        assert_eq!(motd.data, b"hello");
+        assert_eq!(motd.mode, 0o644);
+
+        let link = archive.find("bin/sh").expect("link");
+        assert_eq!(link.kind, EntryKind::Symlink);
+        assert_eq!(link.data, b"/bin/init");
+        assert_eq!(
+            archive.find("bin").map(|entry| entry.kind),
+            Some(EntryKind::Directory)
+        );
+    }
+
+    #[kunit]
+    fn rejects_corrupt_tar_checksum() {
+        let image = tar_image().to_vec().leak();
+        image[0] ^= 1;
+        assert_eq!(parse(image).err(), Some(InitrdError::InvalidHeader));
+    }
+
+    #[kunit]
+    fn parses_cpio_newc_with_implicit_directories() {
+        let archive = parse(cpio_image()).expect("valid cpio");
+
+        let init = archive.find("bin/init").expect("init");
+        assert_eq!(init.data, b"\x7fELF");
+        assert_eq!(init.mode, 0o755);
+        assert_eq!(
+            archive.find("etc/hostname").expect("hostname").data,
+            b"grovean\n"
+        );
+
+        let mut top: Vec<&str> = archive.children("/").map(|entry| entry.name()).collect();
+        top.sort();
+        assert_eq!(top, ["bin", "etc"]);
+    }
+
+    #[kunit]
+    fn truncated_cpio_is_reported() {
+        let image = cpio_image();
+        assert_eq!(parse(&image[..120]).err(), Some(InitrdError::Truncated));
+    }
+
+    #[kunit]
+    fn unknown_formats_are_rejected() {
+        assert_eq!(parse(&[0; 1024]).err(), Some(InitrdError::UnknownFormat));
+    }
+
+    #[kunit]
+    fn normalizes_archive_paths() {
+        assert_eq!(normalize_path("./usr//lib/./x/"), "usr/lib/x");
+        assert_eq!(normalize_path("/"), "");
+    }
+}
@@ -0,0 +1,125 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{normalize_path, Entry, EntryKind, InitrdError};
+
+const BLOCK_SIZE: usize = 512;
+const MAGIC_OFFSET: usize = 257;
+const MAGIC: &[u8; 5] = b"ustar";
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_FILE_OLD: u8 = 0;
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIRECTORY: u8 = b'5';
+
+/// Check for the ustar magic in the first header.
+pub fn is_ustar(data: &[u8]) -> bool {
+    data.len() >= BLOCK_SIZE && &data[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()] == MAGIC
+}
+
+/// Parse a ustar archive. Entries of types other than regular files, directories and symbolic
+/// links are skipped.
+pub fn parse(data: &'static [u8]) -> Result<Vec<Entry>, InitrdError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + BLOCK_SIZE];
+        // The archive ends with zero-filled blocks.
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok(entries);
+        }
+        if &header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()] != MAGIC {
+            return Err(InitrdError::InvalidHeader);
+        }
+        if !checksum_matches(header) {
+            return Err(InitrdError::InvalidHeader);
+        }
+
+        let size = octal(&header[124..136])? as usize;
+        let data_start = offset + BLOCK_SIZE;
+        let data_end = data_start
+            .checked_add(size)
+            .filter(|&end| end <= data.len())
+            .ok_or(InitrdError::Truncated)?;
+
+        let mut path = String::new();
+        let prefix = text(&header[345..500]);
+        if !prefix.is_empty() {
+            path.push_str(&prefix);
+            path.push('/');
+        }
+        path.push_str(&text(&header[0..100]));
+
+        let kind = match header[156] {
+            TYPE_FILE | TYPE_FILE_OLD => Some(EntryKind::File),
+            TYPE_DIRECTORY => Some(EntryKind::Directory),
+            TYPE_SYMLINK => Some(EntryKind::Symlink),
+            _ => None,
+        };
+        let contents = match kind {
+            Some(EntryKind::Symlink) => field(&header[157..257]),
+            _ => &data[data_start..data_end],
+        };
+
+        let path = normalize_path(&path);
+        if let Some(kind) = kind
+            && !path.is_empty()
+        {
+            entries.push(Entry {
+                path,
+                kind,
+                mode: octal(&header[100..108])? as u32 & 0o7777,
+                data: contents,
+            });
+        }
+
+        offset = data_start + size.next_multiple_of(BLOCK_SIZE);
+    }
+
+    // Tolerate archives that stop without the terminating blocks.
+    Ok(entries)
+}
+
+/// The checksum treats its own field as spaces.
+fn checksum_matches(header: &[u8]) -> bool {
+    let Ok(expected) = octal(&header[148..156]) else {
+        return false;
+    };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| {
+            if (148..156).contains(&index) {
+                b' ' as u64
+            } else {
+                byte as u64
+            }
+        })
+        .sum();
+    sum == expected
+}
+
+/// Parse a NUL or space terminated octal field.
+fn octal(field: &[u8]) -> Result<u64, InitrdError> {
+    field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|&&byte| byte != 0 && byte != b' ')
+        .try_fold(0u64, |value, &byte| match byte {
+            b'0'..=b'7' => Ok(value << 3 | (byte - b'0') as u64),
+            _ => Err(InitrdError::InvalidHeader),
+        })
+}
+
+fn field(bytes: &[u8]) -> &[u8] {
+    let length = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..length]
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(field(bytes)).into()
+}
@@ -0,0 +1,111 @@
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod initrd;
+pub mod procfs;
+pub mod tmpfs;
+pub mod vfs;
+
+use alloc::sync::Arc;
+
+use crate::dev::block::{self, BlockDevice};
+
+/// Initialize the filesystems available at boot.
+///
+/// The initrd becomes the root when one was loaded, with a tmpfs on `/tmp` if it has that
+/// directory. Without an initrd the root is a tmpfs, so there is always somewhere to write.
+/// Device files appear on `/dev` and kernel state on `/proc`. Block devices holding FAT or
+/// ext2 filesystems are mounted on `/boot` and `/mnt`.
+pub fn init() {
+    vfs::page_cache::init();
+    initrd::init();
+
+    let result = match initrd::archive() {
+        Some(archive) => mount_initrd_root(archive),
+        None => mount_tmpfs_root(),
+    };
+    if let Err(error) = result {
+        crate::warn_ln!("vfs: failed to mount the root filesystem: {:?}", error);
+        return;
+    }
+    for (path, filesystem) in [
+        ("/dev", devfs::devfs() as Arc<dyn vfs::Filesystem>),
+        ("/proc", procfs::procfs()),
+    ] {
+        if let Err(error) = mount_at(path, filesystem.clone()) {
+            crate::warn_ln!(
+                "vfs: failed to mount {} on {}: {:?}",
+                filesystem.name(),
+                path,
+                error
+            );
+        }
+    }
+    mount_volumes();
+}
+
+fn mount_initrd_root(archive: Arc<initrd::Archive>) -> Result<(), vfs::FsError> {
+    let vfs = vfs::vfs();
+    vfs.mount("/", Arc::new(initrd::InitrdFs::new(archive)))?;
+    crate::info_ln!("vfs: mounted initrd on /");
+
+    if vfs
+        .metadata("/tmp")
+        .is_ok_and(|metadata| metadata.file_type == vfs::FileType::Directory)
+    {
+        vfs.mount("/tmp", Arc::new(tmpfs::Tmpfs::new()))?;
+    }
+    Ok(())
+}
+
+fn mount_tmpfs_root() -> Result<(), vfs::FsError> {
+    let vfs = vfs::vfs();
+    vfs.mount("/", Arc::new(tmpfs::Tmpfs::new()))?;
+    vfs.create_dir("/tmp", 0o1777)?;
+    crate::info_ln!("vfs: no initrd, mounted tmpfs on /");
+    Ok(())
+}
+
+/// Mount the first FAT volume on `/boot` and the first ext2 volume on `/mnt`.
+fn mount_volumes() {
+    let mut boot: Option<(Arc<dyn BlockDevice>, Arc<dyn vfs::Filesystem>)> = None;
+    let mut data: Option<(Arc<dyn BlockDevice>, Arc<dyn vfs::Filesystem>)> = None;
+    for device in (0..block::device_count()).filter_map(block::device) {
+        if boot.is_none()
+            && let Ok(fat) = fat::FatFs::new(device.clone())
+        {
+            boot = Some((device, Arc::new(fat)));
+        } else if data.is_none()
+            && let Ok(ext2) = ext2::Ext2Fs::new(device.clone())
+        {
+            data = Some((device, Arc::new(ext2)));
+        }
+    }
+
+    for (path, volume) in [("/boot", boot), ("/mnt", data)] {
+        let Some((device, filesystem)) = volume else {
+            continue;
+        };
+        match mount_at(path, filesystem) {
+            Ok(()) => {
+                crate::info_ln!("vfs: mounted {} on {}", device.name(), path);
+            }
+            Err(error) => {
+                crate::warn_ln!(
+                    "vfs: failed to mount {} on {}: {:?}",
+                    device.name(),
+                    path,
+                    error
+                );
+            }
+        }
+    }
+}
+
+fn mount_at(path: &str, filesystem: Arc<dyn vfs::Filesystem>) -> Result<(), vfs::FsError> {
+    let vfs = vfs::vfs();
+    if vfs.metadata(path).is_err() {
+        vfs.create_dir(path, 0o755)?;
+    }
+    vfs.mount(path, filesystem)
+}
@@ -0,0 +1,536 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::allocator::{self, HeapStats};
+use crate::fs::vfs::page_cache::{self, PageCacheStats, PAGE_SIZE};
+use crate::fs::vfs::{self, DirEntry, FileType, Filesystem, FsError, Inode, Metadata, MountInfo};
+use crate::interrupts::{self, InterruptCount};
+use crate::klog;
+use crate::memory::frame::{self, FrameAllocatorStats, FRAME_SIZE};
+use crate::memory::memory_map::{self, MemoryRegion, MemoryRegionKind};
+use crate::task::{self, TaskInfo};
+use crate::time;
+
+const ROOT_INODE: u64 = 1;
+
+/// Produces the current contents of a file each time it is read.
+pub type Generator = Arc<dyn Fn() -> String + Send + Sync>;
+
+type KernelFile = (&'static str, fn() -> String);
+
+const KERNEL_FILES: [KernelFile; 7] = [
+    ("memmap", memmap),
+    ("meminfo", meminfo),
+    ("interrupts", || format_interrupts(&interrupts::counts())),
+    ("tasks", || format_tasks(&task::tasks())),
+    ("mounts", || format_mounts(&vfs::vfs().mounts())),
+    ("kmsg", klog::contents),
+    ("uptime", uptime),
+];
+
+struct Registry {
+    next_inode: u64,
+    files: BTreeMap<String, Arc<ProcInode>>,
+}
+
+/// A flat directory of read-only text files generated from live kernel state.
+pub struct ProcFs {
+    root: Arc<RootInode>,
+}
+
+impl ProcFs {
+    /// Create an instance without any files.
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(RootInode {
+                registry: Mutex::new(Registry {
+                    next_inode: ROOT_INODE + 1,
+                    files: BTreeMap::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Create an instance with the kernel's own files.
+    pub fn with_kernel_files() -> Self {
+        let procfs = Self::new();
+        for (name, generate) in KERNEL_FILES {
+            procfs
+                .register(name, Arc::new(generate))
+                .expect("kernel procfs names are unique");
+        }
+        procfs
+    }
+
+    /// Add a file called `name` whose contents come from `generate`.
+    pub fn register(&self, name: &str, generate: Generator) -> Result<(), FsError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut registry = self.root.registry.lock();
+        if registry.files.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let inode = registry.next_inode;
+        registry.next_inode += 1;
+        registry
+            .files
+            .insert(name.to_string(), Arc::new(ProcInode { inode, generate }));
+        Ok(())
+    }
+
+    pub fn unregister(&self, name: &str) -> Result<(), FsError> {
+        self.root
+            .registry
+            .lock()
+            .files
+            .remove(name)
+            .map(|_| ())
+            .ok_or(FsError::NotFound)
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filesystem for ProcFs {
+    fn name(&self) -> &str {
+        "procfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct RootInode {
+    registry: Mutex<Registry>,
+}
+
+impl Inode for RootInode {
+    fn metadata(&self) -> Metadata {
+        let now = time::now();
+        Metadata {
+            inode: ROOT_INODE,
+            file_type: FileType::Directory,
+            mode: 0o555,
+            size: 0,
+            links: 2,
+            accessed: now,
+            modified: now,
+            changed: now,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match self.registry.lock().files.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .registry
+            .lock()
+            .files
+            .iter()
+            .map(|(name, inode)| DirEntry {
+                name: name.clone(),
+                inode: inode.inode,
+                file_type: FileType::Regular,
+            })
+            .collect())
+    }
+
+    fn cache_lookups(&self) -> bool {
+        false
+    }
+
+    fn create(&self, _: &str, _: FileType, _: u32) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&self, _: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+struct ProcInode {
+    inode: u64,
+    generate: Generator,
+}
+
+impl Inode for ProcInode {
+    /// The size is reported as zero, since knowing it would mean generating the contents.
+    fn metadata(&self) -> Metadata {
+        let now = time::now();
+        Metadata {
+            inode: self.inode,
+            file_type: FileType::Regular,
+            mode: 0o444,
+            size: 0,
+            links: 1,
+            accessed: now,
+            modified: now,
+            changed: now,
+        }
+    }
+
+    fn lookup(&self, _: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Each read generates the contents afresh, so a file read in pieces may mix snapshots.
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let contents = (self.generate)();
+        let bytes = contents.as_bytes();
+        let available = &bytes[offset.min(bytes.len() as u64) as usize..];
+        let count = available.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&available[..count]);
+        Ok(count)
+    }
+
+    fn write_at(&self, _: u64, _: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+fn memmap() -> String {
+    memory_map::with_boot_memory_map(|map| format_memory_map(map.regions()))
+}
+
+fn meminfo() -> String {
+    let usable = memory_map::with_boot_memory_map(|map| map.usable_memory_bytes());
+    format_meminfo(
+        allocator::stats(),
+        frame::stats(),
+        page_cache::page_cache().stats(),
+        usable,
+    )
+}
+
+/// Seconds since boot with hundredths, as `/proc/uptime` has on other systems.
+fn uptime() -> String {
+    let uptime = time::uptime();
+    format!("{}.{:02}\n", uptime.as_secs(), uptime.subsec_millis() / 10)
+}
+
+fn region_kind_name(kind: MemoryRegionKind) -> &'static str {
+    match kind {
+        MemoryRegionKind::Usable => "usable",
+        MemoryRegionKind::Reserved => "reserved",
+        MemoryRegionKind::AcpiReclaimable => "acpi-reclaimable",
+        MemoryRegionKind::AcpiNvs => "acpi-nvs",
+        MemoryRegionKind::BadMemory => "bad",
+        MemoryRegionKind::BootloaderReclaimable => "bootloader-reclaimable",
+        MemoryRegionKind::ExecutableAndModules => "kernel-and-modules",
+        MemoryRegionKind::Framebuffer => "framebuffer",
+        MemoryRegionKind::Unknown => "unknown",
+    }
+}
+
+/// One line per region: the inclusive physical range, its size and its kind.
+fn format_memory_map(regions: &[MemoryRegion]) -> String {
+    let mut text = String::new();
+    for region in regions {
+        let last = region.base + region.length.saturating_sub(1);
+        let _ = writeln!(
+            text,
+            "{:#018x}-{:#018x} {:>10} kB {}",
+            region.base,
+            last,
+            region.length / 1024,
+            region_kind_name(region.kind)
+        );
+    }
+    text
+}
+
+fn format_meminfo(
+    heap: HeapStats,
+    frames: FrameAllocatorStats,
+    cache: PageCacheStats,
+    usable: u64,
+) -> String {
+    let frame_kib = FRAME_SIZE / 1024;
+    let page_kib = PAGE_SIZE as u64 / 1024;
+    let rows = [
+        ("MemUsable", usable / 1024),
+        ("FramesTotal", frames.total_frames as u64 * frame_kib),
+        ("FramesFree", frames.free_frames as u64 * frame_kib),
+        ("FramesUsed", frames.used_frames() as u64 * frame_kib),
+        ("HeapTotal", heap.size as u64 / 1024),
+        ("HeapUsed", heap.used as u64 / 1024),
+        ("HeapFree", heap.free as u64 / 1024),
+        ("Cached", cache.pages as u64 * page_kib),
+        ("Dirty", cache.dirty as u64 * page_kib),
+    ];
+
+    let mut text = String::new();
+    for (label, kib) in rows {
+        let _ = writeln!(text, "{:<12} {:>10} kB", format!("{}:", label), kib);
+    }
+    text
+}
+
+fn format_interrupts(counts: &[InterruptCount]) -> String {
+    let mut text = String::new();
+    for entry in counts {
+        let _ = writeln!(
+            text,
+            "{:>4}: {:>12} {}",
+            entry.vector,
+            entry.count,
+            entry.name.unwrap_or("")
+        );
+    }
+    text
+}
+
+/// Until there is a scheduler the only task is the boot thread on the bootstrap processor.
+fn format_tasks(tasks: &[TaskInfo]) -> String {
+    let mut text = format!(
+        "{:>4} {:<8} {:<6} {:>3} NAME\n",
+        "ID", "STATE", "PRIO", "CPU"
+    );
+    for info in tasks {
+        let cpu = info.cpu.map_or("-".into(), |cpu| cpu.to_string());
+        let _ = writeln!(
+            text,
+            "{:>4} {:<8} {:<6} {:>3} {}",
+            info.id,
+            info.state.name(),
+            info.priority.name(),
+            cpu,
+            info.name
+        );
+    }
+    text
+}
+
+fn format_mounts(mounts: &[MountInfo]) -> String {
+    let mut text = String::new();
+    for mount in mounts {
+        let _ = writeln!(text, "{} {}", mount.filesystem, mount.path);
+    }
+    text
+}
+
+lazy_static! {
+    /// The instance mounted on `/proc`.
+    static ref PROCFS: Arc<ProcFs> = Arc::new(ProcFs::with_kernel_files());
+}
+
+pub fn procfs() -> Arc<ProcFs> {
+    PROCFS.clone()
+}
+
+/// Make `/proc/<name>` show whatever `generate` returns when read.
+pub fn register(name: &str, generate: Generator) -> Result<(), FsError> {
+    PROCFS.register(name, generate)
+}
+
+pub fn unregister(name: &str) -> Result<(), FsError> {
+    PROCFS.unregister(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use kunit::kunit;
+
+    use super::{
+        format_interrupts, format_meminfo, format_memory_map, format_mounts, format_tasks, ProcFs,
+    };
+    use crate::allocator::HeapStats;
+    use crate::fs::tmpfs::Tmpfs;
+    use crate::fs::vfs::page_cache::PageCacheStats;
+    use crate::fs::vfs::{FsError, MountInfo, OpenFlags, Vfs};
+    use crate::interrupts::InterruptCount;
+    use crate::memory::frame::FrameAllocatorStats;
+    use crate::memory::memory_map::{MemoryRegion, MemoryRegionKind};
+    use crate::task::{Priority, TaskInfo, TaskState};
+
+    fn mounted(procfs: ProcFs) -> Vfs {
+        let vfs = Vfs::new();
+        vfs.mount("/", Arc::new(Tmpfs::new())).expect("root");
+        vfs.create_dir("/proc", 0o555).expect("mkdir");
+        vfs.mount("/proc", Arc::new(procfs)).expect("mount");
+        vfs
+    }
+
+    fn read_all(vfs: &Vfs, path: &str) -> String {
+        let file = vfs.open(path, OpenFlags::READ).expect("open");
+        let mut contents = vec![0u8; 256];
+        let mut length = 0;
+        loop {
+            let count = file.read(&mut contents[length..]).expect("read");
+            if count == 0 {
+                break;
+            }
+            length += count;
+        }
+        contents.truncate(length);
+        String::from_utf8(contents).expect("utf-8")
+    }
+
+    #[kunit]
+    fn files_are_generated_on_every_read() {
+        let reads = Arc::new(AtomicU64::new(0));
+        let procfs = ProcFs::new();
+        let counter = reads.clone();
+        procfs
+            .register(
+                "reads",
+                Arc::new(move || alloc::format!("{}\n", counter.fetch_add(1, Ordering::Relaxed))),
+            )
+            .expect("register");
+        let vfs = mounted(procfs);
+
+        assert_eq!(read_all(&vfs, "/proc/reads"), "0\n");
+        assert_eq!(read_all(&vfs, "/proc/reads"), "2\n");
+        assert!(reads.load(Ordering::Relaxed) >= 4);
+    }
+
+    #[kunit]
+    fn files_cannot_be_written_or_created() {
+        let procfs = ProcFs::new();
+        procfs
+            .register("fixed", Arc::new(|| String::from("x")))
+            .expect("register");
+        let vfs = mounted(procfs);
+
+        let file = vfs
+            .open("/proc/fixed", OpenFlags::READ_WRITE)
+            .expect("open");
+        assert_eq!(file.write(b"y"), Err(FsError::ReadOnly));
+        assert_eq!(
+            vfs.open("/proc/new", OpenFlags::WRITE.union(OpenFlags::CREATE))
+                .err(),
+            Some(FsError::ReadOnly)
+        );
+    }
+
+    #[kunit]
+    fn kernel_files_are_listed() {
+        let vfs = mounted(ProcFs::with_kernel_files());
+
+        let names: alloc::vec::Vec<_> = vfs
+            .read_dir("/proc")
+            .expect("read_dir")
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        for expected in ["interrupts", "kmsg", "meminfo", "memmap", "mounts", "tasks"] {
+            assert!(names.iter().any(|name| name == expected), "{}", expected);
+        }
+        assert!(read_all(&vfs, "/proc/tasks").starts_with("  ID STATE"));
+    }
+
+    #[kunit]
+    fn memory_map_lines_show_inclusive_ranges() {
+        let regions = [
+            MemoryRegion {
+                base: 0,
+                length: 0x9f000,
+                kind: MemoryRegionKind::Usable,
+            },
+            MemoryRegion {
+                base: 0x100000,
+                length: 0x1000,
+                kind: MemoryRegionKind::AcpiNvs,
+            },
+        ];
+
+        assert_eq!(
+            format_memory_map(&regions),
+            "0x0000000000000000-0x000000000009efff        636 kB usable\n\
+             0x0000000000100000-0x0000000000100fff          4 kB acpi-nvs\n"
+        );
+    }
+
+    #[kunit]
+    fn meminfo_reports_kibibytes() {
+        let heap = HeapStats {
+            size: 16 * 1024 * 1024,
+            used: 4096,
+            free: 16 * 1024 * 1024 - 4096,
+        };
+        let frames = FrameAllocatorStats {
+            total_frames: 256,
+            free_frames: 200,
+        };
+
+        let cache = PageCacheStats { pages: 3, dirty: 1 };
+
+        let text = format_meminfo(heap, frames, cache, 1024 * 1024);
+
+        assert!(text.contains("MemUsable:         1024 kB\n"));
+        assert!(text.contains("FramesUsed:         224 kB\n"));
+        assert!(text.contains("HeapUsed:             4 kB\n"));
+        assert!(text.contains("Cached:              12 kB\n"));
+    }
+
+    #[kunit]
+    fn interrupts_and_mounts_have_one_line_each() {
+        let counts = [InterruptCount {
+            vector: 32,
+            count: 7,
+            name: Some("timer"),
+        }];
+        let mounts = [MountInfo {
+            path: "/".into(),
+            filesystem: "tmpfs".into(),
+        }];
+
+        assert_eq!(format_interrupts(&counts), "  32:            7 timer\n");
+        assert_eq!(format_mounts(&mounts), "tmpfs /\n");
+    }
+
+    #[kunit]
+    fn tasks_show_state_priority_and_cpu() {
+        let tasks = [
+            TaskInfo {
+                id: 0,
+                name: "idle0".into(),
+                priority: Priority::Idle,
+                state: TaskState::Ready,
+                cpu: None,
+            },
+            TaskInfo {
+                id: 1,
+                name: "boot".into(),
+                priority: Priority::Normal,
+                state: TaskState::Running,
+                cpu: Some(0),
+            },
+        ];
+
+        assert_eq!(
+            format_tasks(&tasks),
+            "  ID STATE    PRIO   CPU NAME\n   0 ready    idle     - idle0\n   1 running  normal   0 boot\n"
+        );
+    }
+}
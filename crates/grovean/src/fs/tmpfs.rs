@@ -0,0 +1,510 @@
+use alloc::collections::btree_map::{self, BTreeMap};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::fs::vfs::{DirEntry, FileType, Filesystem, FsError, Inode, Metadata};
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::hhdm;
+use crate::time;
+
+const PAGE_SIZE: usize = FRAME_SIZE as usize;
+
+/// State shared by every inode of one tmpfs instance.
+struct Shared {
+    next_inode: AtomicU64,
+    pages: AtomicUsize,
+    /// Most pages the instance may hold, or `None` for no limit beyond free memory.
+    max_pages: Option<usize>,
+}
+
+impl Shared {
+    fn next_inode(&self) -> u64 {
+        self.next_inode.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// A frame of file data, returned to the frame allocator when dropped.
+struct Page {
+    phys: u64,
+    shared: Arc<Shared>,
+}
+
+impl Page {
+    fn new(shared: &Arc<Shared>) -> Result<Self, FsError> {
+        let pages = shared.pages.fetch_add(1, Ordering::Relaxed);
+        if shared.max_pages.is_some_and(|max| pages >= max) {
+            shared.pages.fetch_sub(1, Ordering::Relaxed);
+            return Err(FsError::NoSpace);
+        }
+        match frame::allocate_zeroed_frame() {
+            Some(phys) => Ok(Self {
+                phys,
+                shared: shared.clone(),
+            }),
+            None => {
+                shared.pages.fetch_sub(1, Ordering::Relaxed);
+                Err(FsError::NoSpace)
+            }
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(hhdm::phys_to_virt(self.phys) as *const u8, PAGE_SIZE)
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(hhdm::phys_to_virt(self.phys) as *mut u8, PAGE_SIZE)
+        }
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        frame::deallocate_frames(self.phys, 1);
+        self.shared.pages.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+enum Node {
+    /// File data by page index. Missing pages are holes that read as zeros.
+    File {
+        size: u64,
+        pages: BTreeMap<u64, Page>,
+    },
+    Directory(BTreeMap<String, Arc<TmpfsInode>>),
+    Symlink(String),
+}
+
+/// A writable filesystem kept entirely in memory, with file data in sparse frames.
+pub struct Tmpfs {
+    root: Arc<TmpfsInode>,
+    shared: Arc<Shared>,
+}
+
+impl Tmpfs {
+    pub fn new() -> Self {
+        Self::with_shared(None)
+    }
+
+    /// Create an instance that refuses to grow beyond `bytes` of file data.
+    pub fn with_limit(bytes: u64) -> Self {
+        Self::with_shared(Some(bytes.div_ceil(FRAME_SIZE) as usize))
+    }
+
+    fn with_shared(max_pages: Option<usize>) -> Self {
+        let shared = Arc::new(Shared {
+            next_inode: AtomicU64::new(1),
+            pages: AtomicUsize::new(0),
+            max_pages,
+        });
+        Self {
+            root: TmpfsInode::new(&shared, 0o755, Node::Directory(BTreeMap::new())),
+            shared,
+        }
+    }
+
+    /// Bytes of frames currently holding file data.
+    pub fn used_bytes(&self) -> u64 {
+        self.shared.pages.load(Ordering::Relaxed) as u64 * FRAME_SIZE
+    }
+}
+
+impl Default for Tmpfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filesystem for Tmpfs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct TmpfsInode {
+    inode: u64,
+    mode: u32,
+    /// Unix time of the last change to the contents, also reported as access and change time.
+    modified: AtomicU64,
+    shared: Arc<Shared>,
+    node: Mutex<Node>,
+}
+
+impl TmpfsInode {
+    fn new(shared: &Arc<Shared>, mode: u32, node: Node) -> Arc<Self> {
+        Arc::new(Self {
+            inode: shared.next_inode(),
+            mode,
+            modified: AtomicU64::new(time::now()),
+            shared: shared.clone(),
+            node: Mutex::new(node),
+        })
+    }
+
+    fn file_type(&self) -> FileType {
+        match &*self.node.lock() {
+            Node::File { .. } => FileType::Regular,
+            Node::Directory(_) => FileType::Directory,
+            Node::Symlink(_) => FileType::Symlink,
+        }
+    }
+
+    fn touch(&self) {
+        self.modified.store(time::now(), Ordering::Relaxed);
+    }
+
+    fn insert(&self, name: &str, mode: u32, node: Node) -> Result<Arc<dyn Inode>, FsError> {
+        let Node::Directory(children) = &mut *self.node.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        if children.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let child = TmpfsInode::new(&self.shared, mode, node);
+        children.insert(name.into(), child.clone());
+        self.touch();
+        Ok(child)
+    }
+}
+
+impl Inode for TmpfsInode {
+    fn metadata(&self) -> Metadata {
+        let modified = self.modified.load(Ordering::Relaxed);
+        let (file_type, size, links) = match &*self.node.lock() {
+            Node::File { size, .. } => (FileType::Regular, *size, 1),
+            Node::Directory(children) => {
+                let subdirectories = children
+                    .values()
+                    .filter(|child| child.file_type() == FileType::Directory)
+                    .count();
+                (FileType::Directory, 0, 2 + subdirectories as u32)
+            }
+            Node::Symlink(target) => (FileType::Symlink, target.len() as u64, 1),
+        };
+        Metadata {
+            inode: self.inode,
+            file_type,
+            mode: self.mode,
+            size,
+            links,
+            accessed: modified,
+            modified,
+            changed: modified,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        match &*self.node.lock() {
+            Node::Directory(children) => children
+                .get(name)
+                .map(|child| child.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        match &*self.node.lock() {
+            Node::Directory(children) => Ok(children
+                .iter()
+                .map(|(name, child)| DirEntry {
+                    name: name.clone(),
+                    inode: child.inode,
+                    file_type: child.file_type(),
+                })
+                .collect()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+    ) -> Result<Arc<dyn Inode>, FsError> {
+        let node = match file_type {
+            FileType::Regular => Node::File {
+                size: 0,
+                pages: BTreeMap::new(),
+            },
+            FileType::Directory => Node::Directory(BTreeMap::new()),
+            _ => return Err(FsError::NotSupported),
+        };
+        self.insert(name, mode, node)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.insert(name, 0o777, Node::Symlink(target.into()))
+    }
+
+    fn remove(&self, name: &str) -> Result<(), FsError> {
+        let Node::Directory(children) = &mut *self.node.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        let child = children.get(name).ok_or(FsError::NotFound)?;
+        if let Node::Directory(grandchildren) = &*child.node.lock()
+            && !grandchildren.is_empty()
+        {
+            return Err(FsError::NotEmpty);
+        }
+        // Open files keep the inode, and with it the data, until they are closed.
+        children.remove(name);
+        self.touch();
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.node.lock();
+        let Node::File { size, pages } = &*node else {
+            return match &*node {
+                Node::Directory(_) => Err(FsError::IsADirectory),
+                _ => Err(FsError::InvalidArgument),
+            };
+        };
+
+        let length = buffer.len().min(size.saturating_sub(offset) as usize);
+        let mut done = 0;
+        while done < length {
+            let position = offset + done as u64;
+            let index = position / FRAME_SIZE;
+            let start = (position % FRAME_SIZE) as usize;
+            let chunk = (PAGE_SIZE - start).min(length - done);
+            let target = &mut buffer[done..done + chunk];
+            match pages.get(&index) {
+                Some(page) => target.copy_from_slice(&page.bytes()[start..start + chunk]),
+                None => target.fill(0),
+            }
+            done += chunk;
+        }
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        let mut node = self.node.lock();
+        let Node::File { size, pages } = &mut *node else {
+            return match &*node {
+                Node::Directory(_) => Err(FsError::IsADirectory),
+                _ => Err(FsError::InvalidArgument),
+            };
+        };
+        if offset.checked_add(buffer.len() as u64).is_none() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let index = position / FRAME_SIZE;
+            let start = (position % FRAME_SIZE) as usize;
+            let chunk = (PAGE_SIZE - start).min(buffer.len() - done);
+            let page = match pages.entry(index) {
+                btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                btree_map::Entry::Vacant(entry) => {
+                    match Page::new(&self.shared) {
+                        Ok(page) => entry.insert(page),
+                        // Report a short write if part of the data made it.
+                        Err(error) if done == 0 => return Err(error),
+                        Err(_) => break,
+                    }
+                }
+            };
+            page.bytes_mut()[start..start + chunk].copy_from_slice(&buffer[done..done + chunk]);
+            done += chunk;
+        }
+
+        if done > 0 {
+            *size = (*size).max(offset + done as u64);
+            self.touch();
+        }
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: u64) -> Result<(), FsError> {
+        let mut node = self.node.lock();
+        let Node::File { size, pages } = &mut *node else {
+            return match &*node {
+                Node::Directory(_) => Err(FsError::IsADirectory),
+                _ => Err(FsError::InvalidArgument),
+            };
+        };
+
+        pages.retain(|&index, _| index < new_size.div_ceil(FRAME_SIZE));
+        // Zero the tail of a partial last page so growing the file again reads zeros.
+        let tail = (new_size % FRAME_SIZE) as usize;
+        if tail != 0
+            && let Some(page) = pages.get_mut(&(new_size / FRAME_SIZE))
+        {
+            page.bytes_mut()[tail..].fill(0);
+        }
+        *size = new_size;
+        self.touch();
+        Ok(())
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        match &*self.node.lock() {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{Tmpfs, PAGE_SIZE};
+    use crate::fs::vfs::{FileType, Filesystem, FsError, OpenFlags, SeekFrom, Vfs};
+    use crate::memory::frame::FRAME_SIZE;
+
+    fn mounted(tmpfs: Tmpfs) -> (Arc<Tmpfs>, Vfs) {
+        let tmpfs = Arc::new(tmpfs);
+        let vfs = Vfs::new();
+        vfs.mount("/", tmpfs.clone()).expect("mount");
+        (tmpfs, vfs)
+    }
+
+    #[kunit]
+    fn holes_read_as_zeros_without_backing_pages() {
+        let (tmpfs, _) = mounted(Tmpfs::new());
+        let file = tmpfs
+            .root()
+            .create("sparse", FileType::Regular, 0o644)
+            .expect("create");
+
+        let offset = 3 * FRAME_SIZE + 10;
+        assert_eq!(file.write_at(offset, b"tail").expect("write"), 4);
+        assert_eq!(file.metadata().size, offset + 4);
+        assert_eq!(tmpfs.used_bytes(), FRAME_SIZE);
+
+        let mut buffer = vec![0xffu8; PAGE_SIZE + 20];
+        assert_eq!(
+            file.read_at(2 * FRAME_SIZE, &mut buffer).expect("read"),
+            PAGE_SIZE + 14
+        );
+        assert!(buffer[..PAGE_SIZE + 10].iter().all(|&byte| byte == 0));
+        assert_eq!(&buffer[PAGE_SIZE + 10..PAGE_SIZE + 14], b"tail");
+    }
+
+    #[kunit]
+    fn writes_spanning_pages_round_trip() {
+        let (_, vfs) = mounted(Tmpfs::new());
+        let file = vfs
+            .open("/data", OpenFlags::READ_WRITE.union(OpenFlags::CREATE))
+            .expect("create");
+        let data: Vec<u8> = (0..PAGE_SIZE * 2 + 100).map(|index| index as u8).collect();
+
+        assert_eq!(file.write(&data).expect("write"), data.len());
+        let mut buffer = vec![0u8; data.len()];
+        assert_eq!(
+            file.inode().read_at(0, &mut buffer).expect("read"),
+            data.len()
+        );
+        assert_eq!(buffer, data);
+    }
+
+    #[kunit]
+    fn truncation_frees_pages_and_zeroes_the_tail() {
+        let (tmpfs, _) = mounted(Tmpfs::new());
+        let file = tmpfs
+            .root()
+            .create("file", FileType::Regular, 0o644)
+            .expect("create");
+        file.write_at(0, &vec![b'a'; PAGE_SIZE + 100])
+            .expect("write");
+        assert_eq!(tmpfs.used_bytes(), 2 * FRAME_SIZE);
+
+        file.truncate(50).expect("shrink");
+        assert_eq!(tmpfs.used_bytes(), FRAME_SIZE);
+        file.truncate(100).expect("grow");
+
+        let mut buffer = [0xffu8; 100];
+        assert_eq!(file.read_at(0, &mut buffer).expect("read"), 100);
+        assert!(buffer[..50].iter().all(|&byte| byte == b'a'));
+        assert!(buffer[50..].iter().all(|&byte| byte == 0));
+    }
+
+    #[kunit]
+    fn limits_cause_short_writes_then_no_space() {
+        let (tmpfs, _) = mounted(Tmpfs::with_limit(FRAME_SIZE));
+        let file = tmpfs
+            .root()
+            .create("big", FileType::Regular, 0o644)
+            .expect("create");
+
+        assert_eq!(
+            file.write_at(0, &vec![1u8; PAGE_SIZE * 2]).expect("write"),
+            PAGE_SIZE
+        );
+        assert_eq!(file.metadata().size, FRAME_SIZE);
+        assert_eq!(
+            file.write_at(FRAME_SIZE, &[1]).err(),
+            Some(FsError::NoSpace)
+        );
+    }
+
+    #[kunit]
+    fn removed_files_release_pages_when_closed() {
+        let (tmpfs, vfs) = mounted(Tmpfs::new());
+        let file = vfs
+            .open("/scratch", OpenFlags::READ_WRITE.union(OpenFlags::CREATE))
+            .expect("create");
+        file.write(b"data").expect("write");
+
+        vfs.remove("/scratch").expect("remove");
+        assert_eq!(tmpfs.used_bytes(), FRAME_SIZE);
+        let mut buffer = [0u8; 4];
+        file.seek(SeekFrom::Start(0)).expect("seek");
+        assert_eq!(file.read(&mut buffer).expect("read open file"), 4);
+
+        drop(file);
+        assert_eq!(tmpfs.used_bytes(), 0);
+    }
+
+    #[kunit]
+    fn directories_and_symlinks_work_through_the_vfs() {
+        let (_, vfs) = mounted(Tmpfs::new());
+        vfs.create_dir("/etc", 0o755).expect("mkdir");
+        vfs.open("/etc/hosts", OpenFlags::WRITE.union(OpenFlags::CREATE))
+            .expect("create")
+            .write(b"127.0.0.1 localhost")
+            .expect("write");
+        vfs.symlink("/etc/hosts", "/hosts").expect("symlink");
+
+        assert_eq!(vfs.metadata("/hosts").expect("stat").size, 19);
+        assert_eq!(vfs.metadata("/").expect("stat").links, 3);
+        assert_eq!(
+            vfs.create_dir("/etc", 0o755).err(),
+            Some(FsError::AlreadyExists)
+        );
+        assert_eq!(vfs.remove("/etc").err(), Some(FsError::NotEmpty));
+
+        let names: Vec<_> = vfs
+            .read_dir("/")
+            .expect("list")
+            .into_iter()
+            .map(|entry| (entry.name, entry.file_type))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("etc".into(), FileType::Directory),
+                ("hosts".into(), FileType::Symlink)
+            ]
+        );
+    }
+}
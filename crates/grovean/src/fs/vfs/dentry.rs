@@ -0,0 +1,84 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use super::inode::{FileType, Inode};
+use super::FsError;
+
+/// A name bound to an inode, cached so repeated lookups skip the filesystem.
+///
+/// Dentries own their cached children and point weakly at their parent, so a subtree lives as
+/// long as its mount or anyone holding one of its dentries.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    parent: Option<Weak<Dentry>>,
+    children: Mutex<BTreeMap<String, Arc<Dentry>>>,
+}
+
+impl Dentry {
+    /// Create the dentry for the root inode of a filesystem.
+    pub fn root(inode: Arc<dyn Inode>) -> Arc<Self> {
+        Arc::new(Self {
+            name: String::new(),
+            inode,
+            parent: None,
+            children: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.inode.metadata().file_type
+    }
+
+    /// The parent directory, or `None` at the root of a filesystem or after the parent was
+    /// removed.
+    pub fn parent(&self) -> Option<Arc<Dentry>> {
+        self.parent.as_ref().and_then(Weak::upgrade)
+    }
+
+    /// Find `name` in this directory, asking the filesystem on a cache miss.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> Result<Arc<Dentry>, FsError> {
+        if let Some(child) = self.children.lock().get(name) {
+            return Ok(child.clone());
+        }
+
+        let inode = self.inode.lookup(name)?;
+        if !self.inode.cache_lookups() {
+            return Ok(self.child(name, inode));
+        }
+        Ok(self.insert(name, inode))
+    }
+
+    /// Cache a child created or found by the filesystem.
+    pub fn insert(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        let child = self.child(name, inode);
+        if self.inode.cache_lookups() {
+            self.children.lock().insert(name.into(), child.clone());
+        }
+        child
+    }
+
+    fn child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        Arc::new(Self {
+            name: name.into(),
+            inode,
+            parent: Some(Arc::downgrade(self)),
+            children: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Forget a cached child after it was removed from the filesystem.
+    pub fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+}
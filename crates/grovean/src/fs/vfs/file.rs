@@ -0,0 +1,171 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::inode::{DirEntry, FileType, Filesystem, Inode, Metadata};
+use super::page_cache::page_cache;
+use super::{FsError, Location};
+
+/// How a file is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const READ_WRITE: Self = Self(Self::READ.0 | Self::WRITE.0);
+    /// Create a regular file if the path does not exist.
+    pub const CREATE: Self = Self(1 << 2);
+    /// With [`OpenFlags::CREATE`], fail if the path already exists.
+    pub const EXCLUSIVE: Self = Self(1 << 3);
+    /// Discard the contents of a regular file opened for writing.
+    pub const TRUNCATE: Self = Self(1 << 4);
+    /// Move to the end of the file before every write.
+    pub const APPEND: Self = Self(1 << 5);
+    /// Fail unless the path is a directory.
+    pub const DIRECTORY: Self = Self(1 << 6);
+    /// Open a symbolic link itself rather than its target.
+    pub const NO_FOLLOW: Self = Self(1 << 7);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file description: a resolved location with its own flags and offset, shared by
+/// every descriptor duplicated from it.
+pub struct OpenFile {
+    location: Location,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+    /// Where a read continuing the previous one would start, to detect sequential access.
+    read_end: AtomicU64,
+    /// Whether file data goes through the page cache.
+    cached: bool,
+}
+
+impl OpenFile {
+    pub fn new(location: Location, flags: OpenFlags) -> Arc<Self> {
+        let cached = location.mount().filesystem().uses_page_cache()
+            && location.metadata().file_type == FileType::Regular;
+        Arc::new(Self {
+            location,
+            flags,
+            offset: Mutex::new(0),
+            read_end: AtomicU64::new(0),
+            cached,
+        })
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        self.location.inode()
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.inode().metadata()
+    }
+
+    pub fn offset(&self) -> u64 {
+        *self.offset.lock()
+    }
+
+    /// Read from the current offset, advancing it by the number of bytes read.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::BadDescriptor);
+        }
+        let mut offset = self.offset.lock();
+        let read = if self.cached {
+            let sequential = *offset == self.read_end.load(Ordering::Relaxed);
+            page_cache().read(self.filesystem(), self.inode(), *offset, buffer, sequential)?
+        } else {
+            self.inode().read_at(*offset, buffer)?
+        };
+        *offset += read as u64;
+        self.read_end.store(*offset, Ordering::Relaxed);
+        Ok(read)
+    }
+
+    /// Write at the current offset, or at the end of the file when opened for appending.
+    pub fn write(&self, buffer: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadDescriptor);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.metadata().size;
+        }
+        let written = if self.cached {
+            page_cache().write(self.filesystem(), self.inode(), *offset, buffer)?
+        } else {
+            self.inode().write_at(*offset, buffer)?
+        };
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    pub fn seek(&self, position: SeekFrom) -> Result<u64, FsError> {
+        let mut offset = self.offset.lock();
+        let target = match position {
+            SeekFrom::Start(target) => Some(target),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.metadata().size.checked_add_signed(delta),
+        };
+        *offset = target.ok_or(FsError::InvalidArgument)?;
+        Ok(*offset)
+    }
+
+    /// Set the file's size, dropping cached data past the new end first.
+    pub fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadDescriptor);
+        }
+        if self.cached {
+            page_cache().truncate(self.filesystem(), self.metadata().inode, size);
+        }
+        self.inode().truncate(size)
+    }
+
+    pub fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if self.metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        self.inode().read_dir()
+    }
+
+    pub fn ioctl(&self, request: u32, argument: usize) -> Result<usize, FsError> {
+        self.inode().ioctl(request, argument)
+    }
+
+    /// Write back this file's cached data, then ask its filesystem to make it durable.
+    pub fn sync(&self) -> Result<(), FsError> {
+        if self.cached {
+            page_cache().sync_file(self.filesystem(), self.metadata().inode)?;
+        }
+        self.inode().sync()
+    }
+
+    fn filesystem(&self) -> &Arc<dyn Filesystem> {
+        self.location.mount().filesystem()
+    }
+}
@@ -0,0 +1,122 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::FsError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Unique within the owning filesystem.
+    pub inode: u64,
+    pub file_type: FileType,
+    /// Permission bits.
+    pub mode: u32,
+    pub size: u64,
+    pub links: u32,
+    /// Unix times in seconds, or zero where the filesystem does not record them.
+    pub accessed: u64,
+    pub modified: u64,
+    /// Last change to the metadata or contents.
+    pub changed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// A mountable filesystem instance.
+pub trait Filesystem: Send + Sync {
+    /// Short type name shown in the mount table, such as `tmpfs`.
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Whether reads and writes of regular files should go through the page cache. Storage
+    /// backed filesystems opt in; those already in memory or generating their contents do not.
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
+    /// Write back everything the filesystem buffers.
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// A file, directory or other object inside a filesystem.
+///
+/// Directory operations take names of single components; the VFS resolves paths, `.` and `..`
+/// itself. Operations a type of inode does not support keep the default error.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Whether the VFS may keep the results of `lookup` in its dentry cache. Directories whose
+    /// entries change without going through the VFS answer `false`.
+    fn cache_lookups(&self) -> bool {
+        true
+    }
+
+    /// Create a regular file or directory named `name` in this directory.
+    fn create(
+        &self,
+        _name: &str,
+        _file_type: FileType,
+        _mode: u32,
+    ) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Remove `name` from this directory. Directories must be empty.
+    fn remove(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn read_link(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Device-specific control request.
+    fn ioctl(&self, _request: u32, _argument: usize) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
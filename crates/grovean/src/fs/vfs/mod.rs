@@ -0,0 +1,831 @@
+pub mod dentry;
+pub mod file;
+pub mod inode;
+pub mod mount;
+pub mod page_cache;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::dev::block::BlockError;
+use dentry::Dentry;
+use mount::Mount;
+
+pub use file::{OpenFile, OpenFlags, SeekFrom};
+pub use inode::{DirEntry, FileType, Filesystem, Inode, Metadata};
+pub use mount::MountInfo;
+
+/// Symbolic links followed while resolving one path before giving up, as on Linux.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+static VFS: Vfs = Vfs::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    NotEmpty,
+    /// The path is empty or ends in a component that cannot be created or removed.
+    InvalidPath,
+    TooManySymlinks,
+    ReadOnly,
+    NoSpace,
+    /// The target is a mountpoint or still has filesystems mounted below it.
+    Busy,
+    NotSupported,
+    InvalidArgument,
+    /// The open file was not opened for the requested access.
+    BadDescriptor,
+    NameTooLong,
+    /// The on-disk structures are inconsistent.
+    Corrupt,
+    Io(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::ReadOnly => FsError::ReadOnly,
+            error => FsError::Io(error),
+        }
+    }
+}
+
+/// A position in the mounted tree: a dentry together with the mount it was reached through.
+#[derive(Clone)]
+pub struct Location {
+    mount: Arc<Mount>,
+    dentry: Arc<Dentry>,
+}
+
+impl Location {
+    pub fn mount(&self) -> &Arc<Mount> {
+        &self.mount
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        self.dentry.inode()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.inode().metadata()
+    }
+
+    fn is_mount_root(&self) -> bool {
+        Arc::ptr_eq(&self.dentry, self.mount.root())
+    }
+
+    /// Absolute path of this location, walking up through the mounts it was reached through.
+    pub fn path(&self) -> String {
+        let mut components = Vec::new();
+        let mut location = self.clone();
+        loop {
+            if location.is_mount_root() {
+                match (location.mount.parent(), location.mount.mountpoint()) {
+                    (Some(mount), Some(dentry)) => {
+                        location = Location {
+                            mount: mount.clone(),
+                            dentry: dentry.clone(),
+                        };
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+            let Some(parent) = location.dentry.parent() else {
+                break;
+            };
+            components.push(String::from(location.dentry.name()));
+            location.dentry = parent;
+        }
+
+        let mut path = String::new();
+        for component in components.iter().rev() {
+            path.push('/');
+            path.push_str(component);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+}
+
+/// The mount table and the path operations that walk it.
+pub struct Vfs {
+    mounts: Mutex<Vec<Arc<Mount>>>,
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Self {
+            mounts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The root of the tree, or `NotFound` before a root filesystem is mounted.
+    pub fn root(&self) -> Result<Location, FsError> {
+        let root = self
+            .mounts
+            .lock()
+            .iter()
+            .find(|mount| mount.parent().is_none())
+            .cloned()
+            .ok_or(FsError::NotFound)?;
+        Ok(self.enter_mounts(Location {
+            dentry: root.root().clone(),
+            mount: root,
+        }))
+    }
+
+    /// Step onto the root of whatever is mounted on `location`, the latest mount winning.
+    fn enter_mounts(&self, mut location: Location) -> Location {
+        let mounts = self.mounts.lock();
+        while let Some(mount) = mounts
+            .iter()
+            .rev()
+            .find(|mount| mount.covers(&location.mount, &location.dentry))
+        {
+            location = Location {
+                mount: mount.clone(),
+                dentry: mount.root().clone(),
+            };
+        }
+        location
+    }
+
+    fn child_of(&self, location: &Location, name: &str) -> Result<Location, FsError> {
+        let dentry = location.dentry.lookup(name)?;
+        Ok(self.enter_mounts(Location {
+            mount: location.mount.clone(),
+            dentry,
+        }))
+    }
+
+    /// Resolve `..`, leaving mounts through their mountpoint. The parent of the root is itself.
+    fn parent_of(&self, location: &Location) -> Location {
+        let mut location = location.clone();
+        while location.is_mount_root() {
+            match (location.mount.parent(), location.mount.mountpoint()) {
+                (Some(mount), Some(dentry)) => {
+                    location = Location {
+                        mount: mount.clone(),
+                        dentry: dentry.clone(),
+                    }
+                }
+                _ => return location,
+            }
+        }
+
+        match location.dentry.parent() {
+            Some(dentry) => self.enter_mounts(Location {
+                mount: location.mount,
+                dentry,
+            }),
+            None => location,
+        }
+    }
+
+    fn walk(
+        &self,
+        base: &Location,
+        path: &str,
+        follow: bool,
+        links: &mut usize,
+    ) -> Result<Location, FsError> {
+        let mut location = if path.starts_with('/') {
+            self.root()?
+        } else {
+            base.clone()
+        };
+
+        let mut components = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .peekable();
+        while let Some(component) = components.next() {
+            if location.metadata().file_type != FileType::Directory {
+                return Err(FsError::NotADirectory);
+            }
+            match component {
+                "." => {}
+                ".." => location = self.parent_of(&location),
+                name => {
+                    let child = self.child_of(&location, name)?;
+                    let last = components.peek().is_none();
+                    if child.metadata().file_type == FileType::Symlink && (follow || !last) {
+                        *links += 1;
+                        if *links > MAX_SYMLINK_DEPTH {
+                            return Err(FsError::TooManySymlinks);
+                        }
+                        // Relative targets are resolved from the directory holding the link.
+                        let target = child.inode().read_link()?;
+                        location = self.walk(&location, &target, true, links)?;
+                    } else {
+                        location = child;
+                    }
+                }
+            }
+        }
+        Ok(location)
+    }
+
+    /// Resolve `path` from the root, following a final symbolic link.
+    pub fn resolve(&self, path: &str) -> Result<Location, FsError> {
+        self.resolve_at(&self.root()?, path, true)
+    }
+
+    /// Resolve `path` relative to `base` unless it is absolute.
+    pub fn resolve_at(
+        &self,
+        base: &Location,
+        path: &str,
+        follow: bool,
+    ) -> Result<Location, FsError> {
+        self.walk(base, path, follow, &mut 0)
+    }
+
+    /// Resolve the directory containing the final component of `path`, returning that name.
+    fn resolve_parent<'a>(
+        &self,
+        base: &Location,
+        path: &'a str,
+    ) -> Result<(Location, &'a str), FsError> {
+        let path = path.trim_end_matches('/');
+        let (directory, name) = match path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((directory, name)) => (directory, name),
+            None => ("", path),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidPath);
+        }
+
+        let parent = self.walk(base, directory, true, &mut 0)?;
+        if parent.metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((parent, name))
+    }
+
+    /// Attach `filesystem` at `path`. The first mount must be the root, `/`.
+    pub fn mount(&self, path: &str, filesystem: Arc<dyn Filesystem>) -> Result<(), FsError> {
+        let root = match self.root() {
+            Ok(root) => root,
+            Err(FsError::NotFound) if path.split('/').all(str::is_empty) => {
+                let mount = Mount::new(filesystem, None, "/".into());
+                self.mounts.lock().push(Arc::new(mount));
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mountpoint = self.resolve_at(&root, path, true)?;
+        if mountpoint.metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let mount = Mount::new(
+            filesystem,
+            Some((mountpoint.mount.clone(), mountpoint.dentry.clone())),
+            mountpoint.path(),
+        );
+        self.mounts.lock().push(Arc::new(mount));
+        Ok(())
+    }
+
+    /// Detach the filesystem mounted at `path` after syncing it.
+    pub fn unmount(&self, path: &str) -> Result<(), FsError> {
+        let location = self.resolve(path)?;
+        if !location.is_mount_root() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let mut mounts = self.mounts.lock();
+        let nested = mounts.iter().any(|mount| {
+            mount
+                .parent()
+                .is_some_and(|parent| Arc::ptr_eq(parent, &location.mount))
+        });
+        if nested {
+            return Err(FsError::Busy);
+        }
+        let filesystem = location.mount.filesystem();
+        page_cache::page_cache().sync_filesystem(filesystem)?;
+        page_cache::page_cache().discard_filesystem(filesystem);
+        filesystem.sync()?;
+        mounts.retain(|mount| !Arc::ptr_eq(mount, &location.mount));
+        Ok(())
+    }
+
+    /// List the mount table in mount order.
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.mounts
+            .lock()
+            .iter()
+            .map(|mount| MountInfo {
+                path: mount.path().into(),
+                filesystem: mount.filesystem().name().into(),
+            })
+            .collect()
+    }
+
+    pub fn open(&self, path: &str, flags: OpenFlags) -> Result<Arc<OpenFile>, FsError> {
+        self.open_at(&self.root()?, path, flags)
+    }
+
+    /// Open `path` relative to `base`, creating or truncating it as `flags` ask.
+    pub fn open_at(
+        &self,
+        base: &Location,
+        path: &str,
+        flags: OpenFlags,
+    ) -> Result<Arc<OpenFile>, FsError> {
+        let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+        let location = match self.resolve_at(base, path, follow) {
+            Ok(_) if flags.contains(OpenFlags::CREATE.union(OpenFlags::EXCLUSIVE)) => {
+                return Err(FsError::AlreadyExists);
+            }
+            Ok(location) => location,
+            Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+                let (parent, name) = self.resolve_parent(base, path)?;
+                let inode = parent.inode().create(name, FileType::Regular, 0o644)?;
+                Location {
+                    dentry: parent.dentry.insert(name, inode),
+                    mount: parent.mount,
+                }
+            }
+            Err(error) => return Err(error),
+        };
+
+        let file_type = location.metadata().file_type;
+        if flags.contains(OpenFlags::DIRECTORY) && file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        if flags.contains(OpenFlags::WRITE) && file_type == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if flags.contains(OpenFlags::WRITE.union(OpenFlags::TRUNCATE))
+            && file_type == FileType::Regular
+        {
+            page_cache::page_cache().truncate(
+                location.mount.filesystem(),
+                location.metadata().inode,
+                0,
+            );
+            location.inode().truncate(0)?;
+        }
+        Ok(OpenFile::new(location, flags))
+    }
+
+    pub fn create_dir(&self, path: &str, mode: u32) -> Result<Location, FsError> {
+        let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+        let inode = parent.inode().create(name, FileType::Directory, mode)?;
+        Ok(Location {
+            dentry: parent.dentry.insert(name, inode),
+            mount: parent.mount,
+        })
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`.
+    pub fn symlink(&self, target: &str, path: &str) -> Result<Location, FsError> {
+        let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+        let inode = parent.inode().symlink(name, target)?;
+        Ok(Location {
+            dentry: parent.dentry.insert(name, inode),
+            mount: parent.mount,
+        })
+    }
+
+    /// Remove a file, symbolic link or empty directory. Mountpoints cannot be removed.
+    pub fn remove(&self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = self.resolve_parent(&self.root()?, path)?;
+        let dentry = parent.dentry.lookup(name)?;
+        let covered = self
+            .mounts
+            .lock()
+            .iter()
+            .any(|mount| mount.covers(&parent.mount, &dentry));
+        if covered {
+            return Err(FsError::Busy);
+        }
+
+        let metadata = dentry.inode().metadata();
+        parent.inode().remove(name)?;
+        parent.dentry.forget(name);
+        // The data of a file's last link must not be written back over whatever reuses it.
+        if metadata.file_type == FileType::Regular && metadata.links <= 1 {
+            page_cache::page_cache().discard_file(parent.mount.filesystem(), metadata.inode);
+        }
+        Ok(())
+    }
+
+    pub fn metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        Ok(self.resolve(path)?.metadata())
+    }
+
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        self.resolve(path)?.inode().read_dir()
+    }
+
+    /// Write back the cached file data and then the structures of every mounted filesystem.
+    pub fn sync(&self) -> Result<(), FsError> {
+        let mounts: Vec<_> = self.mounts.lock().clone();
+        mounts.iter().try_for_each(|mount| {
+            page_cache::page_cache().sync_filesystem(mount.filesystem())?;
+            mount.filesystem().sync()
+        })
+    }
+}
+
+/// The kernel-wide filesystem tree.
+pub fn vfs() -> &'static Vfs {
+    &VFS
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use kunit::kunit;
+    use spin::Mutex;
+
+    use super::{
+        DirEntry, FileType, Filesystem, FsError, Inode, Metadata, OpenFlags, SeekFrom, Vfs,
+    };
+
+    enum Contents {
+        File(Vec<u8>),
+        Directory(BTreeMap<String, Arc<MemInode>>),
+        Symlink(String),
+    }
+
+    struct MemInode {
+        inode: u64,
+        next_inode: Arc<AtomicU64>,
+        contents: Mutex<Contents>,
+    }
+
+    impl MemInode {
+        fn new(next_inode: &Arc<AtomicU64>, contents: Contents) -> Arc<Self> {
+            Arc::new(Self {
+                inode: next_inode.fetch_add(1, Ordering::Relaxed),
+                next_inode: next_inode.clone(),
+                contents: Mutex::new(contents),
+            })
+        }
+
+        fn insert(&self, name: &str, contents: Contents) -> Result<Arc<dyn Inode>, FsError> {
+            let Contents::Directory(children) = &mut *self.contents.lock() else {
+                return Err(FsError::NotADirectory);
+            };
+            if children.contains_key(name) {
+                return Err(FsError::AlreadyExists);
+            }
+            let child = MemInode::new(&self.next_inode, contents);
+            children.insert(name.into(), child.clone());
+            Ok(child)
+        }
+    }
+
+    impl Inode for MemInode {
+        fn metadata(&self) -> Metadata {
+            let (file_type, size) = match &*self.contents.lock() {
+                Contents::File(data) => (FileType::Regular, data.len() as u64),
+                Contents::Directory(_) => (FileType::Directory, 0),
+                Contents::Symlink(target) => (FileType::Symlink, target.len() as u64),
+            };
+            Metadata {
+                inode: self.inode,
+                file_type,
+                mode: 0o755,
+                size,
+                links: 1,
+                accessed: 0,
+                modified: 0,
+                changed: 0,
+            }
+        }
+
+        fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+            match &*self.contents.lock() {
+                Contents::Directory(children) => children
+                    .get(name)
+                    .map(|child| child.clone() as Arc<dyn Inode>)
+                    .ok_or(FsError::NotFound),
+                _ => Err(FsError::NotADirectory),
+            }
+        }
+
+        fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+            match &*self.contents.lock() {
+                Contents::Directory(children) => Ok(children
+                    .iter()
+                    .map(|(name, child)| DirEntry {
+                        name: name.clone(),
+                        inode: child.inode,
+                        file_type: child.metadata().file_type,
+                    })
+                    .collect()),
+                _ => Err(FsError::NotADirectory),
+            }
+        }
+
+        fn create(
+            &self,
+            name: &str,
+            file_type: FileType,
+            _mode: u32,
+        ) -> Result<Arc<dyn Inode>, FsError> {
+            match file_type {
+                FileType::Directory => self.insert(name, Contents::Directory(BTreeMap::new())),
+                _ => self.insert(name, Contents::File(Vec::new())),
+            }
+        }
+
+        fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, FsError> {
+            self.insert(name, Contents::Symlink(target.into()))
+        }
+
+        fn remove(&self, name: &str) -> Result<(), FsError> {
+            let Contents::Directory(children) = &mut *self.contents.lock() else {
+                return Err(FsError::NotADirectory);
+            };
+            let child = children.get(name).ok_or(FsError::NotFound)?;
+            if let Contents::Directory(grandchildren) = &*child.contents.lock()
+                && !grandchildren.is_empty()
+            {
+                return Err(FsError::NotEmpty);
+            }
+            children.remove(name);
+            Ok(())
+        }
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+            let Contents::File(data) = &*self.contents.lock() else {
+                return Err(FsError::IsADirectory);
+            };
+            let start = (offset as usize).min(data.len());
+            let length = buffer.len().min(data.len() - start);
+            buffer[..length].copy_from_slice(&data[start..start + length]);
+            Ok(length)
+        }
+
+        fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+            let Contents::File(data) = &mut *self.contents.lock() else {
+                return Err(FsError::IsADirectory);
+            };
+            let end = offset as usize + buffer.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[offset as usize..end].copy_from_slice(buffer);
+            Ok(buffer.len())
+        }
+
+        fn truncate(&self, size: u64) -> Result<(), FsError> {
+            let Contents::File(data) = &mut *self.contents.lock() else {
+                return Err(FsError::IsADirectory);
+            };
+            data.resize(size as usize, 0);
+            Ok(())
+        }
+
+        fn read_link(&self) -> Result<String, FsError> {
+            match &*self.contents.lock() {
+                Contents::Symlink(target) => Ok(target.clone()),
+                _ => Err(FsError::InvalidArgument),
+            }
+        }
+    }
+
+    struct MemFs {
+        root: Arc<MemInode>,
+    }
+
+    impl MemFs {
+        fn new() -> Arc<Self> {
+            let next_inode = Arc::new(AtomicU64::new(1));
+            Arc::new(Self {
+                root: MemInode::new(&next_inode, Contents::Directory(BTreeMap::new())),
+            })
+        }
+    }
+
+    impl Filesystem for MemFs {
+        fn name(&self) -> &str {
+            "memfs"
+        }
+
+        fn root(&self) -> Arc<dyn Inode> {
+            self.root.clone()
+        }
+    }
+
+    fn tree() -> Vfs {
+        let vfs = Vfs::new();
+        vfs.mount("/", MemFs::new()).expect("mount root");
+        vfs.create_dir("/a", 0o755).expect("mkdir /a");
+        vfs.create_dir("/a/b", 0o755).expect("mkdir /a/b");
+        let file = vfs
+            .open("/a/b/file", OpenFlags::WRITE.union(OpenFlags::CREATE))
+            .expect("create file");
+        file.write(b"contents").expect("write");
+        vfs
+    }
+
+    fn read_all(vfs: &Vfs, path: &str) -> Result<Vec<u8>, FsError> {
+        let file = vfs.open(path, OpenFlags::READ)?;
+        let mut buffer = [0u8; 64];
+        let length = file.read(&mut buffer)?;
+        Ok(buffer[..length].to_vec())
+    }
+
+    #[kunit]
+    fn resolves_dot_and_dot_dot() {
+        let vfs = tree();
+        let location = vfs.resolve("/a/./b/../b//file").expect("resolve");
+
+        assert_eq!(location.metadata().file_type, FileType::Regular);
+        assert_eq!(location.path(), "/a/b/file");
+        assert_eq!(vfs.resolve("/../..").expect("root").path(), "/");
+        assert_eq!(
+            vfs.resolve("/a/b/file/x").err(),
+            Some(FsError::NotADirectory)
+        );
+        assert_eq!(vfs.resolve("/a/missing").err(), Some(FsError::NotFound));
+    }
+
+    #[kunit]
+    fn relative_paths_start_at_the_base() {
+        let vfs = tree();
+        let base = vfs.resolve("/a/b").expect("base");
+
+        let location = vfs.resolve_at(&base, "../b/file", true).expect("relative");
+        assert_eq!(location.path(), "/a/b/file");
+    }
+
+    #[kunit]
+    fn follows_relative_and_absolute_symlinks() {
+        let vfs = tree();
+        vfs.symlink("b/file", "/a/relative").expect("symlink");
+        vfs.symlink("/a/b", "/shortcut").expect("symlink");
+
+        assert_eq!(read_all(&vfs, "/a/relative").expect("read"), b"contents");
+        assert_eq!(read_all(&vfs, "/shortcut/file").expect("read"), b"contents");
+        assert_eq!(vfs.resolve("/shortcut/..").expect("parent").path(), "/a");
+
+        let link = vfs
+            .open("/a/relative", OpenFlags::READ.union(OpenFlags::NO_FOLLOW))
+            .expect("open link");
+        assert_eq!(link.metadata().file_type, FileType::Symlink);
+    }
+
+    #[kunit]
+    fn symlink_loops_are_cut_off() {
+        let vfs = tree();
+        vfs.symlink("/loop-b", "/loop-a").expect("symlink");
+        vfs.symlink("/loop-a", "/loop-b").expect("symlink");
+
+        assert_eq!(vfs.resolve("/loop-a").err(), Some(FsError::TooManySymlinks));
+    }
+
+    #[kunit]
+    fn mounts_are_crossed_in_both_directions() {
+        let vfs = tree();
+        vfs.create_dir("/a/mnt", 0o755).expect("mkdir");
+        vfs.mount("/a/mnt", MemFs::new()).expect("mount");
+        vfs.open("/a/mnt/inner", OpenFlags::WRITE.union(OpenFlags::CREATE))
+            .expect("create in mount");
+
+        assert_eq!(vfs.read_dir("/a/mnt").expect("list").len(), 1);
+        assert_eq!(
+            vfs.resolve("/a/mnt/inner").expect("inner").path(),
+            "/a/mnt/inner"
+        );
+        assert_eq!(vfs.resolve("/a/mnt/..").expect("up").path(), "/a");
+        assert_eq!(vfs.remove("/a/mnt").err(), Some(FsError::Busy));
+
+        let mounts = vfs.mounts();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].path, "/a/mnt");
+        assert_eq!(mounts[1].filesystem, "memfs");
+
+        vfs.unmount("/a/mnt").expect("unmount");
+        assert_eq!(vfs.resolve("/a/mnt/inner").err(), Some(FsError::NotFound));
+        vfs.remove("/a/mnt").expect("remove former mountpoint");
+    }
+
+    #[kunit]
+    fn nested_mounts_keep_their_parent_busy() {
+        let vfs = tree();
+        vfs.mount("/a", MemFs::new()).expect("mount");
+        vfs.create_dir("/a/deeper", 0o755).expect("mkdir");
+        vfs.mount("/a/deeper", MemFs::new()).expect("nested mount");
+
+        assert_eq!(vfs.unmount("/a").err(), Some(FsError::Busy));
+        assert_eq!(vfs.unmount("/a/b").err(), Some(FsError::NotFound));
+        vfs.unmount("/a/deeper").expect("unmount nested");
+        vfs.unmount("/a").expect("unmount");
+        assert_eq!(read_all(&vfs, "/a/b/file").expect("read"), b"contents");
+    }
+
+    #[kunit]
+    fn open_files_track_offsets_and_flags() {
+        let vfs = tree();
+        let file = vfs.open("/a/b/file", OpenFlags::READ_WRITE).expect("open");
+        let mut buffer = [0u8; 4];
+
+        file.read(&mut buffer).expect("read");
+        assert_eq!(&buffer, b"cont");
+        assert_eq!(file.seek(SeekFrom::End(-2)).expect("seek"), 6);
+        file.write(b"ED!").expect("write");
+        assert_eq!(read_all(&vfs, "/a/b/file").expect("read"), b"contenED!");
+        assert_eq!(
+            file.seek(SeekFrom::Current(-20)).err(),
+            Some(FsError::InvalidArgument)
+        );
+
+        let appender = vfs
+            .open("/a/b/file", OpenFlags::WRITE.union(OpenFlags::APPEND))
+            .expect("open for append");
+        appender.write(b"?").expect("append");
+        assert_eq!(read_all(&vfs, "/a/b/file").expect("read"), b"contenED!?");
+        assert_eq!(
+            appender.read(&mut buffer).err(),
+            Some(FsError::BadDescriptor)
+        );
+
+        vfs.open("/a/b/file", OpenFlags::WRITE.union(OpenFlags::TRUNCATE))
+            .expect("truncate");
+        assert_eq!(vfs.metadata("/a/b/file").expect("stat").size, 0);
+    }
+
+    #[kunit]
+    fn open_checks_types_and_exclusive_creation() {
+        let vfs = tree();
+        let exclusive = OpenFlags::WRITE
+            .union(OpenFlags::CREATE)
+            .union(OpenFlags::EXCLUSIVE);
+
+        assert_eq!(
+            vfs.open("/a/b/file", exclusive).err(),
+            Some(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            vfs.open("/a", OpenFlags::WRITE).err(),
+            Some(FsError::IsADirectory)
+        );
+        assert_eq!(
+            vfs.open("/a/b/file", OpenFlags::READ.union(OpenFlags::DIRECTORY))
+                .err(),
+            Some(FsError::NotADirectory)
+        );
+        assert_eq!(
+            vfs.open("/missing/file", exclusive).err(),
+            Some(FsError::NotFound)
+        );
+
+        let directory = vfs.open("/a", OpenFlags::READ).expect("open directory");
+        assert_eq!(directory.read_dir().expect("list")[0].name, "b");
+    }
+
+    #[kunit]
+    fn removal_updates_the_dentry_cache() {
+        let vfs = tree();
+
+        assert_eq!(vfs.remove("/a/b").err(), Some(FsError::NotEmpty));
+        vfs.resolve("/a/b/file").expect("cached");
+        vfs.remove("/a/b/file").expect("remove file");
+        assert_eq!(vfs.resolve("/a/b/file").err(), Some(FsError::NotFound));
+        vfs.remove("/a/b").expect("remove directory");
+        assert_eq!(vfs.remove("/").err(), Some(FsError::InvalidPath));
+    }
+
+    #[kunit]
+    fn nothing_resolves_before_the_root_is_mounted() {
+        let vfs = Vfs::new();
+
+        assert_eq!(vfs.resolve("/").err(), Some(FsError::NotFound));
+        assert_eq!(
+            vfs.mount("/mnt", MemFs::new()).err(),
+            Some(FsError::NotFound)
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use super::dentry::Dentry;
+use super::inode::Filesystem;
+
+/// A filesystem attached to the tree.
+pub struct Mount {
+    filesystem: Arc<dyn Filesystem>,
+    root: Arc<Dentry>,
+    /// The mount containing the mountpoint and the dentry covered by this mount, or `None` for
+    /// the root mount.
+    parent: Option<(Arc<Mount>, Arc<Dentry>)>,
+    path: String,
+}
+
+impl Mount {
+    pub fn new(
+        filesystem: Arc<dyn Filesystem>,
+        parent: Option<(Arc<Mount>, Arc<Dentry>)>,
+        path: String,
+    ) -> Self {
+        Self {
+            root: Dentry::root(filesystem.root()),
+            filesystem,
+            parent,
+            path,
+        }
+    }
+
+    pub fn filesystem(&self) -> &Arc<dyn Filesystem> {
+        &self.filesystem
+    }
+
+    pub fn root(&self) -> &Arc<Dentry> {
+        &self.root
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Mount>> {
+        self.parent.as_ref().map(|(mount, _)| mount)
+    }
+
+    pub fn mountpoint(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref().map(|(_, dentry)| dentry)
+    }
+
+    /// Absolute path of the mountpoint at the time of mounting.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether this mount covers `dentry` of `mount`.
+    pub fn covers(&self, mount: &Arc<Mount>, dentry: &Arc<Dentry>) -> bool {
+        self.parent.as_ref().is_some_and(|(parent, mountpoint)| {
+            Arc::ptr_eq(parent, mount) && Arc::ptr_eq(mountpoint, dentry)
+        })
+    }
+}
+
+/// A row of the mount table, for listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub path: String,
+    pub filesystem: String,
+}
@@ -0,0 +1,727 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::{Filesystem, FsError, Inode};
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::hhdm;
+
+pub const PAGE_SIZE: usize = FRAME_SIZE as usize;
+
+/// Pages read beyond the end of a read that continues the previous one.
+pub const READAHEAD_PAGES: u64 = 8;
+
+/// Pages the kernel-wide cache holds at most, bounding the heap its index takes.
+const MAX_PAGES: usize = 64 * 1024;
+
+/// Dirty pages allowed before a writer has to write back its own file.
+const MAX_DIRTY_PAGES: usize = 1024;
+
+static PAGE_CACHE: PageCache = PageCache::new(MAX_PAGES, MAX_DIRTY_PAGES);
+
+/// Identifies a file across every mounted filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct FileKey {
+    filesystem: usize,
+    inode: u64,
+}
+
+impl FileKey {
+    fn new(filesystem: &Arc<dyn Filesystem>, inode: u64) -> Self {
+        Self {
+            filesystem: filesystem_id(filesystem),
+            inode,
+        }
+    }
+}
+
+fn filesystem_id(filesystem: &Arc<dyn Filesystem>) -> usize {
+    Arc::as_ptr(filesystem) as *const () as usize
+}
+
+/// One page of file data in a frame of its own, returned to the frame allocator when dropped.
+struct Page {
+    phys: u64,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl Page {
+    fn new() -> Option<Self> {
+        Some(Self {
+            phys: frame::allocate_frame()?,
+            dirty: false,
+            last_used: 0,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(hhdm::phys_to_virt(self.phys) as *const u8, PAGE_SIZE)
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(hhdm::phys_to_virt(self.phys) as *mut u8, PAGE_SIZE)
+        }
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        frame::deallocate_frames(self.phys, 1);
+    }
+}
+
+struct CachedFile {
+    /// Held so the filesystem's address, part of the key, cannot be reused while cached.
+    _filesystem: Arc<dyn Filesystem>,
+    inode: Arc<dyn Inode>,
+    pages: BTreeMap<u64, Page>,
+}
+
+impl CachedFile {
+    /// Write one dirty page back, leaving out whatever lies past the end of the file.
+    fn write_back(&self, index: u64, page: &Page) -> Result<(), FsError> {
+        let start = index * PAGE_SIZE as u64;
+        let size = self.inode.metadata().size;
+        if start >= size {
+            return Ok(());
+        }
+        let length = (size - start).min(PAGE_SIZE as u64) as usize;
+        write_all(&self.inode, start, &page.bytes()[..length])
+    }
+}
+
+struct State {
+    files: BTreeMap<FileKey, CachedFile>,
+    pages: usize,
+    dirty: usize,
+    clock: u64,
+}
+
+impl State {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Drop the least recently used page, preferring clean ones and writing a dirty one back
+    /// only when `allow_dirty`. Returns whether a page was freed.
+    fn evict(&mut self, allow_dirty: bool) -> Result<bool, FsError> {
+        let mut victim: Option<(FileKey, u64, bool, u64)> = None;
+        for (key, file) in &self.files {
+            for (&index, page) in &file.pages {
+                if page.dirty && !allow_dirty {
+                    continue;
+                }
+                // Clean pages sort before dirty ones, then by age.
+                let better = match victim {
+                    None => true,
+                    Some((_, _, dirty, last_used)) => {
+                        (page.dirty, page.last_used) < (dirty, last_used)
+                    }
+                };
+                if better {
+                    victim = Some((*key, index, page.dirty, page.last_used));
+                }
+            }
+        }
+
+        let Some((key, index, dirty, _)) = victim else {
+            return Ok(false);
+        };
+        let file = self.files.get_mut(&key).expect("victim file is cached");
+        if dirty {
+            file.write_back(index, &file.pages[&index])?;
+            self.dirty -= 1;
+        }
+        file.pages.remove(&index);
+        self.pages -= 1;
+        if file.pages.is_empty() {
+            self.files.remove(&key);
+        }
+        Ok(true)
+    }
+
+    fn sync_file(&mut self, key: FileKey) -> Result<(), FsError> {
+        let Some(file) = self.files.get_mut(&key) else {
+            return Ok(());
+        };
+        let dirty: Vec<u64> = file
+            .pages
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&index, _)| index)
+            .collect();
+        for index in dirty {
+            file.write_back(index, &file.pages[&index])?;
+            file.pages.get_mut(&index).expect("dirty page").dirty = false;
+            self.dirty -= 1;
+        }
+        Ok(())
+    }
+
+    fn discard_file(&mut self, key: FileKey) {
+        if let Some(file) = self.files.remove(&key) {
+            self.pages -= file.pages.len();
+            self.dirty -= file.pages.values().filter(|page| page.dirty).count();
+        }
+    }
+}
+
+/// Counts for reporting, in pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageCacheStats {
+    pub pages: usize,
+    pub dirty: usize,
+}
+
+/// File data cached in page-sized frames, keyed by filesystem, inode and page index.
+///
+/// Writes only dirty the cache; the data reaches the filesystem on `sync`, when the file's
+/// dirty pages exceed the limit, or when a dirty page is evicted. Files grow on disk right
+/// away, so running out of space is reported by the write that caused it.
+pub struct PageCache {
+    state: Mutex<State>,
+    max_pages: usize,
+    max_dirty: usize,
+}
+
+impl PageCache {
+    pub const fn new(max_pages: usize, max_dirty: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                files: BTreeMap::new(),
+                pages: 0,
+                dirty: 0,
+                clock: 0,
+            }),
+            max_pages,
+            max_dirty,
+        }
+    }
+
+    /// Find page `index`, creating it when missing. A new page is read from the inode when
+    /// `fill` and zeroed otherwise. `None` means no frame could be found for it.
+    fn page<'a>(
+        &self,
+        state: &'a mut State,
+        key: FileKey,
+        filesystem: &Arc<dyn Filesystem>,
+        inode: &Arc<dyn Inode>,
+        index: u64,
+        fill: bool,
+    ) -> Result<Option<&'a mut Page>, FsError> {
+        let now = state.tick();
+        let cached = state
+            .files
+            .get(&key)
+            .is_some_and(|file| file.pages.contains_key(&index));
+
+        if !cached {
+            if state.pages >= self.max_pages && !state.evict(true)? {
+                return Ok(None);
+            }
+            let mut page = match Page::new() {
+                Some(page) => page,
+                None if state.evict(true)? => match Page::new() {
+                    Some(page) => page,
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
+            };
+
+            let bytes = page.bytes_mut();
+            let filled = if fill {
+                read_full(inode, index * PAGE_SIZE as u64, bytes)?
+            } else {
+                0
+            };
+            bytes[filled..].fill(0);
+
+            let file = state.files.entry(key).or_insert_with(|| CachedFile {
+                _filesystem: filesystem.clone(),
+                inode: inode.clone(),
+                pages: BTreeMap::new(),
+            });
+            file.pages.insert(index, page);
+            state.pages += 1;
+        }
+
+        let page = state
+            .files
+            .get_mut(&key)
+            .and_then(|file| file.pages.get_mut(&index))
+            .expect("page was just cached");
+        page.last_used = now;
+        Ok(Some(page))
+    }
+
+    /// Read from a regular file through the cache. With `readahead`, the pages following the
+    /// request are loaded as well, ready for the next sequential read.
+    pub fn read(
+        &self,
+        filesystem: &Arc<dyn Filesystem>,
+        inode: &Arc<dyn Inode>,
+        offset: u64,
+        buffer: &mut [u8],
+        readahead: bool,
+    ) -> Result<usize, FsError> {
+        let metadata = inode.metadata();
+        let key = FileKey::new(filesystem, metadata.inode);
+        if offset >= metadata.size || buffer.is_empty() {
+            return Ok(0);
+        }
+        let count = (metadata.size - offset).min(buffer.len() as u64) as usize;
+
+        let mut state = self.state.lock();
+        let mut done = 0;
+        while done < count {
+            let position = offset + done as u64;
+            let index = position / PAGE_SIZE as u64;
+            let within = (position % PAGE_SIZE as u64) as usize;
+            let length = (PAGE_SIZE - within).min(count - done);
+            let target = &mut buffer[done..done + length];
+
+            match self.page(&mut state, key, filesystem, inode, index, true)? {
+                Some(page) => target.copy_from_slice(&page.bytes()[within..within + length]),
+                None => {
+                    read_full(inode, position, target)?;
+                }
+            }
+            done += length;
+        }
+
+        if readahead {
+            let last = (offset + count as u64 - 1) / PAGE_SIZE as u64;
+            let pages = metadata.size.div_ceil(PAGE_SIZE as u64);
+            for index in last + 1..(last + 1 + READAHEAD_PAGES).min(pages) {
+                // Readahead is a hint, so it stops quietly at the first failure.
+                if !matches!(
+                    self.page(&mut state, key, filesystem, inode, index, true),
+                    Ok(Some(_))
+                ) {
+                    break;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Write to a regular file through the cache, growing it first when the write ends past
+    /// its current size.
+    pub fn write(
+        &self,
+        filesystem: &Arc<dyn Filesystem>,
+        inode: &Arc<dyn Inode>,
+        offset: u64,
+        buffer: &[u8],
+    ) -> Result<usize, FsError> {
+        let metadata = inode.metadata();
+        let key = FileKey::new(filesystem, metadata.inode);
+        let end = offset
+            .checked_add(buffer.len() as u64)
+            .ok_or(FsError::InvalidArgument)?;
+        if end > metadata.size {
+            inode.truncate(end)?;
+        }
+
+        let mut state = self.state.lock();
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let index = position / PAGE_SIZE as u64;
+            let within = (position % PAGE_SIZE as u64) as usize;
+            let length = (PAGE_SIZE - within).min(buffer.len() - done);
+            let source = &buffer[done..done + length];
+            // Only bytes that existed before the write need reading around a partial write.
+            let fill = length < PAGE_SIZE && index * (PAGE_SIZE as u64) < metadata.size;
+
+            match self.page(&mut state, key, filesystem, inode, index, fill)? {
+                Some(page) => {
+                    page.bytes_mut()[within..within + length].copy_from_slice(source);
+                    let newly_dirty = !page.dirty;
+                    page.dirty = true;
+                    if newly_dirty {
+                        state.dirty += 1;
+                    }
+                }
+                None => write_all(inode, position, source)?,
+            }
+            done += length;
+        }
+
+        if state.dirty > self.max_dirty {
+            state.sync_file(key)?;
+        }
+        Ok(buffer.len())
+    }
+
+    /// Forget cached data past `size` after the file was shortened, zeroing the tail of a
+    /// page the new end falls inside.
+    pub fn truncate(&self, filesystem: &Arc<dyn Filesystem>, inode: u64, size: u64) {
+        let key = FileKey::new(filesystem, inode);
+        let mut state = self.state.lock();
+        let Some(file) = state.files.get_mut(&key) else {
+            return;
+        };
+
+        let first_dropped = size.div_ceil(PAGE_SIZE as u64);
+        let dropped = file.pages.split_off(&first_dropped);
+        if let Some(page) = file.pages.get_mut(&(size / PAGE_SIZE as u64)) {
+            page.bytes_mut()[(size % PAGE_SIZE as u64) as usize..].fill(0);
+        }
+        let empty = file.pages.is_empty();
+
+        state.pages -= dropped.len();
+        state.dirty -= dropped.values().filter(|page| page.dirty).count();
+        if empty {
+            state.files.remove(&key);
+        }
+    }
+
+    /// Write back the dirty pages of one file.
+    pub fn sync_file(&self, filesystem: &Arc<dyn Filesystem>, inode: u64) -> Result<(), FsError> {
+        self.state.lock().sync_file(FileKey::new(filesystem, inode))
+    }
+
+    /// Write back the dirty pages of every file on `filesystem`.
+    pub fn sync_filesystem(&self, filesystem: &Arc<dyn Filesystem>) -> Result<(), FsError> {
+        let id = filesystem_id(filesystem);
+        let mut state = self.state.lock();
+        let keys: Vec<FileKey> = state
+            .files
+            .keys()
+            .filter(|key| key.filesystem == id)
+            .copied()
+            .collect();
+        keys.into_iter().try_for_each(|key| state.sync_file(key))
+    }
+
+    /// Drop a file's pages without writing them back, once the file no longer exists.
+    pub fn discard_file(&self, filesystem: &Arc<dyn Filesystem>, inode: u64) {
+        self.state
+            .lock()
+            .discard_file(FileKey::new(filesystem, inode));
+    }
+
+    /// Drop every page of `filesystem`, which should have been synced first.
+    pub fn discard_filesystem(&self, filesystem: &Arc<dyn Filesystem>) {
+        let id = filesystem_id(filesystem);
+        let mut state = self.state.lock();
+        let keys: Vec<FileKey> = state
+            .files
+            .keys()
+            .filter(|key| key.filesystem == id)
+            .copied()
+            .collect();
+        for key in keys {
+            state.discard_file(key);
+        }
+    }
+
+    /// Free up to `wanted` clean pages, returning how many were freed.
+    ///
+    /// Called by the frame allocator when it runs short, possibly while this cache is busy
+    /// allocating itself, so it gives up rather than wait for the lock and never does I/O.
+    pub fn reclaim(&self, wanted: usize) -> usize {
+        let Some(mut state) = self.state.try_lock() else {
+            return 0;
+        };
+        let mut freed = 0;
+        while freed < wanted && matches!(state.evict(false), Ok(true)) {
+            freed += 1;
+        }
+        freed
+    }
+
+    pub fn stats(&self) -> PageCacheStats {
+        let state = self.state.lock();
+        PageCacheStats {
+            pages: state.pages,
+            dirty: state.dirty,
+        }
+    }
+}
+
+/// Read until `buffer` is full or the file ends, returning the byte count.
+fn read_full(inode: &Arc<dyn Inode>, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let read = inode.read_at(offset + filled as u64, &mut buffer[filled..])?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+fn write_all(inode: &Arc<dyn Inode>, offset: u64, buffer: &[u8]) -> Result<(), FsError> {
+    let mut written = 0;
+    while written < buffer.len() {
+        let count = inode.write_at(offset + written as u64, &buffer[written..])?;
+        if count == 0 {
+            return Err(FsError::NoSpace);
+        }
+        written += count;
+    }
+    Ok(())
+}
+
+/// The kernel-wide page cache.
+pub fn page_cache() -> &'static PageCache {
+    &PAGE_CACHE
+}
+
+/// Let the frame allocator take clean pages back under memory pressure.
+pub fn init() {
+    frame::register_reclaimer(|wanted| PAGE_CACHE.reclaim(wanted));
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use kunit::kunit;
+
+    use super::{PageCache, PAGE_SIZE, READAHEAD_PAGES};
+    use crate::fs::tmpfs::Tmpfs;
+    use crate::fs::vfs::{FileType, Filesystem, FsError, Inode, Metadata};
+
+    /// Forwards to a tmpfs file, counting the calls that would reach a disk.
+    struct CountingInode {
+        inner: Arc<dyn Inode>,
+        reads: AtomicUsize,
+        writes: AtomicUsize,
+    }
+
+    impl Inode for CountingInode {
+        fn metadata(&self) -> Metadata {
+            self.inner.metadata()
+        }
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read_at(offset, buffer)
+        }
+
+        fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            self.inner.write_at(offset, buffer)
+        }
+
+        fn truncate(&self, size: u64) -> Result<(), FsError> {
+            self.inner.truncate(size)
+        }
+    }
+
+    struct Fixture {
+        filesystem: Arc<dyn Filesystem>,
+        counting: Arc<CountingInode>,
+        inode: Arc<dyn Inode>,
+    }
+
+    fn file(contents: &[u8]) -> Fixture {
+        let tmpfs: Arc<dyn Filesystem> = Arc::new(Tmpfs::new());
+        let inner = tmpfs
+            .root()
+            .create("file", FileType::Regular, 0o644)
+            .expect("create");
+        inner.write_at(0, contents).expect("write");
+        let counting = Arc::new(CountingInode {
+            inner,
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+        });
+        Fixture {
+            filesystem: tmpfs,
+            inode: counting.clone(),
+            counting,
+        }
+    }
+
+    fn pattern(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index % 251) as u8).collect()
+    }
+
+    #[kunit]
+    fn repeated_reads_are_served_from_memory() {
+        let cache = PageCache::new(64, 64);
+        let data = pattern(3 * PAGE_SIZE);
+        let fixture = file(&data);
+        let mut buffer = vec![0u8; 100];
+
+        for _ in 0..3 {
+            let read = cache
+                .read(
+                    &fixture.filesystem,
+                    &fixture.inode,
+                    4000,
+                    &mut buffer,
+                    false,
+                )
+                .expect("read");
+            assert_eq!(read, 100);
+            assert_eq!(buffer, data[4000..4100]);
+        }
+
+        assert_eq!(fixture.counting.reads.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.stats().pages, 2);
+    }
+
+    #[kunit]
+    fn sequential_reads_load_the_following_pages() {
+        let cache = PageCache::new(64, 64);
+        let fixture = file(&pattern(16 * PAGE_SIZE));
+        let mut buffer = vec![0u8; PAGE_SIZE];
+
+        cache
+            .read(&fixture.filesystem, &fixture.inode, 0, &mut buffer, true)
+            .expect("read");
+        let reads = fixture.counting.reads.load(Ordering::Relaxed);
+        for index in 1..=READAHEAD_PAGES {
+            cache
+                .read(
+                    &fixture.filesystem,
+                    &fixture.inode,
+                    index * PAGE_SIZE as u64,
+                    &mut buffer,
+                    false,
+                )
+                .expect("read");
+        }
+
+        assert_eq!(reads, 1 + READAHEAD_PAGES as usize);
+        assert_eq!(fixture.counting.reads.load(Ordering::Relaxed), reads);
+    }
+
+    #[kunit]
+    fn writes_stay_cached_until_synced() {
+        let cache = PageCache::new(64, 64);
+        let fixture = file(&pattern(PAGE_SIZE));
+
+        cache
+            .write(&fixture.filesystem, &fixture.inode, 10, b"cached")
+            .expect("write");
+        let mut on_disk = [0u8; 6];
+        fixture
+            .counting
+            .inner
+            .read_at(10, &mut on_disk)
+            .expect("read");
+        assert_ne!(&on_disk, b"cached");
+        assert_eq!(cache.stats().dirty, 1);
+
+        let number = fixture.inode.metadata().inode;
+        cache.sync_file(&fixture.filesystem, number).expect("sync");
+        fixture
+            .counting
+            .inner
+            .read_at(10, &mut on_disk)
+            .expect("read");
+        assert_eq!(&on_disk, b"cached");
+        assert_eq!(cache.stats().dirty, 0);
+    }
+
+    #[kunit]
+    fn writes_past_the_end_grow_the_file() {
+        let cache = PageCache::new(64, 64);
+        let fixture = file(b"head");
+
+        cache
+            .write(&fixture.filesystem, &fixture.inode, 5000, b"tail")
+            .expect("write");
+
+        assert_eq!(fixture.inode.metadata().size, 5004);
+        let mut buffer = vec![0xffu8; 5004];
+        let read = cache
+            .read(&fixture.filesystem, &fixture.inode, 0, &mut buffer, false)
+            .expect("read");
+        assert_eq!(read, 5004);
+        assert_eq!(&buffer[..4], b"head");
+        assert!(buffer[4..5000].iter().all(|&byte| byte == 0));
+        assert_eq!(&buffer[5000..], b"tail");
+    }
+
+    #[kunit]
+    fn a_full_cache_writes_back_before_evicting() {
+        let cache = PageCache::new(2, 64);
+        let fixture = file(&[]);
+
+        for index in 0..4u64 {
+            cache
+                .write(
+                    &fixture.filesystem,
+                    &fixture.inode,
+                    index * PAGE_SIZE as u64,
+                    &[index as u8 + 1; PAGE_SIZE],
+                )
+                .expect("write");
+        }
+
+        assert_eq!(cache.stats().pages, 2);
+        let mut byte = [0u8];
+        fixture.counting.inner.read_at(0, &mut byte).expect("read");
+        assert_eq!(byte, [1]);
+        fixture
+            .counting
+            .inner
+            .read_at(PAGE_SIZE as u64, &mut byte)
+            .expect("read");
+        assert_eq!(byte, [2]);
+    }
+
+    #[kunit]
+    fn reclaim_takes_only_clean_pages() {
+        let cache = PageCache::new(64, 64);
+        let fixture = file(&pattern(2 * PAGE_SIZE));
+        let mut buffer = vec![0u8; 2 * PAGE_SIZE];
+        cache
+            .read(&fixture.filesystem, &fixture.inode, 0, &mut buffer, false)
+            .expect("read");
+        cache
+            .write(&fixture.filesystem, &fixture.inode, 0, b"dirty")
+            .expect("write");
+
+        assert_eq!(cache.reclaim(8), 1);
+        assert_eq!(cache.stats().pages, 1);
+        assert_eq!(cache.stats().dirty, 1);
+    }
+
+    #[kunit]
+    fn truncation_drops_pages_past_the_end() {
+        let cache = PageCache::new(64, 64);
+        let fixture = file(&pattern(3 * PAGE_SIZE));
+        let number = fixture.inode.metadata().inode;
+        let mut buffer = vec![0u8; 3 * PAGE_SIZE];
+        cache
+            .read(&fixture.filesystem, &fixture.inode, 0, &mut buffer, false)
+            .expect("read");
+        cache
+            .write(
+                &fixture.filesystem,
+                &fixture.inode,
+                2 * PAGE_SIZE as u64,
+                b"gone",
+            )
+            .expect("write");
+
+        cache.truncate(&fixture.filesystem, number, 100);
+        fixture.inode.truncate(100).expect("truncate");
+        fixture.inode.truncate(PAGE_SIZE as u64).expect("grow");
+
+        assert_eq!(cache.stats().pages, 1);
+        assert_eq!(cache.stats().dirty, 0);
+        let read = cache
+            .read(&fixture.filesystem, &fixture.inode, 0, &mut buffer, false)
+            .expect("read");
+        assert_eq!(read, PAGE_SIZE);
+        assert!(buffer[100..PAGE_SIZE].iter().all(|&byte| byte == 0));
+    }
+}
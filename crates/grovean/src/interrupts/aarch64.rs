@@ -0,0 +1,610 @@
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use super::TIMER_HZ;
+use crate::memory::paging;
+use crate::{process, syscall};
+
+/// The EL1 virtual timer's private peripheral interrupt, which counts the same `cntvct_el0`
+/// the clock reads.
+pub const TIMER_VECTOR: usize = 27;
+
+#[derive(Clone, Copy)]
+struct GicConfig {
+    distributor: u64,
+    /// The GICv2 CPU interface, memory mapped.
+    cpu_interface: u64,
+    /// The GICv3 redistributors, one pair of 64 KiB frames per CPU.
+    redistributors: u64,
+    redistributor_count: usize,
+}
+
+const DEFAULT_GIC_CONFIG: GicConfig = GicConfig {
+    // QEMU virt platform GIC, version 2 or 3 depending on `gic-version`.
+    distributor: 0x0800_0000,
+    cpu_interface: 0x0801_0000,
+    redistributors: 0x080a_0000,
+    redistributor_count: 32,
+};
+
+const DISTRIBUTOR_SIZE: u64 = 0x1_0000;
+const CPU_INTERFACE_SIZE: u64 = 0x2000;
+const REDISTRIBUTOR_SIZE: u64 = 0x2_0000;
+/// Offset of the SGI and PPI frame within a redistributor.
+const SGI_FRAME: usize = 0x1_0000;
+
+/// Distributor register offsets (GIC architecture specification, section 12.8).
+const GICD_CTLR: usize = 0x000;
+const GICD_IGROUPR: usize = 0x080;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_IROUTER: usize = 0x6000;
+const GICD_PIDR2: usize = 0xffe8;
+
+/// GICv2 CPU interface register offsets.
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00c;
+const GICC_EOIR: usize = 0x010;
+
+/// GICv3 redistributor register offsets.
+const GICR_TYPER: usize = 0x008;
+const GICR_WAKER: usize = 0x014;
+const GICR_IGROUPR0: usize = SGI_FRAME + 0x080;
+const GICR_ISENABLER0: usize = SGI_FRAME + 0x100;
+const GICR_IPRIORITYR: usize = SGI_FRAME + 0x400;
+
+const GICD_CTLR_ENABLE_GROUPS: u32 = 0b11;
+const GICD_CTLR_RWP: u32 = 1 << 31;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+const INTERRUPT_PRIORITY: u8 = 0xa0;
+const LOWEST_PRIORITY_MASK: u32 = 0xff;
+/// Interrupt IDs from this one up are special, such as 1023 for "nothing pending".
+const SPECIAL_INTERRUPT_IDS: u32 = 1020;
+/// The first shared peripheral interrupt, after the SGIs and PPIs.
+const FIRST_SHARED_INTERRUPT: usize = 32;
+
+const TIMER_ENABLE: u64 = 1 << 0;
+
+static DISTRIBUTOR: AtomicU64 = AtomicU64::new(0);
+static CPU_INTERFACE: AtomicU64 = AtomicU64::new(0);
+static REDISTRIBUTORS: AtomicU64 = AtomicU64::new(0);
+/// Architecture version of the GIC found, zero before `init`.
+static GIC_VERSION: AtomicU8 = AtomicU8::new(0);
+/// Generic timer counts per scheduler tick.
+static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Registers saved on exception entry: every general purpose and SIMD register, since the
+/// interrupted code may be using any of them, and the user stack pointer, since another
+/// process may run before this one returns to user mode.
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub x: [u64; 31],
+    pub elr: u64,
+    pub spsr: u64,
+    pub sp_el0: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    pub q: [u128; 32],
+}
+
+global_asm!(
+    r#"
+.macro SAVE_REST
+    stp x2, x3, [sp, #16 * 1]
+    stp x4, x5, [sp, #16 * 2]
+    stp x6, x7, [sp, #16 * 3]
+    stp x8, x9, [sp, #16 * 4]
+    stp x10, x11, [sp, #16 * 5]
+    stp x12, x13, [sp, #16 * 6]
+    stp x14, x15, [sp, #16 * 7]
+    stp x16, x17, [sp, #16 * 8]
+    stp x18, x19, [sp, #16 * 9]
+    stp x20, x21, [sp, #16 * 10]
+    stp x22, x23, [sp, #16 * 11]
+    stp x24, x25, [sp, #16 * 12]
+    stp x26, x27, [sp, #16 * 13]
+    stp x28, x29, [sp, #16 * 14]
+    mrs x21, elr_el1
+    stp x30, x21, [sp, #16 * 15]
+    mrs x22, spsr_el1
+    mrs x26, sp_el0
+    stp x22, x26, [sp, #16 * 16]
+    mrs x23, fpcr
+    mrs x24, fpsr
+    stp x23, x24, [sp, #16 * 17]
+    add x25, sp, #16 * 18
+    stp q0, q1, [x25, #32 * 0]
+    stp q2, q3, [x25, #32 * 1]
+    stp q4, q5, [x25, #32 * 2]
+    stp q6, q7, [x25, #32 * 3]
+    stp q8, q9, [x25, #32 * 4]
+    stp q10, q11, [x25, #32 * 5]
+    stp q12, q13, [x25, #32 * 6]
+    stp q14, q15, [x25, #32 * 7]
+    stp q16, q17, [x25, #32 * 8]
+    stp q18, q19, [x25, #32 * 9]
+    stp q20, q21, [x25, #32 * 10]
+    stp q22, q23, [x25, #32 * 11]
+    stp q24, q25, [x25, #32 * 12]
+    stp q26, q27, [x25, #32 * 13]
+    stp q28, q29, [x25, #32 * 14]
+    stp q30, q31, [x25, #32 * 15]
+.endm
+
+.macro RESTORE_FRAME
+    add x25, sp, #16 * 18
+    ldp q0, q1, [x25, #32 * 0]
+    ldp q2, q3, [x25, #32 * 1]
+    ldp q4, q5, [x25, #32 * 2]
+    ldp q6, q7, [x25, #32 * 3]
+    ldp q8, q9, [x25, #32 * 4]
+    ldp q10, q11, [x25, #32 * 5]
+    ldp q12, q13, [x25, #32 * 6]
+    ldp q14, q15, [x25, #32 * 7]
+    ldp q16, q17, [x25, #32 * 8]
+    ldp q18, q19, [x25, #32 * 9]
+    ldp q20, q21, [x25, #32 * 10]
+    ldp q22, q23, [x25, #32 * 11]
+    ldp q24, q25, [x25, #32 * 12]
+    ldp q26, q27, [x25, #32 * 13]
+    ldp q28, q29, [x25, #32 * 14]
+    ldp q30, q31, [x25, #32 * 15]
+    ldp x23, x24, [sp, #16 * 17]
+    msr fpcr, x23
+    msr fpsr, x24
+    ldp x22, x26, [sp, #16 * 16]
+    msr spsr_el1, x22
+    msr sp_el0, x26
+    ldp x30, x21, [sp, #16 * 15]
+    msr elr_el1, x21
+    ldp x0, x1, [sp, #16 * 0]
+    ldp x2, x3, [sp, #16 * 1]
+    ldp x4, x5, [sp, #16 * 2]
+    ldp x6, x7, [sp, #16 * 3]
+    ldp x8, x9, [sp, #16 * 4]
+    ldp x10, x11, [sp, #16 * 5]
+    ldp x12, x13, [sp, #16 * 6]
+    ldp x14, x15, [sp, #16 * 7]
+    ldp x16, x17, [sp, #16 * 8]
+    ldp x18, x19, [sp, #16 * 9]
+    ldp x20, x21, [sp, #16 * 10]
+    ldp x22, x23, [sp, #16 * 11]
+    ldp x24, x25, [sp, #16 * 12]
+    ldp x26, x27, [sp, #16 * 13]
+    ldp x28, x29, [sp, #16 * 14]
+    add sp, sp, #800
+.endm
+
+.macro VECTOR entry, kind
+.balign 0x80
+    sub sp, sp, #800
+    stp x0, x1, [sp, #16 * 0]
+    mov x0, #\kind
+    b \entry
+.endm
+
+.macro ENTRY name, handler
+\name:
+    SAVE_REST
+    mov x1, x0
+    mov x0, sp
+    bl \handler
+    b exception_return
+.endm
+
+.section .text
+.balign 0x800
+.global exception_vectors
+exception_vectors:
+    VECTOR synchronous_entry, 0
+    VECTOR irq_entry, 1
+    VECTOR unexpected_entry, 2
+    VECTOR unexpected_entry, 3
+    VECTOR synchronous_entry, 4
+    VECTOR irq_entry, 5
+    VECTOR unexpected_entry, 6
+    VECTOR unexpected_entry, 7
+    VECTOR synchronous_entry, 8
+    VECTOR irq_entry, 9
+    VECTOR unexpected_entry, 10
+    VECTOR unexpected_entry, 11
+    VECTOR unexpected_entry, 12
+    VECTOR unexpected_entry, 13
+    VECTOR unexpected_entry, 14
+    VECTOR unexpected_entry, 15
+
+ENTRY unexpected_entry, {unexpected}
+ENTRY synchronous_entry, {synchronous}
+ENTRY irq_entry, {irq}
+
+exception_return:
+    RESTORE_FRAME
+    eret
+"#,
+    unexpected = sym handle_unexpected,
+    synchronous = sym handle_synchronous,
+    irq = sym handle_irq,
+);
+
+unsafe extern "C" {
+    static exception_vectors: u8;
+}
+
+/// Vector slot of a synchronous exception from user mode.
+const SYNCHRONOUS_FROM_EL0: u64 = 8;
+/// Exception class of `svc` in AArch64 state, a system call.
+const SVC_FROM_AARCH64: u64 = 0x15;
+
+/// Exception classes, vector slots in table order.
+const EXCEPTION_KINDS: [&str; 16] = [
+    "synchronous from EL1t",
+    "IRQ from EL1t",
+    "FIQ from EL1t",
+    "SError from EL1t",
+    "synchronous from EL1h",
+    "IRQ from EL1h",
+    "FIQ from EL1h",
+    "SError from EL1h",
+    "synchronous from EL0 (AArch64)",
+    "IRQ from EL0 (AArch64)",
+    "FIQ from EL0 (AArch64)",
+    "SError from EL0 (AArch64)",
+    "synchronous from EL0 (AArch32)",
+    "IRQ from EL0 (AArch32)",
+    "FIQ from EL0 (AArch32)",
+    "SError from EL0 (AArch32)",
+];
+
+pub fn init() {
+    install_vectors();
+
+    let config = DEFAULT_GIC_CONFIG;
+    let Ok(distributor) = paging::map_mmio(config.distributor, DISTRIBUTOR_SIZE) else {
+        crate::warn_ln!("interrupts: cannot map the GIC distributor");
+        return;
+    };
+    DISTRIBUTOR.store(distributor, Ordering::Relaxed);
+
+    let version = ((read_distributor(GICD_PIDR2) >> 4) & 0xf) as u8;
+    let mapped = match version {
+        2 => paging::map_mmio(config.cpu_interface, CPU_INTERFACE_SIZE)
+            .map(|virt| CPU_INTERFACE.store(virt, Ordering::Relaxed)),
+        3 | 4 => paging::map_mmio(
+            config.redistributors,
+            REDISTRIBUTOR_SIZE * config.redistributor_count as u64,
+        )
+        .map(|virt| REDISTRIBUTORS.store(virt, Ordering::Relaxed)),
+        _ => {
+            crate::warn_ln!("interrupts: unsupported GIC version {}", version);
+            return;
+        }
+    };
+    if mapped.is_err() {
+        crate::warn_ln!("interrupts: cannot map the GIC CPU interface");
+        return;
+    }
+    GIC_VERSION.store(version, Ordering::Relaxed);
+
+    write_distributor(
+        GICD_CTLR,
+        read_distributor(GICD_CTLR) | GICD_CTLR_ENABLE_GROUPS,
+    );
+    while read_distributor(GICD_CTLR) & GICD_CTLR_RWP != 0 {
+        core::hint::spin_loop();
+    }
+    if !init_cpu_interface() {
+        crate::warn_ln!("interrupts: no GIC redistributor for this CPU");
+        return;
+    }
+
+    let frequency: u64;
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack, preserves_flags));
+    }
+    TICK_COUNT.store((frequency / TIMER_HZ).max(1), Ordering::Relaxed);
+    enable_private_interrupt(TIMER_VECTOR);
+    start_timer();
+    crate::info_ln!(
+        "interrupts: GICv{}, generic timer at {} Hz",
+        version,
+        TIMER_HZ
+    );
+}
+
+pub fn init_secondary() {
+    install_vectors();
+    if GIC_VERSION.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if !init_cpu_interface() {
+        crate::warn_ln!("interrupts: no GIC redistributor for this CPU");
+        return;
+    }
+    enable_private_interrupt(TIMER_VECTOR);
+    start_timer();
+}
+
+/// Point the calling CPU at the exception vectors.
+fn install_vectors() {
+    unsafe {
+        // Exceptions are taken on SP_EL1, so run on it from here on, keeping the stack.
+        asm!(
+            "mrs {selected}, spsel",
+            "cbnz {selected}, 1f",
+            "mov {stack}, sp",
+            "msr spsel, #1",
+            "mov sp, {stack}",
+            "1:",
+            "msr vbar_el1, {vectors}",
+            "isb",
+            selected = out(reg) _,
+            stack = out(reg) _,
+            vectors = in(reg) core::ptr::addr_of!(exception_vectors) as u64,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+fn read_register(base: &AtomicU64, offset: usize) -> u32 {
+    let base = base.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base as usize + offset) as *const u32) }
+}
+
+fn write_register(base: &AtomicU64, offset: usize, value: u32) {
+    let base = base.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base as usize + offset) as *mut u32, value) }
+}
+
+fn write_register_byte(base: &AtomicU64, offset: usize, value: u8) {
+    let base = base.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base as usize + offset) as *mut u8, value) }
+}
+
+fn read_distributor(offset: usize) -> u32 {
+    read_register(&DISTRIBUTOR, offset)
+}
+
+fn write_distributor(offset: usize, value: u32) {
+    write_register(&DISTRIBUTOR, offset, value)
+}
+
+/// Find the redistributor whose affinity matches this CPU's, as an offset into the mapping.
+fn redistributor_offset() -> Option<usize> {
+    let mpidr: u64;
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack, preserves_flags));
+    }
+    let affinity = (mpidr & 0xff_ffff) | ((mpidr >> 8) & 0xff00_0000);
+
+    let base = REDISTRIBUTORS.load(Ordering::Relaxed) as usize;
+    for index in 0..DEFAULT_GIC_CONFIG.redistributor_count {
+        let offset = index * REDISTRIBUTOR_SIZE as usize;
+        let typer = unsafe { core::ptr::read_volatile((base + offset + GICR_TYPER) as *const u64) };
+        if typer >> 32 == affinity {
+            return Some(offset);
+        }
+        if typer & GICR_TYPER_LAST != 0 {
+            break;
+        }
+    }
+    None
+}
+
+/// Let the calling CPU take interrupts from the distributor at every priority.
+fn init_cpu_interface() -> bool {
+    if GIC_VERSION.load(Ordering::Relaxed) == 2 {
+        write_register(&CPU_INTERFACE, GICC_PMR, LOWEST_PRIORITY_MASK);
+        write_register(&CPU_INTERFACE, GICC_CTLR, 1);
+        return true;
+    }
+
+    let Some(offset) = redistributor_offset() else {
+        return false;
+    };
+    let waker = read_register(&REDISTRIBUTORS, offset + GICR_WAKER);
+    write_register(
+        &REDISTRIBUTORS,
+        offset + GICR_WAKER,
+        waker & !GICR_WAKER_PROCESSOR_SLEEP,
+    );
+    while read_register(&REDISTRIBUTORS, offset + GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+        core::hint::spin_loop();
+    }
+
+    unsafe {
+        // ICC_SRE_EL1, ICC_PMR_EL1 and ICC_IGRPEN1_EL1 by encoding.
+        asm!(
+            "mrs {tmp}, S3_0_C12_C12_5",
+            "orr {tmp}, {tmp}, #1",
+            "msr S3_0_C12_C12_5, {tmp}",
+            "isb",
+            "msr S3_0_C4_C6_0, {mask}",
+            "msr S3_0_C12_C12_7, {one}",
+            "isb",
+            tmp = out(reg) _,
+            mask = in(reg) LOWEST_PRIORITY_MASK as u64,
+            one = in(reg) 1u64,
+            options(nostack, preserves_flags)
+        );
+    }
+    true
+}
+
+/// Enable a private peripheral interrupt on the calling CPU.
+fn enable_private_interrupt(id: usize) {
+    if GIC_VERSION.load(Ordering::Relaxed) == 2 {
+        write_register_byte(&DISTRIBUTOR, GICD_IPRIORITYR + id, INTERRUPT_PRIORITY);
+        write_distributor(GICD_ISENABLER + id / 32 * 4, 1 << (id % 32));
+        return;
+    }
+
+    let Some(offset) = redistributor_offset() else {
+        return;
+    };
+    let group = read_register(&REDISTRIBUTORS, offset + GICR_IGROUPR0);
+    write_register(&REDISTRIBUTORS, offset + GICR_IGROUPR0, group | 1 << id);
+    write_register_byte(
+        &REDISTRIBUTORS,
+        offset + GICR_IPRIORITYR + id,
+        INTERRUPT_PRIORITY,
+    );
+    write_register(&REDISTRIBUTORS, offset + GICR_ISENABLER0, 1 << id);
+}
+
+pub fn enable_line(line: usize) -> Option<usize> {
+    let id = FIRST_SHARED_INTERRUPT + line;
+    let version = GIC_VERSION.load(Ordering::Relaxed);
+    if version == 0 || id >= SPECIAL_INTERRUPT_IDS as usize {
+        return None;
+    }
+    if version == 2 {
+        // The first target registers read back the calling CPU's own target bit.
+        let target = read_distributor(GICD_ITARGETSR) as u8;
+        write_register_byte(&DISTRIBUTOR, GICD_ITARGETSR + id, target);
+    } else {
+        let mpidr: u64;
+        unsafe {
+            asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack, preserves_flags));
+        }
+        let affinity = mpidr & 0xff_00ff_ffff;
+        let router = GICD_IROUTER + id * 8;
+        write_distributor(router, affinity as u32);
+        write_distributor(router + 4, (affinity >> 32) as u32);
+        let group = GICD_IGROUPR + id / 32 * 4;
+        write_distributor(group, read_distributor(group) | 1 << (id % 32));
+    }
+    write_register_byte(&DISTRIBUTOR, GICD_IPRIORITYR + id, INTERRUPT_PRIORITY);
+    write_distributor(GICD_ISENABLER + id / 32 * 4, 1 << (id % 32));
+    Some(id)
+}
+
+/// Take the highest priority pending interrupt, returning its acknowledgement value.
+fn acknowledge() -> u32 {
+    if GIC_VERSION.load(Ordering::Relaxed) == 2 {
+        return read_register(&CPU_INTERFACE, GICC_IAR);
+    }
+    let iar: u64;
+    unsafe {
+        // ICC_IAR1_EL1
+        asm!("mrs {}, S3_0_C12_C12_0", out(reg) iar, options(nomem, nostack, preserves_flags));
+    }
+    iar as u32
+}
+
+fn end_of_interrupt(iar: u32) {
+    if GIC_VERSION.load(Ordering::Relaxed) == 2 {
+        write_register(&CPU_INTERFACE, GICC_EOIR, iar);
+        return;
+    }
+    unsafe {
+        // ICC_EOIR1_EL1
+        asm!("msr S3_0_C12_C12_1, {}", in(reg) iar as u64, options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Arm the virtual timer for one tick from now, which also clears a pending expiry.
+fn start_timer() {
+    unsafe {
+        asm!(
+            "msr cntv_tval_el0, {count}",
+            "msr cntv_ctl_el0, {control}",
+            "isb",
+            count = in(reg) TICK_COUNT.load(Ordering::Relaxed),
+            control = in(reg) TIMER_ENABLE,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+pub fn enable() {
+    unsafe {
+        asm!("msr daifclr, #2", options(nomem, nostack, preserves_flags));
+    }
+}
+
+pub fn disable() {
+    unsafe {
+        asm!("msr daifset, #2", options(nomem, nostack, preserves_flags));
+    }
+}
+
+pub fn are_enabled() -> bool {
+    let daif: u64;
+    unsafe {
+        asm!("mrs {}, daif", out(reg) daif, options(nomem, nostack, preserves_flags));
+    }
+    daif & (1 << 7) == 0
+}
+
+pub fn enable_and_wait() {
+    // A pending interrupt wakes `wfi` even while masked, and is taken once unmasked.
+    unsafe {
+        asm!(
+            "wfi",
+            "msr daifclr, #2",
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+extern "C" fn handle_irq(_frame: &mut ExceptionFrame, _kind: u64) {
+    let iar = acknowledge();
+    let id = iar & 0x3ff;
+    if id >= SPECIAL_INTERRUPT_IDS {
+        return;
+    }
+    if id as usize == TIMER_VECTOR {
+        start_timer();
+    }
+    end_of_interrupt(iar);
+    super::dispatch(id as usize);
+}
+
+extern "C" fn handle_synchronous(frame: &mut ExceptionFrame, kind: u64) {
+    let (esr, far): (u64, u64);
+    unsafe {
+        asm!("mrs {}, esr_el1", out(reg) esr, options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, far_el1", out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+    // A `brk` instruction is reported and stepped over, like a breakpoint on x86.
+    if esr >> 26 == 0x3c {
+        crate::warn_ln!("interrupts: breakpoint at {:#x}", frame.elr);
+        frame.elr += 4;
+        return;
+    }
+    if kind == SYNCHRONOUS_FROM_EL0 && esr >> 26 == SVC_FROM_AARCH64 {
+        let arguments = [
+            frame.x[0], frame.x[1], frame.x[2], frame.x[3], frame.x[4], frame.x[5],
+        ];
+        frame.x[0] =
+            syscall::dispatch(frame.x[8] as usize, arguments.map(|value| value as usize)) as u64;
+        return;
+    }
+    if kind == SYNCHRONOUS_FROM_EL0 {
+        process::fault(format_args!(
+            "exception at {:#x}, ESR {:#x}, FAR {:#x}",
+            frame.elr, esr, far
+        ));
+    }
+    crate::danger_ln!(
+        "interrupts: {} at {:#x}, ESR {:#x}, FAR {:#x}",
+        EXCEPTION_KINDS[kind as usize],
+        frame.elr,
+        esr,
+        far
+    );
+    crate::hlt_loop()
+}
+
+extern "C" fn handle_unexpected(frame: &mut ExceptionFrame, kind: u64) {
+    crate::danger_ln!(
+        "interrupts: unexpected {} at {:#x}",
+        EXCEPTION_KINDS[kind as usize],
+        frame.elr
+    );
+    crate::hlt_loop()
+}
@@ -0,0 +1,192 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+pub mod softirq;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::{cpu, task};
+
+pub use arch::TIMER_VECTOR;
+
+/// Interrupt vectors tracked, enough for every x86 IDT entry and every GIC SGI, PPI and SPI
+/// the kernel routes.
+pub const VECTOR_COUNT: usize = 256;
+
+/// Timer interrupts per second on every CPU, the rate the scheduler ticks at.
+pub const TIMER_HZ: u64 = 100;
+
+static COUNTS: [AtomicU64; VECTOR_COUNT] = [const { AtomicU64::new(0) }; VECTOR_COUNT];
+static NAMES: Mutex<[Option<&'static str>; VECTOR_COUNT]> = Mutex::new([None; VECTOR_COUNT]);
+/// The `Handler` of each vector as an address, zero while none is installed.
+static HANDLERS: [AtomicUsize; VECTOR_COUNT] = [const { AtomicUsize::new(0) }; VECTOR_COUNT];
+
+crate::cpu_local! {
+    /// Interrupt handlers running on each CPU, nonzero while `dispatch` runs one.
+    static NESTING: Cell<usize> = Cell::new(0);
+}
+
+/// Runs when its vector fires, after the interrupt controller has been told the interrupt
+/// was handled, with interrupts disabled.
+pub type Handler = fn();
+
+/// How often one vector fired since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptCount {
+    pub vector: usize,
+    pub count: u64,
+    /// What the vector was registered for, such as `timer`.
+    pub name: Option<&'static str>,
+}
+
+/// Count one delivery of `vector`. Called from interrupt handlers, so it never blocks.
+pub fn record(vector: usize) {
+    if let Some(count) = COUNTS.get(vector) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Label `vector` in the counters listing.
+pub fn set_name(vector: usize, name: &'static str) {
+    if let Some(slot) = NAMES.lock().get_mut(vector) {
+        *slot = Some(name);
+    }
+}
+
+/// Run `handler` whenever `vector` fires, labelling the vector `name`.
+pub fn set_handler(vector: usize, name: &'static str, handler: Handler) {
+    set_name(vector, name);
+    if let Some(slot) = HANDLERS.get(vector) {
+        slot.store(handler as usize, Ordering::Release);
+    }
+}
+
+/// Count an interrupt and run its handler, then the softirqs it raised, then switch tasks if
+/// any of them asked for it. Called by the architecture's entry code.
+fn dispatch(vector: usize) {
+    record(vector);
+    let handler = match HANDLERS.get(vector) {
+        Some(slot) => slot.load(Ordering::Acquire),
+        None => 0,
+    };
+    if handler != 0 {
+        let handler: Handler = unsafe { core::mem::transmute::<usize, Handler>(handler) };
+        NESTING.with(|nesting| nesting.set(nesting.get() + 1));
+        handler();
+        NESTING.with(|nesting| nesting.set(nesting.get() - 1));
+    }
+    if NESTING.with(Cell::get) == 0 {
+        softirq::run_pending();
+    }
+    if !in_interrupt() && cpu::preemptible() && cpu::reschedule_requested() {
+        task::yield_now();
+    }
+}
+
+/// Whether the caller is an interrupt handler or softirq, which must not block or switch
+/// tasks.
+pub fn in_interrupt() -> bool {
+    NESTING.with(|nesting| nesting.get() > 0) || softirq::is_active()
+}
+
+/// Install the exception vectors, take over the interrupt controller and start the timer on
+/// the bootstrap processor. Interrupts stay disabled until `enable`.
+pub fn init() {
+    arch::init();
+}
+
+/// Install the exception vectors and start the timer on an application processor, using the
+/// interrupt controller `init` set up.
+pub fn init_secondary() {
+    arch::init_secondary();
+}
+
+/// Open device interrupt `line`, an ISA IRQ on x86_64 and a shared peripheral interrupt on
+/// aarch64, delivering it to the calling CPU, which must be the bootstrap processor. Returns
+/// the vector it arrives on for `set_handler`, or `None` without an interrupt controller.
+pub fn enable_line(line: usize) -> Option<usize> {
+    arch::enable_line(line)
+}
+
+pub fn enable() {
+    arch::enable();
+}
+
+pub fn disable() {
+    arch::disable();
+}
+
+pub fn are_enabled() -> bool {
+    arch::are_enabled()
+}
+
+/// Run `f` with interrupts disabled, restoring the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = are_enabled();
+    if enabled {
+        disable();
+    }
+    let result = f();
+    if enabled {
+        enable();
+    }
+    result
+}
+
+/// Enable interrupts and sleep until one arrives. Called with interrupts disabled, so one
+/// arriving in between is not missed.
+pub fn enable_and_wait() {
+    arch::enable_and_wait();
+}
+
+/// List every vector that fired or has a name, in vector order.
+pub fn counts() -> Vec<InterruptCount> {
+    let names = NAMES.lock();
+    COUNTS
+        .iter()
+        .enumerate()
+        .map(|(vector, count)| InterruptCount {
+            vector,
+            count: count.load(Ordering::Relaxed),
+            name: names[vector],
+        })
+        .filter(|entry| entry.count != 0 || entry.name.is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{counts, record, set_name, VECTOR_COUNT};
+
+    #[kunit]
+    fn recorded_and_named_vectors_are_listed() {
+        set_name(250, "test");
+        record(251);
+        record(251);
+        record(VECTOR_COUNT);
+
+        let listed = counts();
+        let named = listed
+            .iter()
+            .find(|entry| entry.vector == 250)
+            .expect("250");
+        let fired = listed
+            .iter()
+            .find(|entry| entry.vector == 251)
+            .expect("251");
+        assert_eq!(named.name, Some("test"));
+        assert!(fired.count >= 2);
+        assert!(listed.iter().all(|entry| entry.vector < VECTOR_COUNT));
+    }
+}
@@ -0,0 +1,118 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Passes over the pending softirqs made before leaving the rest for the next interrupt, so
+/// softirqs that keep raising themselves cannot starve tasks.
+const MAX_RESTARTS: usize = 10;
+
+/// The `Handler` of each softirq as an address, zero while none is installed.
+static HANDLERS: [AtomicUsize; Softirq::COUNT] = [const { AtomicUsize::new(0) }; Softirq::COUNT];
+
+crate::cpu_local! {
+    /// Softirqs raised on each CPU and not yet run, one bit per `Softirq`.
+    static PENDING: AtomicU32 = AtomicU32::new(0);
+    /// Set while each CPU runs softirq handlers.
+    static ACTIVE: Cell<bool> = Cell::new(false);
+}
+
+/// Work an interrupt handler defers to run right after it, with interrupts enabled. Lower
+/// numbered softirqs run first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Softirq {
+    /// Expires kernel timers.
+    Timer,
+    /// Processes completed network transmissions and received frames.
+    Net,
+}
+
+impl Softirq {
+    pub const COUNT: usize = 2;
+    pub const ALL: [Self; Self::COUNT] = [Self::Timer, Self::Net];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::Net => "net",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Runs in interrupt context with interrupts enabled, so it must not block, but other
+/// interrupts can arrive meanwhile. A softirq never runs on two paths of one CPU at once,
+/// though it may run on several CPUs at the same time.
+pub type Handler = fn();
+
+/// Run `handler` whenever `softirq` is raised.
+pub fn set_handler(softirq: Softirq, handler: Handler) {
+    HANDLERS[softirq as usize].store(handler as usize, Ordering::Release);
+}
+
+/// Mark `softirq` pending on this CPU. It runs when the outermost interrupt handler returns,
+/// so when raised outside an interrupt it waits for the next one, at most a timer tick.
+pub fn raise(softirq: Softirq) {
+    PENDING.with(|pending| pending.fetch_or(softirq.bit(), Ordering::Release));
+}
+
+/// Whether this CPU is running softirq handlers.
+pub fn is_active() -> bool {
+    ACTIVE.with(Cell::get)
+}
+
+/// Run the softirqs pending on this CPU. Called with interrupts disabled as the outermost
+/// interrupt handler returns, and returns with them disabled again.
+pub(super) fn run_pending() {
+    if is_active() {
+        return;
+    }
+    ACTIVE.with(|active| active.set(true));
+    for _ in 0..MAX_RESTARTS {
+        let pending = take_pending();
+        if pending == 0 {
+            break;
+        }
+        super::enable();
+        for softirq in Softirq::ALL {
+            if pending & softirq.bit() != 0 {
+                run(softirq);
+            }
+        }
+        super::disable();
+    }
+    ACTIVE.with(|active| active.set(false));
+}
+
+/// Clear and return the softirqs pending on this CPU.
+fn take_pending() -> u32 {
+    PENDING.with(|pending| pending.swap(0, Ordering::Acquire))
+}
+
+fn run(softirq: Softirq) {
+    let handler = HANDLERS[softirq as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: Handler = unsafe { core::mem::transmute::<usize, Handler>(handler) };
+        handler();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{is_active, raise, take_pending, Softirq};
+
+    #[kunit]
+    fn raised_softirqs_stay_pending_until_taken() {
+        take_pending();
+        raise(Softirq::Net);
+        raise(Softirq::Timer);
+        raise(Softirq::Net);
+        assert_eq!(take_pending(), Softirq::Timer.bit() | Softirq::Net.bit());
+        assert_eq!(take_pending(), 0);
+        assert!(!is_active());
+    }
+}
@@ -0,0 +1,347 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use x86_64::instructions::segmentation::GS;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+use super::TIMER_HZ;
+use crate::cpu;
+use crate::memory::paging;
+use crate::process;
+use crate::sync::IrqSpinLock;
+
+/// The local APIC timer, above the vectors the legacy PICs were moved to.
+pub const TIMER_VECTOR: usize = 0x30;
+const SPURIOUS_VECTOR: usize = 0xff;
+
+/// The legacy PICs are remapped past the exceptions and masked until `enable_line` opens a
+/// line. Masked lines can still raise spurious interrupts on the last line of each PIC.
+const PIC_OFFSET: u8 = 0x20;
+const PIC_LINES: usize = 16;
+/// The line the secondary PIC is chained to the primary one on.
+const PIC_CASCADE_LINE: usize = 2;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Local APIC register offsets (Intel SDM volume 3, table 11-1).
+const REG_TASK_PRIORITY: usize = 0x080;
+const REG_EOI: usize = 0x0b0;
+const REG_SPURIOUS: usize = 0x0f0;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_LVT_LINT0: usize = 0x350;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Delivery mode taking the vector from the legacy PICs, which are wired to LINT0.
+const LVT_DELIVERY_EXTINT: u32 = 0b111 << 8;
+/// Divide configuration encoding of divide by 16.
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+const CALIBRATION: Duration = Duration::from_millis(10);
+const CALIBRATION_SPINS: usize = 100_000_000;
+/// Timer counts per tick used when the timer could not be measured.
+const FALLBACK_TICK_COUNT: u32 = 100_000;
+
+/// Virtual address of the local APIC registers, the same physical page on every CPU.
+static LAPIC: AtomicU64 = AtomicU64::new(0);
+/// Timer counts per scheduler tick, measured once on the bootstrap processor.
+static TICK_COUNT: AtomicU32 = AtomicU32::new(0);
+static PICS: IrqSpinLock<ChainedPics> =
+    IrqSpinLock::new(unsafe { ChainedPics::new(PIC_OFFSET, PIC_OFFSET + 8) });
+/// Legacy PIC lines `enable_line` opened, one bit each.
+static ENABLED_LINES: AtomicU16 = AtomicU16::new(0);
+
+/// A handler for each legacy PIC line, which the IDT cannot tell apart otherwise.
+const LEGACY_HANDLERS: [extern "x86-interrupt" fn(InterruptStackFrame); PIC_LINES] = [
+    legacy_pic_interrupt::<0>,
+    legacy_pic_interrupt::<1>,
+    legacy_pic_interrupt::<2>,
+    legacy_pic_interrupt::<3>,
+    legacy_pic_interrupt::<4>,
+    legacy_pic_interrupt::<5>,
+    legacy_pic_interrupt::<6>,
+    legacy_pic_interrupt::<7>,
+    legacy_pic_interrupt::<8>,
+    legacy_pic_interrupt::<9>,
+    legacy_pic_interrupt::<10>,
+    legacy_pic_interrupt::<11>,
+    legacy_pic_interrupt::<12>,
+    legacy_pic_interrupt::<13>,
+    legacy_pic_interrupt::<14>,
+    legacy_pic_interrupt::<15>,
+];
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(divide_error);
+        idt.breakpoint.set_handler_fn(breakpoint);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault)
+                .set_stack_index(cpu::DOUBLE_FAULT_STACK_INDEX);
+        }
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault);
+        idt.page_fault.set_handler_fn(page_fault);
+        for (line, &handler) in LEGACY_HANDLERS.iter().enumerate() {
+            idt[PIC_OFFSET as usize + line].set_handler_fn(handler);
+        }
+        idt[TIMER_VECTOR].set_handler_fn(timer_interrupt);
+        idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt);
+        idt
+    };
+}
+
+pub fn init() {
+    IDT.load();
+
+    {
+        let mut pics = PICS.lock();
+        unsafe {
+            pics.initialize();
+            pics.disable();
+        }
+    }
+
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { apic_base.read() };
+    unsafe {
+        apic_base.write(base | APIC_BASE_ENABLE);
+    }
+    match paging::map_mmio(base & APIC_BASE_ADDRESS_MASK, 0x1000) {
+        Ok(virt) => LAPIC.store(virt, Ordering::Relaxed),
+        Err(error) => {
+            crate::warn_ln!("interrupts: cannot map the local APIC: {:?}", error);
+            return;
+        }
+    }
+
+    enable_local_apic();
+    // Only the bootstrap processor takes the interrupts the legacy PICs pass on.
+    write_register(REG_LVT_LINT0, LVT_DELIVERY_EXTINT);
+    let count = calibrate_timer();
+    TICK_COUNT.store(count, Ordering::Relaxed);
+    start_timer();
+    crate::info_ln!(
+        "interrupts: local APIC timer at {} Hz, {} counts per tick",
+        TIMER_HZ,
+        count
+    );
+}
+
+pub fn init_secondary() {
+    IDT.load();
+    if LAPIC.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    enable_local_apic();
+    write_register(REG_LVT_LINT0, LVT_MASKED);
+    start_timer();
+}
+
+pub fn enable_line(line: usize) -> Option<usize> {
+    if line >= PIC_LINES || LAPIC.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    let mut pics = PICS.lock();
+    ENABLED_LINES.fetch_or(1 << line, Ordering::Relaxed);
+    unsafe {
+        let [mut primary, mut secondary] = pics.read_masks();
+        if line < 8 {
+            primary &= !(1 << line);
+        } else {
+            primary &= !(1 << PIC_CASCADE_LINE);
+            secondary &= !(1 << (line - 8));
+        }
+        pics.write_masks(primary, secondary);
+    }
+    Some(PIC_OFFSET as usize + line)
+}
+
+fn read_register(offset: usize) -> u32 {
+    let base = LAPIC.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base as usize + offset) as *const u32) }
+}
+
+fn write_register(offset: usize, value: u32) {
+    let base = LAPIC.load(Ordering::Relaxed);
+    unsafe { core::ptr::write_volatile((base as usize + offset) as *mut u32, value) }
+}
+
+fn enable_local_apic() {
+    write_register(REG_TASK_PRIORITY, 0);
+    write_register(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+/// Count how far the timer runs down in a known time, measured with the CPU counter.
+fn calibrate_timer() -> u32 {
+    write_register(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write_register(REG_LVT_TIMER, LVT_MASKED);
+    write_register(REG_TIMER_INITIAL, u32::MAX);
+
+    let start = crate::time::uptime();
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..CALIBRATION_SPINS {
+        elapsed = crate::time::uptime() - start;
+        if elapsed >= CALIBRATION {
+            break;
+        }
+    }
+    let counted = u32::MAX - read_register(REG_TIMER_CURRENT);
+    write_register(REG_TIMER_INITIAL, 0);
+
+    if elapsed < CALIBRATION {
+        crate::warn_ln!("interrupts: cannot time the local APIC timer, guessing its rate");
+        return FALLBACK_TICK_COUNT;
+    }
+    let per_second = counted as u128 * 1_000_000_000 / elapsed.as_nanos();
+    (per_second / TIMER_HZ as u128).clamp(1, u32::MAX as u128) as u32
+}
+
+fn start_timer() {
+    write_register(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write_register(REG_LVT_TIMER, LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+    write_register(REG_TIMER_INITIAL, TICK_COUNT.load(Ordering::Relaxed));
+}
+
+fn end_of_interrupt() {
+    write_register(REG_EOI, 0);
+}
+
+pub fn enable() {
+    x86_64::instructions::interrupts::enable();
+}
+
+pub fn disable() {
+    x86_64::instructions::interrupts::disable();
+}
+
+pub fn are_enabled() -> bool {
+    x86_64::instructions::interrupts::are_enabled()
+}
+
+pub fn enable_and_wait() {
+    x86_64::instructions::interrupts::enable_and_hlt();
+}
+
+/// Whether `frame` was pushed for an interrupt or exception taken in user mode.
+fn from_user(frame: &InterruptStackFrame) -> bool {
+    frame.code_segment & 0b11 == 3
+}
+
+/// Run `handler` with the kernel's GS base, which an interrupt or exception from user mode
+/// arrives without, swapping the user's back in before returning to it. Handlers that never
+/// return, like `fault` killing the process, leave the kernel's in place as they should.
+fn with_kernel_gs(frame: &InterruptStackFrame, handler: impl FnOnce()) {
+    let user = from_user(frame);
+    if user {
+        unsafe { GS::swap() };
+    }
+    handler();
+    if user {
+        // The handler may have switched tasks and come back with interrupts enabled; an
+        // interrupt must not find the user's base in the kernel. `iretq` restores the flag.
+        x86_64::instructions::interrupts::disable();
+        unsafe { GS::swap() };
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt(frame: InterruptStackFrame) {
+    with_kernel_gs(&frame, || {
+        end_of_interrupt();
+        super::dispatch(TIMER_VECTOR);
+    });
+}
+
+extern "x86-interrupt" fn spurious_interrupt(frame: InterruptStackFrame) {
+    with_kernel_gs(&frame, || super::record(SPURIOUS_VECTOR));
+}
+
+extern "x86-interrupt" fn legacy_pic_interrupt<const LINE: u8>(frame: InterruptStackFrame) {
+    // A line nobody opened only fires spuriously, and spurious interrupts take no EOI.
+    if ENABLED_LINES.load(Ordering::Relaxed) & (1 << LINE) == 0 {
+        return;
+    }
+    with_kernel_gs(&frame, || {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(PIC_OFFSET + LINE);
+        }
+        super::dispatch(PIC_OFFSET as usize + LINE as usize);
+    });
+}
+
+/// Kill the process that raised an exception in user mode; otherwise report an exception the
+/// kernel cannot recover from and stop. Runs in `with_kernel_gs`.
+fn fault(name: &str, frame: &InterruptStackFrame, detail: fmt::Arguments) -> ! {
+    if from_user(frame) {
+        process::fault(format_args!(
+            "{} at {:#x}{}",
+            name,
+            frame.instruction_pointer.as_u64(),
+            detail
+        ));
+    }
+    crate::danger_ln!(
+        "interrupts: {} at {:#x}{}",
+        name,
+        frame.instruction_pointer.as_u64(),
+        detail
+    );
+    crate::hlt_loop()
+}
+
+extern "x86-interrupt" fn divide_error(frame: InterruptStackFrame) {
+    with_kernel_gs(&frame, || fault("divide error", &frame, format_args!("")));
+}
+
+extern "x86-interrupt" fn breakpoint(frame: InterruptStackFrame) {
+    with_kernel_gs(&frame, || {
+        crate::warn_ln!(
+            "interrupts: breakpoint at {:#x}",
+            frame.instruction_pointer.as_u64()
+        );
+    });
+}
+
+extern "x86-interrupt" fn invalid_opcode(frame: InterruptStackFrame) {
+    with_kernel_gs(&frame, || fault("invalid opcode", &frame, format_args!("")));
+}
+
+extern "x86-interrupt" fn double_fault(frame: InterruptStackFrame, _code: u64) -> ! {
+    with_kernel_gs(&frame, || fault("double fault", &frame, format_args!("")));
+    unreachable!("fault never returns")
+}
+
+extern "x86-interrupt" fn general_protection_fault(frame: InterruptStackFrame, code: u64) {
+    with_kernel_gs(&frame, || {
+        fault(
+            "general protection fault",
+            &frame,
+            format_args!(", error code {:#x}", code),
+        )
+    });
+}
+
+extern "x86-interrupt" fn page_fault(frame: InterruptStackFrame, code: PageFaultErrorCode) {
+    // Read before anything else can fault and replace it.
+    let address = Cr2::read().as_u64();
+    with_kernel_gs(&frame, || {
+        fault(
+            "page fault",
+            &frame,
+            format_args!(", address {:#x}, {:?}", address, code),
+        )
+    });
+}
@@ -0,0 +1,141 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+use crate::time;
+
+/// Bytes of recent kernel messages kept for later reading.
+const LOG_SIZE: usize = 64 * 1024;
+
+static LOG: Mutex<KernelLog<LOG_SIZE>> = Mutex::new(KernelLog::new());
+
+/// A ring of the most recent `N` bytes written, the oldest overwritten first.
+struct KernelLog<const N: usize> {
+    data: [u8; N],
+    /// Index of the oldest byte.
+    start: usize,
+    len: usize,
+    /// Whether overwriting cut the oldest line short.
+    partial_first_line: bool,
+    at_line_start: bool,
+}
+
+impl<const N: usize> KernelLog<N> {
+    const fn new() -> Self {
+        Self {
+            data: [0; N],
+            start: 0,
+            len: 0,
+            partial_first_line: false,
+            at_line_start: true,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == N {
+                self.partial_first_line = self.data[self.start] != b'\n';
+                self.data[self.start] = byte;
+                self.start = (self.start + 1) % N;
+            } else {
+                self.data[(self.start + self.len) % N] = byte;
+                self.len += 1;
+            }
+        }
+        if let Some(&last) = bytes.last() {
+            self.at_line_start = last == b'\n';
+        }
+    }
+
+    /// Append one message, prefixing each new line with the uptime and `level`.
+    fn record(&mut self, uptime_micros: u64, level: &str, args: fmt::Arguments, newline: bool) {
+        if self.at_line_start {
+            let _ = write!(
+                self,
+                "[{:5}.{:06}] {}: ",
+                uptime_micros / 1_000_000,
+                uptime_micros % 1_000_000,
+                level
+            );
+        }
+        let _ = self.write_fmt(args);
+        if newline {
+            self.push(b"\n");
+        }
+    }
+
+    /// Copy out the buffer, oldest first, starting at a line boundary.
+    fn contents(&self) -> Vec<u8> {
+        let (tail, head) = self.data.split_at(self.start);
+        let mut contents: Vec<u8> = head.iter().chain(tail).take(self.len).copied().collect();
+        if self.partial_first_line
+            && let Some(newline) = contents.iter().position(|&byte| byte == b'\n')
+        {
+            contents.drain(..=newline);
+        }
+        contents
+    }
+}
+
+impl<const N: usize> Write for KernelLog<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _record(level: &str, args: fmt::Arguments, newline: bool) {
+    let uptime = time::uptime().as_micros() as u64;
+    LOG.lock().record(uptime, level, args, newline);
+}
+
+/// Get the retained kernel messages as text, oldest first.
+pub fn contents() -> String {
+    String::from_utf8_lossy(&LOG.lock().contents()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::KernelLog;
+
+    #[kunit]
+    fn messages_are_stamped_once_per_line() {
+        let mut log = KernelLog::<256>::new();
+
+        log.record(1_500_000, "INFO", format_args!("disk {}", 0), false);
+        log.record(1_600_000, "INFO", format_args!(" ready"), true);
+        log.record(12_000_001, "WARN", format_args!("late"), true);
+
+        assert_eq!(
+            log.contents(),
+            b"[    1.500000] INFO: disk 0 ready\n[   12.000001] WARN: late\n"
+        );
+    }
+
+    #[kunit]
+    fn overwritten_lines_are_dropped_whole() {
+        let mut log = KernelLog::<16>::new();
+
+        log.push(b"first line\nsecond\nthird\n");
+
+        assert_eq!(log.contents(), b"second\nthird\n");
+    }
+
+    #[kunit]
+    fn contents_survive_wrapping_the_ring() {
+        let mut log = KernelLog::<8>::new();
+
+        log.push(b"abcdef\n");
+        log.push(b"gh\n");
+
+        assert_eq!(log.contents(), b"gh\n");
+        assert_eq!(log.len, 8);
+
+        log.push(b"ijkl\n");
+        assert_eq!(log.contents(), b"gh\nijkl\n");
+    }
+}
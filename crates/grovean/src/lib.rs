@@ -6,14 +6,30 @@
 #![cfg_attr(test, reexport_test_harness_main = "test_main")] // test setup: rename the test harness entry point
 
 #[cfg(test)]
-kunit::klib!("grovean");
+kunit::klib!("grovean", klib_config = &TEST_CONFIG);
+
+#[cfg(test)]
+const TEST_CONFIG: kunit::KlibConfig = kunit::KlibConfigBuilder::new_default()
+    .before_tests(init_for_tests)
+    .build();
 
 extern crate alloc;
 
 pub mod allocator;
+pub mod cpu;
 pub mod dat;
 pub mod dev;
+pub mod executor;
+pub mod fs;
+pub mod interrupts;
+pub mod klog;
 pub mod memory;
+pub mod process;
+pub mod random;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod time;
 
 #[cfg(not(test))]
 #[used]
@@ -55,7 +71,8 @@ pub unsafe extern "C" fn _start() -> ! {
         }
     });
 
-    hlt_loop()
+    process::run_init();
+    task::exit()
 }
 
 #[cfg(not(test))]
@@ -70,12 +87,26 @@ pub fn init() {
     {
         assert!(BASE_REVISION.is_supported());
         memory::init();
+        allocator::init();
+        time::init();
+        cpu::init();
+        interrupts::init();
+        syscall::init();
         dev::framebuffer::fb0::init();
+        dev::init();
+        random::init();
+        fs::init();
+        task::init();
+        executor::init();
+        cpu::start_application_processors();
     }
 }
 
 #[cfg(test)]
-pub fn init_for_tests() {}
+pub fn init_for_tests() {
+    allocator::init_for_tests();
+    memory::frame::init_for_tests();
+}
 
 /// Halt the CPU.
 pub fn hlt_loop() -> ! {
@@ -92,75 +123,95 @@ pub fn hlt_loop() -> ! {
     }
 }
 
-/// Prints INFO to serial and framebuffer terminals.
+/// Prints INFO to serial, framebuffer and virtio console terminals and the kernel log.
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {
-        fb0_info!($($arg)*);
-        serial_info!($($arg)*);
+        $crate::klog::_record("INFO", format_args!($($arg)*), false);
+        $crate::fb0_info!($($arg)*);
+        $crate::serial_info!($($arg)*);
+        $crate::console_info!($($arg)*);
     };
 }
 
-/// Prints INFO to serial and framebuffer terminals, followed by a newline.
+/// Prints INFO to serial, framebuffer and virtio console terminals and the kernel log, followed
+/// by a newline.
 #[macro_export]
 macro_rules! info_ln {
     ($($arg:tt)*) => {
-        fb0_info_ln!($($arg)*);
-        serial_info_ln!($($arg)*);
+        $crate::klog::_record("INFO", format_args!($($arg)*), true);
+        $crate::fb0_info_ln!($($arg)*);
+        $crate::serial_info_ln!($($arg)*);
+        $crate::console_info_ln!($($arg)*);
     };
 }
 
-/// Prints DEBUG to serial and framebuffer terminals.
+/// Prints DEBUG to serial, framebuffer and virtio console terminals and the kernel log.
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {
-        fb0_debug!($($arg)*);
-        serial_debug!($($arg)*);
+        $crate::klog::_record("DEBUG", format_args!($($arg)*), false);
+        $crate::fb0_debug!($($arg)*);
+        $crate::serial_debug!($($arg)*);
+        $crate::console_debug!($($arg)*);
     };
 }
 
-/// Prints DEBUG to serial and framebuffer terminals, followed by a newline.
+/// Prints DEBUG to serial, framebuffer and virtio console terminals and the kernel log, followed
+/// by a newline.
 #[macro_export]
 macro_rules! debug_ln {
     ($($arg:tt)*) => {
-        fb0_debug_ln!($($arg)*);
-        serial_debug_ln!($($arg)*);
+        $crate::klog::_record("DEBUG", format_args!($($arg)*), true);
+        $crate::fb0_debug_ln!($($arg)*);
+        $crate::serial_debug_ln!($($arg)*);
+        $crate::console_debug_ln!($($arg)*);
     };
 }
 
-/// Prints WARN to serial and framebuffer terminals.
+/// Prints WARN to serial, framebuffer and virtio console terminals and the kernel log.
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {
-        fb0_warn!($($arg)*);
-        serial_warn!($($arg)*);
+        $crate::klog::_record("WARN", format_args!($($arg)*), false);
+        $crate::fb0_warn!($($arg)*);
+        $crate::serial_warn!($($arg)*);
+        $crate::console_warn!($($arg)*);
     };
 }
 
-/// Prints WARN to serial and framebuffer terminals, followed by a newline.
+/// Prints WARN to serial, framebuffer and virtio console terminals and the kernel log, followed
+/// by a newline.
 #[macro_export]
 macro_rules! warn_ln {
     ($($arg:tt)*) => {
-        fb0_warn_ln!($($arg)*);
-        serial_warn_ln!($($arg)*);
+        $crate::klog::_record("WARN", format_args!($($arg)*), true);
+        $crate::fb0_warn_ln!($($arg)*);
+        $crate::serial_warn_ln!($($arg)*);
+        $crate::console_warn_ln!($($arg)*);
     };
 }
 
-/// Prints DANGER to serial and framebuffer terminals.
+/// Prints DANGER to serial, framebuffer and virtio console terminals and the kernel log.
 #[macro_export]
 macro_rules! danger {
     ($($arg:tt)*) => {
-        fb0_danger!($($arg)*);
-        serial_danger!($($arg)*);
+        $crate::klog::_record("DANGER", format_args!($($arg)*), false);
+        $crate::fb0_danger!($($arg)*);
+        $crate::serial_danger!($($arg)*);
+        $crate::console_danger!($($arg)*);
     };
 }
 
-/// Prints DANGER to serial and framebuffer terminals, followed by a newline.
+/// Prints DANGER to serial, framebuffer and virtio console terminals and the kernel log, followed
+/// by a newline.
 #[macro_export]
 macro_rules! danger_ln {
     ($($arg:tt)*) => {
-        fb0_danger_ln!($($arg)*);
-        serial_danger_ln!($($arg)*);
+        $crate::klog::_record("DANGER", format_args!($($arg)*), true);
+        $crate::fb0_danger_ln!($($arg)*);
+        $crate::serial_danger_ln!($($arg)*);
+        $crate::console_danger_ln!($($arg)*);
     };
 }
 
@@ -0,0 +1,94 @@
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::hhdm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    OutOfMemory,
+}
+
+/// A zeroed, physically contiguous buffer that devices can access by physical address.
+///
+/// The memory is reached through the higher-half direct map and returned to the frame
+/// allocator when the buffer is dropped.
+pub struct DmaBuffer {
+    phys: u64,
+    frames: usize,
+    len: usize,
+}
+
+// The buffer exclusively owns its frames, so moving it between CPUs is sound.
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Allocate a zeroed buffer of at least `len` bytes.
+    pub fn new(len: usize) -> Result<Self, DmaError> {
+        let frames = (len.max(1) as u64).div_ceil(FRAME_SIZE) as usize;
+        let phys = frame::allocate_contiguous_frames(frames).ok_or(DmaError::OutOfMemory)?;
+
+        let buffer = Self { phys, frames, len };
+        unsafe {
+            core::ptr::write_bytes(buffer.as_ptr(), 0, frames * FRAME_SIZE as usize);
+        }
+        Ok(buffer)
+    }
+
+    /// Get the physical address of the first byte of the buffer.
+    pub fn phys_addr(&self) -> u64 {
+        self.phys
+    }
+
+    /// Get a pointer to the first byte of the buffer.
+    pub fn as_ptr(&self) -> *mut u8 {
+        hhdm::phys_to_virt(self.phys) as *mut u8
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        frame::deallocate_frames(self.phys, self.frames);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::DmaBuffer;
+    use crate::memory::frame::{self, FRAME_SIZE};
+
+    #[kunit]
+    fn buffers_are_zeroed_and_page_aligned() {
+        let buffer = DmaBuffer::new(100).expect("dma buffer should allocate");
+
+        assert_eq!(buffer.phys_addr() % FRAME_SIZE, 0);
+        assert_eq!(buffer.len(), 100);
+        assert!(buffer.as_slice().iter().all(|&byte| byte == 0));
+    }
+
+    #[kunit]
+    fn dropping_a_buffer_returns_its_frames() {
+        let free_before = frame::stats().free_frames;
+        let buffer = DmaBuffer::new(3 * FRAME_SIZE as usize).expect("dma buffer should allocate");
+        assert_eq!(frame::stats().free_frames, free_before - 3);
+
+        drop(buffer);
+        assert_eq!(frame::stats().free_frames, free_before);
+    }
+}
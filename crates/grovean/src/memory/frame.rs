@@ -0,0 +1,503 @@
+use spin::Mutex;
+
+use crate::interrupts;
+use crate::memory::hhdm;
+use crate::memory::memory_map::{MemoryRegion, MemoryRegionKind};
+
+pub const FRAME_SIZE: u64 = 4096;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Only locked with interrupts disabled, through `with_allocator`, so interrupt handlers can
+/// free frames.
+static FRAME_ALLOCATOR: Mutex<BitmapFrameAllocator> = Mutex::new(BitmapFrameAllocator::empty());
+
+/// Frees frames held by a cache, given how many are wanted, and returns how many it freed.
+pub type Reclaimer = fn(usize) -> usize;
+
+const MAX_RECLAIMERS: usize = 4;
+
+static RECLAIMERS: Mutex<[Option<Reclaimer>; MAX_RECLAIMERS]> = Mutex::new([None; MAX_RECLAIMERS]);
+
+/// Single frames each CPU keeps back, so most single-frame allocations and frees skip the
+/// global allocator lock.
+const CACHE_CAPACITY: usize = 32;
+/// Frames moved between a CPU's cache and the allocator at a time.
+const CACHE_BATCH: usize = CACHE_CAPACITY / 2;
+
+crate::cpu_local! {
+    /// Locked by other CPUs only to count or drain it, and always with interrupts disabled.
+    static FRAME_CACHES: Mutex<FrameCache> = Mutex::new(FrameCache::new());
+}
+
+struct FrameCache {
+    frames: [u64; CACHE_CAPACITY],
+    len: usize,
+}
+
+impl FrameCache {
+    const fn new() -> Self {
+        Self {
+            frames: [0; CACHE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<u64> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.frames[self.len])
+    }
+
+    /// Keep `frame`, first handing a batch back to `allocator` when full.
+    fn push(&mut self, frame: u64, allocator: &Mutex<BitmapFrameAllocator>) {
+        if self.len == CACHE_CAPACITY {
+            let mut frames = allocator.lock();
+            for _ in 0..CACHE_BATCH {
+                if let Some(cached) = self.pop() {
+                    frames.deallocate(cached, 1);
+                }
+            }
+        }
+        self.frames[self.len] = frame;
+        self.len += 1;
+    }
+
+    /// Take up to a batch of frames from `allocator`.
+    fn refill(&mut self, allocator: &mut BitmapFrameAllocator) {
+        while self.len < CACHE_BATCH {
+            let Some(frame) = allocator.allocate(1, 1) else {
+                break;
+            };
+            self.frames[self.len] = frame;
+            self.len += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameAllocatorError {
+    NoUsableMemory,
+    BitmapStorageUnavailable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameAllocatorStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+}
+
+impl FrameAllocatorStats {
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+}
+
+/// A physical frame allocator tracking every 4 KiB frame with one bit (set = in use).
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    base: u64,
+    frame_count: usize,
+    managed_frames: usize,
+    free_frames: usize,
+    next_search: usize,
+}
+
+impl BitmapFrameAllocator {
+    pub const fn empty() -> Self {
+        Self {
+            bitmap: &mut [],
+            base: 0,
+            frame_count: 0,
+            managed_frames: 0,
+            free_frames: 0,
+            next_search: 0,
+        }
+    }
+
+    /// Create an allocator covering `frame_count` frames starting at `base`, with every frame
+    /// initially marked as used.
+    pub fn new(bitmap: &'static mut [u64], base: u64, frame_count: usize) -> Self {
+        assert!(bitmap.len() * BITS_PER_WORD >= frame_count);
+        bitmap.fill(u64::MAX);
+
+        Self {
+            bitmap,
+            base: align_down(base),
+            frame_count,
+            managed_frames: 0,
+            free_frames: 0,
+            next_search: 0,
+        }
+    }
+
+    /// Build an allocator over the usable regions of a memory map, placing the bitmap itself
+    /// inside the first usable region large enough to hold it.
+    pub fn from_regions(regions: &[MemoryRegion]) -> Result<Self, FrameAllocatorError> {
+        let mut lowest = u64::MAX;
+        let mut highest = 0;
+        for region in usable_regions(regions) {
+            lowest = lowest.min(align_up(region.base));
+            highest = highest.max(align_down(region.base + region.length));
+        }
+
+        if lowest >= highest {
+            return Err(FrameAllocatorError::NoUsableMemory);
+        }
+
+        let frame_count = ((highest - lowest) / FRAME_SIZE) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_bytes = align_up((words * size_of::<u64>()) as u64);
+
+        let bitmap_base = usable_regions(regions)
+            .map(|region| {
+                (
+                    align_up(region.base),
+                    align_down(region.base + region.length),
+                )
+            })
+            .find(|(start, end)| start < end && end - start >= bitmap_bytes)
+            .map(|(start, _)| start)
+            .ok_or(FrameAllocatorError::BitmapStorageUnavailable)?;
+
+        let bitmap = unsafe {
+            core::slice::from_raw_parts_mut(hhdm::phys_to_virt(bitmap_base) as *mut u64, words)
+        };
+
+        let mut allocator = Self::new(bitmap, lowest, frame_count);
+        for region in usable_regions(regions) {
+            allocator.add_free_region(region.base, region.length);
+        }
+        allocator.reserve_region(bitmap_base, bitmap_bytes);
+        // Never hand out physical address zero, it is too easily confused with null.
+        allocator.reserve_region(0, FRAME_SIZE);
+
+        Ok(allocator)
+    }
+
+    /// Mark every whole frame inside the given range as free.
+    pub fn add_free_region(&mut self, base: u64, length: u64) {
+        let (first, last) = self.frame_range(align_up(base), align_down(base + length));
+        for frame in first..last {
+            if self.is_used(frame) {
+                self.set_free(frame);
+                self.managed_frames += 1;
+            }
+        }
+    }
+
+    /// Mark every frame touching the given range as used, removing it from the managed pool.
+    pub fn reserve_region(&mut self, base: u64, length: u64) {
+        let (first, last) = self.frame_range(align_down(base), align_up(base + length));
+        for frame in first..last {
+            if !self.is_used(frame) {
+                self.set_used(frame);
+                self.managed_frames -= 1;
+            }
+        }
+    }
+
+    /// Allocate `count` physically contiguous frames aligned to `align_frames` frames.
+    pub fn allocate(&mut self, count: usize, align_frames: usize) -> Option<u64> {
+        if count == 0 || count > self.free_frames {
+            return None;
+        }
+
+        let align_frames = align_frames.max(1);
+        let start = self
+            .find_run(self.next_search, self.frame_count, count, align_frames)
+            .or_else(|| self.find_run(0, self.next_search, count, align_frames))?;
+
+        for frame in start..start + count {
+            self.set_used(frame);
+        }
+        self.next_search = start + count;
+
+        Some(self.base + start as u64 * FRAME_SIZE)
+    }
+
+    /// Return `count` frames starting at `address` to the allocator.
+    pub fn deallocate(&mut self, address: u64, count: usize) {
+        let first = ((address - self.base) / FRAME_SIZE) as usize;
+        for frame in first..first + count {
+            assert!(self.is_used(frame), "double free of physical frame");
+            self.set_free(frame);
+        }
+        self.next_search = self.next_search.min(first);
+    }
+
+    pub fn stats(&self) -> FrameAllocatorStats {
+        FrameAllocatorStats {
+            total_frames: self.managed_frames,
+            free_frames: self.free_frames,
+        }
+    }
+
+    fn find_run(&self, from: usize, to: usize, count: usize, align_frames: usize) -> Option<usize> {
+        let mut candidate = self.aligned_frame(from, align_frames);
+        while candidate < to && candidate + count <= self.frame_count {
+            match (candidate..candidate + count).find(|&frame| self.is_used(frame)) {
+                Some(used) => candidate = self.aligned_frame(used + 1, align_frames),
+                None => return Some(candidate),
+            }
+        }
+        None
+    }
+
+    fn aligned_frame(&self, frame: usize, align_frames: usize) -> usize {
+        let base_frame = (self.base / FRAME_SIZE) as usize;
+        (base_frame + frame).next_multiple_of(align_frames) - base_frame
+    }
+
+    fn frame_range(&self, start: u64, end: u64) -> (usize, usize) {
+        let limit = self.base + self.frame_count as u64 * FRAME_SIZE;
+        let start = start.clamp(self.base, limit);
+        let end = end.clamp(self.base, limit);
+        if start >= end {
+            return (0, 0);
+        }
+        (
+            ((start - self.base) / FRAME_SIZE) as usize,
+            ((end - self.base) / FRAME_SIZE) as usize,
+        )
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        self.bitmap[frame / BITS_PER_WORD] |= 1 << (frame % BITS_PER_WORD);
+        self.free_frames -= 1;
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        self.bitmap[frame / BITS_PER_WORD] &= !(1 << (frame % BITS_PER_WORD));
+        self.free_frames += 1;
+    }
+}
+
+fn usable_regions(regions: &[MemoryRegion]) -> impl Iterator<Item = &MemoryRegion> {
+    regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable)
+}
+
+pub const fn align_down(address: u64) -> u64 {
+    address & !(FRAME_SIZE - 1)
+}
+
+pub const fn align_up(address: u64) -> u64 {
+    (address + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)
+}
+
+pub fn init() {
+    #[cfg(not(test))]
+    {
+        let allocator = crate::memory::memory_map::with_boot_memory_map(|memory_map| {
+            BitmapFrameAllocator::from_regions(memory_map.regions())
+        })
+        .expect("failed to initialize the frame allocator");
+
+        with_allocator(|frames| *frames = allocator);
+    }
+}
+
+/// Hand the frame allocator a page-aligned scratch arena, since tests boot without a memory map.
+#[cfg(test)]
+pub fn init_for_tests() {
+    #[repr(C, align(4096))]
+    struct TestArena([u8; TEST_ARENA_SIZE]);
+
+    const TEST_ARENA_SIZE: usize = 4 * 1024 * 1024;
+    static mut TEST_ARENA: TestArena = TestArena([0; TEST_ARENA_SIZE]);
+
+    let base = core::ptr::addr_of_mut!(TEST_ARENA) as u64;
+    let region = MemoryRegion {
+        base,
+        length: TEST_ARENA_SIZE as u64,
+        kind: MemoryRegionKind::Usable,
+    };
+
+    let allocator = BitmapFrameAllocator::from_regions(&[region])
+        .expect("failed to initialize the test frame allocator");
+    with_allocator(|frames| *frames = allocator);
+}
+
+fn with_allocator<R>(f: impl FnOnce(&mut BitmapFrameAllocator) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut FRAME_ALLOCATOR.lock()))
+}
+
+/// Run `f` on the calling CPU's frame cache. The cache is locked before the allocator, never
+/// after it.
+fn with_cache<R>(f: impl FnOnce(&mut FrameCache) -> R) -> R {
+    interrupts::without_interrupts(|| FRAME_CACHES.with(|cache| f(&mut cache.lock())))
+}
+
+/// Hand every CPU's cached frames back to the allocator, returning how many there were.
+fn drain_caches() -> usize {
+    interrupts::without_interrupts(|| {
+        let mut drained = 0;
+        for cache in FRAME_CACHES.iter() {
+            let mut cache = cache.lock();
+            let mut frames = FRAME_ALLOCATOR.lock();
+            while let Some(frame) = cache.pop() {
+                frames.deallocate(frame, 1);
+                drained += 1;
+            }
+        }
+        drained
+    })
+}
+
+/// Ask `reclaimer` for frames whenever an allocation would otherwise fail.
+pub fn register_reclaimer(reclaimer: Reclaimer) {
+    let mut reclaimers = RECLAIMERS.lock();
+    let slot = reclaimers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many frame reclaimers");
+    *slot = Some(reclaimer);
+}
+
+/// Allocate `count` contiguous frames, taking back the CPUs' cached frames and then letting
+/// the reclaimers free memory if none are left.
+fn allocate_or_reclaim(count: usize) -> Option<u64> {
+    if let Some(frame) = with_allocator(|frames| frames.allocate(count, 1)) {
+        return Some(frame);
+    }
+    if drain_caches() > 0
+        && let Some(frame) = with_allocator(|frames| frames.allocate(count, 1))
+    {
+        return Some(frame);
+    }
+
+    let reclaimers = *RECLAIMERS.lock();
+    for reclaimer in reclaimers.into_iter().flatten() {
+        // Reclaimed single frames land in this CPU's cache, so drain it too.
+        if reclaimer(count) > 0 {
+            drain_caches();
+            if let Some(frame) = with_allocator(|frames| frames.allocate(count, 1)) {
+                return Some(frame);
+            }
+        }
+    }
+    None
+}
+
+/// Allocate a single physical frame, from this CPU's cache when it has one.
+pub fn allocate_frame() -> Option<u64> {
+    let cached = with_cache(|cache| {
+        if cache.len == 0 {
+            with_allocator(|frames| cache.refill(frames));
+        }
+        cache.pop()
+    });
+    cached.or_else(|| allocate_or_reclaim(1))
+}
+
+/// Allocate a single physical frame and zero it through the direct map.
+pub fn allocate_zeroed_frame() -> Option<u64> {
+    let frame = allocate_frame()?;
+    unsafe {
+        core::ptr::write_bytes(hhdm::phys_to_virt(frame) as *mut u8, 0, FRAME_SIZE as usize);
+    }
+    Some(frame)
+}
+
+/// Allocate `count` physically contiguous frames.
+pub fn allocate_contiguous_frames(count: usize) -> Option<u64> {
+    allocate_or_reclaim(count)
+}
+
+/// Return `count` frames starting at `address` to the frame allocator. Single frames go to
+/// this CPU's cache.
+pub fn deallocate_frames(address: u64, count: usize) {
+    if count == 1 {
+        with_cache(|cache| cache.push(address, &FRAME_ALLOCATOR));
+    } else {
+        with_allocator(|frames| frames.deallocate(address, count));
+    }
+}
+
+/// Frames sitting in the CPUs' caches count as free.
+pub fn stats() -> FrameAllocatorStats {
+    let mut stats = with_allocator(|frames| frames.stats());
+    stats.free_frames += interrupts::without_interrupts(|| {
+        FRAME_CACHES
+            .iter()
+            .map(|cache| cache.lock().len)
+            .sum::<usize>()
+    });
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use kunit::kunit;
+
+    use super::{BitmapFrameAllocator, FRAME_SIZE};
+    use crate::memory::memory_map::{MemoryRegion, MemoryRegionKind};
+
+    fn allocator(base: u64, frames: usize) -> BitmapFrameAllocator {
+        let bitmap = Box::leak(vec![0u64; frames.div_ceil(64)].into_boxed_slice());
+        BitmapFrameAllocator::new(bitmap, base, frames)
+    }
+
+    #[kunit]
+    fn frames_start_out_reserved() {
+        let mut frames = allocator(0x10_0000, 16);
+
+        assert_eq!(frames.stats().free_frames, 0);
+        assert_eq!(frames.allocate(1, 1), None);
+    }
+
+    #[kunit]
+    fn allocates_and_frees_free_regions() {
+        let mut frames = allocator(0x10_0000, 16);
+        frames.add_free_region(0x10_0000, 4 * FRAME_SIZE);
+
+        let first = frames.allocate(1, 1).expect("frame should be available");
+        let second = frames.allocate(1, 1).expect("frame should be available");
+        assert_eq!(first, 0x10_0000);
+        assert_eq!(second, 0x10_1000);
+        assert_eq!(frames.stats().free_frames, 2);
+
+        frames.deallocate(first, 1);
+        assert_eq!(frames.stats().free_frames, 3);
+        assert_eq!(frames.allocate(1, 1), Some(first));
+    }
+
+    #[kunit]
+    fn contiguous_allocation_skips_holes() {
+        let mut frames = allocator(0, 16);
+        frames.add_free_region(0, 16 * FRAME_SIZE);
+        frames.reserve_region(2 * FRAME_SIZE, FRAME_SIZE);
+
+        assert_eq!(frames.allocate(4, 1), Some(3 * FRAME_SIZE));
+        assert_eq!(frames.allocate(2, 1), Some(0));
+    }
+
+    #[kunit]
+    fn contiguous_allocation_honours_alignment() {
+        let mut frames = allocator(0x1000, 32);
+        frames.add_free_region(0x1000, 32 * FRAME_SIZE);
+
+        let aligned = frames
+            .allocate(2, 4)
+            .expect("aligned run should be available");
+        assert_eq!(aligned % (4 * FRAME_SIZE), 0);
+    }
+
+    #[kunit]
+    fn from_regions_requires_usable_memory() {
+        let regions = [MemoryRegion {
+            base: 0x1000,
+            length: 0x1000,
+            kind: MemoryRegionKind::Reserved,
+        }];
+
+        assert!(BitmapFrameAllocator::from_regions(&regions).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(test))]
+use limine::request::HhdmRequest;
+
+#[cfg(not(test))]
+#[used]
+#[unsafe(link_section = ".requests")]
+static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
+
+/// Virtual offset of the higher-half direct map provided by Limine.
+///
+/// Stays zero in test builds, where physical and virtual addresses coincide.
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    #[cfg(not(test))]
+    {
+        let response = HHDM_REQUEST
+            .get_response()
+            .expect("limine hhdm response is unavailable");
+
+        HHDM_OFFSET.store(response.offset(), Ordering::Relaxed);
+    }
+}
+
+/// Get the offset of the higher-half direct map.
+pub fn offset() -> u64 {
+    HHDM_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Translate a physical address into its direct-mapped virtual address.
+pub fn phys_to_virt(phys: u64) -> u64 {
+    phys + offset()
+}
+
+/// Translate a direct-mapped virtual address back into its physical address.
+pub fn virt_to_phys(virt: u64) -> u64 {
+    virt - offset()
+}
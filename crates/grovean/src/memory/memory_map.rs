@@ -1,7 +1,8 @@
 use limine::memory_map::{Entry, EntryType};
 #[cfg(not(test))]
 use limine::request::MemoryMapRequest;
-use spin::Mutex;
+
+use crate::sync::RwLock;
 
 const MAX_MEMORY_REGIONS: usize = 512;
 
@@ -10,7 +11,8 @@ const MAX_MEMORY_REGIONS: usize = 512;
 #[unsafe(link_section = ".requests")]
 static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 
-static BOOT_MEMORY_MAP: Mutex<BootMemoryMap> = Mutex::new(BootMemoryMap::empty());
+/// Written once by `init`, then only read.
+static BOOT_MEMORY_MAP: RwLock<BootMemoryMap> = RwLock::new(BootMemoryMap::empty());
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryMapError {
@@ -175,7 +177,7 @@ pub fn init() {
         let parsed = BootMemoryMap::from_limine_entries(response.entries())
             .expect("failed to normalize limine memory map");
 
-        let mut boot_memory_map = BOOT_MEMORY_MAP.lock();
+        let mut boot_memory_map = BOOT_MEMORY_MAP.write();
         *boot_memory_map = parsed;
     }
 }
@@ -184,7 +186,7 @@ pub fn with_boot_memory_map<F, R>(f: F) -> R
 where
     F: FnOnce(&BootMemoryMap) -> R,
 {
-    let boot_memory_map = BOOT_MEMORY_MAP.lock();
+    let boot_memory_map = BOOT_MEMORY_MAP.read();
     f(&boot_memory_map)
 }
 
@@ -1,5 +1,12 @@
+pub mod dma;
+pub mod frame;
+pub mod hhdm;
 pub mod memory_map;
+pub mod paging;
 
 pub fn init() {
     memory_map::init();
+    hhdm::init();
+    frame::init();
+    paging::init();
 }
@@ -0,0 +1,353 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::{PageFlags, PagingError};
+use crate::memory::{frame, hhdm};
+
+/// End of the range TTBR0_EL1 translates, which user address spaces fill.
+pub const USER_END: u64 = 0x0001_0000_0000_0000;
+/// End of where user pages can be mapped, all of the range.
+pub(super) const USER_MAP_END: u64 = USER_END;
+
+/// Descriptor bit 0: the entry is valid.
+const DESC_VALID: u64 = 1 << 0;
+/// Descriptor bit 1: next-level table (levels 0-2) or page (level 3) rather than a block.
+const DESC_TABLE_OR_PAGE: u64 = 1 << 1;
+/// Descriptor bit 6: EL0 may access the page.
+const DESC_AP_EL0: u64 = 1 << 6;
+/// Descriptor bit 7: the page is read-only.
+const DESC_AP_READ_ONLY: u64 = 1 << 7;
+/// Descriptor bits 8-9: inner shareable, for normal memory.
+const DESC_INNER_SHAREABLE: u64 = 0b11 << 8;
+/// Descriptor bit 10: access flag, set up front so the first access does not fault.
+const DESC_ACCESS_FLAG: u64 = 1 << 10;
+/// Descriptor bit 11: not global, the translation belongs to one address space.
+const DESC_NOT_GLOBAL: u64 = 1 << 11;
+/// Descriptor bits 53/54: never execute at EL1/EL0.
+const DESC_PXN: u64 = 1 << 53;
+const DESC_UXN: u64 = 1 << 54;
+/// Output address bits [47:12] of a descriptor.
+const DESC_ADDRESS_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// MAIR attribute encodings for Device-nGnRnE and Device-nGnRE memory.
+const MAIR_DEVICE_NGNRNE: u8 = 0x00;
+const MAIR_DEVICE_NGNRE: u8 = 0x04;
+/// MAIR attribute encoding for normal write-back cacheable memory.
+const MAIR_NORMAL_WRITE_BACK: u8 = 0xff;
+
+/// TCR_EL1.T1SZ expected for a 48-bit upper half translated by four levels of 4 KiB tables.
+const EXPECTED_T1SZ: u64 = 16;
+/// TCR_EL1.TG1 encoding of a 4 KiB granule.
+const TG1_4KIB: u64 = 0b10;
+/// TCR_EL1.T0SZ user address spaces are laid out for, a 48-bit lower half.
+const EXPECTED_T0SZ: u64 = 16;
+/// TCR_EL1.TG0 encoding of a 4 KiB granule.
+const TG0_4KIB: u64 = 0b00;
+/// TCR_EL1.EPD0: translation table walks through TTBR0_EL1 are disabled.
+const TCR_EPD0: u64 = 1 << 7;
+
+/// Serializes modifications of the active page tables.
+static PAGE_TABLE_LOCK: Mutex<()> = Mutex::new(());
+/// TTBR0_EL1 as the bootloader left it, which kernel tasks run on. Zero until `init`.
+static KERNEL_TTBR0: AtomicU64 = AtomicU64::new(0);
+/// MAIR slot of normal memory, for user pages.
+static NORMAL_ATTRIBUTE_INDEX: AtomicU64 = AtomicU64::new(0);
+
+fn read_tcr() -> u64 {
+    let tcr: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, tcr_el1", out(reg) tcr, options(nomem, nostack, preserves_flags));
+    }
+    tcr
+}
+
+fn read_ttbr1() -> u64 {
+    let ttbr1: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, ttbr1_el1", out(reg) ttbr1, options(nomem, nostack, preserves_flags));
+    }
+    ttbr1
+}
+
+fn read_ttbr0() -> u64 {
+    let ttbr0: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, ttbr0_el1", out(reg) ttbr0, options(nomem, nostack, preserves_flags));
+    }
+    ttbr0
+}
+
+fn read_mair() -> u64 {
+    let mair: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, mair_el1", out(reg) mair, options(nomem, nostack, preserves_flags));
+    }
+    mair
+}
+
+/// Find a MAIR slot the bootloader left configured as device memory.
+fn device_attribute_index(mair: u64) -> Option<u64> {
+    let attributes = mair.to_le_bytes();
+    attributes
+        .iter()
+        .position(|&attribute| attribute == MAIR_DEVICE_NGNRNE)
+        .or_else(|| {
+            attributes
+                .iter()
+                .position(|&attribute| attribute == MAIR_DEVICE_NGNRE)
+        })
+        .map(|index| index as u64)
+}
+
+/// Find a MAIR slot the bootloader left configured as normal write-back memory.
+fn normal_attribute_index(mair: u64) -> Option<u64> {
+    mair.to_le_bytes()
+        .iter()
+        .position(|&attribute| attribute == MAIR_NORMAL_WRITE_BACK)
+        .map(|index| index as u64)
+}
+
+fn check_layout() -> Result<(), PagingError> {
+    let tcr = read_tcr();
+    let t1sz = (tcr >> 16) & 0x3f;
+    let tg1 = (tcr >> 30) & 0b11;
+    if t1sz != EXPECTED_T1SZ || tg1 != TG1_4KIB {
+        return Err(PagingError::UnsupportedLayout);
+    }
+    Ok(())
+}
+
+fn table_index(virt: u64, level: u32) -> usize {
+    ((virt >> (39 - 9 * level)) & 0x1ff) as usize
+}
+
+/// Walk the upper-half tables down to level 3, creating missing tables along the way.
+///
+/// # Safety
+///
+/// The caller must hold `PAGE_TABLE_LOCK`.
+unsafe fn level_3_entry(virt: u64) -> Result<*mut u64, PagingError> {
+    let mut table = hhdm::phys_to_virt(read_ttbr1() & DESC_ADDRESS_MASK) as *mut u64;
+
+    for level in 0..3 {
+        let entry = unsafe { table.add(table_index(virt, level)) };
+        let descriptor = unsafe { core::ptr::read_volatile(entry) };
+
+        let next_table = if descriptor & DESC_VALID == 0 {
+            let frame = frame::allocate_zeroed_frame().ok_or(PagingError::FrameAllocationFailed)?;
+            unsafe {
+                core::ptr::write_volatile(entry, frame | DESC_VALID | DESC_TABLE_OR_PAGE);
+            }
+            frame
+        } else if descriptor & DESC_TABLE_OR_PAGE == 0 {
+            return Err(PagingError::UnsupportedLayout);
+        } else {
+            descriptor & DESC_ADDRESS_MASK
+        };
+
+        table = hhdm::phys_to_virt(next_table) as *mut u64;
+    }
+
+    Ok(unsafe { table.add(table_index(virt, 3)) })
+}
+
+pub(super) fn map_device_page(virt: u64, phys: u64) -> Result<(), PagingError> {
+    check_layout()?;
+    let attribute_index =
+        device_attribute_index(read_mair()).ok_or(PagingError::UnsupportedLayout)?;
+
+    let _guard = PAGE_TABLE_LOCK.lock();
+    let entry = unsafe { level_3_entry(virt)? };
+    if unsafe { core::ptr::read_volatile(entry) } & DESC_VALID != 0 {
+        return Err(PagingError::AlreadyMapped);
+    }
+
+    let descriptor = (phys & DESC_ADDRESS_MASK)
+        | DESC_VALID
+        | DESC_TABLE_OR_PAGE
+        | DESC_ACCESS_FLAG
+        | (attribute_index << 2)
+        | DESC_PXN
+        | DESC_UXN;
+
+    unsafe {
+        core::ptr::write_volatile(entry, descriptor);
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {page}",
+            "dsb ish",
+            "isb",
+            page = in(reg) (virt >> 12) & 0x0000_0fff_ffff_ffff,
+            options(nostack, preserves_flags)
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that TTBR0_EL1 translates a 48-bit lower half with 4 KiB pages, as user address
+/// spaces are laid out, and remember what kernel tasks run on.
+pub(super) fn init() -> Result<(), PagingError> {
+    let tcr = read_tcr();
+    let t0sz = tcr & 0x3f;
+    let tg0 = (tcr >> 14) & 0b11;
+    if t0sz != EXPECTED_T0SZ || tg0 != TG0_4KIB || tcr & TCR_EPD0 != 0 {
+        return Err(PagingError::UnsupportedLayout);
+    }
+    let index = normal_attribute_index(read_mair()).ok_or(PagingError::UnsupportedLayout)?;
+    NORMAL_ATTRIBUTE_INDEX.store(index, Ordering::Relaxed);
+    KERNEL_TTBR0.store(read_ttbr0(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// An empty top-level table; the kernel half lives in TTBR1_EL1 and needs no copying.
+pub(super) fn new_user_root() -> Result<u64, PagingError> {
+    if KERNEL_TTBR0.load(Ordering::Relaxed) == 0 {
+        return Err(PagingError::UnsupportedLayout);
+    }
+    frame::allocate_zeroed_frame().ok_or(PagingError::FrameAllocationFailed)
+}
+
+pub(super) fn activate(root: Option<u64>) {
+    let ttbr0 = root.unwrap_or(KERNEL_TTBR0.load(Ordering::Relaxed));
+    if ttbr0 == 0 || read_ttbr0() == ttbr0 {
+        return;
+    }
+    // Without address space identifiers, every lower-half translation goes with the switch.
+    unsafe {
+        core::arch::asm!(
+            "dsb ish",
+            "msr ttbr0_el1, {ttbr0}",
+            "isb",
+            "tlbi vmalle1",
+            "dsb nsh",
+            "isb",
+            ttbr0 = in(reg) ttbr0,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+pub(super) fn table_descriptor(phys: u64) -> u64 {
+    (phys & DESC_ADDRESS_MASK) | DESC_VALID | DESC_TABLE_OR_PAGE
+}
+
+pub(super) fn page_descriptor(phys: u64, flags: PageFlags) -> u64 {
+    let mut descriptor = (phys & DESC_ADDRESS_MASK)
+        | DESC_VALID
+        | DESC_TABLE_OR_PAGE
+        | DESC_ACCESS_FLAG
+        | DESC_INNER_SHAREABLE
+        | DESC_NOT_GLOBAL
+        | DESC_AP_EL0
+        | (NORMAL_ATTRIBUTE_INDEX.load(Ordering::Relaxed) << 2)
+        | DESC_PXN;
+    if !flags.contains(PageFlags::WRITE) {
+        descriptor |= DESC_AP_READ_ONLY;
+    }
+    if !flags.contains(PageFlags::EXECUTE) {
+        descriptor |= DESC_UXN;
+    }
+    descriptor
+}
+
+pub(super) fn is_valid(descriptor: u64) -> bool {
+    descriptor & DESC_VALID != 0
+}
+
+pub(super) fn descriptor_address(descriptor: u64) -> u64 {
+    descriptor & DESC_ADDRESS_MASK
+}
+
+pub(super) fn is_writable(descriptor: u64) -> bool {
+    descriptor & DESC_AP_READ_ONLY == 0
+}
+
+/// Drop any translation of the user page at `virt` cached by any CPU, after its entry was
+/// cleared.
+pub(super) fn invalidate_page(virt: u64) {
+    unsafe {
+        core::arch::asm!(
+            "dsb ishst",
+            "tlbi vaae1is, {page}",
+            "dsb ish",
+            "isb",
+            page = in(reg) (virt >> 12) & 0x0000_0fff_ffff_ffff,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Make new entries visible to the page walker before anything runs through them.
+pub(super) fn publish_tables() {
+    unsafe {
+        core::arch::asm!("dsb ishst", "isb", options(nostack, preserves_flags));
+    }
+}
+
+/// Make code just written to `length` bytes at `virt` visible to instruction fetches: clean
+/// the data cache lines to the point of unification, then drop stale instructions.
+pub(super) fn sync_instruction_cache(virt: u64, length: u64) {
+    let ctr: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack, preserves_flags));
+    }
+    let line = 4 << ((ctr >> 16) & 0xf);
+    let mut address = virt & !(line - 1);
+    while address < virt + length {
+        unsafe {
+            core::arch::asm!("dc cvau, {}", in(reg) address, options(nostack, preserves_flags));
+        }
+        address += line;
+    }
+    unsafe {
+        core::arch::asm!(
+            "dsb ish",
+            "ic ialluis",
+            "dsb ish",
+            "isb",
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{
+        device_attribute_index, is_writable, normal_attribute_index, page_descriptor, table_index,
+        DESC_AP_READ_ONLY, DESC_PXN, DESC_UXN,
+    };
+    use crate::memory::paging::PageFlags;
+
+    #[kunit]
+    fn finds_device_attribute_slot() {
+        assert_eq!(device_attribute_index(0x0000_0000_0000_44ff), Some(2));
+        assert_eq!(device_attribute_index(0x0404_0404_0404_04ff), Some(1));
+        assert_eq!(device_attribute_index(0xffff_ffff_ffff_ffff), None);
+    }
+
+    #[kunit]
+    fn user_pages_carry_their_permissions() {
+        assert_eq!(normal_attribute_index(0x0000_0000_0000_44ff), Some(0));
+        let code = page_descriptor(0x5000, PageFlags::EXECUTE);
+        assert_eq!(
+            code & (DESC_AP_READ_ONLY | DESC_UXN | DESC_PXN),
+            DESC_AP_READ_ONLY | DESC_PXN
+        );
+        let data = page_descriptor(0x6000, PageFlags::WRITE);
+        assert_eq!(data & (DESC_AP_READ_ONLY | DESC_UXN), DESC_UXN);
+        assert_eq!(data & 0xffff_f000, 0x6000);
+        assert!(is_writable(data) && !is_writable(code));
+    }
+
+    #[kunit]
+    fn splits_virtual_addresses_into_table_indices() {
+        let virt = 0xffff_ff00_0020_3000;
+        assert_eq!(table_index(virt, 0), 510);
+        assert_eq!(table_index(virt, 1), 0);
+        assert_eq!(table_index(virt, 2), 1);
+        assert_eq!(table_index(virt, 3), 3);
+    }
+}
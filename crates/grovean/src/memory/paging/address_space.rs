@@ -0,0 +1,248 @@
+use super::{arch, PagingError, PAGE_SIZE};
+use crate::memory::{frame, hhdm};
+
+/// Entries in every page table, at every level.
+const ENTRIES: usize = 512;
+/// Levels of tables walked to reach a page.
+const LEVELS: u32 = 4;
+
+/// What user code may do with a page. User pages are always readable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageFlags(u32);
+
+impl PageFlags {
+    pub const READ: Self = Self(0);
+    pub const WRITE: Self = Self(1 << 0);
+    pub const EXECUTE: Self = Self(1 << 1);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// The lower-half page tables of one user process, in frames it owns. The kernel half is
+/// shared with every other address space, so kernel code runs unchanged whichever is active.
+/// Dropping it frees every table and mapped frame, so it must not be active on any CPU then.
+pub struct AddressSpace {
+    /// Physical address of the top-level table.
+    root: u64,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, PagingError> {
+        Ok(Self {
+            root: arch::new_user_root()?,
+        })
+    }
+
+    /// Physical address of the top-level table, what `activate` takes.
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// Map the page at `virt` to the frame at `phys`, which the address space then owns and
+    /// frees when dropped.
+    pub fn map(&mut self, virt: u64, phys: u64, flags: PageFlags) -> Result<(), PagingError> {
+        check_user_range(virt, PAGE_SIZE)?;
+        let entry = self.entry(virt, true)?.ok_or(PagingError::NotMapped)?;
+        if arch::is_valid(unsafe { entry.read_volatile() }) {
+            return Err(PagingError::AlreadyMapped);
+        }
+        unsafe {
+            entry.write_volatile(arch::page_descriptor(phys, flags));
+        }
+        arch::publish_tables();
+        Ok(())
+    }
+
+    /// Back `length` bytes from `virt`, both page aligned, with zeroed frames. On failure the
+    /// pages already backed are unmapped again, leaving the range as it was.
+    pub fn allocate(
+        &mut self,
+        virt: u64,
+        length: u64,
+        flags: PageFlags,
+    ) -> Result<(), PagingError> {
+        check_user_range(virt, length)?;
+        for page in (virt..virt + length).step_by(PAGE_SIZE as usize) {
+            let result = frame::allocate_zeroed_frame()
+                .ok_or(PagingError::FrameAllocationFailed)
+                .and_then(|phys| {
+                    self.map(page, phys, flags)
+                        .inspect_err(|_| frame::deallocate_frames(phys, 1))
+                });
+            if let Err(error) = result {
+                self.release(virt, page - virt);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmap the `length` bytes from `virt`, both page aligned, freeing the frames behind them.
+    /// Pages not mapped are skipped.
+    fn release(&mut self, virt: u64, length: u64) {
+        for page in (virt..virt + length).step_by(PAGE_SIZE as usize) {
+            let Ok(Some(entry)) = self.entry(page, false) else {
+                continue;
+            };
+            let descriptor = unsafe { entry.read_volatile() };
+            if arch::is_valid(descriptor) {
+                unsafe {
+                    entry.write_volatile(0);
+                }
+                arch::invalidate_page(page);
+                frame::deallocate_frames(arch::descriptor_address(descriptor), 1);
+            }
+        }
+    }
+
+    /// The physical address `virt` is mapped to.
+    pub fn translate(&self, virt: u64) -> Option<u64> {
+        self.translate_for(virt, false)
+    }
+
+    /// The physical address `virt` is mapped to, if user code may write there when `write` is
+    /// set, so system calls can check user buffers before touching them.
+    pub fn translate_for(&self, virt: u64, write: bool) -> Option<u64> {
+        check_user_range(virt, 1).ok()?;
+        let entry = self.entry(virt, false).ok()??;
+        let descriptor = unsafe { entry.read_volatile() };
+        (arch::is_valid(descriptor) && (!write || arch::is_writable(descriptor)))
+            .then(|| arch::descriptor_address(descriptor) + virt % PAGE_SIZE)
+    }
+
+    /// Copy `data` to `virt` through the direct map, so the address space need not be active.
+    /// Every page written must be mapped. What is written may be run as code afterwards.
+    pub fn write(&mut self, virt: u64, data: &[u8]) -> Result<(), PagingError> {
+        let mut written = 0;
+        while written < data.len() {
+            let address = virt + written as u64;
+            let phys = self.translate(address).ok_or(PagingError::NotMapped)?;
+            let chunk = (PAGE_SIZE - address % PAGE_SIZE).min((data.len() - written) as u64);
+            let target = hhdm::phys_to_virt(phys);
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[written..].as_ptr(),
+                    target as *mut u8,
+                    chunk as usize,
+                );
+            }
+            arch::sync_instruction_cache(target, chunk);
+            written += chunk as usize;
+        }
+        Ok(())
+    }
+
+    /// Walk to the last-level entry for `virt`, creating missing tables when `create` is set
+    /// and returning `None` at a missing one otherwise.
+    fn entry(&self, virt: u64, create: bool) -> Result<Option<*mut u64>, PagingError> {
+        let mut table = self.root;
+        for level in 0..LEVELS - 1 {
+            let entry = table_entry(table, table_index(virt, level));
+            let descriptor = unsafe { entry.read_volatile() };
+            table = if arch::is_valid(descriptor) {
+                arch::descriptor_address(descriptor)
+            } else if create {
+                let next =
+                    frame::allocate_zeroed_frame().ok_or(PagingError::FrameAllocationFailed)?;
+                unsafe {
+                    entry.write_volatile(arch::table_descriptor(next));
+                }
+                next
+            } else {
+                return Ok(None);
+            };
+        }
+        Ok(Some(table_entry(table, table_index(virt, LEVELS - 1))))
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let user_entries = (arch::USER_END >> 39) as usize;
+        for index in 0..user_entries {
+            free_entry(table_entry(self.root, index), 0);
+        }
+        frame::deallocate_frames(self.root, 1);
+    }
+}
+
+/// Free what a valid entry at `level` points to: a table and everything below it, or at the
+/// last level the mapped frame.
+fn free_entry(entry: *mut u64, level: u32) {
+    let descriptor = unsafe { entry.read_volatile() };
+    if !arch::is_valid(descriptor) {
+        return;
+    }
+    let address = arch::descriptor_address(descriptor);
+    if level < LEVELS - 1 {
+        for index in 0..ENTRIES {
+            free_entry(table_entry(address, index), level + 1);
+        }
+    }
+    frame::deallocate_frames(address, 1);
+}
+
+fn table_entry(table: u64, index: usize) -> *mut u64 {
+    unsafe { (hhdm::phys_to_virt(table) as *mut u64).add(index) }
+}
+
+fn table_index(virt: u64, level: u32) -> usize {
+    ((virt >> (39 - 9 * level)) & 0x1ff) as usize
+}
+
+/// Whether `length` bytes from `virt` lie in the part of the user half pages are mapped in.
+fn check_user_range(virt: u64, length: u64) -> Result<(), PagingError> {
+    match virt.checked_add(length) {
+        Some(end) if end <= arch::USER_MAP_END => Ok(()),
+        _ => Err(PagingError::OutsideUserSpace),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{check_user_range, table_index, PageFlags};
+    use crate::memory::paging::{arch, PagingError};
+
+    #[kunit]
+    fn only_the_lower_half_belongs_to_users() {
+        assert_eq!(check_user_range(0x40_0000, 0x1000), Ok(()));
+        assert_eq!(
+            check_user_range(arch::USER_MAP_END - 0x1000, 0x1000),
+            Ok(())
+        );
+        assert_eq!(
+            check_user_range(arch::USER_MAP_END - 0x1000, 0x2000),
+            Err(PagingError::OutsideUserSpace)
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            check_user_range(arch::USER_END - 0x1000, 0x1000),
+            Err(PagingError::OutsideUserSpace)
+        );
+        assert_eq!(
+            check_user_range(u64::MAX, 2),
+            Err(PagingError::OutsideUserSpace)
+        );
+    }
+
+    #[kunit]
+    fn user_addresses_split_into_table_indices() {
+        let virt = 0x0000_7fff_ffff_f000;
+        assert_eq!(table_index(virt, 0), 255);
+        assert_eq!(table_index(virt, 1), 511);
+        assert_eq!(table_index(virt, 2), 511);
+        assert_eq!(table_index(virt, 3), 511);
+        assert!(PageFlags::WRITE
+            .union(PageFlags::EXECUTE)
+            .contains(PageFlags::EXECUTE));
+        assert!(!PageFlags::READ.contains(PageFlags::WRITE));
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+mod address_space;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use spin::Mutex;
+
+pub use address_space::{AddressSpace, PageFlags};
+pub use arch::USER_END;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Kernel virtual window reserved for device mappings (one top-level table slot, 512 GiB).
+const MMIO_WINDOW_BASE: u64 = 0xffff_ff00_0000_0000;
+const MMIO_WINDOW_SIZE: u64 = 512 * 1024 * 1024 * 1024;
+
+static NEXT_MMIO_ADDRESS: Mutex<u64> = Mutex::new(MMIO_WINDOW_BASE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingError {
+    FrameAllocationFailed,
+    AlreadyMapped,
+    MmioWindowExhausted,
+    UnsupportedLayout,
+    /// The address is not in the user half.
+    OutsideUserSpace,
+    NotMapped,
+}
+
+/// Prepare the kernel half of the page tables to be shared with user address spaces.
+pub fn init() {
+    if let Err(error) = arch::init() {
+        crate::warn_ln!("paging: user address spaces unavailable: {:?}", error);
+    }
+}
+
+/// Switch the calling CPU's lower half to the address space with top-level table `root`, or
+/// back to the kernel's own with `None`. Called with interrupts disabled.
+pub fn activate(root: Option<u64>) {
+    arch::activate(root);
+}
+
+/// Map a physical device region into the kernel MMIO window with caching disabled.
+///
+/// Returns the virtual address corresponding to `phys`, which does not have to be page aligned.
+pub fn map_mmio(phys: u64, length: u64) -> Result<u64, PagingError> {
+    let start = phys & !(PAGE_SIZE - 1);
+    let end = (phys + length.max(1)).next_multiple_of(PAGE_SIZE);
+    let size = end - start;
+
+    let virt_start = {
+        let mut next = NEXT_MMIO_ADDRESS.lock();
+        if *next + size > MMIO_WINDOW_BASE + MMIO_WINDOW_SIZE {
+            return Err(PagingError::MmioWindowExhausted);
+        }
+        let virt_start = *next;
+        *next += size;
+        virt_start
+    };
+
+    for offset in (0..size).step_by(PAGE_SIZE as usize) {
+        map_device_page(virt_start + offset, start + offset)?;
+    }
+
+    Ok(virt_start + (phys - start))
+}
+
+fn map_device_page(virt: u64, phys: u64) -> Result<(), PagingError> {
+    arch::map_device_page(virt, phys)
+}
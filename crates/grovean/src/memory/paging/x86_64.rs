@@ -0,0 +1,191 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{PageFlags, PagingError};
+use crate::memory::{frame, hhdm};
+
+/// End of the lower half, the 128 TiB of canonical addresses below the kernel.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+/// End of where user pages can be mapped. The last page below the non-canonical hole stays
+/// unmapped, so no `syscall` ends there and leaves `sysret` a non-canonical return address,
+/// which would fault in ring 0 on the user's stack.
+pub(super) const USER_MAP_END: u64 = USER_END - 0x1000;
+/// First top-level entry of the kernel half.
+const KERNEL_ENTRY: usize = 256;
+
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Serializes modifications of the active page tables.
+static PAGE_TABLE_LOCK: Mutex<()> = Mutex::new(());
+/// The bootloader's top-level table, which kernel tasks run on. Zero until `init`.
+static KERNEL_ROOT: AtomicU64 = AtomicU64::new(0);
+/// Whether `EFER.NXE` lets pages be marked non-executable.
+static NO_EXECUTE: AtomicBool = AtomicBool::new(false);
+
+struct KernelFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        frame::allocate_frame().map(|address| PhysFrame::containing_address(PhysAddr::new(address)))
+    }
+}
+
+/// Get a mapper for the active page tables, reached through the direct map.
+///
+/// # Safety
+///
+/// The caller must hold `PAGE_TABLE_LOCK` for as long as the mapper is in use.
+unsafe fn active_mapper() -> OffsetPageTable<'static> {
+    let (level_4_frame, _) = Cr3::read();
+    let level_4_table =
+        hhdm::phys_to_virt(level_4_frame.start_address().as_u64()) as *mut PageTable;
+
+    unsafe { OffsetPageTable::new(&mut *level_4_table, VirtAddr::new(hhdm::offset())) }
+}
+
+pub(super) fn map_device_page(virt: u64, phys: u64) -> Result<(), PagingError> {
+    let _guard = PAGE_TABLE_LOCK.lock();
+    let mut mapper = unsafe { active_mapper() };
+
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt));
+    let frame = PhysFrame::containing_address(PhysAddr::new(phys));
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+
+    match unsafe { mapper.map_to(page, frame, flags, &mut KernelFrameAllocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(MapToError::FrameAllocationFailed) => Err(PagingError::FrameAllocationFailed),
+        Err(MapToError::PageAlreadyMapped(_)) => Err(PagingError::AlreadyMapped),
+        Err(MapToError::ParentEntryHugePage) => Err(PagingError::UnsupportedLayout),
+    }
+}
+
+/// Give every kernel-half top-level entry a table, so the kernel half never changes at the top
+/// level and user address spaces can share it by copying those entries once.
+pub(super) fn init() -> Result<(), PagingError> {
+    let _guard = PAGE_TABLE_LOCK.lock();
+    let root = Cr3::read().0.start_address().as_u64();
+    let table = unsafe { &mut *(hhdm::phys_to_virt(root) as *mut PageTable) };
+    for entry in table.iter_mut().skip(KERNEL_ENTRY) {
+        if entry.is_unused() {
+            let frame = frame::allocate_zeroed_frame().ok_or(PagingError::FrameAllocationFailed)?;
+            entry.set_addr(
+                PhysAddr::new(frame),
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            );
+        }
+    }
+    NO_EXECUTE.store(
+        Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE),
+        Ordering::Relaxed,
+    );
+    KERNEL_ROOT.store(root, Ordering::Relaxed);
+    Ok(())
+}
+
+/// A top-level table with an empty lower half and the kernel's upper half.
+pub(super) fn new_user_root() -> Result<u64, PagingError> {
+    let kernel = KERNEL_ROOT.load(Ordering::Relaxed);
+    if kernel == 0 {
+        return Err(PagingError::UnsupportedLayout);
+    }
+    let root = frame::allocate_zeroed_frame().ok_or(PagingError::FrameAllocationFailed)?;
+    let source = hhdm::phys_to_virt(kernel) as *const u64;
+    let target = hhdm::phys_to_virt(root) as *mut u64;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            source.add(KERNEL_ENTRY),
+            target.add(KERNEL_ENTRY),
+            512 - KERNEL_ENTRY,
+        );
+    }
+    Ok(root)
+}
+
+pub(super) fn activate(root: Option<u64>) {
+    let root = root.unwrap_or(KERNEL_ROOT.load(Ordering::Relaxed));
+    if root == 0 || Cr3::read().0.start_address().as_u64() == root {
+        return;
+    }
+    unsafe {
+        Cr3::write(
+            PhysFrame::containing_address(PhysAddr::new(root)),
+            Cr3Flags::empty(),
+        );
+    }
+}
+
+/// An intermediate table entry user pages below it may be reached through.
+pub(super) fn table_descriptor(phys: u64) -> u64 {
+    (PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE).bits()
+        | (phys & ADDRESS_MASK)
+}
+
+pub(super) fn page_descriptor(phys: u64, flags: PageFlags) -> u64 {
+    let mut bits = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if flags.contains(PageFlags::WRITE) {
+        bits |= PageTableFlags::WRITABLE;
+    }
+    if !flags.contains(PageFlags::EXECUTE) && NO_EXECUTE.load(Ordering::Relaxed) {
+        bits |= PageTableFlags::NO_EXECUTE;
+    }
+    bits.bits() | (phys & ADDRESS_MASK)
+}
+
+pub(super) fn is_valid(descriptor: u64) -> bool {
+    descriptor & PageTableFlags::PRESENT.bits() != 0
+}
+
+pub(super) fn descriptor_address(descriptor: u64) -> u64 {
+    descriptor & ADDRESS_MASK
+}
+
+pub(super) fn is_writable(descriptor: u64) -> bool {
+    descriptor & PageTableFlags::WRITABLE.bits() != 0
+}
+
+/// Drop this CPU's cached translation of the user page at `virt`, after its entry was cleared.
+/// Processes run on one CPU at a time, so no other CPU can hold it while the address space is
+/// in use.
+pub(super) fn invalidate_page(virt: u64) {
+    x86_64::instructions::tlb::flush(VirtAddr::new(virt));
+}
+
+/// New entries are seen by the page walker without further ado on x86_64.
+pub(super) fn publish_tables() {}
+
+/// Instruction fetches see earlier stores on x86_64.
+pub(super) fn sync_instruction_cache(_virt: u64, _length: u64) {}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+    use kunit::kunit;
+
+    use super::{is_valid, is_writable, page_descriptor, table_descriptor, NO_EXECUTE};
+    use crate::memory::paging::PageFlags;
+
+    #[kunit]
+    fn user_pages_carry_their_permissions() {
+        NO_EXECUTE.store(true, Ordering::Relaxed);
+        let data = page_descriptor(0x5000, PageFlags::WRITE);
+        assert_eq!(data, 0x5000 | 0b111 | 1 << 63);
+        let code = page_descriptor(0x6000, PageFlags::EXECUTE);
+        assert_eq!(code, 0x6000 | 0b101);
+        assert!(is_writable(data) && !is_writable(code));
+        assert!(is_valid(table_descriptor(0x7000)));
+        assert!(!is_valid(0x7000));
+    }
+}
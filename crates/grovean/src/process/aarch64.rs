@@ -0,0 +1,63 @@
+use core::arch::asm;
+
+/// `e_machine` of executables built for this architecture.
+pub const ELF_MACHINE: u16 = 183;
+/// `R_AARCH64_RELATIVE`, the relocation static position-independent executables need.
+pub const RELATIVE_RELOCATION: u32 = 1027;
+
+/// Drop to EL0 at `entry` with SP_EL0 at `stack`, clearing every register so nothing of the
+/// kernel's leaks. SP_EL1 is reset to the top of the kernel stack first, since exceptions
+/// from EL0 arrive wherever it points.
+///
+/// # Safety
+///
+/// The current task's address space must be active and map `entry` and `stack` for EL0, and
+/// nothing on the kernel stack may be needed again.
+pub unsafe fn enter_user(entry: u64, stack: u64, kernel_stack: usize) -> ! {
+    unsafe {
+        asm!(
+            "msr daifset, #2",
+            "msr sp_el0, {stack}",
+            "msr elr_el1, {entry}",
+            // EL0 with SP_EL0 and every interrupt unmasked.
+            "msr spsr_el1, xzr",
+            "mov sp, {kernel_stack}",
+            "mov x0, xzr",
+            "mov x1, xzr",
+            "mov x2, xzr",
+            "mov x3, xzr",
+            "mov x4, xzr",
+            "mov x5, xzr",
+            "mov x6, xzr",
+            "mov x7, xzr",
+            "mov x8, xzr",
+            "mov x9, xzr",
+            "mov x10, xzr",
+            "mov x11, xzr",
+            "mov x12, xzr",
+            "mov x13, xzr",
+            "mov x14, xzr",
+            "mov x15, xzr",
+            "mov x16, xzr",
+            "mov x17, xzr",
+            "mov x18, xzr",
+            "mov x19, xzr",
+            "mov x20, xzr",
+            "mov x21, xzr",
+            "mov x22, xzr",
+            "mov x23, xzr",
+            "mov x24, xzr",
+            "mov x25, xzr",
+            "mov x26, xzr",
+            "mov x27, xzr",
+            "mov x28, xzr",
+            "mov x29, xzr",
+            "mov x30, xzr",
+            "eret",
+            stack = in(reg) stack,
+            entry = in(reg) entry,
+            kernel_stack = in(reg) kernel_stack,
+            options(noreturn)
+        )
+    }
+}
@@ -0,0 +1,428 @@
+use super::arch;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+
+const TYPE_EXECUTABLE: u16 = 2;
+const TYPE_SHARED: u16 = 3;
+
+pub const SEGMENT_LOAD: u32 = 1;
+pub const SEGMENT_DYNAMIC: u32 = 2;
+pub const SEGMENT_INTERPRETER: u32 = 3;
+pub const SEGMENT_PROGRAM_HEADERS: u32 = 6;
+
+pub const SEGMENT_EXECUTE: u32 = 1 << 0;
+pub const SEGMENT_WRITE: u32 = 1 << 1;
+pub const SEGMENT_READ: u32 = 1 << 2;
+
+const DYNAMIC_NULL: u64 = 0;
+const DYNAMIC_RELA: u64 = 7;
+const DYNAMIC_RELA_SIZE: u64 = 8;
+const DYNAMIC_RELA_ENTRY: u64 = 9;
+const DYNAMIC_ENTRY_SIZE: usize = 16;
+const RELA_SIZE: usize = 24;
+
+const RELOCATION_NONE: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    NotElf,
+    /// Not a 64-bit little-endian file of the current version.
+    UnsupportedFormat,
+    /// Built for another architecture.
+    WrongMachine,
+    /// Neither an executable nor a position-independent one.
+    UnsupportedType,
+    /// A header or table runs past the end of the file.
+    Truncated,
+    /// A segment's sizes, addresses or alignment are inconsistent.
+    InvalidSegment,
+    /// The program needs a dynamic linker, which there is none of.
+    NeedsInterpreter,
+    /// The entry point is not in an executable segment.
+    InvalidEntry,
+    UnsupportedRelocation(u32),
+    /// A relocation patches a word outside the loaded image.
+    InvalidRelocation,
+}
+
+/// A validated ELF64 file for this architecture, borrowed from its bytes.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    /// Whether the file is position independent and must be relocated to where it loads.
+    position_independent: bool,
+    entry: u64,
+    program_headers: usize,
+    program_header_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    /// The file bytes the segment starts with; the rest of it up to `memory_size` is zeroed.
+    /// In the file for every loadable and dynamic segment of a parsed `Elf`.
+    pub fn file_range(&self) -> core::ops::Range<usize> {
+        self.offset as usize..(self.offset + self.file_size) as usize
+    }
+}
+
+/// A relocation the loader applies: the 64-bit word at `offset` becomes the load base plus
+/// `addend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub addend: u64,
+}
+
+impl<'a> Elf<'a> {
+    /// Check the file header, every program header, and that the entry point is in an
+    /// executable segment.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN || data[6] != VERSION_CURRENT {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if u16_at(data, 18) != arch::ELF_MACHINE {
+            return Err(ElfError::WrongMachine);
+        }
+        let position_independent = match u16_at(data, 16) {
+            TYPE_EXECUTABLE => false,
+            TYPE_SHARED => true,
+            _ => return Err(ElfError::UnsupportedType),
+        };
+        let program_headers = u64_at(data, 32) as usize;
+        let entry_size = u16_at(data, 54) as usize;
+        let program_header_count = u16_at(data, 56) as usize;
+        if program_header_count > 0 && entry_size != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        program_header_count
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| program_headers.checked_add(size))
+            .filter(|&end| end <= data.len())
+            .ok_or(ElfError::Truncated)?;
+
+        let elf = Self {
+            data,
+            position_independent,
+            entry: u64_at(data, 24),
+            program_headers,
+            program_header_count,
+        };
+        for header in elf.program_headers() {
+            match header.kind {
+                SEGMENT_INTERPRETER => return Err(ElfError::NeedsInterpreter),
+                SEGMENT_LOAD => check_segment(&header, data.len())?,
+                SEGMENT_DYNAMIC => check_file_range(&header, data.len())?,
+                _ => {}
+            }
+        }
+        let executable = elf.program_headers().any(|header| {
+            header.kind == SEGMENT_LOAD
+                && header.flags & SEGMENT_EXECUTE != 0
+                && (header.vaddr..header.vaddr + header.memory_size).contains(&elf.entry)
+        });
+        if !executable {
+            return Err(ElfError::InvalidEntry);
+        }
+        Ok(elf)
+    }
+
+    pub fn is_position_independent(&self) -> bool {
+        self.position_independent
+    }
+
+    /// Where the program starts, before adding the load base.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.program_header_count
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let start = self.program_headers;
+        (0..self.program_header_count).map(move |index| {
+            let header = &data[start + index * PROGRAM_HEADER_SIZE..];
+            ProgramHeader {
+                kind: u32_at(header, 0),
+                flags: u32_at(header, 4),
+                offset: u64_at(header, 8),
+                vaddr: u64_at(header, 16),
+                file_size: u64_at(header, 32),
+                memory_size: u64_at(header, 40),
+                align: u64_at(header, 48),
+            }
+        })
+    }
+
+    /// The address the program headers are loaded at, before adding the load base: their own
+    /// segment's if there is one, otherwise wherever the loadable segment holding them puts
+    /// them.
+    pub fn program_headers_address(&self) -> Option<u64> {
+        let offset = self.program_headers as u64;
+        self.program_headers()
+            .find(|header| header.kind == SEGMENT_PROGRAM_HEADERS)
+            .map(|header| header.vaddr)
+            .or_else(|| self.file_to_address(offset))
+    }
+
+    /// The relocations the dynamic segment lists, which for a static position-independent
+    /// executable are all relative ones. Executables at a fixed address have none.
+    pub fn relocations(&self) -> Result<impl Iterator<Item = Relocation> + 'a, ElfError> {
+        let mut table = 0..0;
+        let dynamic = self
+            .program_headers()
+            .find(|header| header.kind == SEGMENT_DYNAMIC);
+        if let Some(dynamic) = dynamic {
+            let entries = self
+                .data
+                .get(dynamic.file_range())
+                .ok_or(ElfError::Truncated)?;
+            let (mut address, mut size, mut entry_size) = (None, 0, RELA_SIZE as u64);
+            for entry in entries.as_chunks::<DYNAMIC_ENTRY_SIZE>().0 {
+                let value = u64_at(entry, 8);
+                match u64_at(entry, 0) {
+                    DYNAMIC_NULL => break,
+                    DYNAMIC_RELA => address = Some(value),
+                    DYNAMIC_RELA_SIZE => size = value,
+                    DYNAMIC_RELA_ENTRY => entry_size = value,
+                    _ => {}
+                }
+            }
+            if let Some(address) = address {
+                if entry_size != RELA_SIZE as u64 {
+                    return Err(ElfError::UnsupportedFormat);
+                }
+                let start = self
+                    .address_to_file(address, size)
+                    .ok_or(ElfError::Truncated)?;
+                table = start..start + size as usize;
+            }
+        }
+
+        let data = self.data;
+        let entries = &data[table];
+        for entry in entries.as_chunks::<RELA_SIZE>().0 {
+            match u64_at(entry, 8) as u32 {
+                RELOCATION_NONE | arch::RELATIVE_RELOCATION => {}
+                other => return Err(ElfError::UnsupportedRelocation(other)),
+            }
+        }
+        Ok(entries
+            .as_chunks::<RELA_SIZE>()
+            .0
+            .iter()
+            .filter(|&entry| u64_at(entry, 8) as u32 == arch::RELATIVE_RELOCATION)
+            .map(|entry| Relocation {
+                offset: u64_at(entry, 0),
+                addend: u64_at(entry, 16),
+            }))
+    }
+
+    /// The address the byte at file `offset` is loaded at.
+    fn file_to_address(&self, offset: u64) -> Option<u64> {
+        self.program_headers()
+            .filter(|header| header.kind == SEGMENT_LOAD)
+            .find(|header| (header.offset..header.offset + header.file_size).contains(&offset))
+            .map(|header| header.vaddr + (offset - header.offset))
+    }
+
+    /// The file offset of the `size` bytes loaded at `address`, which must all come from one
+    /// segment's file bytes.
+    fn address_to_file(&self, address: u64, size: u64) -> Option<usize> {
+        self.program_headers()
+            .filter(|header| header.kind == SEGMENT_LOAD)
+            .find(|header| {
+                address >= header.vaddr
+                    && address
+                        .checked_add(size)
+                        .is_some_and(|end| end <= header.vaddr + header.file_size)
+            })
+            .map(|header| (header.offset + (address - header.vaddr)) as usize)
+    }
+}
+
+/// A loadable segment must lie in the file, fit its file bytes in memory, and keep file
+/// offset and address congruent so it can be mapped page by page.
+fn check_segment(header: &ProgramHeader, file_size: usize) -> Result<(), ElfError> {
+    check_file_range(header, file_size)?;
+    let consistent = header.file_size <= header.memory_size
+        && header.vaddr.checked_add(header.memory_size).is_some()
+        && (header.align <= 1
+            || header.align.is_power_of_two()
+                && header.offset % header.align == header.vaddr % header.align);
+    if !consistent {
+        return Err(ElfError::InvalidSegment);
+    }
+    Ok(())
+}
+
+/// A segment read from the file must lie in it.
+fn check_file_range(header: &ProgramHeader, file_size: usize) -> Result<(), ElfError> {
+    header
+        .offset
+        .checked_add(header.file_size)
+        .filter(|&end| end <= file_size as u64)
+        .ok_or(ElfError::Truncated)?;
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{
+        arch, Elf, ElfError, Relocation, HEADER_SIZE, PROGRAM_HEADER_SIZE, SEGMENT_DYNAMIC,
+        SEGMENT_EXECUTE, SEGMENT_INTERPRETER, SEGMENT_LOAD, SEGMENT_READ, SEGMENT_WRITE,
+        TYPE_SHARED,
+    };
+
+    fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    /// A position-independent executable with one writable, executable segment loaded from
+    /// file offset 0
+    /// at address 0, holding the headers, a dynamic section at 0x100 and a relocation table at
+    /// 0x140 with a relative relocation of the word at 0x180 to the base plus 0x20.
+    fn sample() -> Vec<u8> {
+        let mut file = vec![0u8; 0x200];
+        put(&mut file, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut file, 16, &TYPE_SHARED.to_le_bytes());
+        put(&mut file, 18, &arch::ELF_MACHINE.to_le_bytes());
+        put(&mut file, 24, &0x10u64.to_le_bytes());
+        put(&mut file, 32, &(HEADER_SIZE as u64).to_le_bytes());
+        put(&mut file, 54, &(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        put(&mut file, 56, &2u16.to_le_bytes());
+
+        let load = HEADER_SIZE;
+        put(&mut file, load, &SEGMENT_LOAD.to_le_bytes());
+        put(
+            &mut file,
+            load + 4,
+            &(SEGMENT_READ | SEGMENT_WRITE | SEGMENT_EXECUTE).to_le_bytes(),
+        );
+        put(&mut file, load + 32, &0x200u64.to_le_bytes());
+        put(&mut file, load + 40, &0x1800u64.to_le_bytes());
+        put(&mut file, load + 48, &0x1000u64.to_le_bytes());
+
+        let dynamic = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        put(&mut file, dynamic, &SEGMENT_DYNAMIC.to_le_bytes());
+        put(&mut file, dynamic + 8, &0x100u64.to_le_bytes());
+        put(&mut file, dynamic + 16, &0x100u64.to_le_bytes());
+        put(&mut file, dynamic + 32, &0x40u64.to_le_bytes());
+
+        for (index, (tag, value)) in [(7u64, 0x140u64), (8, 24), (9, 24)].iter().enumerate() {
+            put(&mut file, 0x100 + index * 16, &tag.to_le_bytes());
+            put(&mut file, 0x108 + index * 16, &value.to_le_bytes());
+        }
+        put(&mut file, 0x140, &0x180u64.to_le_bytes());
+        put(
+            &mut file,
+            0x148,
+            &(arch::RELATIVE_RELOCATION as u64).to_le_bytes(),
+        );
+        put(&mut file, 0x150, &0x20u64.to_le_bytes());
+        file
+    }
+
+    #[kunit]
+    fn headers_and_relocations_are_read() {
+        let file = sample();
+        let elf = Elf::parse(&file).unwrap();
+        assert!(elf.is_position_independent());
+        assert_eq!(elf.entry(), 0x10);
+        assert_eq!(elf.program_headers().count(), 2);
+        assert_eq!(elf.program_headers_address(), Some(HEADER_SIZE as u64));
+        let relocations: Vec<Relocation> = elf.relocations().unwrap().collect();
+        assert_eq!(
+            relocations,
+            [Relocation {
+                offset: 0x180,
+                addend: 0x20
+            }]
+        );
+    }
+
+    #[kunit]
+    fn malformed_files_are_refused() {
+        assert_eq!(Elf::parse(b"\x7fELF").err(), Some(ElfError::NotElf));
+
+        let mut file = sample();
+        file[18] ^= 0xff;
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::WrongMachine));
+
+        let mut file = sample();
+        put(&mut file, HEADER_SIZE + 32, &0x201u64.to_le_bytes());
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::Truncated));
+
+        let mut file = sample();
+        put(&mut file, HEADER_SIZE + 16, &0x10u64.to_le_bytes());
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::InvalidSegment));
+
+        let mut file = sample();
+        put(
+            &mut file,
+            HEADER_SIZE + PROGRAM_HEADER_SIZE,
+            &SEGMENT_INTERPRETER.to_le_bytes(),
+        );
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::NeedsInterpreter));
+
+        let mut file = sample();
+        let dynamic = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+        put(&mut file, dynamic + 32, &u64::MAX.to_le_bytes());
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::Truncated));
+
+        let mut file = sample();
+        put(&mut file, 24, &0x1800u64.to_le_bytes());
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::InvalidEntry));
+
+        let mut file = sample();
+        put(&mut file, HEADER_SIZE + 4, &SEGMENT_READ.to_le_bytes());
+        assert_eq!(Elf::parse(&file).err(), Some(ElfError::InvalidEntry));
+
+        let mut file = sample();
+        put(&mut file, 0x148, &0xffu64.to_le_bytes());
+        let elf = Elf::parse(&file).unwrap();
+        assert_eq!(
+            elf.relocations().err(),
+            Some(ElfError::UnsupportedRelocation(0xff))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::elf::{Elf, ElfError, SEGMENT_EXECUTE, SEGMENT_LOAD, SEGMENT_WRITE};
+use super::{ProcessError, IMAGE_BASE, USER_STACK_SIZE};
+use crate::memory::frame;
+use crate::memory::paging::{AddressSpace, PageFlags, PAGE_SIZE, USER_END};
+
+/// Where position-independent executables are loaded.
+pub const PIE_BASE: u64 = IMAGE_BASE;
+
+/// The most of the stack arguments, environment and auxiliary vector may take, as on Linux.
+const MAX_ARGUMENTS_SIZE: u64 = USER_STACK_SIZE / 4;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+const PROGRAM_HEADER_SIZE: u64 = 56;
+
+/// Where an executable ended up in its address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadedImage {
+    /// Added to every address in the file; zero for executables at a fixed address.
+    pub base: u64,
+    pub entry: u64,
+}
+
+/// Map every loadable segment of `elf` with the permissions it asks for, copy in its file
+/// bytes, and relocate it if it is position independent.
+pub fn load(address_space: &mut AddressSpace, elf: &Elf) -> Result<LoadedImage, ProcessError> {
+    let base = if elf.is_position_independent() {
+        PIE_BASE
+    } else {
+        0
+    };
+    let segments = || {
+        elf.program_headers()
+            .filter(|header| header.kind == SEGMENT_LOAD && header.memory_size > 0)
+    };
+
+    // Check every segment lies in the user half and the whole image fits in the free frames
+    // before counting out its pages one by one.
+    let mut ranges = Vec::new();
+    let mut page_count = 0;
+    for segment in segments() {
+        let (start, end) = segment_range(base, segment.vaddr, segment.memory_size)?;
+        page_count += (end.div_ceil(PAGE_SIZE) - start / PAGE_SIZE) as usize;
+        ranges.push((start, end, segment_flags(segment.flags)));
+    }
+    if page_count > frame::stats().free_frames {
+        return Err(ProcessError::OutOfMemory);
+    }
+
+    // Segments may share a page at their ends, which then gets the permissions of both.
+    let mut pages = BTreeMap::new();
+    for (start, end, flags) in ranges {
+        for page in (start & !(PAGE_SIZE - 1)..end).step_by(PAGE_SIZE as usize) {
+            let entry = pages.entry(page).or_insert(PageFlags::READ);
+            *entry = entry.union(flags);
+        }
+    }
+    for (&page, &flags) in &pages {
+        address_space.allocate(page, PAGE_SIZE, flags)?;
+    }
+    for segment in segments() {
+        address_space.write(base + segment.vaddr, &elf.data()[segment.file_range()])?;
+    }
+
+    if elf.is_position_independent() {
+        for relocation in elf.relocations()? {
+            let value = base.wrapping_add(relocation.addend);
+            let target = base
+                .checked_add(relocation.offset)
+                .ok_or(ElfError::InvalidRelocation)?;
+            address_space.write(target, &value.to_le_bytes())?;
+        }
+    }
+    // The entry point lies in an executable segment, which was just loaded in range.
+    let entry = base
+        .checked_add(elf.entry())
+        .ok_or(ElfError::InvalidEntry)?;
+    Ok(LoadedImage { base, entry })
+}
+
+/// Where a segment of `memory_size` bytes at `vaddr` starts and ends once loaded at `base`,
+/// which must be within the user half.
+fn segment_range(base: u64, vaddr: u64, memory_size: u64) -> Result<(u64, u64), ElfError> {
+    let start = base.checked_add(vaddr).ok_or(ElfError::InvalidSegment)?;
+    let end = start
+        .checked_add(memory_size)
+        .filter(|&end| end <= USER_END)
+        .ok_or(ElfError::InvalidSegment)?;
+    Ok((start, end))
+}
+
+/// Write the System V initial stack for `image` below `top`: the argument count, the argument
+/// and environment pointers, the auxiliary vector, and the strings they point to. Returns the
+/// stack pointer the program starts with.
+pub fn initialize_stack(
+    address_space: &mut AddressSpace,
+    top: u64,
+    elf: &Elf,
+    image: &LoadedImage,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<u64, ProcessError> {
+    let mut auxv = vec![
+        (AT_PHENT, PROGRAM_HEADER_SIZE),
+        (AT_PHNUM, elf.program_header_count() as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, image.entry),
+    ];
+    if let Some(address) = elf.program_headers_address() {
+        auxv.push((AT_PHDR, image.base + address));
+    }
+    let mut random = [0; 16];
+    crate::random::fill_bytes(&mut random);
+    let (stack_pointer, contents) = build_stack(top, argv, envp, &auxv, random)?;
+    address_space.write(stack_pointer, &contents)?;
+    Ok(stack_pointer)
+}
+
+/// Lay out the initial stack ending at `top`, returning where it starts and its bytes.
+fn build_stack(
+    top: u64,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+    random: [u8; 16],
+) -> Result<(u64, Vec<u8>), ProcessError> {
+    let strings_size: u64 = argv
+        .iter()
+        .chain(envp)
+        .map(|string| string.len() as u64 + 1)
+        .sum();
+    if strings_size > MAX_ARGUMENTS_SIZE {
+        return Err(ProcessError::ArgumentsTooLarge);
+    }
+    let strings = top - strings_size;
+    let random_address = (strings - random.len() as u64) & !15;
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 2);
+    let stack_pointer = (random_address - words as u64 * 8) & !15;
+    if top - stack_pointer > MAX_ARGUMENTS_SIZE {
+        return Err(ProcessError::ArgumentsTooLarge);
+    }
+
+    let mut contents = vec![0u8; (top - stack_pointer) as usize];
+    let mut put = |address: u64, bytes: &[u8]| {
+        let offset = (address - stack_pointer) as usize;
+        contents[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    let mut vector = Vec::with_capacity(words);
+    vector.push(argv.len() as u64);
+    let mut next_string = strings;
+    for list in [argv, envp] {
+        for string in list {
+            put(next_string, string.as_bytes());
+            vector.push(next_string);
+            next_string += string.len() as u64 + 1;
+        }
+        vector.push(0);
+    }
+    put(random_address, &random);
+    for &(key, value) in auxv
+        .iter()
+        .chain(&[(AT_RANDOM, random_address), (AT_NULL, 0)])
+    {
+        vector.extend([key, value]);
+    }
+    for (index, word) in vector.iter().enumerate() {
+        put(stack_pointer + index as u64 * 8, &word.to_le_bytes());
+    }
+    Ok((stack_pointer, contents))
+}
+
+fn segment_flags(flags: u32) -> PageFlags {
+    let mut page = PageFlags::READ;
+    if flags & SEGMENT_WRITE != 0 {
+        page = page.union(PageFlags::WRITE);
+    }
+    if flags & SEGMENT_EXECUTE != 0 {
+        page = page.union(PageFlags::EXECUTE);
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{build_stack, segment_flags, segment_range, AT_NULL, AT_PAGESZ, AT_RANDOM};
+    use crate::memory::paging::PageFlags;
+    use crate::memory::paging::USER_END;
+    use crate::process::elf::ElfError;
+    use crate::process::elf::{SEGMENT_EXECUTE, SEGMENT_READ, SEGMENT_WRITE};
+    use crate::process::ProcessError;
+
+    fn word(contents: &[u8], index: usize) -> u64 {
+        u64::from_le_bytes(contents[index * 8..index * 8 + 8].try_into().unwrap())
+    }
+
+    #[kunit]
+    fn the_initial_stack_follows_the_system_v_layout() {
+        let top = 0x8000;
+        let (stack_pointer, contents) = build_stack(
+            top,
+            &["init", "-v"],
+            &["HOME=/"],
+            &[(AT_PAGESZ, 4096)],
+            [7; 16],
+        )
+        .unwrap();
+        assert_eq!(stack_pointer % 16, 0);
+        assert_eq!(stack_pointer + contents.len() as u64, top);
+
+        let string = |address: u64| {
+            let offset = (address - stack_pointer) as usize;
+            let end = contents[offset..]
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap();
+            core::str::from_utf8(&contents[offset..offset + end]).unwrap()
+        };
+        assert_eq!(word(&contents, 0), 2);
+        assert_eq!(string(word(&contents, 1)), "init");
+        assert_eq!(string(word(&contents, 2)), "-v");
+        assert_eq!(word(&contents, 3), 0);
+        assert_eq!(string(word(&contents, 4)), "HOME=/");
+        assert_eq!(word(&contents, 5), 0);
+        assert_eq!((word(&contents, 6), word(&contents, 7)), (AT_PAGESZ, 4096));
+        assert_eq!(word(&contents, 8), AT_RANDOM);
+        let random = (word(&contents, 9) - stack_pointer) as usize;
+        assert_eq!(contents[random..random + 16], [7; 16]);
+        assert_eq!((word(&contents, 10), word(&contents, 11)), (AT_NULL, 0));
+    }
+
+    #[kunit]
+    fn oversized_arguments_are_refused() {
+        let argument = "x".repeat(64 * 1024);
+        assert_eq!(
+            build_stack(0x10_0000, &[&argument], &[], &[], [0; 16]).err(),
+            Some(ProcessError::ArgumentsTooLarge)
+        );
+    }
+
+    #[kunit]
+    fn segment_permissions_become_page_flags() {
+        assert_eq!(segment_flags(SEGMENT_READ), PageFlags::READ);
+        assert_eq!(
+            segment_flags(SEGMENT_READ | SEGMENT_WRITE),
+            PageFlags::WRITE
+        );
+        assert_eq!(
+            segment_flags(SEGMENT_READ | SEGMENT_EXECUTE),
+            PageFlags::EXECUTE
+        );
+    }
+
+    #[kunit]
+    fn segments_outside_the_user_half_are_refused() {
+        assert_eq!(
+            segment_range(0x40_0000, 0x1000, 0x2000),
+            Ok((0x40_1000, 0x40_3000))
+        );
+        assert_eq!(
+            segment_range(0x40_0000, USER_END, 0x1000),
+            Err(ElfError::InvalidSegment)
+        );
+        assert_eq!(
+            segment_range(0x40_0000, 0, u64::MAX),
+            Err(ElfError::InvalidSegment)
+        );
+        assert_eq!(
+            segment_range(0x40_0000, u64::MAX, 1),
+            Err(ElfError::InvalidSegment)
+        );
+    }
+}
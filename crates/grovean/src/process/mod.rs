@@ -0,0 +1,428 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+pub mod elf;
+pub mod loader;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::fs::vfs::{self, FsError, OpenFile, OpenFlags};
+use crate::memory::frame;
+use crate::memory::paging::{AddressSpace, PageFlags, PagingError, PAGE_SIZE};
+use crate::sync::IrqSpinLock;
+use crate::task::{self, Priority, TaskError, WaitQueue};
+use elf::{Elf, ElfError};
+
+/// The program the kernel starts as the first process.
+pub const INIT_PATH: &str = "/bin/init";
+/// Where flat images are loaded and start running.
+pub const IMAGE_BASE: u64 = 0x40_0000;
+/// Where every process's stack ends, the top page of the user half left unmapped.
+pub const USER_STACK_TOP: u64 = 0x0000_7fff_ffff_f000;
+pub const USER_STACK_SIZE: u64 = 64 * 1024;
+/// Where anonymous mappings a process asks for without an address start, growing up.
+pub const MMAP_BASE: u64 = 0x0000_1000_0000_0000;
+/// Where the mapping area ends, below the stack.
+pub const MMAP_END: u64 = USER_STACK_TOP - USER_STACK_SIZE;
+/// What a new process has open as its standard input, output and error.
+const CONSOLE_PATH: &str = "/dev/ttyS0";
+/// Exit code of a process killed for an exception it raised.
+pub const KILLED_EXIT_CODE: i32 = -1;
+
+pub type Pid = u64;
+
+/// Pid 0 is never handed out, since tasks use it to mean no process.
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+/// Every process not yet reaped by `wait`.
+static PROCESSES: IrqSpinLock<BTreeMap<Pid, Arc<Process>>> = IrqSpinLock::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessError {
+    /// No frames were left for the page tables, the image, or a stack.
+    OutOfMemory,
+    Paging(PagingError),
+    /// The program is not an executable this kernel can load.
+    Elf(ElfError),
+    /// The program could not be read.
+    Fs(FsError),
+    /// The arguments and environment do not fit the initial stack.
+    ArgumentsTooLarge,
+}
+
+impl From<PagingError> for ProcessError {
+    fn from(error: PagingError) -> Self {
+        match error {
+            PagingError::FrameAllocationFailed => Self::OutOfMemory,
+            error => Self::Paging(error),
+        }
+    }
+}
+
+impl From<ElfError> for ProcessError {
+    fn from(error: ElfError) -> Self {
+        Self::Elf(error)
+    }
+}
+
+impl From<FsError> for ProcessError {
+    fn from(error: FsError) -> Self {
+        Self::Fs(error)
+    }
+}
+
+impl From<TaskError> for ProcessError {
+    fn from(error: TaskError) -> Self {
+        match error {
+            TaskError::OutOfMemory => Self::OutOfMemory,
+        }
+    }
+}
+
+/// A program running in user mode on a kernel task of its own, isolated from the kernel and
+/// other processes by its address space.
+pub struct Process {
+    pid: Pid,
+    name: String,
+    /// Taken and freed when the process exits.
+    address_space: Mutex<Option<AddressSpace>>,
+    /// Open files by descriptor, closed when the process exits.
+    files: Mutex<Vec<Option<Arc<OpenFile>>>>,
+    /// Where the next anonymous mapping without an address goes.
+    mmap_next: AtomicU64,
+    exit_code: AtomicI32,
+    exited: AtomicBool,
+    /// Tasks in `wait` for the process to exit.
+    waiters: WaitQueue,
+}
+
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run `f` on the process's address space, or fail with `NotMapped` once it exited.
+    pub fn with_address_space<R>(
+        &self,
+        f: impl FnOnce(&mut AddressSpace) -> Result<R, PagingError>,
+    ) -> Result<R, PagingError> {
+        match self.address_space.lock().as_mut() {
+            Some(address_space) => f(address_space),
+            None => Err(PagingError::NotMapped),
+        }
+    }
+
+    /// Back `length` bytes, rounded up to pages, with zeroed memory at the next free address
+    /// of the mapping area, returning where. A failed mapping leaves nothing behind, so the
+    /// next one can use the same addresses. Lengths past the end of the area or beyond the
+    /// free frames fail before the page tables are touched.
+    pub fn map_anonymous(&self, length: u64, flags: PageFlags) -> Result<u64, PagingError> {
+        let length = length
+            .checked_next_multiple_of(PAGE_SIZE)
+            .filter(|&length| length > 0 && length <= MMAP_END - MMAP_BASE)
+            .ok_or(PagingError::OutsideUserSpace)?;
+        if length / PAGE_SIZE > frame::stats().free_frames as u64 {
+            return Err(PagingError::FrameAllocationFailed);
+        }
+        self.with_address_space(|address_space| {
+            let address = self.mmap_next.load(Ordering::Relaxed);
+            if address + length > MMAP_END {
+                return Err(PagingError::OutsideUserSpace);
+            }
+            address_space.allocate(address, length, flags)?;
+            self.mmap_next.store(address + length, Ordering::Relaxed);
+            Ok(address)
+        })
+    }
+
+    /// Give `file` the lowest free descriptor.
+    pub fn install_file(&self, file: Arc<OpenFile>) -> usize {
+        let mut files = self.files.lock();
+        match files.iter().position(Option::is_none) {
+            Some(descriptor) => {
+                files[descriptor] = Some(file);
+                descriptor
+            }
+            None => {
+                files.push(Some(file));
+                files.len() - 1
+            }
+        }
+    }
+
+    pub fn file(&self, descriptor: usize) -> Option<Arc<OpenFile>> {
+        self.files.lock().get(descriptor)?.clone()
+    }
+
+    /// Free `descriptor`, returning the file it held.
+    pub fn close_file(&self, descriptor: usize) -> Option<Arc<OpenFile>> {
+        self.files.lock().get_mut(descriptor)?.take()
+    }
+
+    /// The code the process exited with, or `None` while it runs.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exited
+            .load(Ordering::Acquire)
+            .then(|| self.exit_code.load(Ordering::Relaxed))
+    }
+}
+
+/// Start a process running the executable at `path` with `argv` and `envp`, named after its
+/// first argument or else the path.
+pub fn spawn(path: &str, argv: &[&str], envp: &[&str]) -> Result<Pid, ProcessError> {
+    let image = read_file(path)?;
+    let name = argv.first().copied().unwrap_or(path);
+    spawn_elf(name, &image, argv, envp)
+}
+
+/// Start `INIT_PATH` as the first process and wait for it, logging how it ends. Without one,
+/// as when there is no initrd, the kernel carries on with its own tasks.
+pub fn run_init() {
+    let pid = match spawn(INIT_PATH, &[INIT_PATH], &["HOME=/"]) {
+        Ok(pid) => pid,
+        Err(error) => {
+            crate::warn_ln!("process: could not start {}: {:?}", INIT_PATH, error);
+            return;
+        }
+    };
+    crate::info_ln!("process: started {} as pid {}", INIT_PATH, pid);
+    if let Some(code) = wait(pid) {
+        crate::info_ln!("process: {} exited with code {}", INIT_PATH, code);
+    }
+}
+
+/// Start a process running the ELF executable `image` with `argv` and `envp`.
+pub fn spawn_elf(
+    name: &str,
+    image: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Pid, ProcessError> {
+    let elf = Elf::parse(image)?;
+    let mut address_space = AddressSpace::new()?;
+    let loaded = loader::load(&mut address_space, &elf)?;
+    allocate_stack(&mut address_space)?;
+    let stack_pointer = loader::initialize_stack(
+        &mut address_space,
+        USER_STACK_TOP,
+        &elf,
+        &loaded,
+        argv,
+        envp,
+    )?;
+    start(name, address_space, loaded.entry, stack_pointer)
+}
+
+/// Start a process running `code`, position-independent machine code, from its first byte.
+pub fn spawn_flat(name: &str, code: &[u8]) -> Result<Pid, ProcessError> {
+    let mut address_space = AddressSpace::new()?;
+    let length = (code.len() as u64).div_ceil(PAGE_SIZE).max(1) * PAGE_SIZE;
+    address_space.allocate(IMAGE_BASE, length, PageFlags::EXECUTE)?;
+    address_space.write(IMAGE_BASE, code)?;
+    allocate_stack(&mut address_space)?;
+    start(name, address_space, IMAGE_BASE, USER_STACK_TOP)
+}
+
+fn allocate_stack(address_space: &mut AddressSpace) -> Result<(), PagingError> {
+    address_space.allocate(
+        USER_STACK_TOP - USER_STACK_SIZE,
+        USER_STACK_SIZE,
+        PageFlags::WRITE,
+    )
+}
+
+/// Give `address_space`, with its program and stack in place, a task that enters it at
+/// `entry` with the stack pointer at `stack_pointer`.
+fn start(
+    name: &str,
+    address_space: AddressSpace,
+    entry: u64,
+    stack_pointer: u64,
+) -> Result<Pid, ProcessError> {
+    let root = address_space.root();
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    let process = Arc::new(Process {
+        pid,
+        name: name.to_string(),
+        address_space: Mutex::new(Some(address_space)),
+        files: Mutex::new(standard_streams()),
+        mmap_next: AtomicU64::new(MMAP_BASE),
+        exit_code: AtomicI32::new(0),
+        exited: AtomicBool::new(false),
+        waiters: WaitQueue::new(),
+    });
+    PROCESSES.lock().insert(pid, process);
+    let result = task::spawn(name, Priority::Normal, move || {
+        run(pid, root, entry, stack_pointer)
+    });
+    if let Err(error) = result {
+        PROCESSES.lock().remove(&pid);
+        return Err(error.into());
+    }
+    Ok(pid)
+}
+
+/// The console, open as standard input, output and error, or nothing if it is missing.
+fn standard_streams() -> Vec<Option<Arc<OpenFile>>> {
+    [OpenFlags::READ, OpenFlags::WRITE, OpenFlags::WRITE]
+        .into_iter()
+        .map(|flags| vfs::vfs().open(CONSOLE_PATH, flags).ok())
+        .collect()
+}
+
+/// Read all of the file at `path`, wherever in the VFS it lives, the initrd included.
+fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = vfs::vfs().open(path, OpenFlags::READ)?;
+    let mut contents = Vec::with_capacity(file.metadata().size as usize);
+    let mut buffer = [0u8; 4096];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(contents),
+            read => contents.extend_from_slice(&buffer[..read]),
+        }
+    }
+}
+
+/// The process's task: switch to its address space and drop to user mode for good.
+fn run(pid: Pid, root: u64, entry: u64, stack_pointer: u64) {
+    task::attach_process(pid, root);
+    let kernel_stack = task::current()
+        .and_then(|task| task.stack_top())
+        .expect("processes run on spawned tasks");
+    unsafe { arch::enter_user(entry, stack_pointer, kernel_stack) }
+}
+
+/// The process the current task runs, if any.
+pub fn current() -> Option<Arc<Process>> {
+    let pid = task::current()?.process()?;
+    get(pid)
+}
+
+/// The process `pid`, until it is reaped.
+pub fn get(pid: Pid) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&pid).cloned()
+}
+
+/// End the current process with `code`, freeing its address space, and exit its task.
+pub fn exit(code: i32) -> ! {
+    let process = current().expect("exit is called from a process");
+    task::detach_process();
+    let address_space = process.address_space.lock().take();
+    drop(address_space);
+    let files = core::mem::take(&mut *process.files.lock());
+    drop(files);
+    process.exit_code.store(code, Ordering::Relaxed);
+    process.exited.store(true, Ordering::Release);
+    process.waiters.wake_all();
+    drop(process);
+    task::exit()
+}
+
+/// Kill the current process for the exception `reason` describes. Returns only when the
+/// current task runs no process, so the exception is the kernel's own.
+pub fn fault(reason: fmt::Arguments) {
+    let Some(process) = current() else {
+        return;
+    };
+    crate::warn_ln!(
+        "process: {} ({}) killed: {}",
+        process.pid,
+        process.name,
+        reason
+    );
+    drop(process);
+    exit(KILLED_EXIT_CODE)
+}
+
+/// Wait for process `pid` to exit and reap it, returning its exit code, or `None` if there is
+/// no such process.
+pub fn wait(pid: Pid) -> Option<i32> {
+    let process = get(pid)?;
+    process.waiters.wait_until(|| process.exit_code().is_some());
+    PROCESSES.lock().remove(&pid);
+    process.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+    use kunit::kunit;
+    use spin::Mutex;
+
+    use super::{ElfError, Process, ProcessError, MMAP_BASE, MMAP_END};
+    use crate::memory::paging::{PageFlags, PagingError};
+    use crate::task::{TaskError, WaitQueue};
+
+    #[kunit]
+    fn exited_processes_keep_their_exit_code_and_nothing_else() {
+        let process = Process {
+            pid: 7,
+            name: "init".into(),
+            address_space: Mutex::new(None),
+            files: Mutex::new(Vec::new()),
+            mmap_next: AtomicU64::new(MMAP_BASE),
+            exit_code: AtomicI32::new(0),
+            exited: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+        };
+        assert_eq!(process.exit_code(), None);
+        process.exit_code.store(3, Ordering::Relaxed);
+        process.exited.store(true, Ordering::Release);
+        assert_eq!(process.exit_code(), Some(3));
+        assert_eq!((process.pid(), process.name()), (7, "init"));
+        assert_eq!(
+            process.map_anonymous(0, PageFlags::WRITE),
+            Err(PagingError::OutsideUserSpace)
+        );
+        assert_eq!(
+            process.map_anonymous(MMAP_END - MMAP_BASE + 1, PageFlags::WRITE),
+            Err(PagingError::OutsideUserSpace)
+        );
+        assert_eq!(
+            process.map_anonymous(u64::MAX, PageFlags::WRITE),
+            Err(PagingError::OutsideUserSpace)
+        );
+        assert_eq!(
+            process.map_anonymous(1, PageFlags::WRITE),
+            Err(PagingError::NotMapped)
+        );
+        assert!(process.file(0).is_none() && process.close_file(0).is_none());
+    }
+
+    #[kunit]
+    fn allocation_failures_become_out_of_memory() {
+        assert_eq!(
+            ProcessError::from(PagingError::FrameAllocationFailed),
+            ProcessError::OutOfMemory
+        );
+        assert_eq!(
+            ProcessError::from(PagingError::AlreadyMapped),
+            ProcessError::Paging(PagingError::AlreadyMapped)
+        );
+        assert_eq!(
+            ProcessError::from(ElfError::NotElf),
+            ProcessError::Elf(ElfError::NotElf)
+        );
+        assert_eq!(
+            ProcessError::from(TaskError::OutOfMemory),
+            ProcessError::OutOfMemory
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use core::arch::asm;
+
+use crate::cpu::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+
+/// `e_machine` of executables built for this architecture.
+pub const ELF_MACHINE: u16 = 62;
+/// `R_X86_64_RELATIVE`, the relocation static position-independent executables need.
+pub const RELATIVE_RELOCATION: u32 = 8;
+
+/// RFLAGS user code starts with: interrupts enabled, plus the bit that always reads as one.
+const USER_FLAGS: u64 = 0x202;
+
+/// Drop to ring 3 at `entry` with the stack pointer at `stack`, clearing every register so
+/// nothing of the kernel's leaks, and with the user's GS base swapped in. Interrupts and
+/// exceptions from user mode arrive on the kernel stack `cpu::set_kernel_stack` recorded, so
+/// the current one is left as is.
+///
+/// # Safety
+///
+/// The current task's address space must be active and map `entry` and `stack` for user mode.
+pub unsafe fn enter_user(entry: u64, stack: u64, _kernel_stack: usize) -> ! {
+    unsafe {
+        asm!(
+            "cli",
+            "push {data}",
+            "push {stack}",
+            "push {flags}",
+            "push {code}",
+            "push {entry}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "swapgs",
+            "iretq",
+            data = in(reg) USER_DATA_SELECTOR as u64,
+            stack = in(reg) stack,
+            flags = in(reg) USER_FLAGS,
+            code = in(reg) USER_CODE_SELECTOR as u64,
+            entry = in(reg) entry,
+            options(noreturn)
+        )
+    }
+}
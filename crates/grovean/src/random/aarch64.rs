@@ -0,0 +1,49 @@
+use core::arch::asm;
+
+const RETRIES: usize = 10;
+
+fn has_rndr() -> bool {
+    let isar0: u64;
+    unsafe {
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack, preserves_flags));
+    }
+    (isar0 >> 60) & 0xf != 0
+}
+
+fn rndr() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let (value, ok): (u64, u64);
+        unsafe {
+            // RNDR, spelled as its system register encoding so no assembler feature is needed.
+            // NZCV reads 0b0100 when the hardware could not produce a value.
+            asm!("mrs {value}, s3_3_c2_c4_0", "cset {ok}, ne", value = out(reg) value, ok = out(reg) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Name the best instruction available for seeding, if any.
+pub fn hardware_source() -> Option<&'static str> {
+    has_rndr().then_some("rndr")
+}
+
+/// Read 64 bits from the FEAT_RNG random number generator.
+pub fn hardware_u64() -> Option<u64> {
+    if has_rndr() {
+        rndr()
+    } else {
+        None
+    }
+}
+
+/// A fast cycle counter whose low bits jitter between reads.
+pub fn timestamp() -> u64 {
+    let count: u64;
+    unsafe {
+        asm!("mrs {}, cntvct_el0", out(reg) count, options(nomem, nostack, preserves_flags));
+    }
+    count
+}
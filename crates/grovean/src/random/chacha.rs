@@ -0,0 +1,199 @@
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+pub const SEED_SIZE: usize = 32;
+const BLOCK_SIZE: usize = 64;
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function (RFC 8439, section 2.3), where `input` holds the counter and
+/// nonce words.
+pub fn chacha20_block(key: &[u32; 8], input: [u32; 4]) -> [u8; BLOCK_SIZE] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    initial[4..12].copy_from_slice(key);
+    initial[12..].copy_from_slice(&input);
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut output = [0u8; BLOCK_SIZE];
+    for (index, word) in state.iter().enumerate() {
+        let word = word.wrapping_add(initial[index]);
+        output[index * 4..index * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    output
+}
+
+const fn key_from_bytes(bytes: &[u8]) -> [u32; 8] {
+    let mut key = [0u32; 8];
+    let mut index = 0;
+    while index < 8 {
+        key[index] = u32::from_le_bytes([
+            bytes[index * 4],
+            bytes[index * 4 + 1],
+            bytes[index * 4 + 2],
+            bytes[index * 4 + 3],
+        ]);
+        index += 1;
+    }
+    key
+}
+
+/// A ChaCha20 keystream generator that replaces its key after every request, so a later
+/// compromise of the state does not reveal earlier output.
+pub struct ChaChaRng {
+    key: [u32; 8],
+    counter: u64,
+    buffer: [u8; BLOCK_SIZE],
+    position: usize,
+}
+
+impl ChaChaRng {
+    pub const fn from_seed(seed: [u8; SEED_SIZE]) -> Self {
+        Self {
+            key: key_from_bytes(&seed),
+            counter: 0,
+            buffer: [0; BLOCK_SIZE],
+            position: BLOCK_SIZE,
+        }
+    }
+
+    /// Fold fresh entropy into the key. Mixing never reduces the entropy already present.
+    pub fn reseed(&mut self, entropy: &[u8]) {
+        for chunk in entropy.chunks(SEED_SIZE) {
+            let mut key = [0u8; SEED_SIZE];
+            for (index, word) in self.key.iter().enumerate() {
+                key[index * 4..index * 4 + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            for (byte, entropy) in key.iter_mut().zip(chunk) {
+                *byte ^= entropy;
+            }
+            self.key = key_from_bytes(&key);
+            self.rekey();
+        }
+    }
+
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut filled = 0;
+        while filled < dest.len() {
+            if self.position == BLOCK_SIZE {
+                self.buffer = self.next_block();
+                self.position = 0;
+            }
+            let count = (BLOCK_SIZE - self.position).min(dest.len() - filled);
+            dest[filled..filled + count]
+                .copy_from_slice(&self.buffer[self.position..self.position + count]);
+            self.position += count;
+            filled += count;
+        }
+        self.rekey();
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn next_block(&mut self) -> [u8; BLOCK_SIZE] {
+        let counter = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+        chacha20_block(&self.key, [counter as u32, (counter >> 32) as u32, 0, 0])
+    }
+
+    /// Replace the key with fresh keystream and drop any buffered output.
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key = key_from_bytes(&block[..SEED_SIZE]);
+        self.buffer = [0; BLOCK_SIZE];
+        self.position = BLOCK_SIZE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{chacha20_block, key_from_bytes, ChaChaRng, SEED_SIZE};
+
+    fn counting_seed() -> [u8; SEED_SIZE] {
+        core::array::from_fn(|index| index as u8)
+    }
+
+    #[kunit]
+    fn block_function_matches_rfc_8439() {
+        let key = key_from_bytes(&counting_seed());
+        let block = chacha20_block(&key, [1, 0x0900_0000, 0x4a00_0000, 0]);
+
+        assert_eq!(
+            &block[..16],
+            &[
+                0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20,
+                0x71, 0xc4
+            ]
+        );
+        assert_eq!(
+            &block[48..],
+            &[
+                0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9, 0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50,
+                0x3c, 0x4e
+            ]
+        );
+    }
+
+    #[kunit]
+    fn deterministic_seed_gives_known_output() {
+        let mut rng = ChaChaRng::from_seed([0; SEED_SIZE]);
+
+        // The first word is the start of the all-zero ChaCha20 keystream.
+        assert_eq!(rng.next_u64(), 0x903d_f1a0_ade0_b876);
+        assert_eq!(rng.next_u64(), 0x4d81_c86c_a003_e062);
+    }
+
+    #[kunit]
+    fn fill_bytes_spans_blocks() {
+        let mut rng = ChaChaRng::from_seed(counting_seed());
+        let mut bytes = [0u8; 70];
+        rng.fill_bytes(&mut bytes);
+
+        assert_eq!(&bytes[..4], &[0x39, 0xfd, 0x2b, 0x7d]);
+        assert_eq!(&bytes[64..], &[0x18, 0xb8, 0x42, 0x31, 0xad, 0xe6]);
+    }
+
+    #[kunit]
+    fn output_never_repeats_after_rekeying() {
+        let mut first = ChaChaRng::from_seed([7; SEED_SIZE]);
+        let mut second = ChaChaRng::from_seed([7; SEED_SIZE]);
+
+        assert_eq!(first.next_u64(), second.next_u64());
+        assert_ne!(first.next_u64(), first.next_u64());
+    }
+
+    #[kunit]
+    fn reseeding_changes_the_stream() {
+        let mut plain = ChaChaRng::from_seed([7; SEED_SIZE]);
+        let mut reseeded = ChaChaRng::from_seed([7; SEED_SIZE]);
+        reseeded.reseed(&[1, 2, 3]);
+
+        assert_ne!(plain.next_u64(), reseeded.next_u64());
+    }
+}
@@ -0,0 +1,118 @@
+pub mod chacha;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::fs::devfs::{self, Device};
+use crate::fs::vfs::FsError;
+
+pub use chacha::{ChaChaRng, SEED_SIZE};
+
+/// The kernel CSPRNG. Output is predictable until `init` has mixed in a seed.
+static RNG: Mutex<ChaChaRng> = Mutex::new(ChaChaRng::from_seed([0; SEED_SIZE]));
+static SEEDED: AtomicBool = AtomicBool::new(false);
+
+/// Timer samples mixed in on every seeding, whatever other sources exist.
+const JITTER_SAMPLES: usize = 64;
+
+/// Seed the kernel CSPRNG from CPU instructions, a virtio-rng device and timer jitter.
+pub fn init() {
+    let mut seed = [0u8; SEED_SIZE];
+    let mut sources: Vec<&str> = Vec::new();
+
+    if let Some(name) = arch::hardware_source() {
+        let mut filled = 0;
+        for chunk in seed.chunks_mut(8) {
+            let Some(value) = arch::hardware_u64() else {
+                break;
+            };
+            chunk.copy_from_slice(&value.to_le_bytes());
+            filled += chunk.len();
+        }
+        if filled == SEED_SIZE {
+            sources.push(name);
+        }
+    }
+    add_entropy(&seed);
+
+    if crate::dev::rng::read_entropy(&mut seed) == SEED_SIZE {
+        sources.push("virtio-rng");
+    }
+    add_entropy(&seed);
+    seed.fill(0);
+
+    add_entropy(&jitter_sample());
+
+    if sources.is_empty() {
+        crate::warn_ln!("random: no hardware entropy, seeded from timer jitter only");
+    } else {
+        crate::info_ln!("random: seeded from {}", sources.join(", "));
+    }
+    SEEDED.store(true, Ordering::Relaxed);
+
+    if let Err(error) = devfs::register("urandom", Arc::new(Urandom)) {
+        crate::warn_ln!("random: failed to register urandom: {:?}", error);
+    }
+}
+
+/// The CSPRNG as a device: reads return random bytes and writes are mixed in as entropy.
+struct Urandom;
+
+impl Device for Urandom {
+    fn mode(&self) -> u32 {
+        0o666
+    }
+
+    fn read(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, FsError> {
+        fill_bytes(buffer);
+        Ok(buffer.len())
+    }
+
+    fn write(&self, _offset: u64, buffer: &[u8]) -> Result<usize, FsError> {
+        add_entropy(buffer);
+        Ok(buffer.len())
+    }
+}
+
+/// Whether the CSPRNG has been seeded since boot.
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::Relaxed)
+}
+
+/// Mix caller-supplied entropy into the CSPRNG state.
+pub fn add_entropy(entropy: &[u8]) {
+    RNG.lock().reseed(entropy);
+}
+
+/// Fill `dest` with cryptographically secure random bytes.
+pub fn fill_bytes(dest: &mut [u8]) {
+    RNG.lock().fill_bytes(dest);
+}
+
+pub fn next_u64() -> u64 {
+    RNG.lock().next_u64()
+}
+
+/// Collect the low bits of back-to-back cycle counter reads.
+fn jitter_sample() -> [u8; JITTER_SAMPLES] {
+    let mut sample = [0u8; JITTER_SAMPLES];
+    let mut previous = arch::timestamp();
+    for byte in sample.iter_mut() {
+        let now = arch::timestamp();
+        *byte = (now.wrapping_sub(previous) ^ now) as u8;
+        previous = now;
+    }
+    sample
+}
@@ -0,0 +1,93 @@
+use core::arch::asm;
+
+/// Intel recommends retrying RDRAND a handful of times before treating it as failed.
+const RETRIES: usize = 10;
+
+fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx);
+    unsafe {
+        // RBX is reserved by LLVM, so swap it through a scratch register.
+        asm!(
+            "mov {scratch:r}, rbx",
+            "cpuid",
+            "xchg {scratch:r}, rbx",
+            scratch = out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    (eax, ebx, ecx, edx)
+}
+
+fn has_rdrand() -> bool {
+    let (_, _, ecx, _) = cpuid(1, 0);
+    ecx & (1 << 30) != 0
+}
+
+fn has_rdseed() -> bool {
+    let (max_leaf, _, _, _) = cpuid(0, 0);
+    max_leaf >= 7 && cpuid(7, 0).1 & (1 << 18) != 0
+}
+
+fn rdrand() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let (value, ok): (u64, u8);
+        unsafe {
+            asm!("rdrand {value}", "setc {ok}", value = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn rdseed() -> Option<u64> {
+    for _ in 0..RETRIES {
+        let (value, ok): (u64, u8);
+        unsafe {
+            asm!("rdseed {value}", "setc {ok}", value = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Name the best instruction available for seeding, if any.
+pub fn hardware_source() -> Option<&'static str> {
+    if has_rdseed() {
+        Some("rdseed")
+    } else if has_rdrand() {
+        Some("rdrand")
+    } else {
+        None
+    }
+}
+
+/// Read 64 bits from the CPU random number generator, preferring the unwhitened RDSEED.
+pub fn hardware_u64() -> Option<u64> {
+    if has_rdseed()
+        && let Some(value) = rdseed()
+    {
+        return Some(value);
+    }
+    if has_rdrand() {
+        rdrand()
+    } else {
+        None
+    }
+}
+
+/// A fast cycle counter whose low bits jitter between reads.
+pub fn timestamp() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
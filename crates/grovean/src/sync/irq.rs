@@ -0,0 +1,121 @@
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+use super::ticket::{TicketLock, TicketLockGuard};
+use crate::interrupts;
+
+/// A ticket lock that also disables interrupts on the holding CPU, for data interrupt
+/// handlers share with other code. The interrupt state is restored when the guard drops.
+pub struct IrqSpinLock<T: ?Sized> {
+    inner: TicketLock<T>,
+}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<TicketLockGuard<'a, T>>,
+    /// Whether interrupts were enabled before locking.
+    enabled: bool,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: TicketLock::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqSpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSpinLockGuard {
+                guard: ManuallyDrop::new(guard),
+                enabled,
+            }),
+            None => {
+                if enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for IrqSpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::IrqSpinLock;
+    use crate::interrupts;
+
+    #[kunit]
+    fn interrupts_stay_off_while_held_and_come_back_after() {
+        let lock = IrqSpinLock::new(0);
+        let before = interrupts::are_enabled();
+        {
+            let mut guard = lock.lock();
+            *guard = 7;
+            assert!(!interrupts::are_enabled());
+            assert!(lock.try_lock().is_none());
+            assert!(!interrupts::are_enabled());
+        }
+        assert_eq!(interrupts::are_enabled(), before);
+        assert_eq!(*lock.lock(), 7);
+    }
+}
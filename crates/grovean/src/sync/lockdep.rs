@@ -0,0 +1,191 @@
+use core::panic::Location;
+
+/// Lock orderings remembered; later new orderings go unchecked.
+#[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+const MAX_EDGES: usize = 256;
+/// Locks one CPU holds at once that are checked; deeper nesting goes unchecked.
+#[cfg(feature = "lock-debug")]
+const MAX_HELD: usize = 16;
+/// How many locks deep a search for the opposite ordering looks.
+#[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+const MAX_DEPTH: usize = 8;
+
+type Caller = &'static Location<'static>;
+
+/// Note that the calling CPU is about to take `lock`, reporting an inversion when the locks it
+/// holds were ever taken after `lock`. Only checked with the `lock-debug` feature.
+#[inline]
+pub fn acquire(lock: usize, caller: Caller) {
+    #[cfg(feature = "lock-debug")]
+    checker::acquire(lock, caller);
+    #[cfg(not(feature = "lock-debug"))]
+    let _ = (lock, caller);
+}
+
+/// Note that the calling CPU released `lock`.
+#[inline]
+pub fn release(lock: usize) {
+    #[cfg(feature = "lock-debug")]
+    checker::release(lock);
+    #[cfg(not(feature = "lock-debug"))]
+    let _ = lock;
+}
+
+/// One lock taken while another was held, and where that first happened.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+struct Edge {
+    outer: usize,
+    inner: usize,
+    caller: Caller,
+}
+
+/// Every ordering between locks seen so far, kept without allocating so locks taken before
+/// the heap exists are checked too.
+#[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+struct OrderGraph {
+    edges: [Option<Edge>; MAX_EDGES],
+    len: usize,
+}
+
+#[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+impl OrderGraph {
+    const fn new() -> Self {
+        Self {
+            edges: [None; MAX_EDGES],
+            len: 0,
+        }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges[..self.len].iter().flatten()
+    }
+
+    /// Record that `inner` was taken while holding `outer`. Returns where the opposite
+    /// ordering was first seen if `outer` was ever taken after `inner`, directly or through
+    /// other locks. Each ordering is checked once.
+    fn record(&mut self, outer: usize, inner: usize, caller: Caller) -> Option<Caller> {
+        if outer == inner
+            || self
+                .edges()
+                .any(|edge| edge.outer == outer && edge.inner == inner)
+        {
+            return None;
+        }
+        let inversion = self.path(inner, outer, MAX_DEPTH);
+        if self.len < MAX_EDGES {
+            self.edges[self.len] = Some(Edge {
+                outer,
+                inner,
+                caller,
+            });
+            self.len += 1;
+        }
+        inversion
+    }
+
+    /// Where the first step of a chain of orderings from `from` to `to` was seen.
+    fn path(&self, from: usize, to: usize, depth: usize) -> Option<Caller> {
+        if depth == 0 {
+            return None;
+        }
+        self.edges()
+            .filter(|edge| edge.outer == from)
+            .find(|edge| edge.inner == to || self.path(edge.inner, to, depth - 1).is_some())
+            .map(|edge| edge.caller)
+    }
+}
+
+#[cfg(feature = "lock-debug")]
+mod checker {
+    use core::cell::RefCell;
+    use spin::Mutex;
+
+    use super::{Caller, OrderGraph, MAX_HELD};
+    use crate::interrupts;
+
+    static GRAPH: Mutex<OrderGraph> = Mutex::new(OrderGraph::new());
+
+    struct HeldLocks {
+        locks: [usize; MAX_HELD],
+        len: usize,
+    }
+
+    crate::cpu_local! {
+        static HELD: RefCell<HeldLocks> = RefCell::new(HeldLocks {
+            locks: [0; MAX_HELD],
+            len: 0,
+        });
+    }
+
+    pub fn acquire(lock: usize, caller: Caller) {
+        let inversion = interrupts::without_interrupts(|| {
+            HELD.with(|held| {
+                let mut held = held.borrow_mut();
+                let mut graph = GRAPH.lock();
+                let mut inversion = None;
+                for &outer in &held.locks[..held.len] {
+                    inversion = inversion.or(graph.record(outer, lock, caller));
+                }
+                if held.len < MAX_HELD {
+                    let len = held.len;
+                    held.locks[len] = lock;
+                    held.len += 1;
+                }
+                inversion
+            })
+        });
+        // Reported with nothing borrowed, since reporting takes locks of its own.
+        if let Some(earlier) = inversion {
+            crate::warn_ln!(
+                "lockdep: lock order inversion at {}, the opposite order was taken at {}",
+                caller,
+                earlier
+            );
+        }
+    }
+
+    pub fn release(lock: usize) {
+        interrupts::without_interrupts(|| {
+            HELD.with(|held| {
+                let mut held = held.borrow_mut();
+                let len = held.len;
+                if let Some(index) = held.locks[..len].iter().rposition(|&held| held == lock) {
+                    held.locks.copy_within(index + 1..len, index);
+                    held.len -= 1;
+                }
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::panic::Location;
+    use kunit::kunit;
+
+    use super::OrderGraph;
+
+    #[kunit]
+    fn opposite_orders_are_reported_once() {
+        let mut graph = OrderGraph::new();
+        let first = Location::caller();
+        assert_eq!(graph.record(1, 2, first), None);
+        assert_eq!(graph.record(1, 2, first), None);
+
+        let reported = graph.record(2, 1, Location::caller());
+        assert_eq!(reported.map(|caller| caller.line()), Some(first.line()));
+        assert_eq!(graph.record(2, 1, Location::caller()), None);
+    }
+
+    #[kunit]
+    fn inversions_through_other_locks_are_found() {
+        let mut graph = OrderGraph::new();
+        let caller = Location::caller();
+        graph.record(1, 2, caller);
+        graph.record(2, 3, caller);
+        assert_eq!(graph.record(3, 4, caller), None);
+        assert!(graph.record(3, 1, caller).is_some());
+        assert_eq!(graph.record(5, 5, caller), None);
+    }
+}
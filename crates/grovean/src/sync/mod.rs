@@ -0,0 +1,12 @@
+mod irq;
+mod lockdep;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod ticket;
+
+pub use irq::{IrqSpinLock, IrqSpinLockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use ticket::{TicketLock, TicketLockGuard};
@@ -0,0 +1,236 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::lockdep;
+use crate::interrupts;
+
+/// Set while a writer holds the lock.
+const WRITER: usize = 1 << 0;
+/// Set while a writer waits, which keeps new readers out so writers are not starved.
+const WRITER_WAITING: usize = 1 << 1;
+/// Each reader adds this; the bits above the flags count readers.
+const READER: usize = 1 << 2;
+
+/// A spinning lock that any number of readers or one writer can hold. Like `IrqSpinLock`, it
+/// disables interrupts on the holding CPU, so interrupt handlers may take it too.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    enabled: bool,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    enabled: bool,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let enabled = disable_interrupts();
+        lockdep::acquire(self.id(), Location::caller());
+        while !self.try_take_read() {
+            core::hint::spin_loop();
+        }
+        RwLockReadGuard {
+            lock: self,
+            enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let enabled = disable_interrupts();
+        if !self.try_take_read() {
+            restore_interrupts(enabled);
+            return None;
+        }
+        lockdep::acquire(self.id(), Location::caller());
+        Some(RwLockReadGuard {
+            lock: self,
+            enabled,
+        })
+    }
+
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let enabled = disable_interrupts();
+        lockdep::acquire(self.id(), Location::caller());
+        while !self.try_take_write() {
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+        RwLockWriteGuard {
+            lock: self,
+            enabled,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let enabled = disable_interrupts();
+        if !self.try_take_write() {
+            restore_interrupts(enabled);
+            return None;
+        }
+        lockdep::acquire(self.id(), Location::caller());
+        Some(RwLockWriteGuard {
+            lock: self,
+            enabled,
+        })
+    }
+
+    /// Number of readers holding the lock.
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn try_take_read(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state & (WRITER | WRITER_WAITING) == 0
+            && self
+                .state
+                .compare_exchange(state, state + READER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    /// Take the lock once it is free, clearing the waiting flag other writers may have set;
+    /// they set it again while they keep spinning.
+    fn try_take_write(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state & !WRITER_WAITING == 0
+            && self
+                .state
+                .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+fn disable_interrupts() -> bool {
+    let enabled = interrupts::are_enabled();
+    interrupts::disable();
+    enabled
+}
+
+fn restore_interrupts(enabled: bool) {
+    if enabled {
+        interrupts::enable();
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+        restore_interrupts(self.enabled);
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+        restore_interrupts(self.enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+    use kunit::kunit;
+
+    use super::{RwLock, WRITER_WAITING};
+
+    #[kunit]
+    fn readers_share_and_writers_exclude() {
+        let lock = RwLock::new(5);
+        {
+            let first = lock.read();
+            let second = lock.try_read().expect("readers share the lock");
+            assert_eq!(*first + *second, 10);
+            assert_eq!(lock.reader_count(), 2);
+            assert!(lock.try_write().is_none());
+        }
+        {
+            let mut writer = lock.write();
+            *writer = 6;
+            assert!(lock.is_write_locked());
+            assert!(lock.try_read().is_none());
+        }
+        assert_eq!(lock.reader_count(), 0);
+        assert_eq!(*lock.read(), 6);
+    }
+
+    #[kunit]
+    fn a_waiting_writer_keeps_new_readers_out() {
+        let lock = RwLock::new(());
+        let reader = lock.read();
+        assert!(lock.try_write().is_none());
+        lock.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+        assert!(lock.try_read().is_none());
+        drop(reader);
+        assert!(lock.try_write().is_some());
+    }
+}
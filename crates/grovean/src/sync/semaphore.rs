@@ -0,0 +1,74 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::task::WaitQueue;
+
+/// Counts available units of some resource, blocking tasks that want one while none are left.
+/// `release` never blocks, so interrupt handlers may signal with it.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Block until a permit is available, then take it.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.waiters
+                .wait_until(|| self.permits.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+        false
+    }
+
+    /// Return a permit, waking a task waiting for one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::Semaphore;
+
+    #[kunit]
+    fn permits_run_out_and_come_back() {
+        let semaphore = Semaphore::new(2);
+        semaphore.acquire();
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.available(), 0);
+
+        semaphore.release();
+        assert_eq!(semaphore.available(), 1);
+        semaphore.acquire();
+        assert_eq!(semaphore.available(), 0);
+    }
+}
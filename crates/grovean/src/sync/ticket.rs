@@ -0,0 +1,130 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::lockdep;
+use crate::cpu::{self, PreemptGuard};
+
+/// A fair spinlock: CPUs get the lock in the order they asked for it. The holder cannot be
+/// preempted, so it never sleeps while others spin, but interrupts stay enabled; data an
+/// interrupt handler takes too belongs in an `IrqSpinLock`.
+pub struct TicketLock<T: ?Sized> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for TicketLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for TicketLock<T> {}
+
+pub struct TicketLockGuard<'a, T: ?Sized> {
+    lock: &'a TicketLock<T>,
+    /// Released after the lock, in field order.
+    _preempt: PreemptGuard,
+}
+
+impl<T> TicketLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> TicketLock<T> {
+    #[track_caller]
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        let preempt = cpu::disable_preemption();
+        lockdep::acquire(self.id(), Location::caller());
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+        TicketLockGuard {
+            lock: self,
+            _preempt: preempt,
+        }
+    }
+
+    /// Take the lock only if nobody holds or waits for it.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        let preempt = cpu::disable_preemption();
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(serving, serving + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        lockdep::acquire(self.id(), Location::caller());
+        Some(TicketLockGuard {
+            lock: self,
+            _preempt: preempt,
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Identifies the lock to the lock order checker.
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T: Default> Default for TicketLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::TicketLock;
+
+    #[kunit]
+    fn a_held_ticket_lock_turns_others_away() {
+        let lock = TicketLock::new(1);
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.is_locked());
+            assert!(lock.try_lock().is_none());
+        }
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.try_lock().expect("the lock is free"), 2);
+    }
+}
@@ -0,0 +1,280 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+mod user;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::sync::Arc;
+use alloc::vec;
+
+use crate::fs::vfs::{self, FsError, OpenFlags};
+use crate::interrupts;
+use crate::memory::paging::PageFlags;
+use crate::process::{self, Process};
+
+pub use user::{check_user, copy_from_user, copy_to_user};
+
+// System call numbers, passed in `rax` on x86_64 and `x8` on aarch64. Arguments go in `rdi`,
+// `rsi`, `rdx`, `r10`, `r8` and `r9`, or `x0` to `x5`, and the result comes back in `rax` or
+// `x0`: a value, or a negated `Errno` code.
+pub const READ: usize = 0;
+pub const WRITE: usize = 1;
+pub const OPEN: usize = 2;
+pub const CLOSE: usize = 3;
+pub const MMAP: usize = 4;
+pub const EXIT: usize = 5;
+pub const GETPID: usize = 6;
+
+/// Flags `open` takes, the bits of `OpenFlags` in the same order.
+pub const OPEN_READ: usize = 1 << 0;
+pub const OPEN_WRITE: usize = 1 << 1;
+pub const OPEN_CREATE: usize = 1 << 2;
+pub const OPEN_EXCLUSIVE: usize = 1 << 3;
+pub const OPEN_TRUNCATE: usize = 1 << 4;
+pub const OPEN_APPEND: usize = 1 << 5;
+pub const OPEN_DIRECTORY: usize = 1 << 6;
+
+/// Protection `mmap` takes. Mapped memory is always readable.
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+/// Longest path `open` takes.
+const MAX_PATH: usize = 4096;
+/// Most bytes `read` and `write` move through a kernel buffer at a time.
+const TRANSFER_CHUNK: usize = 4096;
+
+type Handler = fn([usize; 6]) -> Result<usize, Errno>;
+
+/// Handlers by system call number.
+const TABLE: [Handler; 7] = [
+    sys_read, sys_write, sys_open, sys_close, sys_mmap, sys_exit, sys_getpid,
+];
+
+/// Why a system call failed, with the codes Linux uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    NoEntry = 2,
+    NoSuchProcess = 3,
+    Io = 5,
+    BadDescriptor = 9,
+    NoMemory = 12,
+    BadAddress = 14,
+    Busy = 16,
+    Exists = 17,
+    NotADirectory = 20,
+    IsADirectory = 21,
+    InvalidArgument = 22,
+    NoSpace = 28,
+    ReadOnly = 30,
+    NameTooLong = 36,
+    NoSystemCall = 38,
+    NotEmpty = 39,
+    TooManySymlinks = 40,
+    NotSupported = 95,
+}
+
+impl Errno {
+    /// What the system call returns to user mode.
+    pub fn code(self) -> isize {
+        -(self as isize)
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => Self::NoEntry,
+            FsError::AlreadyExists => Self::Exists,
+            FsError::NotADirectory => Self::NotADirectory,
+            FsError::IsADirectory => Self::IsADirectory,
+            FsError::NotEmpty => Self::NotEmpty,
+            FsError::InvalidPath | FsError::InvalidArgument => Self::InvalidArgument,
+            FsError::TooManySymlinks => Self::TooManySymlinks,
+            FsError::ReadOnly => Self::ReadOnly,
+            FsError::NoSpace => Self::NoSpace,
+            FsError::Busy => Self::Busy,
+            FsError::NotSupported => Self::NotSupported,
+            FsError::BadDescriptor => Self::BadDescriptor,
+            FsError::NameTooLong => Self::NameTooLong,
+            FsError::Corrupt | FsError::Io(_) => Self::Io,
+        }
+    }
+}
+
+/// Set up the system call instruction on the calling CPU.
+pub fn init() {
+    arch::init();
+}
+
+/// Run system call `number` for the current process, with interrupts enabled so it can block
+/// and be preempted. Entered with interrupts disabled, and returns with them disabled.
+pub fn dispatch(number: usize, arguments: [usize; 6]) -> isize {
+    interrupts::enable();
+    let result = call(number, arguments);
+    interrupts::disable();
+    match result {
+        Ok(value) => value as isize,
+        Err(error) => error.code(),
+    }
+}
+
+fn call(number: usize, arguments: [usize; 6]) -> Result<usize, Errno> {
+    let handler = TABLE.get(number).ok_or(Errno::NoSystemCall)?;
+    handler(arguments)
+}
+
+/// The calling process. Handlers hold it only while they run, so `exit` leaves no reference
+/// behind.
+fn current() -> Result<Arc<Process>, Errno> {
+    process::current().ok_or(Errno::NoSuchProcess)
+}
+
+/// `read(descriptor, buffer, length)`: read up to `length` bytes, returning how many.
+fn sys_read([descriptor, buffer, length, ..]: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    let file = process.file(descriptor).ok_or(Errno::BadDescriptor)?;
+    let mut chunk = vec![0; length.min(TRANSFER_CHUNK)];
+    // Checked before reading, so a bad buffer does not swallow input.
+    check_user(&process, buffer, chunk.len(), true)?;
+    let read = file.read(&mut chunk)?;
+    copy_to_user(&process, buffer, &chunk[..read])?;
+    Ok(read)
+}
+
+/// `write(descriptor, buffer, length)`: write `length` bytes, returning how many were taken.
+fn sys_write([descriptor, buffer, length, ..]: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    let file = process.file(descriptor).ok_or(Errno::BadDescriptor)?;
+    let mut chunk = vec![0; length.min(TRANSFER_CHUNK)];
+    let mut written = 0;
+    while written < length {
+        let size = (length - written).min(TRANSFER_CHUNK);
+        copy_from_user(&process, buffer + written, &mut chunk[..size])?;
+        match file.write(&chunk[..size]) {
+            Ok(count) => {
+                written += count;
+                if count < size {
+                    break;
+                }
+            }
+            Err(error) if written == 0 => return Err(error.into()),
+            Err(_) => break,
+        }
+    }
+    Ok(written)
+}
+
+/// `open(path, path_length, flags)`: open the file at the UTF-8 `path`, returning its
+/// descriptor.
+fn sys_open([path, length, flags, ..]: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    if length > MAX_PATH {
+        return Err(Errno::NameTooLong);
+    }
+    let mut bytes = vec![0; length];
+    copy_from_user(&process, path, &mut bytes)?;
+    let path = core::str::from_utf8(&bytes).map_err(|_| Errno::InvalidArgument)?;
+    let file = vfs::vfs().open(path, open_flags(flags)?)?;
+    Ok(process.install_file(file))
+}
+
+/// `close(descriptor)`.
+fn sys_close([descriptor, ..]: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    process.close_file(descriptor).ok_or(Errno::BadDescriptor)?;
+    Ok(0)
+}
+
+/// `mmap(address, length, protection)`: map `length` bytes of zeroed memory, returning where.
+/// Only the kernel chooses addresses, so `address` must be zero.
+fn sys_mmap([address, length, protection, ..]: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    if address != 0 || length == 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    // Nothing is mapped unless the whole length fits the mapping area and the free memory.
+    let address = process
+        .map_anonymous(length as u64, page_flags(protection)?)
+        .map_err(|_| Errno::NoMemory)?;
+    Ok(address as usize)
+}
+
+/// `exit(code)`: end the process. Never returns.
+fn sys_exit([code, ..]: [usize; 6]) -> Result<usize, Errno> {
+    process::exit(code as i32)
+}
+
+/// `getpid()`.
+fn sys_getpid(_: [usize; 6]) -> Result<usize, Errno> {
+    let process = current()?;
+    Ok(process.pid() as usize)
+}
+
+fn open_flags(flags: usize) -> Result<OpenFlags, Errno> {
+    const FLAGS: [(usize, OpenFlags); 7] = [
+        (OPEN_READ, OpenFlags::READ),
+        (OPEN_WRITE, OpenFlags::WRITE),
+        (OPEN_CREATE, OpenFlags::CREATE),
+        (OPEN_EXCLUSIVE, OpenFlags::EXCLUSIVE),
+        (OPEN_TRUNCATE, OpenFlags::TRUNCATE),
+        (OPEN_APPEND, OpenFlags::APPEND),
+        (OPEN_DIRECTORY, OpenFlags::DIRECTORY),
+    ];
+    let known = FLAGS.iter().fold(0, |known, &(bit, _)| known | bit);
+    if flags & !known != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    Ok(FLAGS
+        .iter()
+        .filter(|&&(bit, _)| flags & bit != 0)
+        .fold(OpenFlags::default(), |open, &(_, flag)| open.union(flag)))
+}
+
+fn page_flags(protection: usize) -> Result<PageFlags, Errno> {
+    if protection & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::InvalidArgument);
+    }
+    let mut flags = PageFlags::READ;
+    if protection & PROT_WRITE != 0 {
+        flags = flags.union(PageFlags::WRITE);
+    }
+    if protection & PROT_EXEC != 0 {
+        flags = flags.union(PageFlags::EXECUTE);
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use kunit::kunit;
+
+    use super::{
+        open_flags, page_flags, Errno, OPEN_CREATE, OPEN_READ, OPEN_WRITE, PROT_READ, PROT_WRITE,
+    };
+    use crate::fs::vfs::{FsError, OpenFlags};
+    use crate::memory::paging::PageFlags;
+
+    #[kunit]
+    fn errors_reach_user_mode_as_negated_codes() {
+        assert_eq!(Errno::from(FsError::NotFound).code(), -2);
+        assert_eq!(Errno::from(FsError::ReadOnly), Errno::ReadOnly);
+        assert_eq!(Errno::NoSystemCall.code(), -38);
+    }
+
+    #[kunit]
+    fn user_flags_translate_to_kernel_ones() {
+        assert_eq!(
+            open_flags(OPEN_READ | OPEN_WRITE | OPEN_CREATE),
+            Ok(OpenFlags::READ_WRITE.union(OpenFlags::CREATE))
+        );
+        assert_eq!(open_flags(1 << 20), Err(Errno::InvalidArgument));
+        assert_eq!(page_flags(PROT_READ | PROT_WRITE), Ok(PageFlags::WRITE));
+        assert_eq!(page_flags(1 << 3), Err(Errno::InvalidArgument));
+    }
+}
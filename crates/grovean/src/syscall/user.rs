@@ -0,0 +1,136 @@
+use super::Errno;
+use crate::memory::hhdm;
+use crate::memory::paging::{PagingError, PAGE_SIZE, USER_END};
+use crate::process::Process;
+
+/// Copy `buffer.len()` bytes from `address` in `process`'s address space, failing with
+/// `BadAddress` unless every byte is mapped for user code.
+pub fn copy_from_user(process: &Process, address: usize, buffer: &mut [u8]) -> Result<(), Errno> {
+    let length = buffer.len();
+    for_each_page(
+        process,
+        address,
+        length,
+        false,
+        |offset, phys, chunk| unsafe {
+            core::ptr::copy_nonoverlapping(
+                hhdm::phys_to_virt(phys) as *const u8,
+                buffer[offset..].as_mut_ptr(),
+                chunk,
+            );
+        },
+    )
+}
+
+/// Copy `data` to `address` in `process`'s address space, failing with `BadAddress` unless
+/// every byte is mapped writable for user code. Nothing is written then.
+pub fn copy_to_user(process: &Process, address: usize, data: &[u8]) -> Result<(), Errno> {
+    for_each_page(
+        process,
+        address,
+        data.len(),
+        true,
+        |offset, phys, chunk| unsafe {
+            core::ptr::copy_nonoverlapping(
+                data[offset..].as_ptr(),
+                hhdm::phys_to_virt(phys) as *mut u8,
+                chunk,
+            );
+        },
+    )
+}
+
+/// Check that the `length` bytes at `address` in `process`'s address space are mapped for user
+/// code, and writable too if `write` is set, without touching them.
+pub fn check_user(
+    process: &Process,
+    address: usize,
+    length: usize,
+    write: bool,
+) -> Result<(), Errno> {
+    for_each_page(process, address, length, write, |_, _, _| {})
+}
+
+/// Check the `length` bytes at `address` first, then call `copy` on each piece within one page
+/// with its offset from `address`, the physical address it is mapped to, and its length.
+/// The page tables are walked through the direct map, so the copy works whichever address
+/// space is active and never faults.
+fn for_each_page(
+    process: &Process,
+    address: usize,
+    length: usize,
+    write: bool,
+    mut copy: impl FnMut(usize, u64, usize),
+) -> Result<(), Errno> {
+    check_range(address, length)?;
+    process
+        .with_address_space(|address_space| {
+            let check = |(virt, _, _)| address_space.translate_for(virt, write).is_some();
+            if !pieces(address as u64, length).all(check) {
+                return Err(PagingError::NotMapped);
+            }
+            for (virt, offset, chunk) in pieces(address as u64, length) {
+                let phys = address_space
+                    .translate_for(virt, write)
+                    .ok_or(PagingError::NotMapped)?;
+                copy(offset, phys, chunk);
+            }
+            Ok(())
+        })
+        .map_err(|_| Errno::BadAddress)
+}
+
+/// Whether the `length` bytes at `address` lie in the user half.
+fn check_range(address: usize, length: usize) -> Result<(), Errno> {
+    match (address as u64).checked_add(length as u64) {
+        Some(end) if end <= USER_END => Ok(()),
+        _ => Err(Errno::BadAddress),
+    }
+}
+
+/// Split the `length` bytes at `address` at page boundaries, as each piece's address, offset
+/// from `address`, and length.
+fn pieces(address: u64, length: usize) -> impl Iterator<Item = (u64, usize, usize)> {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        if offset >= length {
+            return None;
+        }
+        let virt = address + offset as u64;
+        let chunk = ((PAGE_SIZE - virt % PAGE_SIZE) as usize).min(length - offset);
+        let piece = (virt, offset, chunk);
+        offset += chunk;
+        Some(piece)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{check_range, pieces, Errno};
+    use crate::memory::paging::USER_END;
+
+    #[kunit]
+    fn buffers_split_at_page_boundaries() {
+        let split: Vec<_> = pieces(0x1ff0, 0x1020).collect();
+        assert_eq!(
+            split,
+            [
+                (0x1ff0, 0, 0x10),
+                (0x2000, 0x10, 0x1000),
+                (0x3000, 0x1010, 0x10)
+            ]
+        );
+        assert_eq!(pieces(0x1000, 0).count(), 0);
+    }
+
+    #[kunit]
+    fn kernel_addresses_are_refused() {
+        let end = USER_END as usize;
+        assert_eq!(check_range(end - 8, 8), Ok(()));
+        assert_eq!(check_range(end - 8, 9), Err(Errno::BadAddress));
+        assert_eq!(check_range(usize::MAX, 1), Err(Errno::BadAddress));
+    }
+}
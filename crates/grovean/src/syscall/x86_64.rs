@@ -0,0 +1,96 @@
+use core::arch::naked_asm;
+use x86_64::instructions::segmentation::{Segment, CS, SS};
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::VirtAddr;
+
+use crate::cpu::{SYSCALL_STACK_OFFSET, USER_CODE_SELECTOR, USER_DATA_SELECTOR, USER_STACK_OFFSET};
+
+/// The user registers `syscall_entry` saves on the kernel stack, in the order it pushes them
+/// from the bottom up. The result goes back to user mode in `rax`.
+#[repr(C)]
+struct SyscallFrame {
+    rax: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    r10: u64,
+    r8: u64,
+    r9: u64,
+    /// The user RFLAGS, which `syscall` leaves in `r11` and `sysret` restores.
+    r11: u64,
+    /// The user instruction pointer, which `syscall` leaves in `rcx` and `sysret` returns to.
+    rcx: u64,
+    rsp: u64,
+}
+
+/// Point `syscall` at `syscall_entry` on the calling CPU, with interrupts and single stepping
+/// off until the kernel stack is in place.
+pub fn init() {
+    unsafe {
+        Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS);
+    }
+    LStar::write(VirtAddr::from_ptr(syscall_entry as *const ()));
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    Star::write(
+        SegmentSelector(USER_CODE_SELECTOR),
+        SegmentSelector(USER_DATA_SELECTOR),
+        CS::get_reg(),
+        SS::get_reg(),
+    )
+    .expect("the GDT puts the user segments where sysret expects them");
+}
+
+/// Where `syscall` lands: swap in the kernel's GS base, switch to the task's kernel stack
+/// through the CPU-local block, save the user registers the call may change, dispatch, and
+/// swap the user's GS base back for `sysret` with everything but `rax` as it was.
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_stack}]",
+        "push rcx",
+        "push r11",
+        "push r9",
+        "push r8",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rax",
+        "mov rdi, rsp",
+        "call {handle}",
+        "cli",
+        "swapgs",
+        "pop rax",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop r10",
+        "pop r8",
+        "pop r9",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        // `rcx` is canonical: it follows a `syscall`, and the last user page is never mapped.
+        "sysretq",
+        user_stack = const USER_STACK_OFFSET,
+        kernel_stack = const SYSCALL_STACK_OFFSET,
+        handle = sym handle,
+    )
+}
+
+extern "C" fn handle(frame: &mut SyscallFrame) {
+    let arguments = [
+        frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+    ];
+    frame.rax = super::dispatch(frame.rax as usize, arguments.map(|value| value as usize)) as u64;
+}
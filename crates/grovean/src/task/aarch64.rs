@@ -0,0 +1,59 @@
+use core::arch::naked_asm;
+
+/// Bytes `switch_context` keeps on a stack that is switched out: x19 to x30, then d8 to d15.
+const SAVED_BYTES: usize = 160;
+/// Offset of the saved link register, where a switch returns to.
+const LINK_REGISTER_OFFSET: usize = 88;
+
+/// Save the callee-saved registers on the stack, store the stack pointer to `from`, then load
+/// `to` and resume whatever was switched out there.
+///
+/// # Safety
+///
+/// `to` must come from `initial_stack_pointer` or an earlier `switch_context`, and interrupts
+/// must be masked.
+#[unsafe(naked)]
+pub unsafe extern "C" fn switch_context(from: *mut usize, to: usize) {
+    naked_asm!(
+        "sub sp, sp, #160",
+        "stp x19, x20, [sp, #0]",
+        "stp x21, x22, [sp, #16]",
+        "stp x23, x24, [sp, #32]",
+        "stp x25, x26, [sp, #48]",
+        "stp x27, x28, [sp, #64]",
+        "stp x29, x30, [sp, #80]",
+        "stp d8, d9, [sp, #96]",
+        "stp d10, d11, [sp, #112]",
+        "stp d12, d13, [sp, #128]",
+        "stp d14, d15, [sp, #144]",
+        "mov x9, sp",
+        "str x9, [x0]",
+        "mov sp, x1",
+        "ldp x19, x20, [sp, #0]",
+        "ldp x21, x22, [sp, #16]",
+        "ldp x23, x24, [sp, #32]",
+        "ldp x25, x26, [sp, #48]",
+        "ldp x27, x28, [sp, #64]",
+        "ldp x29, x30, [sp, #80]",
+        "ldp d8, d9, [sp, #96]",
+        "ldp d10, d11, [sp, #112]",
+        "ldp d12, d13, [sp, #128]",
+        "ldp d14, d15, [sp, #144]",
+        "add sp, sp, #160",
+        "ret",
+    )
+}
+
+/// Lay out a fresh stack so that switching to it calls `entry`, returning its stack pointer.
+pub fn initial_stack_pointer(top: usize, entry: extern "C" fn() -> !) -> usize {
+    let top = top & !0xf;
+    let stack_pointer = top - SAVED_BYTES;
+    let words = stack_pointer as *mut usize;
+    unsafe {
+        for index in 0..SAVED_BYTES / 8 {
+            words.add(index).write(0);
+        }
+        words.add(LINK_REGISTER_OFFSET / 8).write(entry as usize);
+    }
+    stack_pointer
+}
@@ -0,0 +1,466 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+mod queue;
+mod scheduler;
+mod wait_queue;
+mod work_queue;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::memory::frame::{self, FRAME_SIZE};
+use crate::memory::{hhdm, paging};
+use crate::{cpu, interrupts, time};
+use scheduler::SCHEDULER;
+
+pub use wait_queue::WaitQueue;
+pub use work_queue::{schedule_work, WorkQueue};
+
+/// Size of every task's kernel stack.
+pub const STACK_SIZE: usize = 64 * 1024;
+
+/// Marks a task that is not running on any CPU.
+const NO_CPU: usize = usize::MAX;
+/// Marks a task that runs no user process, and the kernel's own page tables.
+const NO_PROCESS: u64 = 0;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub type TaskId = u64;
+
+/// Ready tasks of a higher priority always run first; equal ones take turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Runs only when nothing else is ready, like the per-CPU idle tasks.
+    Idle,
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const COUNT: usize = 4;
+    pub const ALL: [Self; Self::COUNT] = [Self::Idle, Self::Low, Self::Normal, Self::High];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TaskState {
+    Ready,
+    Running,
+    Sleeping,
+    /// Waiting on a `WaitQueue`, or created and not yet queued.
+    Blocked,
+    Exited,
+}
+
+impl TaskState {
+    const ALL: [Self; 5] = [
+        Self::Ready,
+        Self::Running,
+        Self::Sleeping,
+        Self::Blocked,
+        Self::Exited,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Running => "running",
+            Self::Sleeping => "sleeping",
+            Self::Blocked => "blocked",
+            Self::Exited => "exited",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskError {
+    /// No frames were left for the task's stack.
+    OutOfMemory,
+}
+
+/// A task's kernel stack, in physically contiguous frames reached through the direct map.
+struct Stack {
+    phys: u64,
+}
+
+impl Stack {
+    fn new() -> Option<Self> {
+        let frames = STACK_SIZE / FRAME_SIZE as usize;
+        Some(Self {
+            phys: frame::allocate_contiguous_frames(frames)?,
+        })
+    }
+
+    fn top(&self) -> usize {
+        hhdm::phys_to_virt(self.phys) as usize + STACK_SIZE
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        frame::deallocate_frames(self.phys, STACK_SIZE / FRAME_SIZE as usize);
+    }
+}
+
+/// A kernel thread of execution with its own stack.
+pub struct Task {
+    id: TaskId,
+    name: String,
+    priority: Priority,
+    state: AtomicU8,
+    /// The CPU running the task, or `NO_CPU`.
+    cpu: AtomicUsize,
+    /// Where the registers were saved while the task is switched out. Only touched with the
+    /// scheduler locked.
+    stack_pointer: UnsafeCell<usize>,
+    /// `None` for the boot task, which keeps the stack the bootloader gave it.
+    stack: Mutex<Option<Stack>>,
+    /// Where the stack ends, or zero for the boot task.
+    stack_top: usize,
+    /// The user process the task runs, or `NO_PROCESS`.
+    process: AtomicU64,
+    /// The top-level page table of the process's address space, or `NO_PROCESS` for the
+    /// kernel's.
+    page_table_root: AtomicU64,
+    entry: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    /// Tasks joining this one.
+    exit: WaitQueue,
+}
+
+// The stack pointer is only accessed with the scheduler locked.
+unsafe impl Sync for Task {}
+
+impl Task {
+    fn new(
+        name: &str,
+        priority: Priority,
+        entry: Box<dyn FnOnce() + Send>,
+    ) -> Result<Arc<Self>, TaskError> {
+        let stack = Stack::new().ok_or(TaskError::OutOfMemory)?;
+        let stack_pointer = arch::initial_stack_pointer(stack.top(), start);
+        Ok(Arc::new(Self {
+            stack_pointer: UnsafeCell::new(stack_pointer),
+            stack_top: stack.top(),
+            stack: Mutex::new(Some(stack)),
+            entry: Mutex::new(Some(entry)),
+            ..Self::bare(name, priority)
+        }))
+    }
+
+    /// A task for code that is already running on a stack of its own.
+    fn adopt(name: &str, priority: Priority) -> Arc<Self> {
+        let task = Self::bare(name, priority);
+        task.set_state(TaskState::Running);
+        task.set_cpu(Some(cpu::current_id()));
+        Arc::new(task)
+    }
+
+    fn bare(name: &str, priority: Priority) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+            priority,
+            state: AtomicU8::new(TaskState::Blocked as u8),
+            cpu: AtomicUsize::new(NO_CPU),
+            stack_pointer: UnsafeCell::new(0),
+            stack: Mutex::new(None),
+            stack_top: 0,
+            process: AtomicU64::new(NO_PROCESS),
+            page_table_root: AtomicU64::new(NO_PROCESS),
+            entry: Mutex::new(None),
+            exit: WaitQueue::new(),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn state(&self) -> TaskState {
+        TaskState::ALL[self.state.load(Ordering::Acquire) as usize]
+    }
+
+    fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn cpu(&self) -> Option<usize> {
+        match self.cpu.load(Ordering::Relaxed) {
+            NO_CPU => None,
+            cpu => Some(cpu),
+        }
+    }
+
+    fn set_cpu(&self, cpu: Option<usize>) {
+        self.cpu.store(cpu.unwrap_or(NO_CPU), Ordering::Relaxed);
+    }
+
+    /// The user process the task runs, if any.
+    pub fn process(&self) -> Option<u64> {
+        match self.process.load(Ordering::Relaxed) {
+            NO_PROCESS => None,
+            pid => Some(pid),
+        }
+    }
+
+    /// Where the task's kernel stack ends, which user mode enters the kernel on.
+    pub fn stack_top(&self) -> Option<usize> {
+        (self.stack_top != 0).then_some(self.stack_top)
+    }
+
+    /// Load what the task needs on the CPU about to run it: its address space, and the kernel
+    /// stack interrupts in user mode arrive on. Called with interrupts disabled.
+    fn prepare_to_run(&self) {
+        match self.page_table_root.load(Ordering::Relaxed) {
+            NO_PROCESS => paging::activate(None),
+            root => paging::activate(Some(root)),
+        }
+        if let Some(top) = self.stack_top() {
+            cpu::set_kernel_stack(top);
+        }
+    }
+
+    /// Free the stack of a task that has exited and been switched away from for good.
+    fn release_stack(&self) {
+        drop(self.stack.lock().take());
+    }
+}
+
+/// A snapshot of one task, for listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,
+    pub state: TaskState,
+    pub cpu: Option<usize>,
+}
+
+/// Owns the right to wait for a task and take the value it returned.
+pub struct JoinHandle<T> {
+    task: Arc<Task>,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn task(&self) -> &Arc<Task> {
+        &self.task
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.state() == TaskState::Exited
+    }
+
+    /// Block until the task returns, then hand over what it returned.
+    pub fn join(self) -> T {
+        let task = &self.task;
+        task.exit.wait_until(|| task.state() == TaskState::Exited);
+        self.result
+            .lock()
+            .take()
+            .expect("an exited task leaves its result")
+    }
+}
+
+/// Start `f` on a kernel thread of its own.
+pub fn spawn<F, T>(name: &str, priority: Priority, f: F) -> Result<JoinHandle<T>, TaskError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let slot = result.clone();
+    let task = Task::new(
+        name,
+        priority,
+        Box::new(move || {
+            let value = f();
+            *slot.lock() = Some(value);
+        }),
+    )?;
+
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.tasks.insert(task.id, task.clone());
+        if scheduler.make_ready(task.clone()) {
+            scheduler::preempt(scheduler);
+        }
+    });
+    Ok(JoinHandle { task, result })
+}
+
+/// Let other ready tasks of the same or a higher priority run first.
+pub fn yield_now() {
+    interrupts::without_interrupts(|| {
+        let scheduler = SCHEDULER.lock();
+        if scheduler.current().is_some() {
+            scheduler::reschedule(scheduler);
+        }
+    });
+}
+
+/// Block the current task for at least `duration`, to the resolution of the timer tick.
+/// Before the scheduler starts this spins instead.
+pub fn sleep(duration: Duration) {
+    let wake_at = time::uptime() + duration;
+    let slept = interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let Some(current) = scheduler.current().cloned() else {
+            return false;
+        };
+        scheduler.sleep_until(current, wake_at);
+        scheduler::reschedule(scheduler);
+        true
+    });
+    if !slept {
+        while time::uptime() < wake_at {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// End the current task, waking whoever joins it.
+pub fn exit() -> ! {
+    interrupts::disable();
+    let current = current().expect("exit is called from a task");
+    current.set_state(TaskState::Exited);
+    current.exit.wake_all();
+    drop(current);
+    scheduler::reschedule(SCHEDULER.lock());
+    unreachable!("an exited task was scheduled again");
+}
+
+/// Make the current task run user process `pid`, in the address space whose top-level table
+/// is `root`, switching to that address space now.
+pub fn attach_process(pid: u64, root: u64) {
+    interrupts::without_interrupts(|| {
+        let current = current().expect("a process runs on a task");
+        current.process.store(pid, Ordering::Relaxed);
+        current.page_table_root.store(root, Ordering::Relaxed);
+        current.prepare_to_run();
+    });
+}
+
+/// Return the current task to the kernel's page tables, so its process's address space can be
+/// freed.
+pub fn detach_process() {
+    interrupts::without_interrupts(|| {
+        let current = current().expect("a process runs on a task");
+        current.process.store(NO_PROCESS, Ordering::Relaxed);
+        current.page_table_root.store(NO_PROCESS, Ordering::Relaxed);
+        current.prepare_to_run();
+    });
+}
+
+/// The task running on this CPU, or `None` before the scheduler starts.
+pub fn current() -> Option<Arc<Task>> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current().cloned())
+}
+
+/// Every task that has not exited, in creation order.
+pub fn tasks() -> Vec<TaskInfo> {
+    interrupts::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .tasks
+            .values()
+            .map(|task| TaskInfo {
+                id: task.id,
+                name: task.name.clone(),
+                priority: task.priority,
+                state: task.state(),
+                cpu: task.cpu(),
+            })
+            .collect()
+    })
+}
+
+/// Where every new task starts, entered from the `switch_context` of whoever ran before.
+extern "C" fn start() -> ! {
+    scheduler::finish_switch();
+    interrupts::enable();
+    let entry = current().and_then(|task| task.entry.lock().take());
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit()
+}
+
+/// Join the scheduler on an application processor, running its idle task until there is work.
+pub fn start_secondary() -> ! {
+    interrupts::disable();
+    scheduler::enter_idle(SCHEDULER.lock())
+}
+
+/// The timer interrupt: run the scheduler tick and expire kernel timers once it returns.
+fn timer_interrupt() {
+    time::timer::tick();
+    scheduler::tick();
+}
+
+fn idle() {
+    loop {
+        interrupts::disable();
+        interrupts::enable_and_wait();
+    }
+}
+
+/// Turn the boot code into the first task, give every CPU an idle task, start taking timer
+/// interrupts and start the system work queue.
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        for cpu in 0..cpu::count() {
+            let idle = Task::new(&format!("idle{cpu}"), Priority::Idle, Box::new(idle))
+                .expect("failed to allocate an idle task stack");
+            scheduler.tasks.insert(idle.id, idle.clone());
+            scheduler.cpus.get_mut(cpu).idle = Some(idle);
+        }
+        let boot = Task::adopt("boot", Priority::Normal);
+        scheduler.tasks.insert(boot.id, boot.clone());
+        scheduler.cpus.get_mut(cpu::current_id()).current = Some(boot);
+    });
+
+    time::timer::init();
+    interrupts::set_handler(interrupts::TIMER_VECTOR, "timer", timer_interrupt);
+    interrupts::enable();
+    work_queue::init();
+    crate::info_ln!(
+        "task: scheduler running on {} CPU(s), {} Hz tick",
+        cpu::count(),
+        interrupts::TIMER_HZ
+    );
+}
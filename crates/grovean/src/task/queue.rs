@@ -0,0 +1,110 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use core::time::Duration;
+
+use super::Priority;
+
+/// Tasks ready to run: the highest priority first, and round robin within a priority.
+pub struct RunQueue<T> {
+    levels: [VecDeque<T>; Priority::COUNT],
+}
+
+impl<T> RunQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            levels: [const { VecDeque::new() }; Priority::COUNT],
+        }
+    }
+
+    /// Queue `item` behind everything else at its priority.
+    pub fn push(&mut self, priority: Priority, item: T) {
+        self.levels[priority as usize].push_back(item);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.levels.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// The priority `pop` would take from next.
+    pub fn highest(&self) -> Option<Priority> {
+        Priority::ALL
+            .into_iter()
+            .rev()
+            .find(|&priority| !self.levels[priority as usize].is_empty())
+    }
+}
+
+/// Sleeping tasks ordered by when they are due.
+pub struct SleepQueue<T> {
+    /// Keyed by wake time, then by arrival so equal times stay distinct and in order.
+    sleepers: BTreeMap<(Duration, u64), T>,
+    arrivals: u64,
+}
+
+impl<T> SleepQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            sleepers: BTreeMap::new(),
+            arrivals: 0,
+        }
+    }
+
+    pub fn insert(&mut self, wake_at: Duration, item: T) {
+        self.sleepers.insert((wake_at, self.arrivals), item);
+        self.arrivals += 1;
+    }
+
+    /// Take the earliest sleeper due at `now`.
+    pub fn pop_expired(&mut self, now: Duration) -> Option<T> {
+        let entry = self.sleepers.first_entry()?;
+        if entry.key().0 > now {
+            return None;
+        }
+        Some(entry.remove())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::time::Duration;
+    use kunit::kunit;
+
+    use super::{Priority, RunQueue, SleepQueue};
+
+    #[kunit]
+    fn higher_priorities_run_first_and_equal_ones_take_turns() {
+        let mut queue = RunQueue::new();
+        queue.push(Priority::Normal, "a");
+        queue.push(Priority::Low, "low");
+        queue.push(Priority::Normal, "b");
+        queue.push(Priority::High, "high");
+        assert_eq!(queue.highest(), Some(Priority::High));
+
+        assert_eq!(queue.pop(), Some("high"));
+        assert_eq!(queue.pop(), Some("a"));
+        queue.push(Priority::Normal, "a");
+        assert_eq!(queue.pop(), Some("b"));
+        assert_eq!(queue.pop(), Some("a"));
+        assert_eq!(queue.highest(), Some(Priority::Low));
+        assert_eq!(queue.pop(), Some("low"));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.highest(), None);
+    }
+
+    #[kunit]
+    fn sleepers_wake_in_deadline_order() {
+        let mut queue = SleepQueue::new();
+        queue.insert(Duration::from_millis(30), 3);
+        queue.insert(Duration::from_millis(10), 1);
+        queue.insert(Duration::from_millis(30), 4);
+        queue.insert(Duration::from_millis(20), 2);
+
+        assert_eq!(queue.pop_expired(Duration::from_millis(5)), None);
+        let mut woken = Vec::new();
+        while let Some(item) = queue.pop_expired(Duration::from_millis(30)) {
+            woken.push(item);
+        }
+        assert_eq!(woken, [1, 2, 3, 4]);
+        assert_eq!(queue.pop_expired(Duration::MAX), None);
+    }
+}
@@ -0,0 +1,245 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use spin::{Mutex, MutexGuard};
+
+use super::queue::{RunQueue, SleepQueue};
+use super::{arch, Priority, Task, TaskId, TaskState};
+use crate::cpu::{self, CacheAligned, CpuLocal, MAX_CPUS};
+use crate::{interrupts, time};
+
+/// Timer ticks a task runs before a ready task of the same priority gets a turn.
+const TIME_SLICE_TICKS: u32 = 2;
+
+/// What one CPU is running and has queued.
+pub struct Cpu {
+    pub current: Option<Arc<Task>>,
+    /// `None` until `init` gives the CPU its idle task.
+    pub idle: Option<Arc<Task>>,
+    /// Tasks ready to run here: a task is queued on the CPU that made it ready, and a CPU with
+    /// nothing queued takes work from the others.
+    ready: RunQueue<Arc<Task>>,
+    /// The task just switched away from, kept alive until the switch has completed.
+    previous: Option<Arc<Task>>,
+    /// Ticks left of the current task's time slice.
+    slice: u32,
+}
+
+impl Cpu {
+    const fn new() -> Self {
+        Self {
+            current: None,
+            idle: None,
+            ready: RunQueue::new(),
+            previous: None,
+            slice: TIME_SLICE_TICKS,
+        }
+    }
+
+    fn is_idle(&self, task: &Arc<Task>) -> bool {
+        self.idle
+            .as_ref()
+            .is_some_and(|idle| Arc::ptr_eq(idle, task))
+    }
+}
+
+/// Locked only with interrupts disabled, since the timer interrupt takes it.
+pub static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+
+pub struct Scheduler {
+    sleeping: SleepQueue<Arc<Task>>,
+    /// Every task that has not been reaped, for listing.
+    pub tasks: BTreeMap<TaskId, Arc<Task>>,
+    /// Other CPUs take work from a CPU's queue, so these are guarded by the scheduler lock
+    /// rather than only by disabling preemption.
+    pub cpus: CpuLocal<Cpu>,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Self {
+            sleeping: SleepQueue::new(),
+            tasks: BTreeMap::new(),
+            cpus: CpuLocal::new([const { CacheAligned::new(Cpu::new()) }; MAX_CPUS]),
+        }
+    }
+
+    /// The task running on the calling CPU, or `None` before the scheduler starts.
+    pub fn current(&self) -> Option<&Arc<Task>> {
+        self.cpus.get(cpu::current_id()).current.as_ref()
+    }
+
+    /// Queue a task that is new, sleeping or blocked on this CPU, returning whether it should
+    /// preempt the task running here.
+    pub fn make_ready(&mut self, task: Arc<Task>) -> bool {
+        if task.state() == TaskState::Running || task.state() == TaskState::Ready {
+            return false;
+        }
+        task.set_state(TaskState::Ready);
+        let priority = task.priority();
+        let cpu = self.cpus.get_mut(cpu::current_id());
+        cpu.ready.push(priority, task);
+
+        match &cpu.current {
+            Some(current) if current.state() == TaskState::Running => {
+                cpu.is_idle(current) || priority > current.priority()
+            }
+            _ => false,
+        }
+    }
+
+    /// Park the current task until `wake_at`, measured as uptime.
+    pub fn sleep_until(&mut self, task: Arc<Task>, wake_at: core::time::Duration) {
+        task.set_state(TaskState::Sleeping);
+        self.sleeping.insert(wake_at, task);
+    }
+
+    /// The highest priority queued on any CPU.
+    fn highest_ready(&self) -> Option<Priority> {
+        self.cpus.iter().filter_map(|cpu| cpu.ready.highest()).max()
+    }
+
+    /// Take the most urgent task queued on another CPU.
+    fn steal(&mut self, thief: usize) -> Option<Arc<Task>> {
+        let victim = (0..cpu::count())
+            .filter(|&index| index != thief)
+            .filter_map(|index| Some((self.cpus.get(index).ready.highest()?, index)))
+            .max()?
+            .1;
+        self.cpus.get_mut(victim).ready.pop()
+    }
+}
+
+/// Switch to a task that was just made ready and should preempt the current one: at once, or
+/// once this CPU is preemptible again when called from an interrupt handler or with
+/// preemption disabled.
+pub fn preempt(scheduler: MutexGuard<'_, Scheduler>) {
+    if interrupts::in_interrupt() || !cpu::preemptible() {
+        drop(scheduler);
+        cpu::request_reschedule();
+        return;
+    }
+    reschedule(scheduler);
+}
+
+/// Switch this CPU to the next task to run, returning once the current task runs again.
+///
+/// The current task goes back on the run queue if it is still running; otherwise whoever
+/// parked it will make it ready. Called with interrupts disabled. The lock is handed over to
+/// the next task, which releases it in `finish_switch`, so no other CPU can pick up the
+/// current task before its registers are saved.
+pub fn reschedule(mut scheduler: MutexGuard<'_, Scheduler>) {
+    cpu::clear_reschedule_request();
+    let cpu_id = cpu::current_id();
+    let state = &mut *scheduler;
+    let cpu = state.cpus.get_mut(cpu_id);
+    let current = cpu
+        .current
+        .take()
+        .expect("the scheduler has a current task");
+
+    if current.state() == TaskState::Running && !cpu.is_idle(&current) {
+        current.set_state(TaskState::Ready);
+        cpu.ready.push(current.priority(), current.clone());
+    }
+    let queued = match cpu.ready.pop() {
+        Some(task) => Some(task),
+        None => state.steal(cpu_id),
+    };
+    let cpu = state.cpus.get_mut(cpu_id);
+    let next = queued
+        .or_else(|| cpu.idle.clone())
+        .expect("every CPU has an idle task");
+    next.set_state(TaskState::Running);
+    cpu.slice = TIME_SLICE_TICKS;
+
+    if Arc::ptr_eq(&next, &current) {
+        cpu.current = Some(current);
+        return;
+    }
+    current.set_cpu(None);
+    next.set_cpu(Some(cpu_id));
+    next.prepare_to_run();
+    let from = current.stack_pointer.get();
+    let to = unsafe { *next.stack_pointer.get() };
+    cpu.current = Some(next);
+    cpu.previous = Some(current);
+
+    core::mem::forget(scheduler);
+    unsafe {
+        arch::switch_context(from, to);
+    }
+    finish_switch();
+}
+
+/// Start scheduling on a CPU that has no task yet by switching to its idle task, leaving the
+/// caller's stack behind for good. Called with interrupts disabled.
+pub fn enter_idle(mut scheduler: MutexGuard<'_, Scheduler>) -> ! {
+    let cpu_id = cpu::current_id();
+    let cpu = scheduler.cpus.get_mut(cpu_id);
+    let idle = cpu.idle.clone().expect("every CPU has an idle task");
+    idle.set_state(TaskState::Running);
+    idle.set_cpu(Some(cpu_id));
+    let to = unsafe { *idle.stack_pointer.get() };
+    cpu.current = Some(idle);
+
+    core::mem::forget(scheduler);
+    let mut abandoned = 0;
+    unsafe {
+        arch::switch_context(&mut abandoned, to);
+    }
+    unreachable!("nothing switches back to a CPU's startup stack");
+}
+
+/// Complete a switch on the side of the task switched to: release the scheduler lock the
+/// previous task handed over, then reap the previous task if it exited.
+pub fn finish_switch() {
+    unsafe {
+        SCHEDULER.force_unlock();
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let previous = scheduler.cpus.get_mut(cpu::current_id()).previous.take();
+    if let Some(task) = previous
+        && task.state() == TaskState::Exited
+    {
+        scheduler.tasks.remove(&task.id());
+        drop(scheduler);
+        task.release_stack();
+    }
+}
+
+/// The timer interrupt: wake sleepers that are due and preempt the current task when a
+/// higher priority task is ready, its time slice ran out, or the CPU is idle with work queued.
+pub fn tick() {
+    let mut scheduler = SCHEDULER.lock();
+    let cpu_id = cpu::current_id();
+    if scheduler.cpus.get(cpu_id).idle.is_none() {
+        return;
+    }
+
+    let now = time::uptime();
+    let mut switch = false;
+    while let Some(task) = scheduler.sleeping.pop_expired(now) {
+        switch |= scheduler.make_ready(task);
+    }
+
+    // An idle CPU takes work from any queue on the next tick, since only the CPU queueing a
+    // task checks whether to run it at once.
+    let anywhere = scheduler.highest_ready();
+    let cpu = scheduler.cpus.get_mut(cpu_id);
+    cpu.slice = cpu.slice.saturating_sub(1);
+    let expired = cpu.slice == 0;
+    if expired {
+        cpu.slice = TIME_SLICE_TICKS;
+    }
+    if let Some(current) = &cpu.current {
+        if cpu.is_idle(current) {
+            switch |= anywhere.is_some();
+        } else if let Some(contender) = cpu.ready.highest() {
+            switch |= expired && contender >= current.priority();
+        }
+    }
+
+    if switch {
+        preempt(scheduler);
+    }
+}
@@ -0,0 +1,88 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use super::scheduler::{self, SCHEDULER};
+use super::{Task, TaskState};
+use crate::interrupts;
+
+/// Tasks blocked until some condition holds, woken by whoever makes it hold.
+pub struct WaitQueue {
+    /// Locked after the scheduler, never before it.
+    waiters: Mutex<VecDeque<Arc<Task>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Block the current task until `condition` returns true, checking it again after every
+    /// wakeup.
+    ///
+    /// The condition is checked with the scheduler locked, so a waker that makes it true and
+    /// then calls `wake_one` or `wake_all` is never missed. It must not block. Before the
+    /// scheduler starts there is nothing else to run, so this spins instead.
+    pub fn wait_until(&self, condition: impl Fn() -> bool) {
+        loop {
+            let satisfied = interrupts::without_interrupts(|| {
+                let scheduler = SCHEDULER.lock();
+                if condition() {
+                    return true;
+                }
+                let Some(current) = scheduler.current().cloned() else {
+                    return false;
+                };
+                current.set_state(TaskState::Blocked);
+                self.waiters.lock().push_back(current);
+                scheduler::reschedule(scheduler);
+                false
+            });
+            if satisfied {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Wake the task that has waited longest, returning whether there was one.
+    pub fn wake_one(&self) -> bool {
+        self.wake(1) == 1
+    }
+
+    /// Wake every waiting task, returning how many there were.
+    pub fn wake_all(&self) -> usize {
+        self.wake(usize::MAX)
+    }
+
+    fn wake(&self, limit: usize) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            let mut woken = 0;
+            let mut preempt = false;
+            while woken < limit {
+                let Some(task) = self.waiters.lock().pop_front() else {
+                    break;
+                };
+                preempt |= scheduler.make_ready(task);
+                woken += 1;
+            }
+            if preempt {
+                scheduler::preempt(scheduler);
+            }
+            woken
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        interrupts::without_interrupts(|| self.waiters.lock().is_empty())
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
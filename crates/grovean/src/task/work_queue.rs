@@ -0,0 +1,110 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use super::{Priority, TaskError, WaitQueue};
+use crate::sync::IrqSpinLock;
+
+type Work = Box<dyn FnOnce() + Send>;
+
+/// The queue `schedule_work` uses, run by the `events` task.
+static SYSTEM: WorkQueue = WorkQueue::new();
+
+/// Functions run one after another on a kernel task of their own, so interrupt handlers and
+/// softirqs can hand off work that blocks or takes long. Queues are statics; `start` gives
+/// each its task, and work queued before that waits for it.
+pub struct WorkQueue {
+    pending: IrqSpinLock<VecDeque<Work>>,
+    /// The worker, while it waits for work.
+    worker: WaitQueue,
+}
+
+impl WorkQueue {
+    pub const fn new() -> Self {
+        Self {
+            pending: IrqSpinLock::new(VecDeque::new()),
+            worker: WaitQueue::new(),
+        }
+    }
+
+    /// Spawn the task running this queue's work.
+    pub fn start(&'static self, name: &str, priority: Priority) -> Result<(), TaskError> {
+        super::spawn(name, priority, move || self.run())?;
+        Ok(())
+    }
+
+    /// Run `work` on the queue's task after the work queued before it. Never blocks, so
+    /// interrupt handlers may call it.
+    pub fn queue(&self, work: impl FnOnce() + Send + 'static) {
+        self.pending.lock().push_back(Box::new(work));
+        self.worker.wake_one();
+    }
+
+    /// Work queued and not yet started.
+    pub fn len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().is_empty()
+    }
+
+    /// The worker's loop, running work with the queue unlocked.
+    fn run(&self) {
+        loop {
+            self.worker.wait_until(|| !self.is_empty());
+            while let Some(work) = self.pop() {
+                work();
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<Work> {
+        self.pending.lock().pop_front()
+    }
+}
+
+impl Default for WorkQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run `work` on the system work queue.
+pub fn schedule_work(work: impl FnOnce() + Send + 'static) {
+    SYSTEM.queue(work);
+}
+
+/// Start the system work queue.
+pub fn init() {
+    SYSTEM
+        .start("events", Priority::Normal)
+        .expect("failed to allocate the events task stack");
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use kunit::kunit;
+
+    use super::WorkQueue;
+
+    #[kunit]
+    fn queued_work_runs_in_order() {
+        let queue = WorkQueue::new();
+        let order = Arc::new(AtomicUsize::new(0));
+        for step in 1..=3 {
+            let order = order.clone();
+            queue.queue(move || {
+                let previous = order.swap(step, Ordering::Relaxed);
+                assert_eq!(previous, step - 1);
+            });
+        }
+        assert_eq!(queue.len(), 3);
+        while let Some(work) = queue.pop() {
+            work();
+        }
+        assert_eq!(order.load(Ordering::Relaxed), 3);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+use core::arch::naked_asm;
+
+/// Callee-saved registers `switch_context` keeps on a stack that is switched out.
+const SAVED_REGISTERS: usize = 6;
+
+/// Push the callee-saved registers, store the stack pointer to `from`, then load `to` and
+/// resume whatever was switched out there.
+///
+/// # Safety
+///
+/// `to` must come from `initial_stack_pointer` or an earlier `switch_context`, and interrupts
+/// must be disabled.
+#[unsafe(naked)]
+pub unsafe extern "C" fn switch_context(from: *mut usize, to: usize) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    )
+}
+
+/// Lay out a fresh stack so that switching to it calls `entry`, returning its stack pointer.
+pub fn initial_stack_pointer(top: usize, entry: extern "C" fn() -> !) -> usize {
+    let top = top & !0xf;
+    // Zeroed registers, then `entry` for `ret`, then an empty return address for `entry`
+    // itself, leaving the stack aligned as if it had been called.
+    let stack_pointer = top - (SAVED_REGISTERS + 2) * 8;
+    let words = stack_pointer as *mut usize;
+    unsafe {
+        for index in 0..SAVED_REGISTERS {
+            words.add(index).write(0);
+        }
+        words.add(SAVED_REGISTERS).write(entry as usize);
+        words.add(SAVED_REGISTERS + 1).write(0);
+    }
+    stack_pointer
+}
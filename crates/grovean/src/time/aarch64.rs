@@ -0,0 +1,19 @@
+use core::arch::asm;
+
+/// The virtual count of the generic timer.
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe {
+        asm!("isb", "mrs {}, cntvct_el0", out(reg) count, options(nomem, nostack, preserves_flags));
+    }
+    count
+}
+
+/// Firmware programs the generic timer frequency before handing over.
+pub fn counter_frequency() -> u64 {
+    let frequency: u64;
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack, preserves_flags));
+    }
+    frequency
+}
@@ -0,0 +1,94 @@
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+pub mod timer;
+mod wheel;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+use aarch64 as arch;
+#[cfg(target_arch = "x86_64")]
+use x86_64 as arch;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+#[cfg(not(test))]
+use limine::request::DateAtBootRequest;
+
+#[cfg(not(test))]
+#[used]
+#[unsafe(link_section = ".requests")]
+static DATE_AT_BOOT_REQUEST: DateAtBootRequest = DateAtBootRequest::new();
+
+/// Unix time in seconds when the bootloader read the RTC.
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+/// Counter value when `init` ran, the zero point of `uptime`.
+static BOOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Counter ticks per second, or zero when the frequency could not be determined.
+static COUNTER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Record the boot date and measure the frequency of the CPU counter.
+pub fn init() {
+    #[cfg(not(test))]
+    if let Some(response) = DATE_AT_BOOT_REQUEST.get_response() {
+        BOOT_TIME.store(response.timestamp().as_secs(), Ordering::Relaxed);
+    }
+
+    let frequency = arch::counter_frequency();
+    COUNTER_FREQUENCY.store(frequency, Ordering::Relaxed);
+    BOOT_COUNTER.store(arch::counter(), Ordering::Relaxed);
+    if frequency == 0 {
+        crate::warn_ln!("time: counter frequency unknown, the clock will not advance");
+    } else {
+        crate::info_ln!(
+            "time: boot at {} s, counter at {} Hz",
+            BOOT_TIME.load(Ordering::Relaxed),
+            frequency
+        );
+    }
+}
+
+/// Time elapsed since `init`.
+pub fn uptime() -> Duration {
+    let ticks = arch::counter().wrapping_sub(BOOT_COUNTER.load(Ordering::Relaxed));
+    ticks_to_duration(ticks, COUNTER_FREQUENCY.load(Ordering::Relaxed))
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    BOOT_TIME.load(Ordering::Relaxed) + uptime().as_secs()
+}
+
+fn ticks_to_duration(ticks: u64, frequency: u64) -> Duration {
+    if frequency == 0 {
+        return Duration::ZERO;
+    }
+    let nanos = ticks as u128 * 1_000_000_000 / frequency as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use kunit::kunit;
+
+    use super::ticks_to_duration;
+
+    #[kunit]
+    fn ticks_convert_without_overflow() {
+        assert_eq!(
+            ticks_to_duration(62_500_000, 62_500_000),
+            Duration::from_secs(1)
+        );
+        assert_eq!(ticks_to_duration(3, 1_000), Duration::from_millis(3));
+        assert_eq!(
+            ticks_to_duration(u64::MAX, 1_000_000_000),
+            Duration::from_nanos(u64::MAX)
+        );
+    }
+
+    #[kunit]
+    fn unknown_frequency_stops_the_clock() {
+        assert_eq!(ticks_to_duration(12345, 0), Duration::ZERO);
+    }
+}
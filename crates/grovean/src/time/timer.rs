@@ -0,0 +1,97 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use super::wheel::{TimerId, TimerWheel};
+use crate::interrupts::softirq::{self, Softirq};
+use crate::interrupts::TIMER_HZ;
+use crate::sync::IrqSpinLock;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Pending kernel timers, in scheduler ticks since boot.
+static TIMERS: IrqSpinLock<TimerWheel<Callback>> = IrqSpinLock::new(TimerWheel::new(0));
+
+/// A pending call to a function at some later tick, which may still be cancelled. Dropping
+/// it leaves the timer pending.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Timer {
+    id: TimerId,
+}
+
+impl Timer {
+    /// Stop the timer, returning whether it had not fired yet.
+    pub fn cancel(self) -> bool {
+        TIMERS.lock().cancel(self.id).is_some()
+    }
+}
+
+/// Call `callback` once `delay` has passed, to the resolution of the timer tick. It runs in
+/// the timer softirq, so it must not block; work that might should be queued with
+/// `task::schedule_work`.
+pub fn add(delay: Duration, callback: impl FnOnce() + Send + 'static) -> Timer {
+    let expires = current_tick().saturating_add(delay_ticks(delay));
+    Timer {
+        id: TIMERS.lock().insert(expires, Box::new(callback)),
+    }
+}
+
+/// Kernel timers waiting to fire.
+pub fn pending() -> usize {
+    TIMERS.lock().len()
+}
+
+/// Called from the timer interrupt: expire due timers once it returns.
+pub fn tick() {
+    softirq::raise(Softirq::Timer);
+}
+
+/// Start expiring kernel timers from the timer softirq.
+pub fn init() {
+    softirq::set_handler(Softirq::Timer, run_expired);
+}
+
+/// The timer softirq. Another CPU already expiring timers covers this tick too.
+fn run_expired() {
+    let mut expired = Vec::new();
+    {
+        let Some(mut timers) = TIMERS.try_lock() else {
+            return;
+        };
+        timers.advance(current_tick(), |callback| expired.push(callback));
+    }
+    for callback in expired {
+        callback();
+    }
+}
+
+fn current_tick() -> u64 {
+    duration_to_ticks(super::uptime())
+}
+
+/// Ticks to wait for at least `delay` to pass, counting the partial tick under way as none.
+fn delay_ticks(delay: Duration) -> u64 {
+    let ticks = delay.as_nanos().div_ceil(1_000_000_000 / TIMER_HZ as u128);
+    u64::try_from(ticks).unwrap_or(u64::MAX).saturating_add(1)
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() * TIMER_HZ as u128 / 1_000_000_000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use kunit::kunit;
+
+    use super::{delay_ticks, duration_to_ticks};
+
+    #[kunit]
+    fn delays_round_up_to_whole_ticks() {
+        assert_eq!(delay_ticks(Duration::ZERO), 1);
+        assert_eq!(delay_ticks(Duration::from_millis(10)), 2);
+        assert_eq!(delay_ticks(Duration::from_millis(11)), 3);
+        assert_eq!(duration_to_ticks(Duration::from_millis(29)), 2);
+        assert_eq!(duration_to_ticks(Duration::from_secs(3)), 300);
+    }
+}
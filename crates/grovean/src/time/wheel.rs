@@ -0,0 +1,167 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Slots per level, each level's slot spanning a whole turn of the level below.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+/// Levels of the wheel. Four reach 2^24 ticks ahead, over 46 hours at 100 Hz; timeouts past
+/// that wait in the last level and are placed again as it turns.
+const LEVELS: usize = 4;
+const MAX_DELTA: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+pub type TimerId = u64;
+
+/// A timer waiting in a slot.
+#[derive(Clone, Copy)]
+struct Entry {
+    id: TimerId,
+    expires: u64,
+}
+
+/// Timeouts sorted into slots by how far ahead they expire, so each tick only looks at the
+/// timers in one slot however many are pending. Timers in the first
+/// level expire at their exact tick; later ones move down a level each time the level below
+/// completes a turn.
+pub struct TimerWheel<T> {
+    levels: [[Vec<Entry>; SLOTS]; LEVELS],
+    /// Pending timers by id; a cancelled timer leaves its slot entry behind, skipped later.
+    timers: BTreeMap<TimerId, T>,
+    /// The next tick to process.
+    next: u64,
+    next_id: TimerId,
+}
+
+impl<T> TimerWheel<T> {
+    /// A wheel whose next tick to process is `now`.
+    pub const fn new(now: u64) -> Self {
+        Self {
+            levels: [const { [const { Vec::new() }; SLOTS] }; LEVELS],
+            timers: BTreeMap::new(),
+            next: now,
+            next_id: 0,
+        }
+    }
+
+    /// Add `item` to expire at tick `expires`, or at the next tick processed if that has
+    /// passed.
+    pub fn insert(&mut self, expires: u64, item: T) -> TimerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.insert(id, item);
+        self.place(Entry { id, expires });
+        id
+    }
+
+    /// Remove a pending timer, handing back its item.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        self.timers.remove(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Process every tick up to and including `now`, passing each expired item to `expire`
+    /// in the order the ticks come.
+    pub fn advance(&mut self, now: u64, mut expire: impl FnMut(T)) {
+        while self.next <= now {
+            let tick = self.next;
+            self.cascade(tick);
+            let slot = (tick & SLOT_MASK) as usize;
+            for entry in core::mem::take(&mut self.levels[0][slot]) {
+                if entry.expires > tick {
+                    self.place(entry);
+                } else if let Some(item) = self.timers.remove(&entry.id) {
+                    expire(item);
+                }
+            }
+            self.next = tick + 1;
+        }
+    }
+
+    /// Move the timers of each level's next slot down once the levels below complete a turn.
+    fn cascade(&mut self, tick: u64) {
+        for level in 1..LEVELS {
+            let shift = SLOT_BITS * level as u32;
+            if tick & ((1 << shift) - 1) != 0 {
+                break;
+            }
+            let slot = ((tick >> shift) & SLOT_MASK) as usize;
+            for entry in core::mem::take(&mut self.levels[level][slot]) {
+                if self.timers.contains_key(&entry.id) {
+                    self.place(entry);
+                }
+            }
+        }
+    }
+
+    fn place(&mut self, entry: Entry) {
+        let expires = entry.expires.max(self.next);
+        let delta = (expires - self.next).min(MAX_DELTA);
+        let level = (0..LEVELS)
+            .find(|&level| delta < 1 << (SLOT_BITS * (level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let slot = ((self.next + delta) >> (SLOT_BITS * level as u32)) & SLOT_MASK;
+        self.levels[level][slot as usize].push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use kunit::kunit;
+
+    use super::{TimerWheel, MAX_DELTA};
+
+    fn expired(wheel: &mut TimerWheel<u64>, now: u64) -> Vec<u64> {
+        let mut expired = Vec::new();
+        wheel.advance(now, |item| expired.push(item));
+        expired
+    }
+
+    #[kunit]
+    fn timers_expire_on_their_tick_at_every_level() {
+        let mut wheel = TimerWheel::new(10);
+        for expires in [11, 74, 75, 4_200, 300_000] {
+            wheel.insert(expires, expires);
+        }
+        assert_eq!(expired(&mut wheel, 10), vec![]);
+        assert_eq!(expired(&mut wheel, 11), vec![11]);
+        assert_eq!(expired(&mut wheel, 74), vec![74]);
+        assert_eq!(expired(&mut wheel, 75), vec![75]);
+        assert_eq!(expired(&mut wheel, 4_199), vec![]);
+        assert_eq!(expired(&mut wheel, 4_200), vec![4_200]);
+        assert_eq!(expired(&mut wheel, 299_999), vec![]);
+        assert_eq!(expired(&mut wheel, 300_000), vec![300_000]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[kunit]
+    fn past_deadlines_expire_on_the_next_tick() {
+        let mut wheel = TimerWheel::new(100);
+        wheel.insert(3, 3);
+        assert_eq!(expired(&mut wheel, 100), vec![3]);
+    }
+
+    #[kunit]
+    fn cancelled_timers_never_expire() {
+        let mut wheel = TimerWheel::new(0);
+        let cancelled = wheel.insert(5_000, 1);
+        wheel.insert(5_000, 2);
+        assert_eq!(wheel.cancel(cancelled), Some(1));
+        assert_eq!(wheel.cancel(cancelled), None);
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(expired(&mut wheel, 6_000), vec![2]);
+    }
+
+    #[kunit]
+    fn timeouts_beyond_the_last_level_wait_their_turn() {
+        let mut wheel = TimerWheel::new(0);
+        let far = MAX_DELTA + 100;
+        wheel.insert(far, far);
+        assert_eq!(expired(&mut wheel, far - 1), vec![]);
+        assert_eq!(expired(&mut wheel, far), vec![far]);
+    }
+}
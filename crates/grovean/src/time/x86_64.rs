@@ -0,0 +1,77 @@
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use x86_64::instructions::port::Port;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_CHANNEL_2: u16 = 0x42;
+/// Port B of the keyboard controller, which gates PIT channel 2 and reads back its output.
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUTPUT: u8 = 1 << 5;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary.
+const PIT_ONE_SHOT: u8 = 0b1011_0000;
+
+const CALIBRATION_MS: u64 = 10;
+/// Give up on a PIT that never fires, as on machines without legacy devices.
+const CALIBRATION_SPINS: usize = 100_000_000;
+
+/// The time stamp counter, which runs at a constant rate on every CPU QEMU models.
+pub fn counter() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Take the TSC frequency from CPUID when it is enumerated, otherwise time it against the PIT.
+pub fn counter_frequency() -> u64 {
+    cpuid_frequency().unwrap_or_else(calibrate_with_pit)
+}
+
+fn cpuid_frequency() -> Option<u64> {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf < 0x15 {
+        return None;
+    }
+    // Leaf 0x15 gives the TSC to core crystal clock ratio and, sometimes, the crystal frequency.
+    let leaf = __cpuid(0x15);
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+    Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64)
+}
+
+fn calibrate_with_pit() -> u64 {
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_2);
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    unsafe {
+        let saved = port_b.read();
+        port_b.write((saved & !PORT_B_SPEAKER) | PORT_B_GATE);
+        command.write(PIT_ONE_SHOT);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+
+        let start = counter();
+        let mut fired = false;
+        for _ in 0..CALIBRATION_SPINS {
+            if port_b.read() & PORT_B_OUTPUT != 0 {
+                fired = true;
+                break;
+            }
+        }
+        let end = counter();
+        port_b.write(saved);
+
+        if fired {
+            (end - start) * 1000 / CALIBRATION_MS
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use grovean_rt::io::{self, File, STDIN, STDOUT};
+use grovean_rt::syscall::{self, Errno, OPEN_READ};
+use grovean_rt::{entry, env, eprintln};
+
+entry!(main);
+
+/// Copy the files named as arguments to standard output, or standard input if there are none.
+fn main() -> i32 {
+    let mut status = 0;
+    let mut paths = env::args().skip(1).peekable();
+    if paths.peek().is_none()
+        && let Err(error) = copy(STDIN)
+    {
+        eprintln!("cat: standard input: {:?}", error);
+        status = 1;
+    }
+    for path in paths {
+        let result = File::open(path, OPEN_READ).and_then(|file| copy(file.descriptor()));
+        if let Err(error) = result {
+            eprintln!("cat: {}: {:?}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
+
+fn copy(descriptor: usize) -> Result<(), Errno> {
+    let mut buffer = [0; 4096];
+    loop {
+        match syscall::read(descriptor, &mut buffer)? {
+            0 => return Ok(()),
+            read => io::write_all(STDOUT, &buffer[..read])?,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use grovean_rt::{entry, env, println, syscall};
+
+entry!(main);
+
+/// The first process: report what it was started with and show the heap working. There is no
+/// way to start other programs yet, so it exits once done.
+fn main() -> i32 {
+    println!("init: running as pid {}", syscall::getpid());
+    for (index, argument) in env::args().enumerate() {
+        println!("init: argv[{}] = {}", index, argument);
+    }
+    for variable in env::vars() {
+        println!("init: {}", variable);
+    }
+
+    let squares: Vec<u64> = (1..=16).map(|n| n * n).collect();
+    let mut line = String::new();
+    for square in &squares {
+        line.push_str(&alloc::format!("{} ", square));
+    }
+    println!("init: squares {}", line.trim_end());
+    println!(
+        "init: {} bytes of heap in one block",
+        Vec::<u8>::with_capacity(1 << 20).capacity()
+    );
+    0
+}